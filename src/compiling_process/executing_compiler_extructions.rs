@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::compiling_process::parsing::{defined_types, parse_source};
+use crate::inner_representation::abstract_syntax_tree::{CompilerCommand, Let, AST};
+
+// the prelude is built into the compiler, `include prelude;` reads it from here and not from a file
+pub const PRELUDE: &str = include_str!("prelude.tl");
+
+// collects the definitions of a program and of everything it includes, each file only once
+pub struct Loader {
+    included: Vec<PathBuf>,
+    commands: Vec<CompilerCommand>,
+    program: Vec<Let>,
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Loader {
+            included: vec![],
+            commands: vec![],
+            program: vec![],
+        }
+    }
+
+    pub fn definitions(&self) -> &[Let] {
+        &self.program
+    }
+
+    // the types defined so far, what comes later may use them
+    pub fn types(&self) -> Vec<String> {
+        defined_types(&self.program)
+    }
+
+    fn add(&mut self, text: String, directory: &Path) -> Result<(), String> {
+        let source = parse_source(text)?;
+        // included files come before the one including them
+        for command in source.get_commands() {
+            self.execute(command, directory)?;
+        }
+        let ast = source.read(&self.types())?;
+        self.program.extend(ast.get_program().iter().cloned());
+        Ok(())
+    }
+
+    // `include list;` means list.tl next to the including file, `load /lib/list.tl/;` a path
+    pub fn execute(&mut self, command: &CompilerCommand, directory: &Path) -> Result<(), String> {
+        match command {
+            CompilerCommand::Include(name) if name == "prelude" => {
+                if self.included.iter().any(|x| x.as_os_str() == "prelude") {
+                    return Ok(());
+                }
+                self.included.push(PathBuf::from("prelude"));
+                self.add(PRELUDE.to_string(), directory)
+                    .map_err(|error| format!("in the prelude: {}", error))
+            },
+            CompilerCommand::Include(name) => self.load(&directory.join(format!("{}.tl", name))),
+            CompilerCommand::Load(path) => self.load(&directory.join(path)),
+            CompilerCommand::Terminating(_) => {
+                self.commands.push(command.clone());
+                Ok(())
+            },
+        }
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        if self.included.iter().any(|x| x == path) {
+            return Ok(());
+        }
+        self.included.push(path.to_path_buf());
+        let text = fs::read_to_string(path).map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        self.add(text, directory).map_err(|error| format!("in {}: {}", path.display(), error))
+    }
+
+    pub fn finish(self) -> AST {
+        AST::new(self.commands, self.program)
+    }
+}
+
+// a program with what it includes, and how many of its definitions come from the prelude,
+// which goes first unless it is turned off
+pub fn load(path: &Path, prelude: bool) -> Result<(AST, usize), String> {
+    let mut loader = Loader::new();
+    if prelude {
+        loader.execute(&CompilerCommand::Include("prelude".to_string()), Path::new("."))?;
+    }
+    let skip = loader.definitions().len();
+    loader.load(path)?;
+    Ok((loader.finish(), skip))
+}
+
+#[cfg(test)]
+mod executing_compiler_extructions_tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::compiling_process::interpreting::Interpreter;
+    use crate::compiling_process::static_analysis::type_inference::infer_program;
+    use crate::inner_representation::abstract_syntax_tree::CompilerCommand;
+    use super::{load, Loader};
+
+    #[test]
+    fn unit_tests() {
+        let mut loader = Loader::new();
+        let prelude = CompilerCommand::Include("prelude".to_string());
+        loader.execute(&prelude, Path::new(".")).unwrap();
+        let count = loader.finish().get_program().len();
+
+        // including it twice changes nothing
+        let mut loader = Loader::new();
+        loader.execute(&prelude, Path::new(".")).unwrap();
+        loader.execute(&prelude, Path::new(".")).unwrap();
+        assert!(["Bool", "Maybe", "Either", "List", "Pair"].iter().all(|x| loader.types().contains(&x.to_string())));
+        let ast = loader.finish();
+        assert_eq!(ast.get_program().len(), count);
+
+        let types = infer_program(ast.get_program()).unwrap();
+        assert_eq!(types["map"].to_string(), "{A : @0} -> {B : @0} -> (A -> B) -> List A -> List B");
+        assert_eq!(types["not"].to_string(), "Bool -> Bool");
+    }
+
+    #[test]
+    fn programs() {
+        let directory = std::env::temp_dir().join("test-language-loader");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("numbers.tl"), "$numbers = Cons (1, Cons (2, Cons (3, Nil)));").unwrap();
+        fs::write(directory.join("main.tl"), "
+            include numbers;
+            $main = length (filter (x ~> bool (#lt 1 x)) (append numbers numbers));
+        ").unwrap();
+        let (ast, skip) = load(&directory.join("main.tl"), true).unwrap();
+        // numbers and main come after the prelude
+        assert_eq!(ast.get_program().len(), skip + 2);
+        infer_program(ast.get_program()).unwrap();
+        let interpreter = Interpreter::new(ast.get_program());
+        assert_eq!(interpreter.get("main").unwrap().to_string(), "4");
+
+        // without the prelude there is no List and no length
+        let error = load(&directory.join("main.tl"), false)
+            .and_then(|(ast, _)| infer_program(ast.get_program()).map(|_| ()))
+            .unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("length"));
+
+        let error = load(&directory.join("missing.tl"), true).unwrap_err();
+        assert!(error.starts_with("cannot read"));
+    }
+}
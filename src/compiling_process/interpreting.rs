@@ -0,0 +1,561 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::compiling_process::pattern_compiling::compile_match;
+use crate::compiling_process::primitives::{primitive, Primitive};
+use crate::compiling_process::static_analysis::type_inference::Codata;
+use crate::inner_representation::abstract_syntax_tree::{AtomicValue, Expr, Let, Name, Pattern, Type, Value};
+use crate::inner_representation::decision_tree::{Case, DecisionTree, Step};
+
+#[derive(Clone, Debug)]
+pub enum RuntimeValue<'a> {
+    Int(i32),
+    StringLiteral(String),
+    Tuple(Vec<RuntimeValue<'a>>),
+    Record(Vec<(String, RuntimeValue<'a>)>),
+    Either(String, Box<RuntimeValue<'a>>),
+    Closure(Rc<Closure<'a>>),
+    // a primitive with the arguments it was given so far
+    Primitive(Primitive, Vec<RuntimeValue<'a>>),
+    Type,
+    // a field of codata, only evaluated once looked at so it can go on forever
+    Lazy(Rc<Thunk<'a>>),
+}
+
+#[derive(Debug)]
+pub struct Thunk<'a>(RefCell<Suspension<'a>>);
+
+#[derive(Debug)]
+enum Suspension<'a> {
+    Delayed(&'a Value, Environment<'a>),
+    Forcing,
+    Forced(RuntimeValue<'a>),
+}
+
+#[derive(Debug)]
+pub struct Closure<'a> {
+    parameters: &'a [(Name, Option<Type>)],
+    body: &'a Expr,
+    environment: Environment<'a>,
+    arguments: Vec<RuntimeValue<'a>>,
+    // a local function is given itself under its name when called, its environment can't hold it without a cycle
+    name: Option<&'a str>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Environment<'a>(Option<Rc<(String, RuntimeValue<'a>, Environment<'a>)>>);
+
+impl<'a> Environment<'a> {
+    pub fn new() -> Self {
+        Environment(None)
+    }
+
+    pub fn extend(&self, name: &str, value: RuntimeValue<'a>) -> Self {
+        Environment(Some(Rc::new((name.to_string(), value, self.clone()))))
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&RuntimeValue<'a>> {
+        let mut current = self;
+        while let Some(node) = &current.0 {
+            if node.0 == name {
+                return Some(&node.1);
+            }
+            current = &node.2;
+        }
+        None
+    }
+}
+
+enum Global<'a> {
+    Pending(&'a Value),
+    InProgress,
+    Done(RuntimeValue<'a>),
+}
+
+// what is left to compute in tail position, done by the loop in run instead of a call so loops don't grow the stack
+enum Tail<'a> {
+    Done(RuntimeValue<'a>),
+    Evaluate(&'a Value, Environment<'a>),
+    Apply(RuntimeValue<'a>, Vec<RuntimeValue<'a>>),
+}
+
+pub struct Interpreter<'a> {
+    globals: RefCell<HashMap<String, Global<'a>>>,
+    trees: RefCell<HashMap<usize, Rc<DecisionTree>>>,
+    codata: Codata,
+}
+
+fn fits(case: &Case, value: &RuntimeValue) -> bool {
+    match (case, value) {
+        (Case::Constructor(expected), RuntimeValue::Either(name, _)) => expected == name,
+        (Case::Literal(AtomicValue::Int(expected)), RuntimeValue::Int(n)) => expected == n,
+        (Case::Literal(AtomicValue::StringLiteral(expected)), RuntimeValue::StringLiteral(text)) => expected == text,
+        _ => false,
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a [Let]) -> Self {
+        let globals = program
+            .iter()
+            .map(|Let(name, value, _)| (name.get_name().to_string(), Global::Pending(value)))
+            .collect();
+        Interpreter {
+            globals: RefCell::new(globals),
+            trees: RefCell::new(HashMap::new()),
+            codata: Codata::new(program),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<RuntimeValue<'a>, String> {
+        let state = self.globals.borrow_mut().insert(name.to_string(), Global::InProgress);
+        let value = match state {
+            Some(Global::Done(value)) => value,
+            Some(Global::Pending(value)) => match self.evaluate(value, &Environment::new()) {
+                Ok(value) => value,
+                Err(error) => {
+                    self.globals.borrow_mut().insert(name.to_string(), Global::Pending(value));
+                    return Err(error);
+                },
+            },
+            Some(Global::InProgress) => return Err(format!("{} is defined in terms of itself", name)),
+            None => {
+                self.globals.borrow_mut().remove(name);
+                return Err(format!("unbound name {}", name));
+            },
+        };
+        self.globals.borrow_mut().insert(name.to_string(), Global::Done(value.clone()));
+        Ok(value)
+    }
+
+    // the value at the end of the occurrence, the ones on the way there are forced
+    fn select(&self, value: &RuntimeValue<'a>, occurrence: &[Step]) -> Result<RuntimeValue<'a>, String> {
+        occurrence.iter().try_fold(value.clone(), |value, step| match (step, self.force(value)?) {
+            (Step::Payload, RuntimeValue::Either(_, payload)) => Ok(*payload),
+            (Step::Index(i), RuntimeValue::Tuple(items)) => {
+                items.get(*i).cloned().ok_or(format!("tuple has no element {}", i))
+            },
+            (Step::Field(field), RuntimeValue::Record(fields)) => fields
+                .into_iter()
+                .find(|(name, _)| name == field)
+                .map(|(_, x)| x)
+                .ok_or(format!("record has no field {}", field)),
+            (_, value) => Err(format!("can't match {} against the pattern", value)),
+        })
+    }
+
+    // values that can neither fail nor loop, computing them now is the same as delaying them
+    fn is_immediate(value: &Value, environment: &Environment<'a>) -> bool {
+        match value {
+            Value::Constant(_, _) | Value::Function(_, _) | Value::Type(_) | Value::Refl => true,
+            Value::Var(name) => environment.lookup(name.get_name()).is_some(),
+            Value::Tuple(items) => items.iter().all(|x| Self::is_immediate(x, environment)),
+            Value::Record(fields) => fields.iter().all(|(_, x)| Self::is_immediate(x, environment)),
+            Value::Either(_, payload) => Self::is_immediate(payload, environment),
+            _ => false,
+        }
+    }
+
+    fn delay(&self, value: &'a Value, environment: &Environment<'a>) -> Result<RuntimeValue<'a>, String> {
+        if Self::is_immediate(value, environment) {
+            return self.evaluate(value, environment);
+        }
+        Ok(RuntimeValue::Lazy(Rc::new(Thunk(RefCell::new(Suspension::Delayed(value, environment.clone()))))))
+    }
+
+    // only what is inside codata waits until it is looked at
+    fn contents(&self, lazy: bool, value: &'a Value, environment: &Environment<'a>) -> Result<RuntimeValue<'a>, String> {
+        if lazy { self.delay(value, environment) } else { self.evaluate(value, environment) }
+    }
+
+    pub fn force(&self, value: RuntimeValue<'a>) -> Result<RuntimeValue<'a>, String> {
+        let thunk = match value {
+            RuntimeValue::Lazy(thunk) => thunk,
+            other => return Ok(other),
+        };
+        let state = thunk.0.replace(Suspension::Forcing);
+        let value = match state {
+            Suspension::Forced(value) => value,
+            Suspension::Delayed(value, environment) => match self.evaluate(value, &environment).and_then(|x| self.force(x)) {
+                Ok(value) => value,
+                Err(error) => {
+                    thunk.0.replace(Suspension::Delayed(value, environment));
+                    return Err(error);
+                },
+            },
+            Suspension::Forcing => return Err("a lazy value is defined in terms of itself".to_string()),
+        };
+        thunk.0.replace(Suspension::Forced(value.clone()));
+        Ok(value)
+    }
+
+    // forces everything inside as well, which never ends for infinite codata
+    pub fn force_all(&self, value: RuntimeValue<'a>) -> Result<RuntimeValue<'a>, String> {
+        match self.force(value)? {
+            RuntimeValue::Tuple(items) => Ok(RuntimeValue::Tuple(
+                items.into_iter().map(|x| self.force_all(x)).collect::<Result<_, _>>()?
+            )),
+            RuntimeValue::Record(fields) => Ok(RuntimeValue::Record(
+                fields
+                    .into_iter()
+                    .map(|(name, x)| Ok((name, self.force_all(x)?)))
+                    .collect::<Result<_, String>>()?
+            )),
+            RuntimeValue::Either(name, payload) => Ok(RuntimeValue::Either(name, Box::new(self.force_all(*payload)?))),
+            other => Ok(other),
+        }
+    }
+
+    fn decision_tree(&self, arms: &'a [(Pattern, Value)]) -> Result<Rc<DecisionTree>, String> {
+        let key = arms.as_ptr() as usize;
+        if let Some(tree) = self.trees.borrow().get(&key) {
+            return Ok(tree.clone());
+        }
+        let patterns: Vec<&Pattern> = arms.iter().map(|(pattern, _)| pattern).collect();
+        let tree = Rc::new(compile_match(&patterns)?);
+        self.trees.borrow_mut().insert(key, tree.clone());
+        Ok(tree)
+    }
+
+    fn evaluate_match(
+        &self,
+        scrutinee: RuntimeValue<'a>,
+        arms: &'a [(Pattern, Value)],
+        environment: &Environment<'a>,
+    ) -> Result<Tail<'a>, String> {
+        let tree = self.decision_tree(arms)?;
+        let mut current = &*tree;
+        loop {
+            match current {
+                DecisionTree::Fail => return Err(format!("no pattern matches {}", scrutinee)),
+                DecisionTree::Leaf(arm, bindings) => {
+                    let mut environment = environment.clone();
+                    for (name, occurrence) in bindings {
+                        let value = self.select(&scrutinee, occurrence)?;
+                        environment = environment.extend(name.get_name(), value);
+                    }
+                    return Ok(Tail::Evaluate(&arms[*arm].1, environment));
+                },
+                DecisionTree::Switch(occurrence, cases, default) => {
+                    let value = self.force(self.select(&scrutinee, occurrence)?)?;
+                    current = match cases.iter().find(|(case, _)| fits(case, &value)) {
+                        Some((_, tree)) => tree,
+                        None => match default {
+                            Some(tree) => tree,
+                            None => return Err(format!("no pattern matches {}", scrutinee)),
+                        },
+                    };
+                },
+            }
+        }
+    }
+
+    fn run(&self, mut next: Tail<'a>) -> Result<RuntimeValue<'a>, String> {
+        loop {
+            next = match next {
+                Tail::Done(value) => return Ok(value),
+                Tail::Evaluate(value, environment) => self.step(value, &environment)?,
+                Tail::Apply(function, arguments) => self.enter(function, arguments)?,
+            };
+        }
+    }
+
+    // the lets are computed here, the value is left in tail position
+    fn expr(&self, expr: &'a Expr, environment: &Environment<'a>) -> Result<Tail<'a>, String> {
+        let Expr(lets, value) = expr;
+        let mut environment = environment.clone();
+        for local in lets {
+            let Let(name, value, _) = local;
+            let value = match value {
+                Value::Function(parameters, body) if local.is_recursive() => RuntimeValue::Closure(Rc::new(Closure {
+                    parameters,
+                    body,
+                    environment: environment.clone(),
+                    arguments: vec![],
+                    name: Some(name.get_name()),
+                })),
+                value => self.evaluate(value, &environment)?,
+            };
+            environment = environment.extend(name.get_name(), value);
+        }
+        Ok(Tail::Evaluate(value, environment))
+    }
+
+    pub fn evaluate_expr(&self, expr: &'a Expr, environment: &Environment<'a>) -> Result<RuntimeValue<'a>, String> {
+        self.expr(expr, environment).and_then(|x| self.run(x))
+    }
+
+    pub fn evaluate(&self, value: &'a Value, environment: &Environment<'a>) -> Result<RuntimeValue<'a>, String> {
+        self.run(Tail::Evaluate(value, environment.clone()))
+    }
+
+    fn step(&self, value: &'a Value, environment: &Environment<'a>) -> Result<Tail<'a>, String> {
+        let value = match value {
+            Value::Var(name) => match environment.lookup(name.get_name()) {
+                Some(value) => value.clone(),
+                None => match primitive(name.get_name()) {
+                    Some(primitive) => RuntimeValue::Primitive(primitive, vec![]),
+                    None => self.get(name.get_name())?,
+                },
+            },
+            Value::Tuple(items) => RuntimeValue::Tuple(
+                items
+                    .iter()
+                    .map(|x| self.evaluate(x, environment))
+                    .collect::<Result<_, _>>()?
+            ),
+            Value::Record(fields) => {
+                let lazy = self.codata.is_lazy_record(fields.iter().map(|(name, _)| name.get_name()));
+                RuntimeValue::Record(
+                    fields
+                        .iter()
+                        .map(|(name, x)| Ok((name.get_name().to_string(), self.contents(lazy, x, environment)?)))
+                        .collect::<Result<_, String>>()?
+                )
+            },
+            Value::Either(name, payload) => RuntimeValue::Either(
+                name.get_name().to_string(),
+                Box::new(self.contents(self.codata.is_lazy_constructor(name.get_name()), payload, environment)?),
+            ),
+            Value::Match(scrutinee, arms) => {
+                let scrutinee = self.evaluate(scrutinee, environment)?;
+                return self.evaluate_match(scrutinee, arms, environment);
+            },
+            Value::Function(parameters, body) => RuntimeValue::Closure(Rc::new(Closure {
+                parameters,
+                body,
+                environment: environment.clone(),
+                arguments: vec![],
+                name: None,
+            })),
+            Value::Application(function, arguments) => {
+                let function = self.evaluate(function, environment)?;
+                // implicit arguments only matter to the type checker
+                let arguments = arguments
+                    .iter()
+                    .filter(|x| !matches!(x, Value::Implicit(_)))
+                    .map(|x| self.evaluate(x, environment))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if arguments.is_empty() {
+                    return Ok(Tail::Done(function));
+                }
+                return Ok(Tail::Apply(function, arguments));
+            },
+            Value::Constant(AtomicValue::Int(n), _) => RuntimeValue::Int(*n),
+            Value::Constant(AtomicValue::StringLiteral(text), _) => RuntimeValue::StringLiteral(text.clone()),
+            Value::Type(_) => RuntimeValue::Type,
+            Value::Hole(name) => return Err(format!("reached the unfinished hole ?{}", name)),
+            Value::Implicit(_) => return Err("implicit arguments can only be passed to functions".to_string()),
+            // proofs carry no information
+            Value::Refl => RuntimeValue::Tuple(vec![]),
+        };
+        Ok(Tail::Done(value))
+    }
+
+    fn enter(&self, function: RuntimeValue<'a>, arguments: Vec<RuntimeValue<'a>>) -> Result<Tail<'a>, String> {
+        let closure = match self.force(function)? {
+            RuntimeValue::Closure(closure) => closure,
+            RuntimeValue::Primitive(primitive, mut collected) => {
+                collected.extend(arguments);
+                if collected.len() < primitive.arity() {
+                    return Ok(Tail::Done(RuntimeValue::Primitive(primitive, collected)));
+                }
+                let rest = collected.split_off(primitive.arity());
+                let arguments = collected
+                    .into_iter()
+                    .map(|x| match self.force(x)? {
+                        RuntimeValue::Int(n) => Ok(AtomicValue::Int(n)),
+                        RuntimeValue::StringLiteral(text) => Ok(AtomicValue::StringLiteral(text)),
+                        other => Err(format!("{} expects ints and strings but got {}", primitive.name(), other)),
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                let result = match primitive.apply(&arguments)? {
+                    AtomicValue::Int(n) => RuntimeValue::Int(n),
+                    AtomicValue::StringLiteral(text) => RuntimeValue::StringLiteral(text),
+                };
+                return Ok(if rest.is_empty() { Tail::Done(result) } else { Tail::Apply(result, rest) });
+            },
+            RuntimeValue::Type => return Ok(Tail::Done(RuntimeValue::Type)),
+            other => return Err(format!("{} is not a function", other)),
+        };
+        let mut collected = closure.arguments.clone();
+        collected.extend(arguments);
+        let arity = closure.parameters.len();
+        if collected.len() < arity {
+            return Ok(Tail::Done(RuntimeValue::Closure(Rc::new(Closure {
+                parameters: closure.parameters,
+                body: closure.body,
+                environment: closure.environment.clone(),
+                arguments: collected,
+                name: closure.name,
+            }))));
+        }
+
+        let rest = collected.split_off(arity);
+        let environment = match closure.name {
+            Some(name) => closure.environment.extend(name, RuntimeValue::Closure(Rc::new(Closure {
+                parameters: closure.parameters,
+                body: closure.body,
+                environment: closure.environment.clone(),
+                arguments: vec![],
+                name: closure.name,
+            }))),
+            None => closure.environment.clone(),
+        };
+        let environment = closure
+            .parameters
+            .iter()
+            .zip(collected)
+            .fold(environment, |environment, ((name, _), value)| {
+                environment.extend(name.get_name(), value)
+            });
+        // the body is only left in tail position when nothing is applied to its result
+        if rest.is_empty() {
+            return self.expr(closure.body, &environment);
+        }
+        Ok(Tail::Apply(self.evaluate_expr(closure.body, &environment)?, rest))
+    }
+}
+
+impl<'a> RuntimeValue<'a> {
+    fn is_either(&self) -> bool {
+        match self {
+            RuntimeValue::Either(_, _) => true,
+            RuntimeValue::Lazy(thunk) => matches!(&*thunk.0.borrow(), Suspension::Forced(value) if value.is_either()),
+            _ => false,
+        }
+    }
+}
+
+impl<'a> fmt::Display for RuntimeValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeValue::Int(n) => write!(f, "{}", n),
+            RuntimeValue::StringLiteral(text) => write!(f, "/{}/", text),
+            RuntimeValue::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|x| x.to_string()).collect();
+                write!(f, "({})", items.join(", "))
+            },
+            RuntimeValue::Record(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, x)| format!("{} = {}", name, x)).collect();
+                write!(f, "{{{}}}", fields.join(", "))
+            },
+            RuntimeValue::Either(name, payload) if payload.is_either() => write!(f, "{} ({})", name, payload),
+            RuntimeValue::Either(name, payload) => write!(f, "{} {}", name, payload),
+            RuntimeValue::Closure(_) | RuntimeValue::Primitive(_, _) => write!(f, "<function>"),
+            RuntimeValue::Type => write!(f, "<type>"),
+            RuntimeValue::Lazy(thunk) => match &*thunk.0.borrow() {
+                Suspension::Forced(value) => write!(f, "{}", value),
+                _ => write!(f, "..."),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod interpreting_tests {
+    use crate::compiling_process::translating::testing::bare as program;
+    use super::Interpreter;
+
+    #[test]
+    fn unit_tests() {
+        let program = program("
+            $sum = l ~> l | Nil _ -> 0 | Cons (x, Nil _) -> x | Cons ((1 | 2), rest) -> sum rest | Cons (x, _) -> x;
+            $main: . = sum (Cons (3, Cons (2, Cons (1, Nil))));
+            $single = sum (Cons (5, Nil));
+            $loop = loop;
+        ");
+        let interpreter = Interpreter::new(&program);
+        let result = interpreter.get("main").unwrap();
+        println!("{}", result);
+        assert_eq!(result.to_string(), "3");
+        assert_eq!(interpreter.get("single").unwrap().to_string(), "5");
+
+        assert!(interpreter.get("loop").is_err());
+        assert!(interpreter.get("missing").is_err());
+    }
+
+    #[test]
+    fn tail_calls() {
+        // each call is in tail position
+        let program = program("
+            $count = n acc ~> n | 0 -> acc | _ -> count (#sub n 1) (#add acc 1);
+            $main = count 100000 0;
+        ");
+        let interpreter = Interpreter::new(&program);
+        assert_eq!(interpreter.get("main").unwrap().to_string(), "100000");
+    }
+
+    #[test]
+    fn recursive_lets() {
+        // the local function sees itself when called, returned or not
+        let program = program("
+            $count = n ~> $go = m acc ~> m | 0 -> acc | _ -> go (#sub m 1) (#add acc n); go;
+            $main = count 2 100000 0;
+            $partial = count 3 4;
+            $given = partial 1;
+        ");
+        let interpreter = Interpreter::new(&program);
+        assert_eq!(interpreter.get("main").unwrap().to_string(), "200000");
+        assert_eq!(interpreter.get("given").unwrap().to_string(), "13");
+    }
+
+    #[test]
+    fn codata() {
+        let streams = program("
+            $Stream = A ~> codata head A * tail (Stream A);
+            $ones = {head = 1, tail = ones};
+            $from = n ~> {head = n, tail = from (S n)};
+            $take = n s ~> n | Z _ -> Nil | S m -> (s | {head, tail} -> Cons (head, take m tail));
+            $some = take (S (S (S Z))) ones;
+            $nats = take (S (S (S Z))) (from Z);
+        ");
+        let interpreter = Interpreter::new(&streams);
+
+        let ones = interpreter.get("ones").unwrap();
+        assert_eq!(ones.to_string(), "{head = 1, tail = ...}");
+        let some = interpreter.force_all(interpreter.get("some").unwrap()).unwrap();
+        assert_eq!(some.to_string(), "Cons (1, Cons (1, Cons (1, Nil ())))");
+        let nats = interpreter.force_all(interpreter.get("nats").unwrap()).unwrap();
+        println!("{}", nats);
+        assert_eq!(nats.to_string(), "Cons (Z (), Cons (S (Z ()), Cons (S (S (Z ())), Nil ())))");
+
+        // only codata waits, the same record without the codata type is computed at once
+        let strict = program("
+            $ones = {head = 1, tail = ones};
+            $length = xs ~> xs | Nil -> 0 | Cons (_, rest) -> #add 1 (length rest);
+            $strict = length (Cons (#div 1 0, Nil));
+        ");
+        let interpreter = Interpreter::new(&strict);
+        assert_eq!(interpreter.get("ones").unwrap_err(), "ones is defined in terms of itself");
+        assert_eq!(interpreter.get("strict").unwrap_err(), "division by zero");
+    }
+
+    #[test]
+    fn primitives() {
+        let program = program("
+            $fact = n ~> #le n 0 | 1 -> 1 | _ -> #mul n (fact (#sub n 1));
+            $Stream = A ~> codata head A * tail (Stream A);
+            $from = n ~> {head = n, tail = from (#add n 1)};
+            $nats = from 0;
+            $take = n s ~> #le n 0 | 1 -> Nil | _ -> (s | {head, tail} -> Cons (head, take (#sub n 1) tail));
+            $answer = #concat (#show (fact 5)) /!/;
+            $first = take 10 nats;
+            $broken = #div 1 0;
+            $increment = #add 1;
+            $three = increment 2;
+        ");
+        let interpreter = Interpreter::new(&program);
+        assert_eq!(interpreter.get("answer").unwrap().to_string(), "/120!/");
+
+        let first = interpreter.force_all(interpreter.get("first").unwrap()).unwrap().to_string();
+        println!("{}", first);
+        assert!(first.starts_with("Cons (0, Cons (1, Cons (2, "));
+        assert!(first.ends_with("Cons (9, Nil ()))))))))))"));
+
+        assert_eq!(interpreter.get("broken").unwrap_err(), "division by zero");
+        assert_eq!(interpreter.get("three").unwrap().to_string(), "3");
+    }
+}
@@ -1,5 +1,8 @@
 pub mod executing_compiler_extructions;
+pub mod interpreting;
 pub mod parsing;
+pub mod pattern_compiling;
+pub mod primitives;
 pub mod tokenizing;
 pub mod translating;
 pub mod static_analysis;
\ No newline at end of file
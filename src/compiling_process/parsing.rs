@@ -2,6 +2,11 @@ use std::collections::VecDeque;
 
 use crate::inner_representation::token_tree::TreeBuilder;
 use crate::inner_representation::token::{Token, TokenKind, TokenDir};
+use crate::inner_representation::abstract_syntax_tree::{
+    AtomicType, AtomicValue, CompilerCommand, Context, Expr, Let, Level, Name, Pattern, Span, Type, Value, Visibility, AST,
+};
+use crate::compiling_process::tokenizing::tokenize;
+use crate::compiling_process::static_analysis::type_inference::as_type_definition;
 
 pub fn preparse(mut program: Vec<Token>) -> Vec<Token> {
     let mut result = Vec::new();
@@ -139,49 +144,868 @@ pub fn parse_value(builder: &mut TreeBuilder, mut program: Vec<Token>) -> Result
     Ok(()) 
 }
 
-fn get_first_kind(input: &Vec<Token>) -> Result<TokenKind, String> {
-    Ok(input.last().ok_or("empty input")?.get_kind())
+// the surface syntax is shared by types, values and patterns, a term is only read as one of
+// them once it is known where it stands
+#[derive(Clone, Debug)]
+enum Term {
+    Name(String, Option<Span>),
+    Int(i32, Option<Span>),
+    Text(String, Option<Span>),
+    Top,
+    Bottom,
+    Universe(Level),
+    // ..r, the rest of a record or variant
+    Row(String, Option<Span>),
+    Apply(Box<Term>, Vec<Term>),
+    // @{Int}
+    Implicit(Box<Term>),
+    // (x : A), {x : A} or [x : A]
+    Binder(Visibility, String, Option<Span>, Box<Term>),
+    Tuple(Vec<Term>),
+    Record(Vec<(String, Option<Span>, Term)>),
+    Lambda(Vec<Term>, Vec<Definition>, Box<Term>),
+    Match(Box<Term>, Vec<(Pattern, Term)>),
+    Arrow(Box<Term>, Box<Term>),
+    Sum(Vec<Term>),
+    Product(Vec<Term>),
+    Equal(Box<Term>, Box<Term>),
+    Class(Box<Term>),
+    Codata(Box<Term>),
 }
 
-fn parse_let(builder: &mut TreeBuilder, mut program: Vec<Token>) -> Result<(), String> {
-    let mut typ: Vec<Token> = Vec::new();
-    
-    if TokenKind::Name == get_first_kind(&program)? {
-        builder.push_one(program.pop().unwrap());
-    } 
+// $name: annotation = value;
+type Definition = (String, Option<Span>, Option<Term>, Term);
 
-    if TokenKind::Type == get_first_kind(&program)? {
-        builder.push(program.pop().unwrap())
-    } else {
-        return Err("can't parse let".to_string());
+// the fields of a product, with the row that ends it when it is open
+type Fields = (Vec<(Name, Type)>, Option<Name>);
+
+fn capitalised(name: &str) -> bool {
+    name.starts_with(|x: char| x.is_uppercase())
+}
+
+fn lowercase(name: &str) -> bool {
+    name.starts_with(|x: char| x.is_lowercase())
+}
+
+// the second token starts right where the first one ends
+fn adjacent(first: Option<Span>, second: Option<Span>) -> bool {
+    matches!((first, second), (Some(first), Some(second)) if first.end == second.begin)
+}
+
+// a name of the program, with where it was written when that is known
+fn located(name: &str, context: Context, span: Option<Span>) -> Name {
+    let name = Name::new(name.to_string(), context);
+    match span {
+        Some(span) => name.with_span(span),
+        None => name,
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<TokenKind> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<TokenKind> {
+        self.tokens.get(self.position + offset).map(|x| x.get_kind())
+    }
+
+    fn peek_name(&self) -> Option<&str> {
+        match self.tokens.get(self.position) {
+            Some(token) if token.get_kind() == TokenKind::Name => Some(token.text()),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> Result<&Token, String> {
+        let token = self.tokens.get(self.position).ok_or("unexpected end of the program")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<(), String> {
+        let token = self.next()?;
+        if token.get_kind() == kind {
+            Ok(())
+        } else {
+            Err(format!("expected {:?} but found {}", kind, token))
+        }
     }
 
-    loop {
-        match get_first_kind(&program)? {
-            TokenKind::Eq => {
-                typ.reverse();
-                parse_type(builder, typ)?;
-                builder.pop();
-                builder.push(program.pop().unwrap());
-                parse_expr(builder, program)?;
-                builder.pop();
-                break;
+    fn name(&mut self) -> Result<String, String> {
+        self.located_name().map(|(name, _)| name)
+    }
+
+    fn located_name(&mut self) -> Result<(String, Option<Span>), String> {
+        let token = self.next()?;
+        match token.get_kind() {
+            TokenKind::Name => Ok((token.text().to_string(), token.get_span())),
+            _ => Err(format!("expected a name but found {}", token)),
+        }
+    }
+
+    fn span(&self) -> Option<Span> {
+        self.tokens.get(self.position).and_then(|x| x.get_span())
+    }
+
+    fn program(&mut self) -> Result<(Vec<CompilerCommand>, Vec<Definition>), String> {
+        let mut commands = vec![];
+        let mut definitions = vec![];
+        while let Some(kind) = self.peek() {
+            match kind {
+                TokenKind::Let => definitions.push(self.definition()?),
+                TokenKind::Include | TokenKind::Load => {
+                    self.next()?;
+                    let token = self.next()?;
+                    let target = match token.get_kind() {
+                        TokenKind::Name | TokenKind::StringLiteral => token.text().to_string(),
+                        _ => return Err(format!("expected a file after include but found {}", token)),
+                    };
+                    self.expect(TokenKind::LetEnd)?;
+                    commands.push(match kind {
+                        TokenKind::Include => CompilerCommand::Include(target),
+                        _ => CompilerCommand::Load(target),
+                    });
+                },
+                TokenKind::Name if self.peek_name() == Some("terminating") => {
+                    self.next()?;
+                    commands.push(CompilerCommand::Terminating(self.name()?));
+                    self.expect(TokenKind::LetEnd)?;
+                },
+                _ => return Err(format!("expected a definition but found {}", self.next()?)),
+            }
+        }
+        Ok((commands, definitions))
+    }
+
+    fn definition(&mut self) -> Result<Definition, String> {
+        self.expect(TokenKind::Let)?;
+        let (name, span) = self.located_name()?;
+        let annotation = match self.peek() {
+            Some(TokenKind::Type) => {
+                self.next()?;
+                Some(self.term()?)
             },
-            _ => typ.push(program.pop().unwrap()),
+            _ => None,
+        };
+        self.expect(TokenKind::Eq)?;
+        let (lets, value) = self.expr()?;
+        if !lets.is_empty() {
+            return Err(format!("the local definitions of {} can only be given inside a function", name));
         }
-    } 
-    Ok(())
+        self.expect(TokenKind::LetEnd)?;
+        Ok((name, span, annotation, value))
+    }
+
+    // local definitions, then the value they are used in
+    fn expr(&mut self) -> Result<(Vec<Definition>, Term), String> {
+        let mut lets = vec![];
+        while self.peek() == Some(TokenKind::Let) {
+            lets.push(self.definition()?);
+        }
+        Ok((lets, self.term()?))
+    }
+
+    // s | p -> x | q -> y, a match inside an arm has to be put in brackets
+    fn term(&mut self) -> Result<Term, String> {
+        let scrutinee = self.tuple()?;
+        if self.peek() != Some(TokenKind::Cases) {
+            return Ok(scrutinee);
+        }
+        let mut arms = vec![];
+        while self.peek() == Some(TokenKind::Cases) {
+            self.next()?;
+            let pattern = self.pattern()?;
+            self.expect(TokenKind::Function)?;
+            arms.push((pattern, self.tuple()?));
+        }
+        Ok(Term::Match(Box::new(scrutinee), arms))
+    }
+
+    fn tuple(&mut self) -> Result<Term, String> {
+        let mut items = vec![self.arrow()?];
+        while self.peek() == Some(TokenKind::Tuple) {
+            self.next()?;
+            items.push(self.arrow()?);
+        }
+        Ok(if items.len() == 1 { items.pop().unwrap() } else { Term::Tuple(items) })
+    }
+
+    fn arrow(&mut self) -> Result<Term, String> {
+        let from = self.equal()?;
+        if self.peek() != Some(TokenKind::Function) {
+            return Ok(from);
+        }
+        self.next()?;
+        Ok(Term::Arrow(Box::new(from), Box::new(self.arrow()?)))
+    }
+
+    // a == b, written with two `=`
+    fn equal(&mut self) -> Result<Term, String> {
+        let left = self.sum()?;
+        if self.peek() != Some(TokenKind::Eq) || self.peek_at(1) != Some(TokenKind::Eq) {
+            return Ok(left);
+        }
+        self.position += 2;
+        Ok(Term::Equal(Box::new(left), Box::new(self.sum()?)))
+    }
+
+    fn sum(&mut self) -> Result<Term, String> {
+        match self.peek_name() {
+            Some("class") => {
+                self.next()?;
+                return Ok(Term::Class(Box::new(self.sum()?)));
+            },
+            Some("codata") => {
+                self.next()?;
+                return Ok(Term::Codata(Box::new(self.sum()?)));
+            },
+            _ => (),
+        }
+        let mut items = vec![self.product()?];
+        while self.peek() == Some(TokenKind::Sum) {
+            self.next()?;
+            items.push(self.product()?);
+        }
+        Ok(if items.len() == 1 { items.pop().unwrap() } else { Term::Sum(items) })
+    }
+
+    fn product(&mut self) -> Result<Term, String> {
+        let mut items = vec![self.application()?];
+        while self.peek() == Some(TokenKind::Prod) {
+            self.next()?;
+            items.push(self.application()?);
+        }
+        Ok(if items.len() == 1 { items.pop().unwrap() } else { Term::Product(items) })
+    }
+
+    // f x y, or the parameters of a function when `~>` follows
+    fn application(&mut self) -> Result<Term, String> {
+        let mut atoms = vec![];
+        while let Some(atom) = self.atom()? {
+            atoms.push(atom);
+        }
+        if self.peek() == Some(TokenKind::Lambda) {
+            self.next()?;
+            let (lets, body) = self.expr()?;
+            return Ok(Term::Lambda(atoms, lets, Box::new(body)));
+        }
+        if atoms.is_empty() {
+            return Err(match self.tokens.get(self.position) {
+                Some(token) => format!("expected a term but found {}", token),
+                None => "unexpected end of the program".to_string(),
+            });
+        }
+        let function = atoms.remove(0);
+        Ok(if atoms.is_empty() { function } else { Term::Apply(Box::new(function), atoms) })
+    }
+
+    fn binder(&mut self, visibility: Visibility, close: TokenKind) -> Result<Term, String> {
+        let (name, span) = self.located_name()?;
+        self.expect(TokenKind::Type)?;
+        let typ = self.term()?;
+        self.expect(close)?;
+        Ok(Term::Binder(visibility, name, span, Box::new(typ)))
+    }
+
+    fn atom(&mut self) -> Result<Option<Term>, String> {
+        let token = match self.tokens.get(self.position) {
+            Some(token) => token.clone(),
+            None => return Ok(None),
+        };
+        let binds = self.peek_at(1) == Some(TokenKind::Name) && self.peek_at(2) == Some(TokenKind::Type);
+        let atom = match token.get_kind() {
+            TokenKind::Name => {
+                self.next()?;
+                Term::Name(token.text().to_string(), token.get_span())
+            },
+            TokenKind::Int => {
+                self.next()?;
+                Term::Int(token.text().parse::<i32>().map_err(|error| error.to_string())?, token.get_span())
+            },
+            TokenKind::StringLiteral => {
+                self.next()?;
+                Term::Text(token.text().to_string(), token.get_span())
+            },
+            TokenKind::Top if self.peek_at(1) == Some(TokenKind::Top) => {
+                self.position += 2;
+                let (row, span) = self.located_name()?;
+                Term::Row(row, span)
+            },
+            TokenKind::Top => {
+                self.next()?;
+                Term::Top
+            },
+            TokenKind::Bottom => {
+                self.next()?;
+                Term::Bottom
+            },
+            TokenKind::Universe => {
+                self.next()?;
+                let span = self.span();
+                match self.tokens.get(self.position).map(|x| (x.get_kind(), x.text().to_string())) {
+                    Some((TokenKind::OpenCurly, _)) => {
+                        self.next()?;
+                        let argument = self.term()?;
+                        self.expect(TokenKind::CloseCurly)?;
+                        Term::Implicit(Box::new(argument))
+                    },
+                    Some((TokenKind::Int, level)) => {
+                        self.next()?;
+                        Term::Universe(Level::Constant(level.parse::<usize>().map_err(|error| error.to_string())?))
+                    },
+                    Some((TokenKind::Name, level)) if lowercase(&level) => {
+                        self.next()?;
+                        Term::Universe(Level::Variable(located(&level, Context::TypeContext, span)))
+                    },
+                    // @(l+1) or @(max l k), only right after the @ so that `F @ (List A)` stays two arguments
+                    Some((TokenKind::OpenBracket, _)) if adjacent(token.get_span(), span) => {
+                        self.next()?;
+                        let level = self.level()?;
+                        self.expect(TokenKind::CloseBracket)?;
+                        Term::Universe(level)
+                    },
+                    _ => Term::Universe(Level::Constant(0)),
+                }
+            },
+            TokenKind::OpenBracket if binds => {
+                self.next()?;
+                self.binder(Visibility::Explicit, TokenKind::CloseBracket)?
+            },
+            TokenKind::OpenBracket => {
+                self.next()?;
+                if self.peek() == Some(TokenKind::CloseBracket) {
+                    self.next()?;
+                    return Ok(Some(Term::Tuple(vec![])));
+                }
+                let inner = self.term()?;
+                self.expect(TokenKind::CloseBracket)?;
+                inner
+            },
+            TokenKind::OpenCurly if binds => {
+                self.next()?;
+                self.binder(Visibility::Implicit, TokenKind::CloseCurly)?
+            },
+            TokenKind::OpenCurly => {
+                self.next()?;
+                let mut fields = vec![];
+                while self.peek() != Some(TokenKind::CloseCurly) {
+                    let (name, span) = self.located_name()?;
+                    let value = match self.peek() {
+                        Some(TokenKind::Eq) => {
+                            self.next()?;
+                            self.arrow()?
+                        },
+                        _ => Term::Name(name.clone(), span),
+                    };
+                    fields.push((name, span, value));
+                    match self.peek() {
+                        Some(TokenKind::Tuple) => self.next().map(|_| ())?,
+                        _ => break,
+                    }
+                }
+                self.expect(TokenKind::CloseCurly)?;
+                Term::Record(fields)
+            },
+            TokenKind::OpenSquear => {
+                self.next()?;
+                self.binder(Visibility::Instance, TokenKind::CloseSquear)?
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(atom))
+    }
+
+    // max l k+1, the largest of levels that may each be some levels above another
+    fn level(&mut self) -> Result<Level, String> {
+        if self.peek_name() != Some("max") {
+            return self.level_offset();
+        }
+        self.next()?;
+        let mut levels = vec![self.level_offset()?];
+        while matches!(self.peek(), Some(TokenKind::Int | TokenKind::Name | TokenKind::OpenBracket)) {
+            levels.push(self.level_offset()?);
+        }
+        Ok(Level::Maximum(levels))
+    }
+
+    fn level_offset(&mut self) -> Result<Level, String> {
+        let token = self.next()?.clone();
+        let mut level = match token.get_kind() {
+            TokenKind::Int => Level::Constant(token.text().parse::<usize>().map_err(|error| error.to_string())?),
+            TokenKind::Name if lowercase(token.text()) => {
+                Level::Variable(located(token.text(), Context::TypeContext, token.get_span()))
+            },
+            TokenKind::OpenBracket => {
+                let level = self.level()?;
+                self.expect(TokenKind::CloseBracket)?;
+                level
+            },
+            _ => return Err(format!("expected a level but found {}", token)),
+        };
+        while self.peek() == Some(TokenKind::Sum) {
+            self.next()?;
+            let token = self.next()?;
+            let offset = match token.get_kind() {
+                TokenKind::Int => token.text().parse::<usize>().map_err(|error| error.to_string())?,
+                _ => return Err(format!("expected a number of levels but found {}", token)),
+            };
+            level = (0..offset).fold(level, |x, _| Level::Successor(Box::new(x)));
+        }
+        Ok(level)
+    }
+
+    // Cons (x, _), {head = h, tail}, (0 | 1), refl
+    fn pattern(&mut self) -> Result<Pattern, String> {
+        let mut items = vec![self.pattern_application()?];
+        while self.peek() == Some(TokenKind::Tuple) {
+            self.next()?;
+            items.push(self.pattern_application()?);
+        }
+        Ok(if items.len() == 1 { items.pop().unwrap() } else { Pattern::Tuple(items) })
+    }
+
+    fn pattern_application(&mut self) -> Result<Pattern, String> {
+        match self.peek_name() {
+            Some(name) if capitalised(name) => {
+                let name = located(name, Context::Constructor, self.span());
+                self.next()?;
+                let inner = self.pattern_atom()?.unwrap_or(Pattern::Wildcard);
+                Ok(Pattern::Constructor(name, Box::new(inner)))
+            },
+            _ => self.pattern_atom()?.ok_or(match self.tokens.get(self.position) {
+                Some(token) => format!("expected a pattern but found {}", token),
+                None => "unexpected end of the program".to_string(),
+            }),
+        }
+    }
+
+    fn pattern_atom(&mut self) -> Result<Option<Pattern>, String> {
+        let token = match self.tokens.get(self.position) {
+            Some(token) => token.clone(),
+            None => return Ok(None),
+        };
+        let pattern = match token.get_kind() {
+            TokenKind::Name => {
+                self.next()?;
+                match token.text() {
+                    "_" => Pattern::Wildcard,
+                    "refl" => Pattern::Refl,
+                    name if capitalised(name) => {
+                        Pattern::Constructor(located(name, Context::Constructor, token.get_span()), Box::new(Pattern::Wildcard))
+                    },
+                    name => Pattern::Binder(located(name, Context::ValueContext, token.get_span())),
+                }
+            },
+            TokenKind::Int => {
+                self.next()?;
+                Pattern::Literal(AtomicValue::Int(token.text().parse::<i32>().map_err(|error| error.to_string())?))
+            },
+            TokenKind::StringLiteral => {
+                self.next()?;
+                Pattern::Literal(AtomicValue::StringLiteral(token.text().to_string()))
+            },
+            TokenKind::Top => {
+                self.next()?;
+                Pattern::Tuple(vec![])
+            },
+            TokenKind::OpenBracket => {
+                self.next()?;
+                if self.peek() == Some(TokenKind::CloseBracket) {
+                    self.next()?;
+                    return Ok(Some(Pattern::Tuple(vec![])));
+                }
+                let mut alternatives = vec![self.pattern()?];
+                while self.peek() == Some(TokenKind::Cases) {
+                    self.next()?;
+                    alternatives.push(self.pattern()?);
+                }
+                self.expect(TokenKind::CloseBracket)?;
+                if alternatives.len() == 1 { alternatives.pop().unwrap() } else { Pattern::Or(alternatives) }
+            },
+            TokenKind::OpenCurly => {
+                self.next()?;
+                let mut fields = vec![];
+                while self.peek() != Some(TokenKind::CloseCurly) {
+                    let (name, span) = self.located_name()?;
+                    let name = located(&name, Context::ValueContext, span);
+                    let inner = match self.peek() {
+                        Some(TokenKind::Eq) => {
+                            self.next()?;
+                            self.pattern_application()?
+                        },
+                        _ => Pattern::Binder(name.clone()),
+                    };
+                    fields.push((name, inner));
+                    match self.peek() {
+                        Some(TokenKind::Tuple) => self.next().map(|_| ())?,
+                        _ => break,
+                    }
+                }
+                self.expect(TokenKind::CloseCurly)?;
+                Pattern::Record(fields)
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(pattern))
+    }
 }
 
-fn parse_expr(builder: &mut TreeBuilder, mut program: Vec<Token>) -> Result<(), String> {
-    todo!()
+// decides what the terms are, a capitalised name is a type when the program defines a type by
+// that name or binds it, otherwise a constructor
+struct Reading<'a> {
+    types: &'a [String],
+    bound: Vec<String>,
+}
+
+impl<'a> Reading<'a> {
+    fn is_constructor(&self, name: &str) -> bool {
+        capitalised(name) && !self.is_type(name) && !self.bound.iter().any(|x| x == name)
+    }
+
+    fn is_type(&self, name: &str) -> bool {
+        name == "Int" || name == "String" || self.types.iter().any(|x| x == name)
+    }
+
+    fn within<T>(&mut self, names: Vec<String>, read: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        let depth = self.bound.len();
+        self.bound.extend(names);
+        let result = read(self);
+        self.bound.truncate(depth);
+        result
+    }
+
+    fn definition(&mut self, (name, span, annotation, value): &Definition) -> Result<Let, String> {
+        let annotation = annotation.as_ref().map(|x| self.typ(x)).transpose()?;
+        let value = match value {
+            // $List = A ~> Nil . + Cons A * List A, a capitalised definition gives a type
+            _ if !capitalised(name) => self.value(value)?,
+            Term::Lambda(parameters, lets, body) if lets.is_empty() && !matches!(**body, Term::Match(_, _)) => {
+                let parameters = self.parameters(parameters)?;
+                let names = parameters.iter().map(|(x, _)| x.get_name().to_string()).collect();
+                let body = self.within(names, |x| x.definition_body(name, body))?;
+                Value::Function(parameters, Box::new(Expr(vec![], Value::Type(Box::new(body)))))
+            },
+            Term::Lambda(_, _, _) | Term::Match(_, _) => self.value(value)?,
+            body => Value::Type(Box::new(self.definition_body(name, body)?)),
+        };
+        let context = if capitalised(name) { Context::TypeContext } else { Context::ValueContext };
+        Ok(Let(located(name, context, *span), value, annotation))
+    }
+
+    // `$Box = A ~> Box A` has the constructor Box, even though the name is a type
+    fn definition_body(&mut self, name: &str, body: &Term) -> Result<Type, String> {
+        let head = match body {
+            Term::Apply(head, _) => head,
+            Term::Product(items) => match &items[0] {
+                Term::Apply(head, _) => head,
+                _ => return self.typ(body),
+            },
+            _ => return self.typ(body),
+        };
+        match &**head {
+            Term::Name(x, _) if x == name => {
+                let (constructor, payload) = self.summand(body, true)?.unwrap();
+                Ok(Type::CoProduct(vec![(constructor, Box::new(payload))]))
+            },
+            _ => self.typ(body),
+        }
+    }
+
+    // Cons A * List A, a constructor and its payload
+    fn summand(&mut self, term: &Term, forced: bool) -> Result<Option<(Name, Type)>, String> {
+        let constructor = |name: &str, span: &Option<Span>| located(name, Context::Constructor, *span);
+        let payload = |arguments: &[Term]| match arguments {
+            [argument] => argument.clone(),
+            _ => Term::Apply(Box::new(arguments[0].clone()), arguments[1..].to_vec()),
+        };
+        match term {
+            Term::Apply(head, arguments) => match &**head {
+                Term::Name(name, span) if forced || self.is_constructor(name) => {
+                    Ok(Some((constructor(name, span), self.typ(&payload(arguments))?)))
+                },
+                _ => Ok(None),
+            },
+            Term::Product(items) => match &items[0] {
+                Term::Apply(head, arguments) => match &**head {
+                    Term::Name(name, span) if forced || self.is_constructor(name) => {
+                        let mut rest = vec![payload(arguments)];
+                        rest.extend(items[1..].iter().cloned());
+                        Ok(Some((constructor(name, span), self.typ(&Term::Product(rest))?)))
+                    },
+                    _ => Ok(None),
+                },
+                _ => Ok(None),
+            },
+            Term::Name(name, span) if self.is_constructor(name) => {
+                Ok(Some((constructor(name, span), Type::Atomic(AtomicType::Top))))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    // head A * tail Stream A, fields start with their lowercase name, others are numbered
+    fn fields(&mut self, items: &[Term]) -> Result<Fields, String> {
+        let mut fields = vec![];
+        let mut tail = None;
+        for (i, item) in items.iter().enumerate() {
+            match item {
+                Term::Row(row, span) if i + 1 == items.len() => tail = Some(located(row, Context::TypeContext, *span)),
+                Term::Apply(head, arguments)
+                    if matches!(&**head, Term::Name(x, _) if lowercase(x) && !self.bound.contains(x)) =>
+                {
+                    let label = match &**head {
+                        Term::Name(label, span) => located(label, Context::ValueContext, *span),
+                        _ => unreachable!(),
+                    };
+                    let typ = match &arguments[..] {
+                        [argument] => self.typ(argument)?,
+                        _ => self.typ(&Term::Apply(Box::new(arguments[0].clone()), arguments[1..].to_vec()))?,
+                    };
+                    fields.push((label, typ));
+                },
+                other => fields.push((Name::new(i.to_string(), Context::ValueContext), self.typ(other)?)),
+            }
+        }
+        Ok((fields, tail))
+    }
+
+    fn typ(&mut self, term: &Term) -> Result<Type, String> {
+        if let Some((constructor, payload)) = self.summand(term, false)? {
+            if !matches!(term, Term::Name(_, _)) {
+                return Ok(Type::CoProduct(vec![(constructor, Box::new(payload))]));
+            }
+        }
+        match term {
+            Term::Name(name, _) if name == "Int" => Ok(Type::Atomic(AtomicType::Int)),
+            Term::Name(name, _) if name == "String" => Ok(Type::Atomic(AtomicType::String)),
+            Term::Name(name, span) if name.starts_with('?') => Ok(Type::Hole(located(&name[1..], Context::TypeContext, *span))),
+            Term::Name(name, span) => Ok(Type::TypeVar(located(name, Context::TypeContext, *span))),
+            Term::Top => Ok(Type::Atomic(AtomicType::Top)),
+            Term::Bottom => Ok(Type::Atomic(AtomicType::Bottom)),
+            Term::Universe(level) => Ok(Type::Universe(level.clone())),
+            // a record with one field, its label is not a type
+            Term::Apply(head, _) if matches!(&**head, Term::Name(x, _) if lowercase(x) && !self.bound.contains(x)) => {
+                Ok(Type::Product(self.fields(std::slice::from_ref(term))?.0))
+            },
+            Term::Apply(function, arguments) => Ok(Type::Application(
+                Box::new(self.typ(function)?),
+                arguments.iter().map(|x| self.type_argument(x)).collect::<Result<_, String>>()?,
+            )),
+            Term::Arrow(from, to) => match &**from {
+                Term::Binder(visibility, name, span, from) => {
+                    let from = self.typ(from)?;
+                    let to = self.within(vec![name.clone()], |x| x.typ(to))?;
+                    Ok(Type::Pi(*visibility, located(name, Context::TypeContext, *span), Box::new(from), Box::new(to)))
+                },
+                from => Ok(Type::Function(Box::new(self.typ(from)?), Box::new(self.typ(to)?))),
+            },
+            Term::Sum(items) => {
+                let mut constructors = vec![];
+                let mut tail = None;
+                for (i, item) in items.iter().enumerate() {
+                    match item {
+                        Term::Row(row, span) if i + 1 == items.len() => tail = Some(located(row, Context::TypeContext, *span)),
+                        item => match self.summand(item, true)? {
+                            Some((constructor, payload)) => constructors.push((constructor, Box::new(payload))),
+                            None => return Err(format!("expected a constructor in the variant {:?}", item)),
+                        },
+                    }
+                }
+                Ok(match tail {
+                    Some(tail) => Type::OpenCoProduct(constructors, tail),
+                    None => Type::CoProduct(constructors),
+                })
+            },
+            Term::Product(items) => Ok(match self.fields(items)? {
+                (fields, Some(tail)) => Type::OpenProduct(fields, tail),
+                (fields, None) => Type::Product(fields),
+            }),
+            Term::Equal(left, right) => Ok(Type::Equal(Box::new(self.value(left)?), Box::new(self.value(right)?))),
+            Term::Class(methods) => match &**methods {
+                Term::Product(items) => Ok(Type::Class(self.fields(items)?.0)),
+                method => Ok(Type::Class(self.fields(std::slice::from_ref(method))?.0)),
+            },
+            Term::Codata(body) => Ok(Type::Codata(Box::new(self.typ(body)?))),
+            other => Err(format!("expected a type but found {:?}", other)),
+        }
+    }
+
+    // Int, List A, Blue . or x Int * ..r, and not 3, n or Succ Zero
+    fn is_type_term(&self, term: &Term) -> bool {
+        match term {
+            Term::Name(name, _) => capitalised(name) && !self.is_constructor(name),
+            Term::Apply(head, arguments) => match &**head {
+                Term::Name(name, _) if self.is_constructor(name) => arguments.iter().all(|x| self.is_type_term(x)),
+                Term::Name(name, _) => capitalised(name),
+                _ => false,
+            },
+            Term::Top | Term::Bottom | Term::Universe(_) | Term::Arrow(_, _) | Term::Sum(_) | Term::Product(_) => true,
+            Term::Equal(_, _) | Term::Class(_) | Term::Codata(_) => true,
+            _ => false,
+        }
+    }
+
+    // Shape (Square Int) extends a variant with Square, while Vec (Succ Zero) is indexed by a value
+    fn type_argument(&mut self, term: &Term) -> Result<Value, String> {
+        match term {
+            Term::Apply(head, _) if matches!(&**head, Term::Name(x, _) if self.is_constructor(x)) && self.is_type_term(term) => {
+                Ok(Value::Type(Box::new(self.typ(term)?)))
+            },
+            // List . is the list of anything, not of the empty tuple
+            Term::Top | Term::Bottom => Ok(Value::Type(Box::new(self.typ(term)?))),
+            term => self.value(term),
+        }
+    }
+
+    fn parameters(&mut self, parameters: &[Term]) -> Result<Vec<(Name, Option<Type>)>, String> {
+        let mut result = vec![];
+        for parameter in parameters {
+            result.push(match parameter {
+                Term::Name(name, span) => (located(name, Context::ValueContext, *span), None),
+                Term::Binder(Visibility::Explicit, name, span, typ) => {
+                    let typ = self.typ(typ)?;
+                    (located(name, Context::ValueContext, *span), Some(typ))
+                },
+                other => return Err(format!("expected a parameter but found {:?}", other)),
+            });
+            // later parameters may mention earlier ones
+            self.bound.push(result.last().unwrap().0.get_name().to_string());
+        }
+        self.bound.truncate(self.bound.len() - result.len());
+        Ok(result)
+    }
+
+    fn value(&mut self, term: &Term) -> Result<Value, String> {
+        match term {
+            Term::Name(name, _) if name == "refl" => Ok(Value::Refl),
+            Term::Name(name, span) if name.starts_with('?') => Ok(Value::Hole(located(&name[1..], Context::ValueContext, *span))),
+            Term::Name(name, span) if self.bound.contains(name) => Ok(Value::Var(located(name, Context::ValueContext, *span))),
+            Term::Name(name, _) if name == "Int" || name == "String" => Ok(Value::Type(Box::new(self.typ(term)?))),
+            Term::Name(name, span) if self.is_constructor(name) => Ok(Value::Either(
+                located(name, Context::Constructor, *span),
+                Box::new(Value::Tuple(vec![])),
+            )),
+            Term::Name(name, span) => Ok(Value::Var(located(name, Context::ValueContext, *span))),
+            Term::Int(n, span) => Ok(Value::Constant(AtomicValue::Int(*n), *span)),
+            Term::Text(text, span) => Ok(Value::Constant(AtomicValue::StringLiteral(text.clone()), *span)),
+            // . is the empty tuple among values
+            Term::Top => Ok(Value::Tuple(vec![])),
+            Term::Apply(function, arguments) => match &**function {
+                Term::Name(name, span) if self.is_constructor(name) => {
+                    let payload = match &arguments[..] {
+                        [argument] => self.value(argument)?,
+                        _ => self.value(&Term::Apply(Box::new(arguments[0].clone()), arguments[1..].to_vec()))?,
+                    };
+                    Ok(Value::Either(located(name, Context::Constructor, *span), Box::new(payload)))
+                },
+                Term::Name(name, _) if self.is_type(name) && !self.bound.contains(name) => {
+                    Ok(Value::Type(Box::new(self.typ(term)?)))
+                },
+                function => Ok(Value::Application(
+                    Box::new(self.value(function)?),
+                    arguments.iter().map(|x| self.value(x)).collect::<Result<_, String>>()?,
+                )),
+            },
+            Term::Implicit(argument) => Ok(Value::Implicit(Box::new(self.value(argument)?))),
+            Term::Tuple(items) => Ok(Value::Tuple(items.iter().map(|x| self.value(x)).collect::<Result<_, String>>()?)),
+            Term::Record(fields) => Ok(Value::Record(
+                fields
+                    .iter()
+                    .map(|(name, span, x)| Ok((located(name, Context::ValueContext, *span), self.value(x)?)))
+                    .collect::<Result<_, String>>()?
+            )),
+            Term::Lambda(parameters, lets, body) => {
+                let parameters = self.parameters(parameters)?;
+                let names = parameters.iter().map(|(x, _)| x.get_name().to_string()).collect();
+                let expr = self.within(names, |reading| {
+                    let mut converted = vec![];
+                    for definition in lets {
+                        converted.push(reading.definition(definition)?);
+                        reading.bound.push(definition.0.clone());
+                    }
+                    let value = reading.value(body)?;
+                    reading.bound.truncate(reading.bound.len() - lets.len());
+                    Ok(Expr(converted, value))
+                })?;
+                Ok(Value::Function(parameters, Box::new(expr)))
+            },
+            Term::Match(scrutinee, arms) => Ok(Value::Match(
+                Box::new(self.value(scrutinee)?),
+                arms
+                    .iter()
+                    .map(|(pattern, body)| {
+                        let names = pattern.binders().iter().map(|x| x.get_name().to_string()).collect();
+                        Ok((pattern.clone(), self.within(names, |x| x.value(body))?))
+                    })
+                    .collect::<Result<_, String>>()?
+            )),
+            Term::Binder(_, name, _, _) => Err(format!("the binder {} has to be followed by -> or ~>", name)),
+            Term::Row(row, _) => Err(format!("the row ..{} can only end a record or variant", row)),
+            typ => Ok(Value::Type(Box::new(self.typ(typ)?))),
+        }
+    }
+}
+
+// the type definitions of a program, by their capitalised names
+pub fn defined_types(program: &[Let]) -> Vec<String> {
+    program
+        .iter()
+        .filter(|Let(name, value, _)| capitalised(name.get_name()) && as_type_definition(value).is_some())
+        .map(|Let(name, _, _)| name.get_name().to_string())
+        .collect()
+}
+
+// a parsed file whose definitions are not read yet, what the names in them are depends on the types
+// of the files it includes
+pub struct Source {
+    commands: Vec<CompilerCommand>,
+    definitions: Vec<Definition>,
+}
+
+impl Source {
+    pub fn get_commands(&self) -> &Vec<CompilerCommand> {
+        &self.commands
+    }
+
+    // `types` are the types the file may use without defining them
+    pub fn read(&self, types: &[String]) -> Result<AST, String> {
+        let mut known = types.to_vec();
+        known.extend(self.definitions.iter().map(|(name, _, _, _)| name.clone()).filter(|x| capitalised(x)));
+        let mut reading = Reading {
+            types: &known,
+            bound: vec![],
+        };
+        let program = self.definitions.iter().map(|x| reading.definition(x)).collect::<Result<_, String>>()?;
+        Ok(AST::new(self.commands.clone(), program))
+    }
+}
+
+pub fn parse_source(text: String) -> Result<Source, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+    };
+    let (commands, definitions) = parser.program()?;
+    Ok(Source { commands, definitions })
+}
+
+pub fn parse_program(text: String, types: &[String]) -> Result<AST, String> {
+    parse_source(text)?.read(types)
 }
 
 #[cfg(test)]
 mod parsing_tests {
     use crate::inner_representation::token_tree::TreeBuilder;
     use crate::compiling_process::tokenizing::tokenize;
-    use super::parse_type;
+    use crate::compiling_process::static_analysis::type_inference::{as_type_definition, infer_program};
+    use crate::inner_representation::abstract_syntax_tree::{CompilerCommand, Let, Type, Value};
+    use super::{parse_program, parse_type};
 
     #[test]
     fn unit_tests() {
@@ -222,4 +1046,100 @@ mod parsing_tests {
         parse_type(&mut builder, tokens);
         println!("{}", builder);
     }
+
+    fn definitions(text: &str) -> Vec<(String, String)> {
+        let ast = parse_program(text.to_string(), &[]).unwrap();
+        ast.get_program()
+            .iter()
+            .map(|Let(name, value, _)| match as_type_definition(value) {
+                Some((_, body)) => (name.get_name().to_string(), body.to_string()),
+                None => (name.get_name().to_string(), format!("{:?}", value)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn programs() {
+        let types = definitions("
+            $List: @ -> @ = A ~> Nil . + Cons A * List A;
+            $Pair = A B ~> first A * second B;
+            $Box = A ~> Box A;
+            $Stream = A ~> codata head A * tail (Stream A);
+            $Show = A ~> class show (A -> String);
+            $Id = {A : @} -> A -> A;
+            $Row = r ~> x Int * ..r;
+        ");
+        let shown: Vec<&str> = types.iter().map(|(_, x)| &x[..]).collect();
+        assert_eq!(shown, vec![
+            "Nil . + Cons A * List A",
+            "first A * second B",
+            "Box A",
+            "codata head A * tail (Stream A)",
+            "class show (A -> String)",
+            "{A : @0} -> A -> A",
+            "x Int * ..r",
+        ]);
+
+        // what is printed parses back to the same type, a record of one field is not a type applied
+        let records = definitions("$One = x Int; $Later = codata later Int; $Nested = inner (x Int) * y Int;");
+        for (name, body) in types.iter().chain(&records) {
+            assert_eq!(definitions(&format!("${} = A B r ~> {};", name, body))[0].1, *body);
+        }
+        let ast = parse_program("$One = x Int;".to_string(), &[]).unwrap();
+        let one = &ast.get_program()[0].1;
+        assert!(matches!(one, Value::Type(typ) if matches!(&**typ, Type::Product(fields) if fields.len() == 1)));
+
+        let ast = parse_program("
+            include prelude;
+            terminating loop;
+            $List = A ~> Nil . + Cons A * List A;
+            #* the length of a list *#
+            $length: {A : @} -> List A -> Int = xs ~> xs | Nil -> 0 | Cons (_, rest) -> #add 1 (length rest);
+            $greeting = #concat /hello, / /world/;
+            $p: #add 1 1 == 2 = refl;
+        ".to_string(), &[]).unwrap();
+        assert_eq!(ast.get_commands(), &vec![
+            CompilerCommand::Include("prelude".to_string()),
+            CompilerCommand::Terminating("loop".to_string()),
+        ]);
+        let types = infer_program(ast.get_program()).unwrap();
+        assert_eq!(types["greeting"].to_string(), "String");
+        assert_eq!(types["p"].to_string(), "#add 1 1 == 2");
+
+        // in type position a constructor applied to types is a variant, applied to values it stays a value
+        let types = ["Shape".to_string(), "Vec".to_string()];
+        let ast = parse_program("$wide: Shape (Square Int) = Square 1; $two: Vec (Succ Zero) = v;".to_string(), &types).unwrap();
+        let arguments: Vec<&Value> = ast.get_program()
+            .iter()
+            .map(|Let(_, _, typ)| match typ {
+                Some(Type::Application(_, arguments)) => &arguments[0],
+                other => panic!("{:?} is not an application", other),
+            })
+            .collect();
+        assert!(matches!(arguments[0], Value::Type(typ) if matches!(**typ, Type::CoProduct(_))));
+        assert!(matches!(arguments[1], Value::Either(_, _)));
+
+        let error = parse_program("$x = (1, 2;".to_string(), &[]).unwrap_err();
+        println!("{}", error);
+        assert_eq!(error, "expected CloseBracket but found [;]");
+        assert!(parse_program("$x = $y = 1; y;".to_string(), &[]).is_err());
+    }
+
+    #[test]
+    fn functions() {
+        let ast = parse_program("
+            $List = A ~> Nil . + Cons A * List A;
+            $map: {A : @} -> {B : @} -> (A -> B) -> List A -> List B = f xs ~> xs
+                | Nil -> Nil
+                | Cons (x, rest) -> Cons (f x, map f rest);
+            $twice = f x ~> $y = f x; f y;
+            $shout = {name = /you/, times = 2};
+            $lists = map (x ~> #add x 1) (Cons (1, Nil));
+            $zero = x ~> x | (0 | 1) -> 0 | n -> n;
+            $pick = r ~> r | {name} -> name;
+        ".to_string(), &[]).unwrap();
+        let types = infer_program(ast.get_program()).unwrap();
+        assert_eq!(types["lists"].to_string(), "List Int");
+        assert_eq!(types["zero"].to_string(), "Int -> Int");
+    }
 }
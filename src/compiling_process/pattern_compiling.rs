@@ -0,0 +1,354 @@
+use crate::inner_representation::abstract_syntax_tree::{Name, Pattern};
+use crate::inner_representation::decision_tree::{Case, DecisionTree, Occurrence, Step};
+
+#[derive(Clone)]
+struct Row {
+    patterns: Vec<Pattern>,
+    bindings: Vec<(Name, Occurrence)>,
+    arm: usize,
+}
+
+fn check_alternatives(pattern: &Pattern) -> Result<(), String> {
+    match pattern {
+        Pattern::Or(alternatives) => {
+            let mut expected: Vec<&str> = pattern.binders().iter().map(|x| x.get_name()).collect();
+            expected.sort_unstable();
+            for alternative in alternatives {
+                check_alternatives(alternative)?;
+                let mut names: Vec<&str> = alternative.binders().iter().map(|x| x.get_name()).collect();
+                names.sort_unstable();
+                if names != expected {
+                    return Err(format!("alternatives of {} bind different names", pattern));
+                }
+            }
+            Ok(())
+        },
+        Pattern::Constructor(_, inner) => check_alternatives(inner),
+        Pattern::Tuple(items) => items.iter().try_for_each(check_alternatives),
+        Pattern::Record(fields) => fields.iter().try_for_each(|(_, x)| check_alternatives(x)),
+        _ => Ok(()),
+    }
+}
+
+// binders and refl become wildcards, or-patterns become several rows
+fn normalise(columns: &[Occurrence], rows: Vec<Row>) -> Vec<Row> {
+    let mut result = Vec::new();
+    for row in rows {
+        let mut pending = vec![row];
+        'rows: while let Some(mut row) = pending.pop() {
+            for (i, occurrence) in columns.iter().enumerate() {
+                match &row.patterns[i] {
+                    Pattern::Binder(name) => {
+                        row.bindings.push((name.clone(), occurrence.clone()));
+                        row.patterns[i] = Pattern::Wildcard;
+                    },
+                    Pattern::Refl => row.patterns[i] = Pattern::Wildcard,
+                    Pattern::Or(alternatives) => {
+                        for alternative in alternatives.iter().rev() {
+                            let mut new_row = row.clone();
+                            new_row.patterns[i] = alternative.clone();
+                            pending.push(new_row);
+                        }
+                        continue 'rows;
+                    },
+                    _ => (),
+                }
+            }
+            result.push(row);
+        }
+    }
+    result
+}
+
+fn expand_tuple(column: usize, arity: usize, rows: &mut [Row]) -> Result<(), String> {
+    for row in rows.iter_mut() {
+        let items = match row.patterns.remove(column) {
+            Pattern::Tuple(items) if items.len() == arity => items,
+            Pattern::Wildcard => vec![Pattern::Wildcard; arity],
+            pattern => return Err(format!("pattern {} does not fit a tuple of {} elements", pattern, arity)),
+        };
+        row.patterns.splice(column..column, items);
+    }
+    Ok(())
+}
+
+fn expand_record(column: usize, fields: &[String], rows: &mut [Row]) -> Result<(), String> {
+    for row in rows.iter_mut() {
+        let items = match row.patterns.remove(column) {
+            Pattern::Record(given) => fields
+                .iter()
+                .map(|field| {
+                    given
+                        .iter()
+                        .find(|(name, _)| name.get_name() == field)
+                        .map_or(Pattern::Wildcard, |(_, x)| x.clone())
+                })
+                .collect(),
+            Pattern::Wildcard => vec![Pattern::Wildcard; fields.len()],
+            pattern => return Err(format!("pattern {} does not fit a record", pattern)),
+        };
+        row.patterns.splice(column..column, items);
+    }
+    Ok(())
+}
+
+// tuples and records never fail, so they are split into their components up front
+fn expand_structures(columns: &mut Vec<Occurrence>, rows: &mut [Row]) -> Result<bool, String> {
+    for i in 0..columns.len() {
+        let tuple = rows.iter().find_map(|row| match &row.patterns[i] {
+            Pattern::Tuple(items) => Some(items.len()),
+            _ => None,
+        });
+        if let Some(arity) = tuple {
+            expand_tuple(i, arity, rows)?;
+            let occurrence = columns.remove(i);
+            let items = (0..arity).map(|j| {
+                let mut x = occurrence.clone();
+                x.push(Step::Index(j));
+                x
+            });
+            columns.splice(i..i, items);
+            return Ok(true);
+        }
+
+        let mut fields: Vec<String> = Vec::new();
+        for row in rows.iter() {
+            if let Pattern::Record(given) = &row.patterns[i] {
+                for (name, _) in given {
+                    if !fields.iter().any(|x| x == name.get_name()) {
+                        fields.push(name.get_name().to_string());
+                    }
+                }
+            }
+        }
+        if !fields.is_empty() {
+            expand_record(i, &fields, rows)?;
+            let occurrence = columns.remove(i);
+            let items = fields.iter().map(|field| {
+                let mut x = occurrence.clone();
+                x.push(Step::Field(field.clone()));
+                x
+            });
+            columns.splice(i..i, items);
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn head_of(pattern: &Pattern) -> Option<Case> {
+    match pattern {
+        Pattern::Constructor(name, _) => Some(Case::Constructor(name.get_name().to_string())),
+        Pattern::Literal(value) => Some(Case::Literal(value.clone())),
+        _ => None,
+    }
+}
+
+fn specialize(column: usize, case: &Case, rows: &[Row]) -> Vec<Row> {
+    rows
+        .iter()
+        .filter_map(|row| {
+            let mut row = row.clone();
+            let pattern = row.patterns.remove(column);
+            match (case, pattern) {
+                (Case::Constructor(_), Pattern::Wildcard) => {
+                    row.patterns.insert(column, Pattern::Wildcard);
+                    Some(row)
+                },
+                (Case::Constructor(expected), Pattern::Constructor(name, inner)) => {
+                    if name.get_name() == expected {
+                        row.patterns.insert(column, *inner);
+                        Some(row)
+                    } else {
+                        None
+                    }
+                },
+                (Case::Literal(_), Pattern::Wildcard) => Some(row),
+                (Case::Literal(expected), Pattern::Literal(value)) => {
+                    if value == *expected {
+                        Some(row)
+                    } else {
+                        None
+                    }
+                },
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn compile(mut columns: Vec<Occurrence>, rows: Vec<Row>) -> Result<DecisionTree, String> {
+    let mut rows = normalise(&columns, rows);
+    while expand_structures(&mut columns, &mut rows)? {
+        rows = normalise(&columns, rows);
+    }
+
+    let first = match rows.first() {
+        Some(first) => first,
+        None => return Ok(DecisionTree::Fail),
+    };
+    let column = match first.patterns.iter().position(|x| *x != Pattern::Wildcard) {
+        Some(column) => column,
+        None => return Ok(DecisionTree::Leaf(first.arm, first.bindings.clone())),
+    };
+
+    let mut heads: Vec<Case> = Vec::new();
+    for row in &rows {
+        if let Some(head) = head_of(&row.patterns[column]) {
+            if !heads.contains(&head) {
+                heads.push(head);
+            }
+        }
+    }
+
+    let mut cases = Vec::new();
+    for head in heads {
+        let mut new_columns = columns.clone();
+        if let Case::Literal(_) = head {
+            new_columns.remove(column);
+        } else {
+            new_columns[column].push(Step::Payload);
+        }
+        let tree = compile(new_columns, specialize(column, &head, &rows))?;
+        cases.push((head, tree));
+    }
+
+    let defaults: Vec<Row> = rows
+        .into_iter()
+        .filter(|row| row.patterns[column] == Pattern::Wildcard)
+        .map(|mut row| {
+            row.patterns.remove(column);
+            row
+        })
+        .collect();
+    let default = if defaults.is_empty() {
+        None
+    } else {
+        let mut new_columns = columns.clone();
+        new_columns.remove(column);
+        Some(Box::new(compile(new_columns, defaults)?))
+    };
+    Ok(DecisionTree::Switch(columns[column].clone(), cases, default))
+}
+
+pub fn compile_match(patterns: &[&Pattern]) -> Result<DecisionTree, String> {
+    for pattern in patterns {
+        check_alternatives(pattern)?;
+    }
+    let rows = patterns
+        .iter()
+        .enumerate()
+        .map(|(arm, pattern)| Row {
+            patterns: vec![(*pattern).clone()],
+            bindings: vec![],
+            arm,
+        })
+        .collect();
+    compile(vec![vec![]], rows)
+}
+
+#[cfg(test)]
+mod pattern_compiling_tests {
+    use crate::compiling_process::translating::testing::bare as program;
+    use crate::inner_representation::abstract_syntax_tree::{Let, Pattern, Value};
+    use crate::inner_representation::decision_tree::{Case, DecisionTree, Step};
+    use super::compile_match;
+
+    // the patterns of the arms, one arm a line
+    fn patterns(arms: &str) -> Vec<Pattern> {
+        let arms: Vec<String> =
+            arms.lines().map(str::trim).filter(|x| !x.is_empty()).map(|x| format!("| {} -> 0", x)).collect();
+        let program = program(&format!("$f = v ~> v {};", arms.join(" ")));
+        let Let(_, value, _) = &program[0];
+        match value {
+            Value::Function(_, body) => match &body.1 {
+                Value::Match(_, arms) => arms.iter().map(|(pattern, _)| pattern.clone()).collect(),
+                _ => panic!("expected a match"),
+            },
+            _ => panic!("expected a function"),
+        }
+    }
+
+    fn compile(arms: &str) -> Result<DecisionTree, String> {
+        let patterns = patterns(arms);
+        compile_match(&patterns.iter().collect::<Vec<_>>())
+    }
+
+    // the names a leaf binds and where they are found
+    fn bindings(tree: &DecisionTree) -> Vec<(String, Vec<Step>)> {
+        match tree {
+            DecisionTree::Leaf(_, bindings) => {
+                bindings.iter().map(|(x, steps)| (x.get_name().to_string(), steps.clone())).collect()
+            },
+            _ => panic!("expected a leaf"),
+        }
+    }
+
+    #[test]
+    fn nested_constructors() {
+        let tree = compile("
+            Cons (x, Cons (y, _))
+            Cons (x, Nil _)
+            Nil _
+        ").unwrap();
+        println!("{}", tree);
+
+        match &tree {
+            DecisionTree::Switch(occurrence, cases, None) => {
+                assert!(occurrence.is_empty());
+                assert_eq!(cases.len(), 2);
+                assert_eq!(cases[1], (Case::Constructor("Nil".to_string()), DecisionTree::Leaf(2, vec![])));
+            },
+            _ => panic!("expected a switch on the root"),
+        }
+        assert_eq!(size(&tree), 5);
+    }
+
+    fn size(tree: &DecisionTree) -> usize {
+        match tree {
+            DecisionTree::Fail | DecisionTree::Leaf(_, _) => 1,
+            DecisionTree::Switch(_, cases, default) => {
+                let default_size = default.as_ref().map_or(0, |x| size(x));
+                cases.iter().map(|(_, x)| size(x)).sum::<usize>() + default_size + 1
+            },
+        }
+    }
+
+    #[test]
+    fn literals_and_alternatives() {
+        let tree = compile("
+            (1 | 2)
+            n
+        ").unwrap();
+        println!("{}", tree);
+
+        match tree {
+            DecisionTree::Switch(_, cases, Some(default)) => {
+                assert_eq!(cases.len(), 2);
+                assert!(matches!(*default, DecisionTree::Leaf(1, _)));
+                assert_eq!(bindings(&default), vec![("n".to_string(), vec![])]);
+            },
+            _ => panic!("expected a switch with a default"),
+        }
+
+        assert!(compile("(a | b)").is_err());
+    }
+
+    #[test]
+    fn records() {
+        let tree = compile("
+            {x = 0}
+            {y}
+        ").unwrap();
+        println!("{}", tree);
+
+        match tree {
+            DecisionTree::Switch(occurrence, _, Some(default)) => {
+                assert_eq!(occurrence, vec![Step::Field("x".to_string())]);
+                assert!(matches!(*default, DecisionTree::Leaf(1, _)));
+                assert_eq!(bindings(&default), vec![("y".to_string(), vec![Step::Field("y".to_string())])]);
+            },
+            _ => panic!("expected a switch on the field x"),
+        }
+    }
+}
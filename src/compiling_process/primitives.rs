@@ -0,0 +1,151 @@
+use crate::inner_representation::abstract_syntax_tree::{AtomicType, AtomicValue};
+
+// operations on ints and strings no program can write itself, ints wrap around on overflow,
+// comparisons give 1 for true and 0 for false, string lengths and slices count bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Equal,
+    Less,
+    LessOrEqual,
+    Length,
+    Concatenate,
+    Slice,
+    Show,
+}
+
+// the reserved names the primitives go by, no definition may take them
+pub const PRIMITIVES: &[(&str, Primitive)] = &[
+    ("#add", Primitive::Add),
+    ("#sub", Primitive::Subtract),
+    ("#mul", Primitive::Multiply),
+    ("#div", Primitive::Divide),
+    ("#mod", Primitive::Remainder),
+    ("#eq", Primitive::Equal),
+    ("#lt", Primitive::Less),
+    ("#le", Primitive::LessOrEqual),
+    ("#length", Primitive::Length),
+    ("#concat", Primitive::Concatenate),
+    ("#slice", Primitive::Slice),
+    ("#show", Primitive::Show),
+];
+
+pub fn primitive(name: &str) -> Option<Primitive> {
+    PRIMITIVES.iter().find(|(x, _)| *x == name).map(|(_, primitive)| *primitive)
+}
+
+fn int(value: &AtomicValue) -> Result<i32, String> {
+    match value {
+        AtomicValue::Int(n) => Ok(*n),
+        other => Err(format!("expected an int but got {}", other)),
+    }
+}
+
+fn string(value: &AtomicValue) -> Result<&str, String> {
+    match value {
+        AtomicValue::StringLiteral(text) => Ok(text),
+        other => Err(format!("expected a string but got {}", other)),
+    }
+}
+
+impl Primitive {
+    pub fn name(self) -> &'static str {
+        PRIMITIVES.iter().find(|(_, x)| *x == self).map(|(name, _)| *name).unwrap()
+    }
+
+    // the types of the arguments and of the result
+    pub fn signature(self) -> (Vec<AtomicType>, AtomicType) {
+        use AtomicType::{Int, String};
+        match self {
+            Primitive::Add | Primitive::Subtract | Primitive::Multiply | Primitive::Divide | Primitive::Remainder => {
+                (vec![Int, Int], Int)
+            },
+            Primitive::Equal | Primitive::Less | Primitive::LessOrEqual => (vec![Int, Int], Int),
+            Primitive::Length => (vec![String], Int),
+            Primitive::Concatenate => (vec![String, String], String),
+            Primitive::Slice => (vec![String, Int, Int], String),
+            Primitive::Show => (vec![Int], String),
+        }
+    }
+
+    // the same as the length of the signature, without building it on every call
+    pub fn arity(self) -> usize {
+        match self {
+            Primitive::Length | Primitive::Show => 1,
+            Primitive::Slice => 3,
+            _ => 2,
+        }
+    }
+
+    pub fn apply(self, arguments: &[AtomicValue]) -> Result<AtomicValue, String> {
+        if arguments.len() != self.arity() {
+            return Err(format!("{} expects {} arguments but got {}", self.name(), self.arity(), arguments.len()));
+        }
+        let number = |i: usize| int(&arguments[i]);
+        let text = |i: usize| string(&arguments[i]);
+        let truth = |x: bool| AtomicValue::Int(x as i32);
+        Ok(match self {
+            Primitive::Add => AtomicValue::Int(number(0)?.wrapping_add(number(1)?)),
+            Primitive::Subtract => AtomicValue::Int(number(0)?.wrapping_sub(number(1)?)),
+            Primitive::Multiply => AtomicValue::Int(number(0)?.wrapping_mul(number(1)?)),
+            Primitive::Divide | Primitive::Remainder if number(1)? == 0 => return Err("division by zero".to_string()),
+            Primitive::Divide => AtomicValue::Int(number(0)?.wrapping_div(number(1)?)),
+            Primitive::Remainder => AtomicValue::Int(number(0)?.wrapping_rem(number(1)?)),
+            Primitive::Equal => truth(number(0)? == number(1)?),
+            Primitive::Less => truth(number(0)? < number(1)?),
+            Primitive::LessOrEqual => truth(number(0)? <= number(1)?),
+            Primitive::Length => AtomicValue::Int(text(0)?.len() as i32),
+            Primitive::Concatenate => AtomicValue::StringLiteral(format!("{}{}", text(0)?, text(1)?)),
+            // the bounds are clamped to the string, an empty slice when they cross
+            Primitive::Slice => {
+                let bytes = text(0)?.as_bytes();
+                let clamp = |n: i32| (n.max(0) as usize).min(bytes.len());
+                let (from, to) = (clamp(number(1)?), clamp(number(2)?));
+                let slice = if from < to { &bytes[from..to] } else { &[] };
+                AtomicValue::StringLiteral(String::from_utf8_lossy(slice).into_owned())
+            },
+            Primitive::Show => AtomicValue::StringLiteral(number(0)?.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod primitives_tests {
+    use crate::inner_representation::abstract_syntax_tree::AtomicValue;
+    use super::{primitive, Primitive, PRIMITIVES};
+
+    fn run(name: &str, arguments: &[AtomicValue]) -> Result<String, String> {
+        primitive(name).unwrap().apply(arguments).map(|x| x.to_string())
+    }
+
+    #[test]
+    fn unit_tests() {
+        let int = AtomicValue::Int;
+        let text = |x: &str| AtomicValue::StringLiteral(x.to_string());
+        assert!(PRIMITIVES.iter().all(|(name, x)| x.name() == *name));
+        assert!(PRIMITIVES.iter().all(|(_, x)| x.arity() == x.signature().0.len()));
+        assert_eq!(primitive("add"), None);
+        assert_eq!(primitive("#mod"), Some(Primitive::Remainder));
+
+        assert_eq!(run("#add", &[int(2), int(3)]), Ok("5".to_string()));
+        assert_eq!(run("#add", &[int(i32::MAX), int(1)]), Ok(i32::MIN.to_string()));
+        assert_eq!(run("#div", &[int(-7), int(2)]), Ok("-3".to_string()));
+        assert_eq!(run("#mod", &[int(-7), int(2)]), Ok("-1".to_string()));
+        assert_eq!(run("#div", &[int(1), int(0)]), Err("division by zero".to_string()));
+        assert_eq!(run("#lt", &[int(1), int(2)]), Ok("1".to_string()));
+        assert_eq!(run("#eq", &[int(1), int(2)]), Ok("0".to_string()));
+
+        assert_eq!(run("#length", &[text("hello")]), Ok("5".to_string()));
+        assert_eq!(run("#concat", &[text("ab"), text("cd")]), run("#slice", &[text("xabcdx"), int(1), int(5)]));
+        assert_eq!(run("#slice", &[text("abc"), int(2), int(10)]), Ok(text("c").to_string()));
+        assert_eq!(run("#slice", &[text("abc"), int(2), int(1)]), Ok(text("").to_string()));
+        assert_eq!(run("#show", &[int(-12)]), Ok(text("-12").to_string()));
+
+        assert!(run("#add", &[int(1)]).is_err());
+        assert!(run("#length", &[int(1)]).is_err());
+    }
+}
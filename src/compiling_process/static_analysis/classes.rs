@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use crate::compiling_process::static_analysis::type_inference::{as_type_definition, Inference, Scheme};
+use crate::inner_representation::abstract_syntax_tree::{
+    Context, Expr, Level, Let, Name, Pattern, Type, Value, Visibility,
+};
+
+// the instance parameter of every method
+const DICTIONARY: &str = "instance";
+
+fn type_name(text: &str) -> Name {
+    Name::new(text.to_string(), Context::TypeContext)
+}
+
+fn value_name(text: &str) -> Name {
+    Name::new(text.to_string(), Context::ValueContext)
+}
+
+// `$Eq: @0 -> @0 = A ~> class eq (A -> A -> Int)` gives
+// `$eq: {A : @0} -> [instance : Eq A] -> A -> A -> Int = instance | {eq = eq} -> eq`
+fn class_methods(Let(name, value, annotation): &Let) -> Vec<Let> {
+    let (parameters, fields) = match as_type_definition(value) {
+        Some((parameters, Type::Class(fields))) => (parameters, fields),
+        _ => return vec![],
+    };
+    let mut kinds = vec![];
+    let mut kind = annotation.as_ref();
+    while let Some(Type::Function(from, to)) = kind {
+        kinds.push((**from).clone());
+        kind = Some(to);
+    }
+    kinds.resize(parameters.len(), Type::Universe(Level::Constant(0)));
+    let class = match parameters.len() {
+        0 => Type::TypeVar(name.clone()),
+        _ => Type::Application(
+            Box::new(Type::TypeVar(name.clone())),
+            parameters.iter().map(|x| Value::Type(Box::new(Type::TypeVar(type_name(x))))).collect(),
+        ),
+    };
+    fields
+        .iter()
+        .map(|(method, typ)| {
+            let dictionary = value_name(DICTIONARY);
+            let instance = Type::Pi(Visibility::Instance, dictionary.clone(), Box::new(class.clone()), Box::new(typ.clone()));
+            let typ = parameters.iter().zip(&kinds).rev().fold(instance, |to, (parameter, kind)| {
+                Type::Pi(Visibility::Implicit, type_name(parameter), Box::new(kind.clone()), Box::new(to))
+            });
+            let pattern = Pattern::Record(fields.iter().map(|(field, _)| {
+                let inner = if field == method { Pattern::Binder(method.clone()) } else { Pattern::Wildcard };
+                (field.clone(), inner)
+            }).collect());
+            let body = Value::Match(Box::new(Value::Var(dictionary)), vec![(pattern, Value::Var(method.clone()))]);
+            Let(method.clone(), body, Some(typ))
+        })
+        .collect()
+}
+
+// the methods of the classes of the program, as definitions
+pub fn methods(program: &[Let]) -> Vec<Let> {
+    program.iter().flat_map(class_methods).collect()
+}
+
+// classes become records, instance parameters ordinary parameters
+fn explicit(typ: &Type) -> Type {
+    match typ {
+        Type::Product(fields) | Type::Class(fields) => Type::Product(
+            fields.iter().map(|(name, x)| (name.clone(), explicit(x))).collect()
+        ),
+        Type::CoProduct(constructors) => Type::CoProduct(
+            constructors.iter().map(|(name, x)| (name.clone(), Box::new(explicit(x)))).collect()
+        ),
+        Type::Function(from, to) => Type::Function(Box::new(explicit(from)), Box::new(explicit(to))),
+        Type::Codata(body) => Type::Codata(Box::new(explicit(body))),
+        Type::Pi(visibility, name, from, to) => {
+            let visibility = match visibility {
+                Visibility::Instance => Visibility::Explicit,
+                other => *other,
+            };
+            Type::Pi(visibility, name.clone(), Box::new(explicit(from)), Box::new(explicit(to)))
+        },
+        _ => typ.clone(),
+    }
+}
+
+// the instance parameters a definition starts with
+fn instance_parameters(typ: &Type) -> Vec<Name> {
+    match typ {
+        Type::Pi(Visibility::Implicit, _, _, to) => instance_parameters(to),
+        Type::Pi(Visibility::Instance, name, _, to) => {
+            let mut result = vec![name.clone()];
+            result.extend(instance_parameters(to));
+            result
+        },
+        _ => vec![],
+    }
+}
+
+struct Elaboration {
+    // the dictionaries passed at each use, in checking order, taken as the uses are met
+    uses: Vec<(Name, Option<Vec<Value>>)>,
+}
+
+impl Elaboration {
+    fn dictionaries(&mut self, site: &Name) -> Vec<Value> {
+        self.uses
+            .iter_mut()
+            .find(|(name, found)| name == site && found.is_some())
+            .and_then(|(_, found)| found.take())
+            .unwrap_or_default()
+    }
+
+    fn definition(&mut self, Let(name, value, annotation): &Let) -> Let {
+        let value = self.value(value);
+        let parameters = annotation.as_ref().map(instance_parameters).unwrap_or_default();
+        let value = match value {
+            _ if parameters.is_empty() => value,
+            Value::Function(inner, body) => {
+                let mut all: Vec<(Name, Option<Type>)> = parameters.into_iter().map(|x| (x, None)).collect();
+                all.extend(inner);
+                Value::Function(all, body)
+            },
+            value => Value::Function(
+                parameters.into_iter().map(|x| (x, None)).collect(),
+                Box::new(Expr(vec![], value)),
+            ),
+        };
+        Let(name.clone(), value, annotation.as_ref().map(explicit))
+    }
+
+    fn expr(&mut self, Expr(lets, value): &Expr) -> Expr {
+        Expr(lets.iter().map(|x| self.definition(x)).collect(), self.value(value))
+    }
+
+    fn value(&mut self, value: &Value) -> Value {
+        match value {
+            Value::Var(name) => {
+                let dictionaries = self.dictionaries(name);
+                if dictionaries.is_empty() {
+                    value.clone()
+                } else {
+                    Value::Application(Box::new(value.clone()), dictionaries)
+                }
+            },
+            Value::Tuple(items) => Value::Tuple(items.iter().map(|x| self.value(x)).collect()),
+            Value::Record(fields) => Value::Record(
+                fields.iter().map(|(name, x)| (name.clone(), self.value(x))).collect()
+            ),
+            Value::Either(name, payload) => Value::Either(name.clone(), Box::new(self.value(payload))),
+            Value::Match(scrutinee, arms) => Value::Match(
+                Box::new(self.value(scrutinee)),
+                arms.iter().map(|(pattern, x)| (pattern.clone(), self.value(x))).collect(),
+            ),
+            Value::Function(parameters, body) => Value::Function(
+                parameters.iter().map(|(name, x)| (name.clone(), x.as_ref().map(explicit))).collect(),
+                Box::new(self.expr(body)),
+            ),
+            Value::Application(function, arguments) => {
+                let mut all = match &**function {
+                    Value::Var(name) => self.dictionaries(name),
+                    _ => vec![],
+                };
+                let function = match &**function {
+                    Value::Var(_) => (**function).clone(),
+                    _ => self.value(function),
+                };
+                all.extend(arguments.iter().map(|x| self.value(x)));
+                Value::Application(Box::new(function), all)
+            },
+            Value::Type(typ) => Value::Type(Box::new(explicit(typ))),
+            Value::Implicit(value) => Value::Implicit(Box::new(self.value(value))),
+            Value::Constant(_, _) | Value::Hole(_) | Value::Refl => value.clone(),
+        }
+    }
+}
+
+// checks the program with a checker that has been set up, with the pragmas of the program for instance,
+// and passes the dictionaries of instance arguments explicitly, the result has no classes and no instance parameters left
+pub fn elaborate_with(inference: Inference, program: &[Let]) -> Result<Vec<Let>, String> {
+    elaborate_typed(inference, program).map(|(program, _)| program)
+}
+
+// the elaborated program with the types of its definitions, methods included, the ones the checker found with their
+// instance parameters made explicit
+pub fn elaborate_typed(mut inference: Inference, program: &[Let]) -> Result<(Vec<Let>, HashMap<String, Scheme>), String> {
+    let types = inference.infer_program(program)?;
+    let mut elaboration = Elaboration {
+        uses: inference
+            .dictionaries()
+            .into_iter()
+            .map(|(site, found)| (site.clone(), Some(found.into_iter().cloned().collect())))
+            .collect(),
+    };
+    let program = program.iter().cloned().chain(methods(program)).map(|x| elaboration.definition(&x)).collect();
+    let types = types.into_iter().map(|(name, Scheme(variables, ty))| (name, Scheme(variables, ty.explicit()))).collect();
+    Ok((program, types))
+}
+
+#[cfg(test)]
+mod classes_tests {
+    use crate::compiling_process::interpreting::Interpreter;
+    use crate::compiling_process::static_analysis::type_inference::{infer_program, Inference};
+    use crate::compiling_process::translating::testing::bare;
+    use crate::inner_representation::abstract_syntax_tree::{Let, Value};
+    use super::elaborate_with;
+
+    const CLASS: &str = "
+        $Eq: @0 -> @0 = A ~> class eq (A -> A -> Int);
+        $Box: @0 -> @0 = A ~> Boxed A;
+    ";
+
+    const INSTANCES: &str = "
+        $eqInt: Eq Int = {eq = x y ~> 7};
+        $eqBox: {A : @0} -> [d : Eq A] -> Eq (Box A) = {eq = x y ~> x | Boxed a -> (y | Boxed b -> eq a b)};
+        $same: {A : @0} -> [d : Eq A] -> A -> A -> Int = x y ~> eq x y;
+        $answer: Int = same (Boxed 1) (Boxed 2);
+    ";
+
+    fn program(more: &str) -> Vec<Let> {
+        bare(&format!("{} {} {}", CLASS, INSTANCES, more))
+    }
+
+    // the applications of the elaborated program, written back the way they are read
+    fn shape(value: &Value) -> String {
+        match value {
+            Value::Var(name) => name.get_name().to_string(),
+            Value::Either(name, payload) => format!("({} {})", name.get_name(), shape(payload)),
+            Value::Application(function, arguments) => {
+                let arguments: Vec<String> = arguments.iter().map(shape).collect();
+                format!("({} {})", shape(function), arguments.join(" "))
+            },
+            Value::Constant(constant, _) => constant.to_string(),
+            other => format!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn unit_tests() {
+        let program = program("");
+        let types = infer_program(&program).unwrap();
+        assert_eq!(types["eq"].to_string(), "{A : @0} -> [instance : Eq A] -> A -> A -> Int");
+
+        let elaborated = elaborate_with(Inference::new(), &program).unwrap();
+        let answer = elaborated.iter().find(|Let(x, _, _)| x.get_name() == "answer").unwrap();
+        assert_eq!(shape(&answer.1), "(same (eqBox eqInt) (Boxed 1) (Boxed 2))");
+        let types = infer_program(&elaborated).unwrap();
+        assert_eq!(types["same"].to_string(), "{A : @0} -> (d : Eq A) -> A -> A -> Int");
+
+        let interpreter = Interpreter::new(&elaborated);
+        assert_eq!(interpreter.get("answer").unwrap().to_string(), "7");
+    }
+
+    #[test]
+    fn instance_errors() {
+        let error = infer_program(&program("$bad = eq /a/ /b/;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.starts_with("in bad: no instance Eq String for eq"));
+
+        let error = elaborate_with(Inference::new(), &program("$eqInt2: Eq Int = {eq = x y ~> 8};")).unwrap_err();
+        assert!(error.contains("overlapping instances eqInt and eqInt2 for Eq Int"));
+
+        assert!(infer_program(&program("$loose = eq;")).unwrap_err().contains("ambiguous instance Eq"));
+    }
+}
@@ -0,0 +1,274 @@
+use crate::inner_representation::abstract_syntax_tree::{Context, Name, Pattern};
+
+// what the checker knows about the type of a matched column
+pub enum TypeView<T> {
+    Sum(Vec<(String, T)>),
+    Product(Vec<(String, T)>),
+    Opaque,
+    Empty,
+}
+
+// refl matches every proof, like a wildcard
+fn is_wildcard(pattern: &Pattern) -> bool {
+    matches!(pattern, Pattern::Wildcard | Pattern::Binder(_) | Pattern::Refl)
+}
+
+fn expand_alternatives(rows: Vec<Vec<Pattern>>) -> Vec<Vec<Pattern>> {
+    let mut result = Vec::new();
+    for row in rows {
+        match row.first() {
+            Some(Pattern::Or(alternatives)) => {
+                let expanded = alternatives
+                    .iter()
+                    .map(|alternative| {
+                        let mut new_row = row.clone();
+                        new_row[0] = alternative.clone();
+                        new_row
+                    })
+                    .collect();
+                result.extend(expand_alternatives(expanded));
+            },
+            _ => result.push(row),
+        }
+    }
+    result
+}
+
+fn default_rows(rows: &[Vec<Pattern>]) -> Vec<Vec<Pattern>> {
+    rows
+        .iter()
+        .filter(|row| is_wildcard(&row[0]))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+fn product_rows(fields: &[String], rows: &[Vec<Pattern>]) -> Result<Vec<Vec<Pattern>>, String> {
+    rows
+        .iter()
+        .map(|row| {
+            let mut items = match &row[0] {
+                Pattern::Tuple(items) if items.len() == fields.len() => items.clone(),
+                Pattern::Record(given) => fields
+                    .iter()
+                    .map(|field| {
+                        given
+                            .iter()
+                            .find(|(name, _)| name.get_name() == field)
+                            .map_or(Pattern::Wildcard, |(_, x)| x.clone())
+                    })
+                    .collect(),
+                pattern if is_wildcard(pattern) => vec![Pattern::Wildcard; fields.len()],
+                pattern => return Err(format!("pattern {} does not fit a product of {} fields", pattern, fields.len())),
+            };
+            items.extend_from_slice(&row[1..]);
+            Ok(items)
+        })
+        .collect()
+}
+
+fn constructor_rows(constructor: &str, rows: &[Vec<Pattern>]) -> Vec<Vec<Pattern>> {
+    rows
+        .iter()
+        .filter_map(|row| {
+            let payload = match &row[0] {
+                Pattern::Constructor(name, inner) if name.get_name() == constructor => (**inner).clone(),
+                pattern if is_wildcard(pattern) => Pattern::Wildcard,
+                _ => return None,
+            };
+            let mut items = vec![payload];
+            items.extend_from_slice(&row[1..]);
+            Some(items)
+        })
+        .collect()
+}
+
+fn constructor_pattern(name: &str, inner: Pattern) -> Pattern {
+    Pattern::Constructor(Name::new(name.to_string(), Context::Constructor), Box::new(inner))
+}
+
+fn prepend(head: Pattern, witness: Option<Vec<Pattern>>) -> Option<Vec<Pattern>> {
+    witness.map(|mut witness| {
+        witness.insert(0, head);
+        witness
+    })
+}
+
+// returns patterns, one per column, describing values that no row matches
+fn missing<T: Clone>(
+    rows: Vec<Vec<Pattern>>,
+    types: &[T],
+    view: &dyn Fn(&T) -> Result<TypeView<T>, String>,
+) -> Result<Option<Vec<Pattern>>, String> {
+    if types.is_empty() {
+        return Ok(if rows.is_empty() { Some(vec![]) } else { None });
+    }
+    let rows = expand_alternatives(rows);
+    let rest = &types[1..];
+
+    let typ = view(&types[0])?;
+    if let TypeView::Empty = typ {
+        return Ok(None);
+    }
+    if rows.iter().all(|row| is_wildcard(&row[0])) {
+        return Ok(prepend(Pattern::Wildcard, missing(default_rows(&rows), rest, view)?));
+    }
+
+    match typ {
+        TypeView::Empty => Ok(None),
+        TypeView::Opaque => Ok(prepend(Pattern::Wildcard, missing(default_rows(&rows), rest, view)?)),
+        TypeView::Product(fields) => {
+            let names: Vec<String> = fields.iter().map(|(name, _)| name.clone()).collect();
+            let mut new_types: Vec<T> = fields.into_iter().map(|(_, x)| x).collect();
+            new_types.extend_from_slice(rest);
+            Ok(missing(product_rows(&names, &rows)?, &new_types, view)?.map(|mut witness| {
+                let tail = witness.split_off(names.len());
+                let mut result = vec![Pattern::Tuple(witness)];
+                result.extend(tail);
+                result
+            }))
+        },
+        TypeView::Sum(constructors) => {
+            let used: Vec<&str> = rows
+                .iter()
+                .filter_map(|row| match &row[0] {
+                    Pattern::Constructor(name, _) => Some(name.get_name()),
+                    _ => None,
+                })
+                .collect();
+            if let Some((name, _)) = constructors.iter().find(|(name, _)| !used.contains(&name.as_str())) {
+                let head = constructor_pattern(name, Pattern::Wildcard);
+                return Ok(prepend(head, missing(default_rows(&rows), rest, view)?));
+            }
+            for (name, payload) in constructors {
+                let mut new_types = vec![payload];
+                new_types.extend_from_slice(rest);
+                if let Some(mut witness) = missing(constructor_rows(&name, &rows), &new_types, view)? {
+                    let payload = witness.remove(0);
+                    witness.insert(0, constructor_pattern(&name, payload));
+                    return Ok(Some(witness));
+                }
+            }
+            Ok(None)
+        },
+    }
+}
+
+pub fn check_exhaustiveness<T: Clone>(
+    patterns: &[&Pattern],
+    typ: &T,
+    view: &dyn Fn(&T) -> Result<TypeView<T>, String>,
+) -> Result<(), String> {
+    let rows = patterns.iter().map(|x| vec![(*x).clone()]).collect();
+    match missing(rows, std::slice::from_ref(typ), view)? {
+        Some(witness) => Err(format!("non-exhaustive match, {} is not covered", witness[0])),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod exhaustiveness_tests {
+    use std::collections::HashMap;
+
+    use crate::inner_representation::abstract_syntax_tree::{AtomicType, AtomicValue, Context, Name, Pattern, Type};
+    use super::{check_exhaustiveness, TypeView};
+
+    fn name(text: &str, context: Context) -> Name {
+        Name::new(text.to_string(), context)
+    }
+
+    fn constructor(text: &str, inner: Pattern) -> Pattern {
+        Pattern::Constructor(name(text, Context::Constructor), Box::new(inner))
+    }
+
+    // view of surface types, with named types looked up in the given definitions
+    fn view_type(typ: &Type, definitions: &HashMap<String, Type>) -> Result<TypeView<Type>, String> {
+        match typ {
+            Type::Product(fields) | Type::Class(fields) | Type::OpenProduct(fields, _) => Ok(TypeView::Product(
+                fields
+                    .iter()
+                    .map(|(name, x)| (name.get_name().to_string(), x.clone()))
+                    .collect()
+            )),
+            Type::CoProduct(constructors) => Ok(TypeView::Sum(
+                constructors
+                    .iter()
+                    .map(|(name, x)| (name.get_name().to_string(), (**x).clone()))
+                    .collect()
+            )),
+            Type::TypeVar(name) => match definitions.get(name.get_name()) {
+                Some(definition) => view_type(definition, definitions),
+                None => Ok(TypeView::Opaque),
+            },
+            Type::Application(function, _) | Type::Codata(function) => view_type(function, definitions),
+            Type::Atomic(AtomicType::Bottom) => Ok(TypeView::Empty),
+            // an open variant may hold constructors no pattern names
+            Type::OpenCoProduct(_, _) => Ok(TypeView::Opaque),
+            Type::Atomic(_) | Type::Universe(_) | Type::Function(_, _) | Type::Pi(_, _, _, _) | Type::Hole(_) | Type::Equal(_, _) => {
+                Ok(TypeView::Opaque)
+            },
+        }
+    }
+
+    // $List: @ = Nil . + Cons Int * List
+    fn definitions() -> HashMap<String, Type> {
+        let list = Type::CoProduct(vec![
+            (name("Nil", Context::Constructor), Box::new(Type::Atomic(AtomicType::Top))),
+            (name("Cons", Context::Constructor), Box::new(Type::Product(vec![
+                (name("0", Context::None), Type::Atomic(AtomicType::Int)),
+                (name("1", Context::None), Type::TypeVar(name("List", Context::TypeContext))),
+            ]))),
+        ]);
+        let mut definitions = HashMap::new();
+        definitions.insert("List".to_string(), list);
+        definitions
+    }
+
+    fn check(patterns: &[&Pattern]) -> Result<(), String> {
+        let definitions = definitions();
+        let list = Type::TypeVar(name("List", Context::TypeContext));
+        check_exhaustiveness(patterns, &list, &|x| view_type(x, &definitions))
+    }
+
+    #[test]
+    fn unit_tests() {
+        let nil = constructor("Nil", Pattern::Wildcard);
+        let cons = constructor("Cons", Pattern::Wildcard);
+        assert_eq!(check(&[&nil, &cons]), Ok(()));
+        assert_eq!(check(&[&Pattern::Or(vec![nil.clone(), cons.clone()])]), Ok(()));
+
+        let error = check(&[&nil]).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("Cons _"));
+
+        // Nil _ | Cons (_, Nil _) | Cons (0, Cons _)
+        let single = constructor("Cons", Pattern::Tuple(vec![Pattern::Wildcard, nil.clone()]));
+        let zero = constructor("Cons", Pattern::Tuple(vec![
+            Pattern::Literal(AtomicValue::Int(0)),
+            cons.clone(),
+        ]));
+        let error = check(&[&nil, &single, &zero]).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("Cons (_, Cons _)"));
+
+        let any = constructor("Cons", Pattern::Tuple(vec![
+            Pattern::Binder(name("x", Context::ValueContext)),
+            cons,
+        ]));
+        assert_eq!(check(&[&nil, &single, &zero, &any]), Ok(()));
+    }
+
+    #[test]
+    fn bottom_and_literals() {
+        let definitions = HashMap::new();
+        let bottom = Type::Atomic(AtomicType::Bottom);
+        assert_eq!(check_exhaustiveness(&[], &bottom, &|x| view_type(x, &definitions)), Ok(()));
+
+        let int = Type::Atomic(AtomicType::Int);
+        let one = Pattern::Literal(AtomicValue::Int(1));
+        assert!(check_exhaustiveness(&[&one], &int, &|x| view_type(x, &definitions)).is_err());
+        assert_eq!(
+            check_exhaustiveness(&[&one, &Pattern::Wildcard], &int, &|x| view_type(x, &definitions)),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+
+use crate::compiling_process::static_analysis::type_inference::{Inference, Ty};
+use crate::inner_representation::abstract_syntax_tree::{Expr, Let, Name, Span, Type, Value};
+
+// what is expected of a hole, and what was in scope there
+#[derive(Clone, Debug, PartialEq)]
+pub struct Goal {
+    pub name: String,
+    pub span: Option<Span>,
+    pub expected: Ty,
+    pub context: Vec<(String, Ty)>,
+    // holes standing for types may be fixed by unification
+    pub solution: Option<Ty>,
+}
+
+impl fmt::Display for Goal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "?{} : {}", self.name, self.expected)?;
+        if let Some(span) = self.span {
+            write!(f, " (at {})", span)?;
+        }
+        if let Some(solution) = &self.solution {
+            write!(f, "\n  solved by {}", solution)?;
+        }
+        for (name, ty) in &self.context {
+            write!(f, "\n  {} : {}", name, ty)?;
+        }
+        Ok(())
+    }
+}
+
+fn type_holes<'a>(typ: &'a Type, result: &mut Vec<&'a Name>) {
+    match typ {
+        Type::Product(fields) | Type::Class(fields) => fields.iter().for_each(|(_, x)| type_holes(x, result)),
+        Type::OpenProduct(fields, _) => fields.iter().for_each(|(_, x)| type_holes(x, result)),
+        Type::CoProduct(constructors) | Type::OpenCoProduct(constructors, _) => {
+            constructors.iter().for_each(|(_, x)| type_holes(x, result))
+        },
+        Type::Function(from, to) | Type::Pi(_, _, from, to) => {
+            type_holes(from, result);
+            type_holes(to, result);
+        },
+        Type::Application(function, arguments) => {
+            type_holes(function, result);
+            arguments.iter().for_each(|x| value_holes(x, result));
+        },
+        Type::Hole(name) => result.push(name),
+        Type::Codata(body) => type_holes(body, result),
+        Type::Equal(left, right) => {
+            value_holes(left, result);
+            value_holes(right, result);
+        },
+        Type::TypeVar(_) | Type::Universe(_) | Type::Atomic(_) => (),
+    }
+}
+
+fn value_holes<'a>(value: &'a Value, result: &mut Vec<&'a Name>) {
+    match value {
+        Value::Tuple(items) => items.iter().for_each(|x| value_holes(x, result)),
+        Value::Record(fields) => fields.iter().for_each(|(_, x)| value_holes(x, result)),
+        Value::Either(_, payload) => value_holes(payload, result),
+        Value::Match(scrutinee, arms) => {
+            value_holes(scrutinee, result);
+            arms.iter().for_each(|(_, x)| value_holes(x, result));
+        },
+        Value::Function(parameters, body) => {
+            parameters.iter().filter_map(|(_, x)| x.as_ref()).for_each(|x| type_holes(x, result));
+            expr_holes(body, result);
+        },
+        Value::Application(function, arguments) => {
+            value_holes(function, result);
+            arguments.iter().for_each(|x| value_holes(x, result));
+        },
+        Value::Type(typ) => type_holes(typ, result),
+        Value::Implicit(value) => value_holes(value, result),
+        Value::Hole(name) => result.push(name),
+        Value::Var(_) | Value::Constant(_, _) | Value::Refl => (),
+    }
+}
+
+fn expr_holes<'a>(Expr(lets, value): &'a Expr, result: &mut Vec<&'a Name>) {
+    lets.iter().for_each(|x| let_holes(x, result));
+    value_holes(value, result);
+}
+
+fn let_holes<'a>(Let(_, value, annotation): &'a Let, result: &mut Vec<&'a Name>) {
+    if let Some(typ) = annotation {
+        type_holes(typ, result);
+    }
+    value_holes(value, result);
+}
+
+// every hole of the program, in the order they are written
+pub fn holes(program: &[Let]) -> Vec<&Name> {
+    let mut result = vec![];
+    program.iter().for_each(|x| let_holes(x, &mut result));
+    result
+}
+
+// checks the program, holes are accepted and reported instead of failing
+pub fn report_goals(mut inference: Inference, program: &[Let]) -> Result<Vec<Goal>, String> {
+    inference.infer_program(program)?;
+    Ok(inference.goals())
+}
+
+#[cfg(test)]
+mod holes_tests {
+    use crate::compiling_process::static_analysis::type_inference::{Inference, Ty};
+    use crate::compiling_process::translating::testing::bare as program;
+    use crate::inner_representation::abstract_syntax_tree::AtomicType;
+    use super::{holes, report_goals};
+
+    #[test]
+    fn unit_tests() {
+        let program = program("$f: Int -> Int = x ~> ?body; $n: ?T = 3;");
+        assert_eq!(holes(&program).len(), 2);
+
+        let goals = report_goals(Inference::new(), &program).unwrap();
+        for goal in &goals {
+            println!("{}", goal);
+        }
+        assert_eq!(goals.len(), 2);
+        assert_eq!(goals[0].to_string(), "?body : Int (at 1:23)\n  x : Int");
+        assert_eq!(goals[1].solution.as_ref().map(|x| x.to_string()), Some("Int".to_string()));
+        assert_eq!(goals[1].expected.to_string(), "@0");
+    }
+
+    #[test]
+    fn inferred_holes() {
+        let goals = report_goals(Inference::new(), &program("$g = (y : String) ~> ?result;")).unwrap();
+        assert_eq!(goals.len(), 1);
+        assert_eq!(goals[0].context, vec![("y".to_string(), Ty::Atomic(AtomicType::String))]);
+        // an unknown type is printed as the checker prints it
+        assert!(goals[0].to_string().starts_with("?result : t"));
+    }
+}
@@ -0,0 +1,7 @@
+pub mod classes;
+pub mod exhaustiveness;
+pub mod holes;
+pub mod normalising;
+pub mod positivity;
+pub mod termination;
+pub mod type_inference;
@@ -0,0 +1,914 @@
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::compiling_process::primitives::{primitive, Primitive};
+use crate::compiling_process::static_analysis::type_inference::{extend_row, Ty, UniverseLevel};
+use crate::inner_representation::abstract_syntax_tree::{AtomicType, AtomicValue, Pattern, Visibility};
+
+// reductions allowed for one question before giving up
+pub const DEFAULT_FUEL: usize = 5000;
+
+// what unfolding a recursive definition costs, so that the fuel runs out before the stack does
+const UNFOLDING_COST: usize = 10;
+
+#[derive(Clone, Debug)]
+pub enum Semantic {
+    Neutral(Head, Vec<Elimination>),
+    Lambda(Closure),
+    Pi(Visibility, Box<Semantic>, Closure),
+    Function(Box<Semantic>, Box<Semantic>),
+    Named(String, Vec<Semantic>),
+    Product(Vec<(String, Semantic)>),
+    CoProduct(Vec<(String, Semantic)>),
+    // the tail is neutral, rows whose tail evaluates to a row are merged
+    OpenProduct(Vec<(String, Semantic)>, Box<Semantic>),
+    OpenCoProduct(Vec<(String, Semantic)>, Box<Semantic>),
+    Record(Vec<(String, Semantic)>),
+    Either(String, Box<Semantic>),
+    Universe(UniverseLevel),
+    Atomic(AtomicType),
+    Literal(AtomicValue),
+    Equal(Box<Semantic>, Box<Semantic>),
+    Refl,
+}
+
+// what a neutral term is stuck on
+#[derive(Clone, Debug)]
+pub enum Head {
+    Rigid(String),
+    // variables introduced by read-back, counted from the outermost binder
+    Bound(usize),
+    Meta(usize),
+    Global(String),
+    // a tuple, record or constructor a match can't see through yet
+    Blocked(Box<Semantic>),
+}
+
+#[derive(Clone, Debug)]
+pub enum Elimination {
+    Apply(Semantic),
+    Match(Environment, Vec<(Pattern, Ty)>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Closure {
+    name: String,
+    body: Ty,
+    environment: Environment,
+}
+
+#[derive(Clone, Debug)]
+pub struct Environment(Option<Rc<(String, Semantic, Environment)>>);
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment(None)
+    }
+
+    pub fn extend(&self, name: &str, value: Semantic) -> Self {
+        Environment(Some(Rc::new((name.to_string(), value, self.clone()))))
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Semantic> {
+        let mut current = self;
+        while let Some(node) = &current.0 {
+            if node.0 == name {
+                return Some(&node.1);
+            }
+            current = &node.2;
+        }
+        None
+    }
+}
+
+enum Matching {
+    Matched(Vec<(String, Semantic)>),
+    Failed,
+    Stuck,
+}
+
+enum Step {
+    Reduce(Ty, Environment),
+    Done(Semantic),
+}
+
+enum Chosen<'t> {
+    Arm(&'t Ty, Environment),
+    Stuck(Semantic),
+}
+
+fn bound(level: usize) -> Semantic {
+    Semantic::Neutral(Head::Bound(level), vec![])
+}
+
+// names chosen while reading back, and the free names they must not shadow
+struct Scope {
+    names: Vec<String>,
+    free: Vec<String>,
+}
+
+impl Scope {
+    fn fresh(&mut self, name: &str) -> String {
+        let mut name = name.to_string();
+        while self.names.contains(&name) || self.free.contains(&name) {
+            name.push('\'');
+        }
+        self.names.push(name.clone());
+        name
+    }
+}
+
+pub struct Normaliser<'a> {
+    globals: &'a HashMap<String, Ty>,
+    metas: &'a [Option<Ty>],
+    // rigid variables known to stand for a term, learned from matching on refl
+    equations: &'a [(String, Ty)],
+    // definitions of codata, only unfolded when a match looks at them
+    corecursive: &'a [String],
+    // the fuel every normalisation or conversion check starts with
+    limit: usize,
+    fuel: Cell<usize>,
+    // whether a definition reaches itself, found once it is asked about
+    recursive: RefCell<HashMap<String, bool>>,
+}
+
+impl<'a> Normaliser<'a> {
+    pub fn new(globals: &'a HashMap<String, Ty>, metas: &'a [Option<Ty>]) -> Self {
+        Normaliser {
+            globals,
+            metas,
+            equations: &[],
+            corecursive: &[],
+            limit: DEFAULT_FUEL,
+            fuel: Cell::new(DEFAULT_FUEL),
+            recursive: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_equations(mut self, equations: &'a [(String, Ty)]) -> Self {
+        self.equations = equations;
+        self
+    }
+
+    pub fn with_corecursive(mut self, corecursive: &'a [String]) -> Self {
+        self.corecursive = corecursive;
+        self
+    }
+
+    pub fn with_fuel(mut self, fuel: usize) -> Self {
+        self.limit = fuel;
+        self
+    }
+
+    fn spend(&self) -> Result<(), String> {
+        match self.fuel.get() {
+            0 => Err("gave up normalising, the term may not terminate".to_string()),
+            fuel => {
+                self.fuel.set(fuel - 1);
+                Ok(())
+            },
+        }
+    }
+
+    fn is_recursive(&self, name: &str) -> bool {
+        if let Some(known) = self.recursive.borrow().get(name) {
+            return *known;
+        }
+        let mut reached = vec![];
+        if let Some(definition) = self.globals.get(name) {
+            definition.globals(&mut reached);
+        }
+        let mut i = 0;
+        while i < reached.len() && reached[i] != name {
+            if let Some(definition) = self.globals.get(&reached[i]) {
+                definition.globals(&mut reached);
+            }
+            i += 1;
+        }
+        let recursive = i < reached.len();
+        self.recursive.borrow_mut().insert(name.to_string(), recursive);
+        recursive
+    }
+
+    // a recursive definition whose unfolding is stuck on a neutral scrutinee is kept folded, reading back the arms
+    // of the stuck match would unfold it again without end
+    pub fn evaluate(&self, ty: &Ty, environment: &Environment) -> Result<Semantic, String> {
+        let mut unfolding = None;
+        let value = self.reduce(ty, environment, &mut unfolding)?;
+        match (unfolding, &value) {
+            (Some((name, arguments)), Semantic::Neutral(head, spine))
+                if matches!(head, Head::Blocked(_)) || spine.iter().any(|x| matches!(x, Elimination::Match(_, _))) =>
+            {
+                Ok(Semantic::Neutral(Head::Global(name), arguments.into_iter().map(Elimination::Apply).collect()))
+            },
+            _ => Ok(value),
+        }
+    }
+
+    // unfolding definitions and applying lambdas go on in the loop, so long reductions use little stack, the
+    // recursive definition applied last in the loop is kept in `unfolding`
+    fn reduce(
+        &self,
+        ty: &Ty,
+        environment: &Environment,
+        unfolding: &mut Option<(String, Vec<Semantic>)>,
+    ) -> Result<Semantic, String> {
+        let (mut ty, mut environment) = (Cow::Borrowed(ty), environment.clone());
+        loop {
+            (ty, environment) = match &*ty {
+                Ty::Meta(id) => match &self.metas[*id] {
+                    Some(solution) => (Cow::Borrowed(solution), environment),
+                    None => return Ok(Semantic::Neutral(Head::Meta(*id), vec![])),
+                },
+                Ty::Rigid(name) => match environment.lookup(name) {
+                    Some(value) => return Ok(value.clone()),
+                    None => match self.equations.iter().rev().find(|(x, _)| x == name) {
+                        Some((_, value)) => {
+                            self.spend()?;
+                            (Cow::Borrowed(value), Environment::new())
+                        },
+                        None => return Ok(Semantic::Neutral(Head::Rigid(name.clone()), vec![])),
+                    },
+                },
+                Ty::Global(name) => match self.globals.get(name) {
+                    Some(definition) if !self.corecursive.contains(name) => {
+                        self.spend()?;
+                        (Cow::Borrowed(definition), Environment::new())
+                    },
+                    _ => return Ok(Semantic::Neutral(Head::Global(name.clone()), vec![])),
+                },
+                Ty::Application(_, _) => match self.application(&ty, &environment, unfolding)? {
+                    Step::Reduce(body, environment) => (Cow::Owned(body), environment),
+                    Step::Done(value) => return Ok(value),
+                },
+                Ty::Match(scrutinee, arms) => match self.select_arm(scrutinee, &environment, arms)? {
+                    Step::Reduce(body, environment) => (Cow::Owned(body), environment),
+                    Step::Done(value) => return Ok(value),
+                },
+                other => return self.evaluate_structure(other, &environment),
+            };
+        }
+    }
+
+    // the steps of `reduce` that evaluate more are kept apart so the frame the loop keeps on the stack is small
+    fn application(
+        &self,
+        ty: &Ty,
+        environment: &Environment,
+        unfolding: &mut Option<(String, Vec<Semantic>)>,
+    ) -> Result<Step, String> {
+        let (function, argument) = match (ty, self.recursive_call(ty, environment)?) {
+            (_, Some((name, arguments))) => return self.unfold_call(name, arguments, unfolding),
+            (Ty::Application(function, argument), None) => (function, argument),
+            _ => unreachable!(),
+        };
+        let function = self.evaluate(function, environment)?;
+        let argument = self.evaluate(argument, environment)?;
+        match function {
+            Semantic::Lambda(Closure { name, body, environment }) => {
+                self.spend()?;
+                Ok(Step::Reduce(body, environment.extend(&name, argument)))
+            },
+            function => Ok(Step::Done(self.apply(function, argument)?)),
+        }
+    }
+
+    fn unfold_call(
+        &self,
+        name: String,
+        arguments: Vec<Semantic>,
+        unfolding: &mut Option<(String, Vec<Semantic>)>,
+    ) -> Result<Step, String> {
+        let (last, before) = arguments.split_last().unwrap();
+        let mut function = self.evaluate(&self.globals[&name], &Environment::new())?;
+        for argument in before {
+            function = self.apply(function, argument.clone())?;
+        }
+        match function {
+            Semantic::Lambda(Closure { name: parameter, body, environment }) => {
+                self.spend()?;
+                let environment = environment.extend(&parameter, last.clone());
+                *unfolding = Some((name, arguments));
+                Ok(Step::Reduce(body, environment))
+            },
+            function => Ok(Step::Done(self.apply(function, last.clone())?)),
+        }
+    }
+
+    fn select_arm(&self, scrutinee: &Ty, environment: &Environment, arms: &[(Pattern, Ty)]) -> Result<Step, String> {
+        let scrutinee = self.evaluate(scrutinee, environment)?;
+        match self.choose(scrutinee, environment, arms)? {
+            Chosen::Arm(body, environment) => Ok(Step::Reduce(body.clone(), environment)),
+            Chosen::Stuck(value) => Ok(Step::Done(value)),
+        }
+    }
+
+    // a recursive definition applied to arguments, with the arguments evaluated, each unfolding of one costs the
+    // fuel of the stack it may take
+    fn recursive_call(&self, ty: &Ty, environment: &Environment) -> Result<Option<(String, Vec<Semantic>)>, String> {
+        let mut arguments = vec![];
+        let mut head = ty;
+        while let Ty::Application(function, argument) = head {
+            arguments.push(&**argument);
+            head = function;
+        }
+        let name = match head {
+            Ty::Global(name) if self.globals.contains_key(name) && !self.corecursive.contains(name) => name,
+            _ => return Ok(None),
+        };
+        if !self.is_recursive(name) {
+            return Ok(None);
+        }
+        (0..UNFOLDING_COST).try_for_each(|_| self.spend())?;
+        let arguments = arguments.into_iter().rev().map(|x| self.evaluate(x, environment)).collect::<Result<_, String>>()?;
+        Ok(Some((name.clone(), arguments)))
+    }
+
+    // kept apart from `evaluate` so deep reductions use little stack
+    fn evaluate_structure(&self, ty: &Ty, environment: &Environment) -> Result<Semantic, String> {
+        let fields = |fields: &[(String, Ty)]| -> Result<Vec<(String, Semantic)>, String> {
+            fields.iter().map(|(name, x)| Ok((name.clone(), self.evaluate(x, environment)?))).collect()
+        };
+        let closure = |name: &str, body: &Ty| Closure {
+            name: name.to_string(),
+            body: body.clone(),
+            environment: environment.clone(),
+        };
+        match ty {
+            Ty::Named(name, arguments) => Ok(Semantic::Named(
+                name.clone(),
+                arguments.iter().map(|x| self.evaluate(x, environment)).collect::<Result<_, String>>()?,
+            )),
+            Ty::Function(from, to) => Ok(Semantic::Function(
+                Box::new(self.evaluate(from, environment)?),
+                Box::new(self.evaluate(to, environment)?),
+            )),
+            Ty::Pi(visibility, name, from, to) => Ok(Semantic::Pi(
+                *visibility,
+                Box::new(self.evaluate(from, environment)?),
+                closure(name, to),
+            )),
+            Ty::Product(x) => Ok(Semantic::Product(fields(x)?)),
+            Ty::CoProduct(x) => Ok(Semantic::CoProduct(fields(x)?)),
+            Ty::OpenProduct(x, tail) | Ty::OpenCoProduct(x, tail) => {
+                let product = matches!(ty, Ty::OpenProduct(_, _));
+                let mut x = fields(x)?;
+                match self.evaluate(tail, environment)? {
+                    Semantic::Product(more) | Semantic::CoProduct(more) => {
+                        x.extend(more);
+                        Ok(if product { Semantic::Product(x) } else { Semantic::CoProduct(x) })
+                    },
+                    Semantic::OpenProduct(more, tail) | Semantic::OpenCoProduct(more, tail) => {
+                        x.extend(more);
+                        Ok(if product { Semantic::OpenProduct(x, tail) } else { Semantic::OpenCoProduct(x, tail) })
+                    },
+                    tail if product => Ok(Semantic::OpenProduct(x, Box::new(tail))),
+                    tail => Ok(Semantic::OpenCoProduct(x, Box::new(tail))),
+                }
+            },
+            Ty::Record(x) => Ok(Semantic::Record(fields(x)?)),
+            Ty::Universe(level) => Ok(Semantic::Universe(level.clone())),
+            Ty::Atomic(atomic) => Ok(Semantic::Atomic(*atomic)),
+            Ty::Literal(value) => Ok(Semantic::Literal(value.clone())),
+            Ty::Lambda(name, body) => Ok(Semantic::Lambda(closure(name, body))),
+            Ty::Either(name, payload) => Ok(Semantic::Either(name.clone(), Box::new(self.evaluate(payload, environment)?))),
+            Ty::Equal(left, right) => Ok(Semantic::Equal(
+                Box::new(self.evaluate(left, environment)?),
+                Box::new(self.evaluate(right, environment)?),
+            )),
+            Ty::Refl => Ok(Semantic::Refl),
+            Ty::Meta(_) | Ty::Rigid(_) | Ty::Global(_) | Ty::Application(_, _) | Ty::Match(_, _) => {
+                self.evaluate(ty, environment)
+            },
+        }
+    }
+
+    pub fn apply(&self, function: Semantic, argument: Semantic) -> Result<Semantic, String> {
+        match function {
+            Semantic::Lambda(closure) => {
+                self.spend()?;
+                self.instantiate(&closure, argument)
+            },
+            Semantic::Neutral(head, mut spine) => {
+                spine.push(Elimination::Apply(argument));
+                let found = match &head {
+                    Head::Global(name) => primitive(name),
+                    _ => None,
+                };
+                match found {
+                    Some(primitive) => self.apply_primitive(primitive, head, spine),
+                    None => Ok(Semantic::Neutral(head, spine)),
+                }
+            },
+            _ => Err("only functions can be applied".to_string()),
+        }
+    }
+
+    // primitives compute once all their arguments are literals, kept apart so `apply` uses little stack
+    fn apply_primitive(&self, primitive: Primitive, head: Head, spine: Vec<Elimination>) -> Result<Semantic, String> {
+        let literals: Vec<AtomicValue> = spine
+            .iter()
+            .filter_map(|x| match x {
+                Elimination::Apply(Semantic::Literal(value)) => Some(value.clone()),
+                _ => None,
+            })
+            .collect();
+        if primitive.arity() != spine.len() || literals.len() != spine.len() {
+            return Ok(Semantic::Neutral(head, spine));
+        }
+        self.spend()?;
+        Ok(Semantic::Literal(primitive.apply(&literals)?))
+    }
+
+    fn instantiate(&self, closure: &Closure, argument: Semantic) -> Result<Semantic, String> {
+        self.evaluate(&closure.body, &closure.environment.extend(&closure.name, argument))
+    }
+
+    // a corecursive definition applied to all its arguments gives its next layer
+    fn unfold(&self, value: &Semantic) -> Result<Option<Semantic>, String> {
+        let (definition, spine) = match value {
+            Semantic::Neutral(Head::Global(name), spine) if self.corecursive.contains(name) => match self.globals.get(name) {
+                Some(definition) => (definition, spine),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        self.spend()?;
+        let mut result = self.evaluate(definition, &Environment::new())?;
+        for elimination in spine {
+            result = match elimination {
+                Elimination::Apply(argument) => self.apply(result, argument.clone())?,
+                Elimination::Match(environment, arms) => self.select(result, environment, arms)?,
+            };
+        }
+        Ok(Some(result))
+    }
+
+    fn select(&self, scrutinee: Semantic, environment: &Environment, arms: &[(Pattern, Ty)]) -> Result<Semantic, String> {
+        match self.choose(scrutinee, environment, arms)? {
+            Chosen::Arm(body, environment) => self.evaluate(body, &environment),
+            Chosen::Stuck(value) => Ok(value),
+        }
+    }
+
+    // the arm a match goes on with, or the match itself when its scrutinee is not known enough
+    fn choose<'t>(
+        &self,
+        scrutinee: Semantic,
+        environment: &Environment,
+        arms: &'t [(Pattern, Ty)],
+    ) -> Result<Chosen<'t>, String> {
+        for (pattern, body) in arms {
+            match self.matching(pattern, &scrutinee)? {
+                Matching::Matched(bindings) => {
+                    self.spend()?;
+                    let environment = bindings.into_iter().fold(environment.clone(), |x, (name, value)| {
+                        x.extend(&name, value)
+                    });
+                    return Ok(Chosen::Arm(body, environment));
+                },
+                Matching::Failed => continue,
+                Matching::Stuck => break,
+            }
+        }
+        let elimination = Elimination::Match(environment.clone(), arms.to_vec());
+        match scrutinee {
+            Semantic::Neutral(head, mut spine) => {
+                spine.push(elimination);
+                Ok(Chosen::Stuck(Semantic::Neutral(head, spine)))
+            },
+            Semantic::Record(_) | Semantic::Either(_, _) => {
+                Ok(Chosen::Stuck(Semantic::Neutral(Head::Blocked(Box::new(scrutinee)), vec![elimination])))
+            },
+            _ => Err("no arm of the match fits".to_string()),
+        }
+    }
+
+    fn matching(&self, pattern: &Pattern, value: &Semantic) -> Result<Matching, String> {
+        let all = |matchings: Vec<Matching>| {
+            let mut result = vec![];
+            let mut stuck = false;
+            for matching in matchings {
+                match matching {
+                    Matching::Matched(bindings) => result.extend(bindings),
+                    Matching::Failed => return Matching::Failed,
+                    Matching::Stuck => stuck = true,
+                }
+            }
+            if stuck { Matching::Stuck } else { Matching::Matched(result) }
+        };
+        Ok(match (pattern, value) {
+            (Pattern::Wildcard, _) => Matching::Matched(vec![]),
+            (Pattern::Binder(name), _) => Matching::Matched(vec![(name.get_name().to_string(), value.clone())]),
+            (Pattern::Or(alternatives), _) => {
+                for alternative in alternatives {
+                    match self.matching(alternative, value)? {
+                        Matching::Failed => continue,
+                        other => return Ok(other),
+                    }
+                }
+                Matching::Failed
+            },
+            (_, Semantic::Neutral(_, _)) => match self.unfold(value)? {
+                Some(unfolded) => self.matching(pattern, &unfolded)?,
+                None => Matching::Stuck,
+            },
+            (Pattern::Refl, Semantic::Refl) => Matching::Matched(vec![]),
+            (Pattern::Literal(expected), Semantic::Literal(actual)) => {
+                if expected == actual { Matching::Matched(vec![]) } else { Matching::Failed }
+            },
+            (Pattern::Constructor(name, inner), Semantic::Either(actual, payload)) => {
+                if name.get_name() == actual { self.matching(inner, payload)? } else { Matching::Failed }
+            },
+            (Pattern::Tuple(items), Semantic::Record(fields)) if items.len() == fields.len() => all(
+                items.iter().zip(fields).map(|(item, (_, x))| self.matching(item, x)).collect::<Result<_, _>>()?
+            ),
+            (Pattern::Record(given), Semantic::Record(fields)) => all(
+                given
+                    .iter()
+                    .map(|(name, item)| match fields.iter().find(|(x, _)| x == name.get_name()) {
+                        Some((_, x)) => self.matching(item, x),
+                        None => Ok(Matching::Failed),
+                    })
+                    .collect::<Result<_, _>>()?
+            ),
+            _ => Matching::Failed,
+        })
+    }
+
+    fn read_back(&self, value: &Semantic, scope: &mut Scope) -> Result<Ty, String> {
+        let fields = |fields: &[(String, Semantic)], scope: &mut Scope| -> Result<Vec<(String, Ty)>, String> {
+            fields.iter().map(|(name, x)| Ok((name.clone(), self.read_back(x, scope)?))).collect()
+        };
+        match value {
+            Semantic::Neutral(head, spine) => {
+                let mut result = match head {
+                    Head::Rigid(name) => Ty::Rigid(name.clone()),
+                    Head::Bound(level) => Ty::Rigid(scope.names[*level].clone()),
+                    Head::Meta(id) => Ty::Meta(*id),
+                    Head::Global(name) => Ty::Global(name.clone()),
+                    Head::Blocked(value) => self.read_back(value, scope)?,
+                };
+                for elimination in spine {
+                    result = match elimination {
+                        Elimination::Apply(argument) => {
+                            Ty::Application(Box::new(result), Box::new(self.read_back(argument, scope)?))
+                        },
+                        Elimination::Match(environment, arms) => {
+                            let arms = arms
+                                .iter()
+                                .map(|(pattern, body)| self.read_back_arm(pattern, body, environment, scope))
+                                .collect::<Result<_, String>>()?;
+                            Ty::Match(Box::new(result), arms)
+                        },
+                    };
+                }
+                Ok(result)
+            },
+            Semantic::Lambda(closure) => {
+                let (name, body) = self.read_back_closure(closure, scope)?;
+                Ok(Ty::Lambda(name, Box::new(body)))
+            },
+            Semantic::Pi(visibility, from, closure) => {
+                let from = self.read_back(from, scope)?;
+                let (name, to) = self.read_back_closure(closure, scope)?;
+                Ok(Ty::Pi(*visibility, name, Box::new(from), Box::new(to)))
+            },
+            Semantic::Function(from, to) => Ok(Ty::Function(
+                Box::new(self.read_back(from, scope)?),
+                Box::new(self.read_back(to, scope)?),
+            )),
+            Semantic::Named(name, arguments) => Ok(Ty::Named(
+                name.clone(),
+                arguments.iter().map(|x| self.read_back(x, scope)).collect::<Result<_, String>>()?,
+            )),
+            Semantic::Product(x) => Ok(Ty::Product(fields(x, scope)?)),
+            Semantic::CoProduct(x) => Ok(Ty::CoProduct(fields(x, scope)?)),
+            Semantic::OpenProduct(x, tail) => Ok(extend_row(true, fields(x, scope)?, Some(self.read_back(tail, scope)?))),
+            Semantic::OpenCoProduct(x, tail) => Ok(extend_row(false, fields(x, scope)?, Some(self.read_back(tail, scope)?))),
+            Semantic::Record(x) => Ok(Ty::Record(fields(x, scope)?)),
+            Semantic::Either(name, payload) => Ok(Ty::Either(name.clone(), Box::new(self.read_back(payload, scope)?))),
+            Semantic::Universe(level) => Ok(Ty::Universe(level.clone())),
+            Semantic::Atomic(atomic) => Ok(Ty::Atomic(*atomic)),
+            Semantic::Literal(value) => Ok(Ty::Literal(value.clone())),
+            Semantic::Equal(left, right) => Ok(Ty::Equal(
+                Box::new(self.read_back(left, scope)?),
+                Box::new(self.read_back(right, scope)?),
+            )),
+            Semantic::Refl => Ok(Ty::Refl),
+        }
+    }
+
+    fn read_back_closure(&self, closure: &Closure, scope: &mut Scope) -> Result<(String, Ty), String> {
+        let level = scope.names.len();
+        let name = scope.fresh(&closure.name);
+        let body = self.instantiate(closure, bound(level)).and_then(|x| self.read_back(&x, scope));
+        scope.names.pop();
+        Ok((name, body?))
+    }
+
+    fn read_back_arm(
+        &self,
+        pattern: &Pattern,
+        body: &Ty,
+        environment: &Environment,
+        scope: &mut Scope,
+    ) -> Result<(Pattern, Ty), String> {
+        let depth = scope.names.len();
+        let mut pattern = pattern.clone();
+        let mut environment = environment.clone();
+        for binder in pattern.clone().binders() {
+            let level = scope.names.len();
+            let name = scope.fresh(binder.get_name());
+            pattern = pattern.rename(binder.get_name(), &name);
+            environment = environment.extend(binder.get_name(), bound(level));
+        }
+        let body = self.evaluate(body, &environment).and_then(|x| self.read_back(&x, scope));
+        scope.names.truncate(depth);
+        Ok((pattern, body?))
+    }
+
+    pub fn normalise(&self, ty: &Ty) -> Result<Ty, String> {
+        self.fuel.set(self.limit);
+        let mut free = vec![];
+        ty.free_variables(&mut free);
+        self.equations.iter().for_each(|(_, x)| x.free_variables(&mut free));
+        let value = self.evaluate(ty, &Environment::new())?;
+        self.read_back(&value, &mut Scope { names: vec![], free })
+    }
+
+    // beta and eta equality, bound names don't matter
+    pub fn equal(&self, left: &Ty, right: &Ty) -> Result<bool, String> {
+        self.fuel.set(self.limit);
+        let left = self.evaluate(left, &Environment::new())?;
+        let right = self.evaluate(right, &Environment::new())?;
+        self.convertible(0, &left, &right)
+    }
+
+    fn convertible(&self, level: usize, left: &Semantic, right: &Semantic) -> Result<bool, String> {
+        let all = |pairs: Vec<(&Semantic, &Semantic)>| -> Result<bool, String> {
+            for (left, right) in pairs {
+                if !self.convertible(level, left, right)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        };
+        let fields = |left: &[(String, Semantic)], right: &[(String, Semantic)]| -> Result<bool, String> {
+            let mut pairs = vec![];
+            for (name, x) in left {
+                match right.iter().find(|(y, _)| y == name) {
+                    Some((_, y)) => pairs.push((x, y)),
+                    None => return Ok(false),
+                }
+            }
+            Ok(left.len() == right.len() && all(pairs)?)
+        };
+        match (left, right) {
+            (Semantic::Lambda(x), Semantic::Lambda(y)) => self.convertible(
+                level + 1,
+                &self.instantiate(x, bound(level))?,
+                &self.instantiate(y, bound(level))?,
+            ),
+            (Semantic::Lambda(x), other @ Semantic::Neutral(_, _))
+            | (other @ Semantic::Neutral(_, _), Semantic::Lambda(x)) => self.convertible(
+                level + 1,
+                &self.instantiate(x, bound(level))?,
+                &self.apply(other.clone(), bound(level))?,
+            ),
+            (Semantic::Pi(visibility, from, x), Semantic::Pi(another_visibility, another_from, y)) => Ok(
+                visibility == another_visibility && self.convertible(level, from, another_from)? && self.convertible(
+                    level + 1,
+                    &self.instantiate(x, bound(level))?,
+                    &self.instantiate(y, bound(level))?,
+                )?
+            ),
+            (Semantic::Pi(Visibility::Explicit, from, x), Semantic::Function(another_from, to))
+            | (Semantic::Function(another_from, to), Semantic::Pi(Visibility::Explicit, from, x)) => Ok(
+                self.convertible(level, from, another_from)?
+                    && self.convertible(level + 1, &self.instantiate(x, bound(level))?, to)?
+            ),
+            (Semantic::Function(from, to), Semantic::Function(another_from, another_to)) => {
+                all(vec![(from, another_from), (to, another_to)])
+            },
+            (Semantic::Neutral(head, spine), Semantic::Neutral(another_head, another_spine)) => {
+                let heads = match (head, another_head) {
+                    (Head::Rigid(x), Head::Rigid(y)) | (Head::Global(x), Head::Global(y)) => x == y,
+                    (Head::Bound(x), Head::Bound(y)) | (Head::Meta(x), Head::Meta(y)) => x == y,
+                    (Head::Blocked(x), Head::Blocked(y)) => self.convertible(level, x, y)?,
+                    _ => false,
+                };
+                if !heads || spine.len() != another_spine.len() {
+                    return Ok(false);
+                }
+                for pair in spine.iter().zip(another_spine) {
+                    let same = match pair {
+                        (Elimination::Apply(x), Elimination::Apply(y)) => self.convertible(level, x, y)?,
+                        (Elimination::Match(environment, arms), Elimination::Match(another_environment, another_arms)) => {
+                            self.convertible_arms(level, (environment, arms), (another_environment, another_arms))?
+                        },
+                        _ => false,
+                    };
+                    if !same {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            },
+            (Semantic::Named(x, arguments), Semantic::Named(y, another_arguments)) => Ok(
+                x == y && arguments.len() == another_arguments.len() && all(arguments.iter().zip(another_arguments).collect())?
+            ),
+            (Semantic::Product(x), Semantic::Product(y))
+            | (Semantic::CoProduct(x), Semantic::CoProduct(y))
+            | (Semantic::Record(x), Semantic::Record(y)) => fields(x, y),
+            (Semantic::OpenProduct(x, tail), Semantic::OpenProduct(y, another_tail))
+            | (Semantic::OpenCoProduct(x, tail), Semantic::OpenCoProduct(y, another_tail)) => {
+                Ok(fields(x, y)? && self.convertible(level, tail, another_tail)?)
+            },
+            (Semantic::Either(x, payload), Semantic::Either(y, another_payload)) => {
+                Ok(x == y && self.convertible(level, payload, another_payload)?)
+            },
+            (Semantic::Universe(x), Semantic::Universe(y)) => Ok(x.includes(y) && y.includes(x)),
+            (Semantic::Atomic(x), Semantic::Atomic(y)) => Ok(x == y),
+            (Semantic::Literal(x), Semantic::Literal(y)) => Ok(x == y),
+            (Semantic::Equal(left, right), Semantic::Equal(another_left, another_right)) => {
+                all(vec![(left, another_left), (right, another_right)])
+            },
+            (Semantic::Refl, Semantic::Refl) => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    // arms agree when their patterns are the same and their bodies are, for any values of the binders
+    fn convertible_arms(
+        &self,
+        level: usize,
+        (environment, arms): (&Environment, &[(Pattern, Ty)]),
+        (another_environment, another_arms): (&Environment, &[(Pattern, Ty)]),
+    ) -> Result<bool, String> {
+        if arms.len() != another_arms.len() {
+            return Ok(false);
+        }
+        for ((pattern, body), (another_pattern, another_body)) in arms.iter().zip(another_arms) {
+            if pattern != another_pattern {
+                return Ok(false);
+            }
+            let binders = pattern.binders();
+            let (mut inner, mut another_inner) = (environment.clone(), another_environment.clone());
+            for (i, binder) in binders.iter().enumerate() {
+                inner = inner.extend(binder.get_name(), bound(level + i));
+                another_inner = another_inner.extend(binder.get_name(), bound(level + i));
+            }
+            let body = self.evaluate(body, &inner)?;
+            let another_body = self.evaluate(another_body, &another_inner)?;
+            if !self.convertible(level + binders.len(), &body, &another_body)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod normalising_tests {
+    use std::collections::HashMap;
+
+    use crate::compiling_process::static_analysis::type_inference::Ty;
+    use crate::inner_representation::abstract_syntax_tree::{AtomicType, AtomicValue, Context, Name, Pattern};
+    use super::Normaliser;
+
+    fn rigid(name: &str) -> Ty {
+        Ty::Rigid(name.to_string())
+    }
+
+    fn lambda(name: &str, body: Ty) -> Ty {
+        Ty::Lambda(name.to_string(), Box::new(body))
+    }
+
+    fn apply(function: Ty, argument: Ty) -> Ty {
+        Ty::Application(Box::new(function), Box::new(argument))
+    }
+
+    fn int(n: i32) -> Ty {
+        Ty::Literal(AtomicValue::Int(n))
+    }
+
+    #[test]
+    fn unit_tests() {
+        // Choose = n ~> n | 0 -> Int | _ -> String
+        let mut globals = HashMap::new();
+        globals.insert("Choose".to_string(), lambda("n", Ty::Match(Box::new(rigid("n")), vec![
+            (Pattern::Literal(AtomicValue::Int(0)), Ty::Atomic(AtomicType::Int)),
+            (Pattern::Wildcard, Ty::Atomic(AtomicType::String)),
+        ])));
+        let normaliser = Normaliser::new(&globals, &[]);
+        let choose = |x: Ty| apply(Ty::Global("Choose".to_string()), x);
+
+        // beta, alpha and eta
+        assert_eq!(normaliser.equal(&apply(lambda("x", rigid("x")), int(1)), &int(1)), Ok(true));
+        assert_eq!(normaliser.equal(&lambda("x", rigid("x")), &lambda("y", rigid("y"))), Ok(true));
+        assert_eq!(normaliser.equal(&rigid("f"), &lambda("x", apply(rigid("f"), rigid("x")))), Ok(true));
+        assert_eq!(normaliser.equal(&lambda("x", rigid("x")), &lambda("x", rigid("y"))), Ok(false));
+
+        assert_eq!(normaliser.equal(&choose(int(0)), &Ty::Atomic(AtomicType::Int)), Ok(true));
+        assert_eq!(normaliser.equal(&choose(int(1)), &Ty::Atomic(AtomicType::Int)), Ok(false));
+        // stuck on n, but the same on both sides
+        let stuck = lambda("n", choose(rigid("n")));
+        assert_eq!(normaliser.equal(&stuck, &Ty::Global("Choose".to_string())), Ok(true));
+        println!("{}", normaliser.normalise(&stuck).unwrap());
+    }
+
+    #[test]
+    fn read_back() {
+        let globals = HashMap::new();
+        let normaliser = Normaliser::new(&globals, &[]);
+
+        // y ~> (x ~> y ~> x) y reads back as y ~> y' ~> y
+        let term = lambda("y", apply(lambda("x", lambda("y", rigid("x"))), rigid("y")));
+        let normal = normaliser.normalise(&term).unwrap();
+        assert_eq!(normal, lambda("y", lambda("y'", rigid("y"))));
+        assert_eq!(normal.to_string(), "y ~> y' ~> y");
+
+        // free names are never captured
+        let term = apply(lambda("x", lambda("a", rigid("x"))), rigid("a"));
+        assert_eq!(normaliser.normalise(&term).unwrap(), lambda("a'", rigid("a")));
+    }
+
+    #[test]
+    fn equations() {
+        let globals = HashMap::new();
+        let equations = vec![("y".to_string(), rigid("x"))];
+        let normaliser = Normaliser::new(&globals, &[]).with_equations(&equations);
+        let equal = |left: Ty, right: Ty| Ty::Equal(Box::new(left), Box::new(right));
+        assert_eq!(normaliser.equal(&equal(rigid("x"), rigid("y")), &equal(rigid("x"), rigid("x"))), Ok(true));
+        assert_eq!(Normaliser::new(&globals, &[]).equal(&rigid("x"), &rigid("y")), Ok(false));
+
+        // refl | refl -> 1 reduces, p | refl -> 1 waits for p
+        let matching = |proof: Ty| Ty::Match(Box::new(proof), vec![(Pattern::Refl, int(1))]);
+        assert_eq!(normaliser.normalise(&matching(Ty::Refl)), Ok(int(1)));
+        assert_eq!(normaliser.normalise(&matching(rigid("p"))), Ok(matching(rigid("p"))));
+    }
+
+    #[test]
+    fn corecursion() {
+        // ones = {head = 1, tail = ones}, left alone until a match looks at it
+        let mut globals = HashMap::new();
+        let ones = Ty::Global("ones".to_string());
+        globals.insert("ones".to_string(), Ty::Record(vec![("head".to_string(), int(1)), ("tail".to_string(), ones.clone())]));
+        let corecursive = vec!["ones".to_string()];
+        let normaliser = Normaliser::new(&globals, &[]).with_corecursive(&corecursive);
+        assert_eq!(normaliser.normalise(&ones), Ok(ones.clone()));
+        assert_eq!(normaliser.equal(&ones, &ones), Ok(true));
+
+        // ones | {tail = {head = h}} -> h, also unfolded beneath a pattern
+        let field = |x: &str, pattern: Pattern| Pattern::Record(vec![(Name::new(x.to_string(), Context::ValueContext), pattern)]);
+        let binder = Pattern::Binder(Name::new("h".to_string(), Context::ValueContext));
+        let second = Ty::Match(Box::new(ones.clone()), vec![(field("tail", field("head", binder)), rigid("h"))]);
+        assert_eq!(normaliser.normalise(&second), Ok(int(1)));
+
+        // without the hint the definition is unfolded for good and runs out of fuel
+        assert!(Normaliser::new(&globals, &[]).with_fuel(50).normalise(&ones).is_err());
+    }
+
+    #[test]
+    fn recursion() {
+        // plus = m n ~> m | Z -> n | S k -> S (plus k n)
+        let constructor = |name: &str, payload: Pattern| {
+            Pattern::Constructor(Name::new(name.to_string(), Context::Constructor), Box::new(payload))
+        };
+        let k = Pattern::Binder(Name::new("k".to_string(), Context::ValueContext));
+        let successor = |x: Ty| Ty::Either("S".to_string(), Box::new(x));
+        let zero = Ty::Either("Z".to_string(), Box::new(Ty::Record(vec![])));
+        let plus = |m: Ty, n: Ty| apply(apply(Ty::Global("plus".to_string()), m), n);
+        let mut globals = HashMap::new();
+        globals.insert("plus".to_string(), lambda("m", lambda("n", Ty::Match(Box::new(rigid("m")), vec![
+            (constructor("Z", Pattern::Wildcard), rigid("n")),
+            (constructor("S", k), successor(plus(rigid("k"), rigid("n")))),
+        ]))));
+        let normaliser = Normaliser::new(&globals, &[]);
+        assert_eq!(normaliser.normalise(&plus(successor(zero.clone()), zero.clone())), Ok(successor(zero.clone())));
+        // stuck on m, the call is kept instead of its match, whose arms would unfold plus again
+        assert_eq!(normaliser.normalise(&plus(rigid("m"), zero.clone())), Ok(plus(rigid("m"), zero.clone())));
+        let deeper = plus(successor(rigid("m")), zero.clone());
+        assert_eq!(normaliser.normalise(&deeper), Ok(successor(plus(rigid("m"), zero.clone()))));
+        assert_eq!(normaliser.equal(&deeper, &successor(rigid("m"))), Ok(false));
+    }
+
+    #[test]
+    fn fuel() {
+        // omega = (x ~> x x) (x ~> x x)
+        let globals = HashMap::new();
+        let half = lambda("x", apply(rigid("x"), rigid("x")));
+        let omega = apply(half.clone(), half);
+        let error = Normaliser::new(&globals, &[]).normalise(&omega).unwrap_err();
+        assert!(error.contains("gave up normalising"));
+
+        // loop = loop
+        let mut globals = HashMap::new();
+        globals.insert("loop".to_string(), Ty::Global("loop".to_string()));
+        let normaliser = Normaliser::new(&globals, &[]).with_fuel(50);
+        assert!(normaliser.equal(&Ty::Global("loop".to_string()), &int(0)).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use crate::compiling_process::static_analysis::type_inference::print_span;
+use crate::inner_representation::abstract_syntax_tree::{Expr, Type, Value};
+
+// the type definitions of a program, with their parameters and bodies
+pub type Definitions<'a> = HashMap<&'a str, (&'a [String], &'a Type)>;
+
+fn value_mentions(value: &Value, name: &str) -> bool {
+    match value {
+        Value::Var(x) => x.get_name() == name,
+        Value::Type(typ) => mentions(typ, name),
+        Value::Tuple(items) => items.iter().any(|x| value_mentions(x, name)),
+        Value::Record(fields) => fields.iter().any(|(_, x)| value_mentions(x, name)),
+        Value::Either(_, x) | Value::Implicit(x) => value_mentions(x, name),
+        Value::Match(scrutinee, arms) => {
+            value_mentions(scrutinee, name) || arms.iter().any(|(_, x)| value_mentions(x, name))
+        },
+        Value::Function(parameters, body) => {
+            let Expr(lets, value) = &**body;
+            parameters.iter().filter_map(|(_, x)| x.as_ref()).any(|x| mentions(x, name))
+                || lets.iter().any(|x| value_mentions(&x.1, name))
+                || value_mentions(value, name)
+        },
+        Value::Application(function, arguments) => {
+            value_mentions(function, name) || arguments.iter().any(|x| value_mentions(x, name))
+        },
+        Value::Constant(_, _) | Value::Hole(_) | Value::Refl => false,
+    }
+}
+
+fn mentions(typ: &Type, name: &str) -> bool {
+    match typ {
+        Type::Product(fields) | Type::Class(fields) => fields.iter().any(|(_, x)| mentions(x, name)),
+        Type::OpenProduct(fields, tail) => tail.get_name() == name || fields.iter().any(|(_, x)| mentions(x, name)),
+        Type::CoProduct(constructors) => constructors.iter().any(|(_, x)| mentions(x, name)),
+        Type::OpenCoProduct(constructors, tail) => {
+            tail.get_name() == name || constructors.iter().any(|(_, x)| mentions(x, name))
+        },
+        Type::Function(from, to) | Type::Pi(_, _, from, to) => mentions(from, name) || mentions(to, name),
+        Type::Application(function, arguments) => {
+            mentions(function, name) || arguments.iter().any(|x| value_mentions(x, name))
+        },
+        Type::TypeVar(x) => x.get_name() == name,
+        Type::Equal(left, right) => value_mentions(left, name) || value_mentions(right, name),
+        Type::Codata(body) => mentions(body, name),
+        Type::Universe(_) | Type::Atomic(_) | Type::Hole(_) => false,
+    }
+}
+
+struct Positivity<'a, 'b> {
+    definitions: &'b Definitions<'a>,
+    // the type definition being checked, or a parameter of the definition it is passed to
+    target: &'b str,
+    parameter: bool,
+    // definitions already looked into, with the name looked for there
+    visited: Vec<(String, String)>,
+}
+
+impl<'a, 'b> Positivity<'a, 'b> {
+    // the definitions a type refers to are looked through, parameters are only seen where they are written
+    fn reaches(&self, typ: &Type, seen: &mut Vec<&'a str>) -> bool {
+        if mentions(typ, self.target) {
+            return true;
+        }
+        if self.parameter {
+            return false;
+        }
+        for (name, (_, body)) in self.definitions.iter() {
+            if !seen.contains(name) && mentions(typ, name) {
+                seen.push(name);
+                if self.reaches(body, seen) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn value_reaches(&self, value: &Value) -> bool {
+        match value {
+            Value::Type(typ) => self.reaches(typ, &mut vec![]),
+            Value::Var(name) => self.reaches(&Type::TypeVar(name.clone()), &mut vec![]),
+            other => value_mentions(other, self.target),
+        }
+    }
+
+    fn negative(&self, typ: &Type, around: &Type, inside: &str) -> String {
+        format!(
+            "{} is not strictly positive, it occurs to the left of the arrow in {} {}{}",
+            self.target, around, print_span(typ.get_span()), inside
+        )
+    }
+
+    // looks for the target in the body of another definition, `inside` says where the message is about
+    fn through(&mut self, definition: &str, inside: &str) -> Result<(), String> {
+        let key = (definition.to_string(), self.target.to_string());
+        if self.visited.contains(&key) || definition == self.target {
+            return Ok(());
+        }
+        self.visited.push(key);
+        let body = self.definitions[definition].1;
+        self.strictly_positive(body, &format!("{}, inside {}", inside, definition))
+    }
+
+    fn strictly_positive(&mut self, typ: &Type, inside: &str) -> Result<(), String> {
+        match typ {
+            Type::Product(fields) | Type::Class(fields) | Type::OpenProduct(fields, _) => {
+                fields.iter().try_for_each(|(_, x)| self.strictly_positive(x, inside))
+            },
+            Type::CoProduct(constructors) | Type::OpenCoProduct(constructors, _) => {
+                constructors.iter().try_for_each(|(_, x)| self.strictly_positive(x, inside))
+            },
+            Type::Function(from, to) | Type::Pi(_, _, from, to) => {
+                if self.reaches(from, &mut vec![]) {
+                    return Err(self.negative(from, typ, inside));
+                }
+                self.strictly_positive(to, inside)
+            },
+            // coinductive types have to be strictly positive as well
+            Type::Codata(body) => self.strictly_positive(body, inside),
+            Type::TypeVar(name) if !self.parameter && self.definitions.contains_key(name.get_name()) => {
+                self.through(name.get_name(), inside)
+            },
+            Type::Application(function, arguments) => {
+                let name = match &**function {
+                    Type::TypeVar(name) if self.definitions.contains_key(name.get_name()) => name.get_name(),
+                    _ => return match arguments.iter().find(|x| self.value_reaches(x)) {
+                        Some(_) => Err(format!(
+                            "{} is not strictly positive, it is passed to {}, which may use it negatively {}{}",
+                            self.target, function, print_span(typ.get_span()), inside
+                        )),
+                        None => self.strictly_positive(function, inside),
+                    },
+                };
+                // nested inductive types are fine when the parameters they are given are themselves used positively
+                let (parameters, body) = self.definitions[name];
+                for (parameter, argument) in parameters.iter().zip(arguments) {
+                    let key = (name.to_string(), parameter.clone());
+                    if !self.value_reaches(argument) || self.visited.contains(&key) {
+                        continue;
+                    }
+                    self.visited.push(key);
+                    let mut nested = Positivity {
+                        definitions: self.definitions,
+                        target: parameter,
+                        parameter: true,
+                        visited: self.visited.clone(),
+                    };
+                    nested.strictly_positive(body, "").map_err(|error| format!(
+                        "{} is not strictly positive, it is passed to {} {}{}: {}",
+                        self.target, name, print_span(typ.get_span()), inside, error
+                    ))?;
+                }
+                // the arguments themselves must be positive, `Wrap (Bad -> Int)` is wrong whatever Wrap does with it
+                for argument in arguments {
+                    if let Value::Type(argument) = argument {
+                        self.strictly_positive(argument, inside)?;
+                    }
+                }
+                if self.parameter {
+                    return Ok(());
+                }
+                self.through(name, inside)
+            },
+            Type::Equal(left, right) => match [left, right].iter().find(|x| self.value_reaches(x)) {
+                Some(_) => Err(format!(
+                    "{} is not strictly positive, it occurs in the equation {} {}{}",
+                    self.target, typ, print_span(typ.get_span()), inside
+                )),
+                None => Ok(()),
+            },
+            Type::TypeVar(_) | Type::Universe(_) | Type::Atomic(_) | Type::Hole(_) => Ok(()),
+        }
+    }
+}
+
+// a type definition may only refer to itself where it is not to the left of an arrow, also through the
+// definitions it uses, otherwise `$Bad = Mk (Bad -> Int)` would give a value of every type
+pub fn check_positivity(name: &str, definitions: &Definitions) -> Result<(), String> {
+    let mut positivity = Positivity {
+        definitions,
+        target: name,
+        parameter: false,
+        visited: vec![(name.to_string(), name.to_string())],
+    };
+    positivity.strictly_positive(definitions[name].1, "")
+}
+
+#[cfg(test)]
+mod positivity_tests {
+    use crate::compiling_process::static_analysis::type_inference::as_type_definition;
+    use crate::compiling_process::translating::testing::{bare, program};
+    use crate::inner_representation::abstract_syntax_tree::Let;
+    use super::{check_positivity, Definitions};
+
+    fn check(program: &[Let], name: &str) -> Result<(), String> {
+        let definitions: Vec<(&str, (Vec<String>, _))> = program
+            .iter()
+            .filter_map(|Let(x, value, _)| as_type_definition(value).map(|definition| (x.get_name(), definition)))
+            .collect();
+        let definitions: Definitions = definitions.iter().map(|(x, (parameters, body))| (*x, (&parameters[..], *body))).collect();
+        check_positivity(name, &definitions)
+    }
+
+    fn checked(text: &str, name: &str) -> Result<(), String> {
+        check(&bare(text), name)
+    }
+
+    #[test]
+    fn unit_tests() {
+        assert_eq!(check(&program(""), "List"), Ok(()));
+
+        let error = checked("$Bad = Mk (Bad -> Int);", "Bad").unwrap_err();
+        println!("{}", error);
+        assert_eq!(error, "Bad is not strictly positive, it occurs to the left of the arrow in Bad -> Int at 1:12");
+
+        // to the right of an arrow is fine
+        assert_eq!(checked("$Stream = Next (Int -> Stream);", "Stream"), Ok(()));
+
+        // left of an arrow left of an arrow is still rejected
+        assert!(checked("$Twice = Mk ((Twice -> Int) -> Int);", "Twice").is_err());
+    }
+
+    #[test]
+    fn through_other_definitions() {
+        // lists use their parameter positively
+        assert_eq!(check(&program("$Rose = Node (List Rose);"), "Rose"), Ok(()));
+
+        let error = checked("$Pred = A ~> Pred (A -> Int); $Wrong = Wrap (Pred Wrong);", "Wrong").unwrap_err();
+        println!("{}", error);
+        assert!(error.starts_with("Wrong is not strictly positive, it is passed to Pred"));
+        assert!(error.contains("A is not strictly positive"));
+
+        let error = checked("$Even = Zero . + Next Odd; $Odd = Succ (Even -> Int);", "Even").unwrap_err();
+        println!("{}", error);
+        assert!(error.ends_with("inside Odd"));
+    }
+
+    #[test]
+    fn negative_arguments() {
+        // the parameter is used positively but the argument given for it is not
+        let error = checked("$Wrap = A ~> MkW A; $Bad = Mk (Wrap (Bad -> !));", "Bad").unwrap_err();
+        println!("{}", error);
+        assert!(error.starts_with("Bad is not strictly positive, it occurs to the left of the arrow in Bad -> !"));
+
+        let error = check(&program("$Bad = Mk (List (Bad -> Int));"), "Bad").unwrap_err();
+        println!("{}", error);
+        assert!(error.starts_with("Bad is not strictly positive, it occurs to the left of the arrow in Bad -> Int"));
+
+        // also when the parameter is not used at all
+        let error = checked("$E = A B ~> MkE A; $Bad = Mk (E Int (Bad -> Int));", "Bad").unwrap_err();
+        println!("{}", error);
+        assert!(error.starts_with("Bad is not strictly positive, it occurs to the left of the arrow in Bad -> Int"));
+    }
+}
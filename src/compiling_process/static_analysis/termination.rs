@@ -0,0 +1,559 @@
+use crate::compiling_process::static_analysis::type_inference::{as_type_definition, print_span};
+use crate::inner_representation::abstract_syntax_tree::{CompilerCommand, Expr, Let, Name, Pattern, Span, Value};
+
+// how an argument of a call compares to a parameter of the caller
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    Smaller,
+    Same,
+}
+
+// the parameter of the caller a local is known to be no bigger than
+type Size = Option<(usize, Change)>;
+
+// what is known of the size of a matched value, a tuple written as the scrutinee is known item by item
+enum Scrutinee {
+    Whole(Size),
+    Items(Vec<Scrutinee>),
+}
+
+// a parameter of the caller, an argument of the callee and how they compare
+type Arc = (usize, usize, Change);
+
+// a call, or a path of calls, with arcs from the parameters of its first caller to the arguments of its last callee
+#[derive(Clone, Debug)]
+struct Graph {
+    from: usize,
+    to: usize,
+    arcs: Vec<Arc>,
+    // the call the path starts with
+    site: usize,
+}
+
+impl Graph {
+    fn same(&self, another: &Graph) -> bool {
+        self.from == another.from && self.to == another.to && self.arcs == another.arcs
+    }
+
+    fn compose(&self, next: &Graph) -> Graph {
+        let mut arcs = vec![];
+        for &(x, y, change) in &self.arcs {
+            for &(_, z, another) in next.arcs.iter().filter(|(x, _, _)| *x == y) {
+                arcs.push((x, z, change.min(another)));
+            }
+        }
+        Graph {
+            from: self.from,
+            to: next.to,
+            arcs: strongest(arcs),
+            site: self.site,
+        }
+    }
+
+    fn decreases(&self) -> bool {
+        self.arcs.iter().any(|&(x, y, change)| x == y && change == Change::Smaller)
+    }
+}
+
+// one arc for each pair of parameter and argument, a smaller one wins
+fn strongest(mut arcs: Vec<Arc>) -> Vec<Arc> {
+    arcs.sort();
+    arcs.dedup_by(|x, y| x.0 == y.0 && x.1 == y.1);
+    arcs
+}
+
+// a name bound in several alternatives is only as small as in the biggest of them
+fn weaker(x: Size, y: Size) -> Size {
+    match (x, y) {
+        (Some((x, change)), Some((y, another))) if x == y => Some((x, change.max(another))),
+        _ => None,
+    }
+}
+
+// the definitions come first in `names`, local functions calling themselves are added after them as they are found
+struct Calls<'a> {
+    names: Vec<&'a str>,
+    caller: usize,
+    locals: Vec<(&'a str, Size)>,
+    // the local functions in scope, with how many locals there were when they were bound
+    functions: Vec<(&'a str, usize, usize)>,
+    // where the parameters of the caller start in `locals`
+    parameters: usize,
+    found: Vec<(usize, usize, Vec<Arc>, Option<Span>)>,
+}
+
+impl<'a> Calls<'a> {
+    fn size(&self, name: &str) -> Size {
+        self.locals.iter().rev().find(|(x, _)| *x == name).and_then(|(_, size)| *size)
+    }
+
+    // the local function the name stands for, unless a local bound after it hides it
+    fn function(&self, name: &str) -> Option<usize> {
+        let (_, function, depth) = self.functions.iter().rev().find(|(x, _, _)| *x == name)?;
+        match self.locals.iter().rposition(|(x, _)| *x == name) {
+            Some(local) if local >= *depth => None,
+            _ => Some(*function),
+        }
+    }
+
+    // a definition used without arguments may be called with anything
+    fn call(&mut self, name: &str, arguments: &[&Value], at: Option<Span>) {
+        let callee = match self.function(name) {
+            Some(function) => function,
+            None if self.locals.iter().any(|(x, _)| *x == name) => return,
+            None => match self.names.iter().position(|x| *x == name) {
+                Some(callee) => callee,
+                None => return,
+            },
+        };
+        let mut arcs = vec![];
+        for (i, argument) in arguments.iter().enumerate() {
+            if let Value::Var(local) = argument {
+                if let Some((parameter, change)) = self.size(local.get_name()) {
+                    arcs.push((parameter, i, change));
+                }
+            }
+        }
+        self.found.push((self.caller, callee, arcs, at));
+    }
+
+    // a local function that sees itself is a caller of its own, the locals around it say nothing of its parameters
+    fn local_function(&mut self, name: &'a str, value: &'a Value) {
+        let function = self.names.len();
+        self.names.push(name);
+        self.functions.push((name, function, self.locals.len()));
+        let unsized_locals = self.locals.iter().map(|(x, _)| (*x, None)).collect();
+        let outer = std::mem::replace(&mut self.locals, unsized_locals);
+        let caller = std::mem::replace(&mut self.caller, function);
+        let parameters = std::mem::replace(&mut self.parameters, self.locals.len());
+        self.definition(value);
+        self.locals = outer;
+        self.caller = caller;
+        self.parameters = parameters;
+    }
+
+    // curried parameters count as parameters of the definition
+    fn definition(&mut self, value: &'a Value) {
+        match value {
+            Value::Function(parameters, body) => {
+                for (name, _) in parameters {
+                    let size = Some((self.locals.len() - self.parameters, Change::Same));
+                    self.locals.push((name.get_name(), size));
+                }
+                match &**body {
+                    Expr(lets, value) if lets.is_empty() => self.definition(value),
+                    body => self.expr(body),
+                }
+            },
+            other => self.value(other),
+        }
+    }
+
+    fn expr(&mut self, Expr(lets, value): &'a Expr) {
+        let (depth, functions) = (self.locals.len(), self.functions.len());
+        for local in lets {
+            let Let(name, value, _) = local;
+            if local.is_recursive() {
+                self.local_function(name.get_name(), value);
+            } else {
+                self.value(value);
+                self.locals.push((name.get_name(), None));
+            }
+        }
+        self.value(value);
+        self.locals.truncate(depth);
+        self.functions.truncate(functions);
+    }
+
+    fn value(&mut self, value: &'a Value) {
+        match value {
+            Value::Var(name) => self.call(name.get_name(), &[], value.get_span()),
+            Value::Application(function, arguments) => {
+                match &**function {
+                    Value::Var(name) => {
+                        let explicit: Vec<&Value> = arguments.iter().filter(|x| !matches!(x, Value::Implicit(_))).collect();
+                        self.call(name.get_name(), &explicit, value.get_span());
+                    },
+                    other => self.value(other),
+                }
+                arguments.iter().for_each(|x| self.value(x));
+            },
+            Value::Tuple(items) => items.iter().for_each(|x| self.value(x)),
+            Value::Record(fields) => fields.iter().for_each(|(_, x)| self.value(x)),
+            Value::Either(_, payload) | Value::Implicit(payload) => self.value(payload),
+            Value::Match(scrutinee, arms) => {
+                self.value(scrutinee);
+                let size = self.scrutinee(scrutinee);
+                for (pattern, body) in arms {
+                    let depth = self.locals.len();
+                    self.bind(pattern, &size, false);
+                    self.value(body);
+                    self.locals.truncate(depth);
+                }
+            },
+            Value::Function(parameters, body) => {
+                let depth = self.locals.len();
+                self.locals.extend(parameters.iter().map(|(name, _)| (name.get_name(), None)));
+                self.expr(body);
+                self.locals.truncate(depth);
+            },
+            Value::Constant(_, _) | Value::Type(_) | Value::Hole(_) | Value::Refl => (),
+        }
+    }
+
+    fn scrutinee(&self, value: &Value) -> Scrutinee {
+        match value {
+            Value::Var(name) => Scrutinee::Whole(self.size(name.get_name())),
+            Value::Tuple(items) => Scrutinee::Items(items.iter().map(|x| self.scrutinee(x)).collect()),
+            _ => Scrutinee::Whole(None),
+        }
+    }
+
+    // names bound inside a constructor, tuple or record are strictly smaller than the matched value,
+    // the items of a tuple pattern matching a written tuple are as big as the items of that tuple
+    fn bind(&mut self, pattern: &'a Pattern, scrutinee: &Scrutinee, beneath: bool) {
+        let size = match scrutinee {
+            Scrutinee::Whole(size) => *size,
+            Scrutinee::Items(_) => None,
+        };
+        match pattern {
+            Pattern::Binder(name) => {
+                let size = size.map(|(parameter, change)| (parameter, if beneath { Change::Smaller } else { change }));
+                self.locals.push((name.get_name(), size));
+            },
+            Pattern::Tuple(items) => match scrutinee {
+                Scrutinee::Items(scrutinees) if scrutinees.len() == items.len() => {
+                    items.iter().zip(scrutinees).for_each(|(x, scrutinee)| self.bind(x, scrutinee, beneath))
+                },
+                _ => items.iter().for_each(|x| self.bind(x, &Scrutinee::Whole(size), true)),
+            },
+            Pattern::Constructor(_, inner) => self.bind(inner, &Scrutinee::Whole(size), true),
+            Pattern::Record(fields) => fields.iter().for_each(|(_, x)| self.bind(x, &Scrutinee::Whole(size), true)),
+            Pattern::Or(alternatives) => {
+                let depth = self.locals.len();
+                alternatives.iter().for_each(|x| self.bind(x, scrutinee, beneath));
+                let bound: Vec<(&str, Size)> = self.locals.drain(depth..).collect();
+                for name in pattern.binders() {
+                    let mut sizes = bound.iter().filter(|(x, _)| *x == name.get_name()).map(|(_, size)| *size);
+                    let first = sizes.next().flatten();
+                    self.locals.push((name.get_name(), sizes.fold(first, weaker)));
+                }
+            },
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Refl => (),
+        }
+    }
+}
+
+// where a body puts the value it is looking at
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Guard {
+    // given back as it is
+    Unguarded,
+    // beneath a constructor, tuple or record, only unfolded when someone looks inside
+    Guarded,
+    // passed to a function or matched on, which may look as deep as it likes
+    Consumed,
+}
+
+struct Guardedness<'a> {
+    caller: &'a str,
+    // the corecursive definitions the caller may end up being called from again
+    cycle: &'a [&'a str],
+    locals: Vec<&'a str>,
+}
+
+impl<'a> Guardedness<'a> {
+    fn call(&self, name: &Name, guard: Guard) -> Result<(), String> {
+        let corecursive = self.cycle.contains(&name.get_name()) && !self.locals.contains(&name.get_name());
+        if !corecursive || guard == Guard::Guarded {
+            return Ok(());
+        }
+        Err(format!(
+            "in {}: the corecursive call to {} {} is not guarded by a constructor",
+            self.caller, name, print_span(name.get_span())
+        ))
+    }
+
+    fn definition(&mut self, value: &'a Value) -> Result<(), String> {
+        match value {
+            Value::Function(parameters, body) => {
+                self.locals.extend(parameters.iter().map(|(name, _)| name.get_name()));
+                match &**body {
+                    Expr(lets, value) if lets.is_empty() => self.definition(value),
+                    body => self.expr(body, Guard::Unguarded),
+                }
+            },
+            other => self.value(other, Guard::Unguarded),
+        }
+    }
+
+    fn expr(&mut self, Expr(lets, value): &'a Expr, guard: Guard) -> Result<(), String> {
+        let depth = self.locals.len();
+        for local in lets {
+            let Let(name, value, _) = local;
+            if local.is_recursive() {
+                self.locals.push(name.get_name());
+            }
+            self.value(value, Guard::Consumed)?;
+            if !local.is_recursive() {
+                self.locals.push(name.get_name());
+            }
+        }
+        self.value(value, guard)?;
+        self.locals.truncate(depth);
+        Ok(())
+    }
+
+    fn value(&mut self, value: &'a Value, guard: Guard) -> Result<(), String> {
+        let beneath = if guard == Guard::Unguarded { Guard::Guarded } else { guard };
+        match value {
+            Value::Var(name) => self.call(name, guard),
+            Value::Application(function, arguments) => {
+                match &**function {
+                    Value::Var(name) => self.call(name, guard)?,
+                    other => self.value(other, Guard::Consumed)?,
+                }
+                arguments.iter().try_for_each(|x| self.value(x, Guard::Consumed))
+            },
+            Value::Tuple(items) => items.iter().try_for_each(|x| self.value(x, beneath)),
+            Value::Record(fields) => fields.iter().try_for_each(|(_, x)| self.value(x, beneath)),
+            Value::Either(_, payload) => self.value(payload, beneath),
+            Value::Implicit(payload) => self.value(payload, Guard::Consumed),
+            Value::Match(scrutinee, arms) => {
+                self.value(scrutinee, Guard::Consumed)?;
+                for (pattern, body) in arms {
+                    let depth = self.locals.len();
+                    self.locals.extend(pattern.binders().into_iter().map(|x| x.get_name()));
+                    self.value(body, guard)?;
+                    self.locals.truncate(depth);
+                }
+                Ok(())
+            },
+            Value::Function(parameters, body) => {
+                let depth = self.locals.len();
+                self.locals.extend(parameters.iter().map(|(name, _)| name.get_name()));
+                self.expr(body, guard)?;
+                self.locals.truncate(depth);
+                Ok(())
+            },
+            Value::Constant(_, _) | Value::Type(_) | Value::Hole(_) | Value::Refl => Ok(()),
+        }
+    }
+}
+
+// the definitions a `Terminating` pragma vouches for
+pub fn terminating(commands: &[CompilerCommand]) -> Vec<String> {
+    commands
+        .iter()
+        .filter_map(|command| match command {
+            CompilerCommand::Terminating(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// size-change termination: every way a definition can end up calling itself again must make
+// one of its arguments structurally smaller, the calls of trusted definitions are not looked at;
+// corecursive definitions may call each other forever, as long as every such call is guarded
+pub fn check_termination(program: &[Let], trusted: &[String], corecursive: &[String]) -> Result<(), String> {
+    let definitions: Vec<&Let> = program.iter().filter(|Let(_, value, _)| as_type_definition(value).is_none()).collect();
+    let mut names: Vec<&str> = definitions.iter().map(|Let(name, _, _)| name.get_name()).collect();
+    let mut found = vec![];
+    for (caller, Let(name, value, _)) in definitions.iter().enumerate() {
+        let mut calls = Calls {
+            names,
+            caller,
+            locals: vec![],
+            functions: vec![],
+            parameters: 0,
+            found: vec![],
+        };
+        if !trusted.iter().any(|x| x == name.get_name()) {
+            calls.definition(value);
+        }
+        names = calls.names;
+        found.push(calls.found);
+    }
+
+    // the definitions each one may end up calling, also from inside its local functions
+    let reaches: Vec<Vec<bool>> = (0..definitions.len())
+        .map(|start| {
+            let mut reached = vec![false; definitions.len()];
+            let mut pending = vec![start];
+            while let Some(caller) = pending.pop() {
+                for &(_, callee, _, _) in &found[caller] {
+                    if callee < definitions.len() && !reached[callee] {
+                        reached[callee] = true;
+                        pending.push(callee);
+                    }
+                }
+            }
+            reached
+        })
+        .collect();
+
+    let mut sites = vec![];
+    let mut calls = vec![];
+    for (caller, Let(name, value, _)) in definitions.iter().enumerate() {
+        let mut found = std::mem::take(&mut found[caller]);
+        if corecursive.iter().any(|x| x == name.get_name()) && !trusted.iter().any(|x| x == name.get_name()) {
+            // only calls that may come back to the caller have to be guarded, others give their streams as they are
+            let cycle: Vec<&str> = (0..definitions.len())
+                .filter(|&x| x == caller || reaches[caller][x] && reaches[x][caller])
+                .map(|x| names[x])
+                .filter(|x| corecursive.iter().any(|y| y == x))
+                .collect();
+            let mut guardedness = Guardedness {
+                caller: name.get_name(),
+                cycle: &cycle,
+                locals: vec![],
+            };
+            guardedness.definition(value)?;
+            found.retain(|(_, callee, _, _)| *callee >= definitions.len() || !cycle.contains(&names[*callee]));
+        }
+        for (caller, callee, arcs, at) in found {
+            calls.push(Graph {
+                from: caller,
+                to: callee,
+                arcs: strongest(arcs),
+                site: sites.len(),
+            });
+            sites.push((caller, callee, at));
+        }
+    }
+
+    // every path of calls, as the graphs of the paths differ
+    let mut paths = calls.clone();
+    let mut index = 0;
+    while index < paths.len() {
+        let end = paths[index].to;
+        for call in calls.iter().filter(|x| x.from == end) {
+            let path = paths[index].compose(call);
+            if !paths.iter().any(|x| x.same(&path)) {
+                paths.push(path);
+            }
+        }
+        index += 1;
+    }
+
+    for path in &paths {
+        if path.from == path.to && path.compose(path).same(path) && !path.decreases() {
+            let (caller, callee, at) = sites[path.site];
+            return Err(format!(
+                "in {}: the call to {} {} may not terminate, no argument gets structurally smaller",
+                names[caller], names[callee], print_span(at)
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod termination_tests {
+    use crate::compiling_process::translating::testing::bare as program;
+    use crate::inner_representation::abstract_syntax_tree::CompilerCommand;
+    use super::{check_termination, terminating};
+
+    fn checked(text: &str) -> Result<(), String> {
+        check_termination(&program(text), &[], &[])
+    }
+
+    fn corecursive(text: &str, names: &[&str]) -> Result<(), String> {
+        check_termination(&program(text), &[], &names.iter().map(|x| x.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn unit_tests() {
+        // structural recursion on the first argument
+        assert_eq!(checked("$length = a b ~> a | Cons (_, rest) -> length rest b | _ -> a;"), Ok(()));
+
+        // the same argument again
+        let looping = "$loop = a b ~> a | Cons (_, rest) -> loop a b | _ -> a;";
+        let error = checked(looping).unwrap_err();
+        println!("{}", error);
+        assert_eq!(error, "in loop: the call to loop at 1:38 may not terminate, no argument gets structurally smaller");
+
+        // the arguments trade places, the first one gets smaller every other call
+        assert_eq!(checked("$swapped = a b ~> a | Cons (_, rest) -> swapped b rest | _ -> a;"), Ok(()));
+        assert!(checked("$swapped = a b ~> a | Cons (_, rest) -> swapped b a | _ -> a;").is_err());
+
+        // trusted by a pragma
+        let trusted = terminating(&[CompilerCommand::Terminating("loop".to_string())]);
+        assert_eq!(check_termination(&program(looping), &trusted, &[]), Ok(()));
+    }
+
+    #[test]
+    fn tuples() {
+        // ack matches on both its arguments at once, the first gets smaller or stays and the second gets smaller
+        let ackermann = |last: &str| checked(&format!("
+            $Nat = Zero . + Succ Nat;
+            $ack: Nat -> Nat -> Nat = m n ~> (m, n)
+                | (Zero, _) -> Succ n
+                | (Succ p, Zero) -> ack p (Succ Zero)
+                | (Succ p, Succ q) -> {};
+        ", last));
+        assert_eq!(ackermann("ack p (ack m q)"), Ok(()));
+        let error = ackermann("ack m (ack m n)").unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("the call to ack at 6:39 may not terminate"));
+
+        // a tuple item bound whole is as big as what was put there
+        assert_eq!(checked("$f = a b ~> (a, b) | (Cons (_, rest), x) -> f rest x | _ -> a;"), Ok(()));
+        assert!(checked("$f = a b ~> (b, a) | (Cons (_, rest), x) -> f x rest | _ -> a;").is_ok());
+        assert!(checked("$f = a b ~> (a, b) | (x, Cons (_, rest)) -> f x rest | _ -> a;").is_ok());
+        assert!(checked("$f = a b ~> (a, b) | (x, y) -> f x y;").is_err());
+    }
+
+    #[test]
+    fn recursive_lets() {
+        // a local function is checked like a definition, what it captures does not change between its calls
+        let total = "$total = xs ~> $go = ys acc ~> ys | Cons (y, rest) -> go rest (#add acc y) | _ -> acc; go xs 0;";
+        assert_eq!(checked(total), Ok(()));
+        let scaled = "$scaled = k xs ~> $go = ys ~> ys | Cons (y, rest) -> Cons (#mul k y, go rest) | _ -> ys; go xs;";
+        assert_eq!(checked(scaled), Ok(()));
+        let error = checked("$count = n ~> $go = m ~> #add 1 (go (#sub m 1)); go n;").unwrap_err();
+        println!("{}", error);
+        assert!(error.starts_with("in go: the call to go at 1:34 may not terminate"));
+        // a local hiding a definition is not a call to it
+        assert_eq!(checked("$go = xs ~> xs; $main = ys ~> $go = zs ~> zs; go ys;"), Ok(()));
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        let even = "$even = a b ~> a | Cons (_, rest) -> odd rest b | _ -> a;";
+        assert_eq!(checked(&format!("{} $odd = a b ~> even a b;", even)), Ok(()));
+
+        let error = checked(&format!("{} $odd = a b ~> even b b;", even)).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("the call to odd at 1:38"));
+
+        // a definition used as a value may be called with anything
+        assert!(checked("$escaping = a ~> apply escaping;").is_err());
+    }
+
+    #[test]
+    fn guarded_corecursion() {
+        let from = "$from = n ~> {head = n, tail = from n};";
+        assert_eq!(corecursive(from, &["from"]), Ok(()));
+        assert!(checked(from).is_err());
+
+        let error = corecursive("$stuck = n ~> stuck n;", &["stuck"]).unwrap_err();
+        println!("{}", error);
+        assert_eq!(error, "in stuck: the corecursive call to stuck at 1:15 is not guarded by a constructor");
+
+        // map may look at all of nats before giving anything back
+        let nats = "$nats = {head = 0, tail = map nats}; $map = s ~> {head = 0, tail = map s};";
+        let error = corecursive(nats, &["nats", "map"]).unwrap_err();
+        assert!(error.starts_with("in nats: the corecursive call to nats"));
+
+        // calls that never come back may give their streams as they are
+        let nats = "$nats = from 0; $doubled = smap double (from 0);";
+        let from = "$from = n ~> {head = n, tail = from n}; $smap = f s ~> {head = f s, tail = smap f s};";
+        assert_eq!(corecursive(&format!("{} {}", from, nats), &["from", "smap", "nats", "doubled"]), Ok(()));
+
+        // peek is no constructor, so the two go round unguarded
+        let error = corecursive("$from = n ~> {head = peek n, tail = from n}; $peek = n ~> from n;", &["from"]).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("may not terminate"));
+    }
+}
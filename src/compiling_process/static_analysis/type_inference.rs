@@ -0,0 +1,2858 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::compiling_process::primitives::{primitive, Primitive};
+use crate::compiling_process::static_analysis::classes::methods;
+use crate::compiling_process::static_analysis::exhaustiveness::{check_exhaustiveness, TypeView};
+use crate::compiling_process::static_analysis::holes::{holes, Goal};
+use crate::compiling_process::static_analysis::normalising::{Normaliser, DEFAULT_FUEL};
+use crate::compiling_process::static_analysis::positivity::{check_positivity, Definitions};
+use crate::compiling_process::static_analysis::termination::check_termination;
+use crate::inner_representation::abstract_syntax_tree::{
+    is_positional, AtomicType, AtomicValue, Context, Expr, Let, Level, Name, Pattern, Span, Type, Value, Visibility,
+};
+
+// types, and the values they depend on
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ty {
+    Meta(usize),
+    Rigid(String),
+    Named(String, Vec<Ty>),
+    Function(Box<Ty>, Box<Ty>),
+    Pi(Visibility, String, Box<Ty>, Box<Ty>),
+    Product(Vec<(String, Ty)>),
+    CoProduct(Vec<(String, Ty)>),
+    // rows with a tail, a meta or rigid row variable, standing for the entries not listed
+    OpenProduct(Vec<(String, Ty)>, Box<Ty>),
+    OpenCoProduct(Vec<(String, Ty)>, Box<Ty>),
+    Universe(UniverseLevel),
+    Atomic(AtomicType),
+    Global(String),
+    Lambda(String, Box<Ty>),
+    Application(Box<Ty>, Box<Ty>),
+    Record(Vec<(String, Ty)>),
+    Either(String, Box<Ty>),
+    Match(Box<Ty>, Vec<(Pattern, Ty)>),
+    Literal(AtomicValue),
+    Equal(Box<Ty>, Box<Ty>),
+    Refl,
+}
+
+impl Ty {
+    fn components(&self) -> Vec<&Ty> {
+        match self {
+            Ty::Named(_, arguments) => arguments.iter().collect(),
+            Ty::Function(from, to) | Ty::Pi(_, _, from, to) | Ty::Application(from, to) | Ty::Equal(from, to) => {
+                vec![from, to]
+            },
+            Ty::Product(fields) | Ty::CoProduct(fields) | Ty::Record(fields) => fields.iter().map(|(_, x)| x).collect(),
+            Ty::OpenProduct(fields, tail) | Ty::OpenCoProduct(fields, tail) => {
+                fields.iter().map(|(_, x)| x).chain(vec![&**tail]).collect()
+            },
+            Ty::Lambda(_, body) | Ty::Either(_, body) => vec![body],
+            Ty::Match(scrutinee, arms) => {
+                let mut result: Vec<&Ty> = vec![scrutinee];
+                result.extend(arms.iter().map(|(_, x)| x));
+                result
+            },
+            Ty::Meta(_) | Ty::Rigid(_) | Ty::Universe(_) | Ty::Atomic(_) | Ty::Global(_) | Ty::Literal(_) | Ty::Refl => {
+                vec![]
+            },
+        }
+    }
+
+    // rebuilds the type with `f` applied to its direct components, binders are kept as they are
+    fn map(&self, f: &mut dyn FnMut(&Ty) -> Ty) -> Ty {
+        let mut fields = |fields: &[(String, Ty)]| -> Vec<(String, Ty)> {
+            fields.iter().map(|(name, x)| (name.clone(), f(x))).collect()
+        };
+        match self {
+            Ty::Product(x) => Ty::Product(fields(x)),
+            Ty::CoProduct(x) => Ty::CoProduct(fields(x)),
+            Ty::OpenProduct(x, tail) => {
+                let x = fields(x);
+                extend_row(true, x, Some(f(tail)))
+            },
+            Ty::OpenCoProduct(x, tail) => {
+                let x = fields(x);
+                extend_row(false, x, Some(f(tail)))
+            },
+            Ty::Record(x) => Ty::Record(fields(x)),
+            Ty::Named(name, arguments) => Ty::Named(name.clone(), arguments.iter().map(&mut *f).collect()),
+            Ty::Function(from, to) => Ty::Function(Box::new(f(from)), Box::new(f(to))),
+            Ty::Pi(visibility, name, from, to) => {
+                Ty::Pi(*visibility, name.clone(), Box::new(f(from)), Box::new(f(to)))
+            },
+            Ty::Application(function, argument) => Ty::Application(Box::new(f(function)), Box::new(f(argument))),
+            Ty::Equal(left, right) => Ty::Equal(Box::new(f(left)), Box::new(f(right))),
+            Ty::Lambda(name, body) => Ty::Lambda(name.clone(), Box::new(f(body))),
+            Ty::Either(name, payload) => Ty::Either(name.clone(), Box::new(f(payload))),
+            Ty::Match(scrutinee, arms) => Ty::Match(
+                Box::new(f(scrutinee)),
+                arms.iter().map(|(pattern, x)| (pattern.clone(), f(x))).collect(),
+            ),
+            Ty::Meta(_) | Ty::Rigid(_) | Ty::Universe(_) | Ty::Atomic(_) | Ty::Global(_) | Ty::Literal(_) | Ty::Refl => {
+                self.clone()
+            },
+        }
+    }
+
+    // the type once classes are elaborated, instance parameters are given explicitly
+    pub fn explicit(&self) -> Ty {
+        match self {
+            Ty::Pi(Visibility::Instance, name, from, to) => {
+                Ty::Pi(Visibility::Explicit, name.clone(), Box::new(from.explicit()), Box::new(to.explicit()))
+            },
+            other => other.map(&mut |x| x.explicit()),
+        }
+    }
+
+    pub fn free_variables(&self, result: &mut Vec<String>) {
+        let mut inner = vec![];
+        let bound: Vec<String> = match self {
+            Ty::Rigid(name) => {
+                if !result.contains(name) {
+                    result.push(name.clone());
+                }
+                return;
+            },
+            Ty::Pi(_, name, from, to) => {
+                from.free_variables(result);
+                to.free_variables(&mut inner);
+                vec![name.clone()]
+            },
+            Ty::Lambda(name, body) => {
+                body.free_variables(&mut inner);
+                vec![name.clone()]
+            },
+            Ty::Match(scrutinee, arms) => {
+                scrutinee.free_variables(result);
+                for (pattern, body) in arms {
+                    let mut names = vec![];
+                    body.free_variables(&mut names);
+                    let binders = pattern.binders();
+                    inner.extend(names.into_iter().filter(|x| !binders.iter().any(|b| b.get_name() == x)));
+                }
+                vec![]
+            },
+            other => {
+                other.components().into_iter().for_each(|x| x.free_variables(result));
+                return;
+            },
+        };
+        for name in inner {
+            if !bound.contains(&name) && !result.contains(&name) {
+                result.push(name);
+            }
+        }
+    }
+
+    // the definitions the type refers to
+    pub fn globals(&self, result: &mut Vec<String>) {
+        match self {
+            Ty::Global(name) if !result.contains(name) => result.push(name.clone()),
+            other => other.components().into_iter().for_each(|x| x.globals(result)),
+        }
+    }
+
+    pub fn mentions(&self, name: &str) -> bool {
+        let mut names = vec![];
+        self.free_variables(&mut names);
+        names.iter().any(|x| x == name)
+    }
+}
+
+// maximum of a constant and of level variables shifted by offsets
+#[derive(Clone, Debug, PartialEq)]
+pub struct UniverseLevel {
+    constant: usize,
+    variables: Vec<(String, usize)>,
+}
+
+impl UniverseLevel {
+    pub fn constant(constant: usize) -> Self {
+        UniverseLevel {
+            constant,
+            variables: vec![],
+        }
+    }
+
+    fn variable(name: &str) -> Self {
+        UniverseLevel {
+            constant: 0,
+            variables: vec![(name.to_string(), 0)],
+        }
+    }
+
+    fn successor(&self) -> Self {
+        UniverseLevel {
+            constant: self.constant + 1,
+            variables: self.variables.iter().map(|(name, offset)| (name.clone(), offset + 1)).collect(),
+        }
+        .simplified()
+    }
+
+    // some levels below, but never below 0
+    fn predecessor(&self, levels: usize) -> Self {
+        UniverseLevel {
+            constant: self.constant.saturating_sub(levels),
+            variables: self.variables.iter().map(|(name, offset)| (name.clone(), offset.saturating_sub(levels))).collect(),
+        }
+        .simplified()
+    }
+
+    // levels are never below 0, so a variable at least as high as the constant makes it useless
+    fn simplified(mut self) -> Self {
+        if self.variables.iter().any(|(_, offset)| *offset >= self.constant) {
+            self.constant = 0;
+        }
+        self
+    }
+
+    fn maximum(&self, another: &UniverseLevel) -> Self {
+        let mut variables = self.variables.clone();
+        for (name, offset) in &another.variables {
+            match variables.iter_mut().find(|(x, _)| x == name) {
+                Some((_, current)) => *current = (*current).max(*offset),
+                None => variables.push((name.clone(), *offset)),
+            }
+        }
+        variables.sort();
+        UniverseLevel {
+            constant: self.constant.max(another.constant),
+            variables,
+        }
+        .simplified()
+    }
+
+    // every instance of `another` is at most the matching instance of `self`
+    pub fn includes(&self, another: &UniverseLevel) -> bool {
+        let bound = self.variables.iter().map(|(_, x)| *x).fold(self.constant, usize::max);
+        another.constant <= bound && another.variables.iter().all(|(name, offset)| {
+            self.variables.iter().any(|(x, current)| x == name && current >= offset)
+        })
+    }
+
+    fn substitute(&self, mapping: &HashMap<String, UniverseLevel>) -> Self {
+        self.variables.iter().fold(UniverseLevel::constant(self.constant), |result, (name, offset)| {
+            let level = match mapping.get(name) {
+                Some(level) => (0..*offset).fold(level.clone(), |x, _| x.successor()),
+                None => UniverseLevel {
+                    constant: 0,
+                    variables: vec![(name.clone(), *offset)],
+                },
+            };
+            result.maximum(&level)
+        })
+    }
+}
+
+// quantified variables appear in the body as rigid variables
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scheme(pub Vec<String>, pub Ty);
+
+struct TypeDefinition {
+    parameters: Vec<String>,
+    kinds: Vec<Ty>,
+    level: UniverseLevel,
+    body: Type,
+    // how each parameter may change between a subtype and its supertype, found once every definition is known
+    variances: Vec<Variance>,
+}
+
+// where a parameter occurs in the body of its definition
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Variance {
+    Unused,
+    Covariant,
+    Contravariant,
+    Invariant,
+}
+
+impl Variance {
+    fn join(self, another: Variance) -> Variance {
+        match (self, another) {
+            (Variance::Unused, x) | (x, Variance::Unused) => x,
+            (x, y) if x == y => x,
+            _ => Variance::Invariant,
+        }
+    }
+
+    fn flip(self) -> Variance {
+        match self {
+            Variance::Covariant => Variance::Contravariant,
+            Variance::Contravariant => Variance::Covariant,
+            other => other,
+        }
+    }
+
+    // an occurrence of the given variance seen from a position of this one
+    fn through(self, inner: Variance) -> Variance {
+        match (self, inner) {
+            (_, Variance::Unused) | (Variance::Unused, _) => Variance::Unused,
+            (Variance::Covariant, x) => x,
+            (Variance::Contravariant, x) => x.flip(),
+            (Variance::Invariant, _) => Variance::Invariant,
+        }
+    }
+}
+
+// the variance of `parameter` in `ty` at the given position, other definitions are read from `table`; rows and
+// anything computed with the parameter are invariant
+fn occurrences(ty: &Ty, parameter: &str, position: Variance, table: &HashMap<String, Vec<Variance>>) -> Variance {
+    let all = |types: Vec<&Ty>, position: Variance| {
+        types.into_iter().fold(Variance::Unused, |x, ty| x.join(occurrences(ty, parameter, position, table)))
+    };
+    match ty {
+        Ty::Rigid(name) if name == parameter => position,
+        Ty::Function(from, to) => all(vec![from], position.flip()).join(all(vec![to], position)),
+        Ty::Pi(_, bound, from, to) => {
+            let to = if bound == parameter { Variance::Unused } else { all(vec![to], position) };
+            all(vec![from], position.flip()).join(to)
+        },
+        Ty::Product(_) | Ty::CoProduct(_) => all(ty.components(), position),
+        Ty::OpenProduct(fields, tail) | Ty::OpenCoProduct(fields, tail) => {
+            all(fields.iter().map(|(_, x)| x).collect(), position).join(all(vec![tail], Variance::Invariant))
+        },
+        Ty::Named(name, arguments) => arguments.iter().enumerate().fold(Variance::Unused, |x, (i, argument)| {
+            let variance = table.get(name).and_then(|x| x.get(i)).copied().unwrap_or(Variance::Invariant);
+            x.join(occurrences(argument, parameter, position.through(variance), table))
+        }),
+        other if other.mentions(parameter) => Variance::Invariant,
+        _ => Variance::Unused,
+    }
+}
+
+// a hole, its expected type and the locals in scope, zonked when the goals are read
+type PendingGoal = (Name, Option<Ty>, Vec<(String, Ty)>);
+
+// the meta inserted for an implicit parameter, the parameter and the function it was passed to
+type ImplicitArgument = (usize, String, String, Option<Span>);
+
+// an instance argument passed where the name is used, whether it is the first one passed there,
+// and the dictionary found for it
+type Constraint = (Name, bool, Ty, Option<Value>);
+
+// instances needing instances are only followed this deep
+const INSTANCE_DEPTH: usize = 32;
+
+pub struct Inference {
+    substitution: Vec<Option<Ty>>,
+    definitions: HashMap<String, TypeDefinition>,
+    constructors: HashMap<String, String>,
+    globals: HashMap<String, Scheme>,
+    values: HashMap<String, Ty>,
+    locals: Vec<(String, Scheme)>,
+    rigid: Vec<String>,
+    cumulative: bool,
+    // the meta standing for the contents of each hole, in program order
+    holes: Vec<(String, usize)>,
+    goals: RefCell<Vec<PendingGoal>>,
+    implicits: Vec<ImplicitArgument>,
+    // definitions whose type is a class, in program order
+    instances: Vec<String>,
+    // instance parameters in scope
+    given: Vec<(String, Ty)>,
+    constraints: Vec<Constraint>,
+    // what matching on refl taught about the variables of the current arm
+    equations: Vec<(String, Ty)>,
+    // definitions asserted to terminate
+    terminating: Vec<String>,
+    // definitions giving codata, they have to be productive rather than terminate
+    corecursive: Vec<String>,
+    // reductions one conversion check may take
+    fuel: usize,
+}
+
+pub fn primitive_type(primitive: Primitive) -> Ty {
+    let (arguments, result) = primitive.signature();
+    arguments.into_iter().rev().fold(Ty::Atomic(result), |to, from| Ty::Function(Box::new(Ty::Atomic(from)), Box::new(to)))
+}
+
+pub fn print_span(span: Option<Span>) -> String {
+    match span {
+        Some(span) => format!("at {}", span),
+        None => "at unknown position".to_string(),
+    }
+}
+
+// renames both bound variables to one name free in neither body
+fn common_binder(x: &str, body: &Ty, y: &str, another_body: &Ty) -> (Ty, Ty) {
+    if x == y {
+        return (body.clone(), another_body.clone());
+    }
+    let mut name = x.to_string();
+    while another_body.mentions(&name) || (name != x && body.mentions(&name)) {
+        name.push('\'');
+    }
+    let rename = |from: &str, body: &Ty| {
+        let mapping = vec![(from.to_string(), Ty::Rigid(name.clone()))].into_iter().collect();
+        Inference::substitute(body, &mapping)
+    };
+    (rename(x, body), rename(y, another_body))
+}
+
+fn misplaced_instance(name: &str, at: Option<Span>) -> String {
+    format!("instance parameter {} can only come first in the type of a definition {}", name, print_span(at))
+}
+
+fn describe(function: &Value) -> String {
+    match function {
+        Value::Var(name) => name.get_name().to_string(),
+        _ => "the function".to_string(),
+    }
+}
+
+// rows stay flat, entries added to a tail that turned out to be a row are merged with it
+pub fn extend_row(product: bool, mut fields: Vec<(String, Ty)>, tail: Option<Ty>) -> Ty {
+    match tail {
+        None if product => Ty::Product(fields),
+        None => Ty::CoProduct(fields),
+        Some(Ty::Product(more)) | Some(Ty::CoProduct(more)) => {
+            fields.extend(more);
+            extend_row(product, fields, None)
+        },
+        Some(Ty::OpenProduct(more, tail)) | Some(Ty::OpenCoProduct(more, tail)) => {
+            fields.extend(more);
+            extend_row(product, fields, Some(*tail))
+        },
+        Some(tail) if fields.is_empty() => tail,
+        Some(tail) if product => Ty::OpenProduct(fields, Box::new(tail)),
+        Some(tail) => Ty::OpenCoProduct(fields, Box::new(tail)),
+    }
+}
+
+// whether a row is a product, its entries and its tail, if it is open
+type Row<'a> = (bool, &'a [(String, Ty)], Option<&'a Ty>);
+
+fn row(ty: &Ty) -> Option<Row<'_>> {
+    match ty {
+        Ty::Product(fields) => Some((true, fields, None)),
+        Ty::CoProduct(fields) => Some((false, fields, None)),
+        Ty::OpenProduct(fields, tail) => Some((true, fields, Some(tail))),
+        Ty::OpenCoProduct(fields, tail) => Some((false, fields, Some(tail))),
+        _ => None,
+    }
+}
+
+fn index_fields(types: Vec<Ty>) -> Vec<(String, Ty)> {
+    types.into_iter().enumerate().map(|(i, x)| (i.to_string(), x)).collect()
+}
+
+fn is_kind(typ: &Type) -> bool {
+    match typ {
+        Type::Universe(_) => true,
+        Type::Function(from, to) => is_kind(from) && is_kind(to),
+        _ => false,
+    }
+}
+
+// `$List: @ -> @ = A ~> Nil . + Cons A * List A` defines a type with one parameter
+pub fn as_type_definition(value: &Value) -> Option<(Vec<String>, &Type)> {
+    match value {
+        Value::Type(typ) => Some((vec![], typ)),
+        Value::Function(parameters, body) => match &**body {
+            Expr(lets, Value::Type(typ)) if lets.is_empty() => Some((
+                parameters.iter().map(|(name, _)| name.get_name().to_string()).collect(),
+                typ,
+            )),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// what the codata definitions of a program make lazy: the payloads of their constructors, and the fields of records
+// with the same fields as one of them, everything else is computed when it is built
+#[derive(Clone, Debug, Default)]
+pub struct Codata {
+    constructors: Vec<String>,
+    records: Vec<Vec<String>>,
+}
+
+impl Codata {
+    pub fn new(program: &[Let]) -> Self {
+        let mut codata = Codata::default();
+        for Let(_, value, _) in program {
+            let body = match as_type_definition(value) {
+                Some((_, Type::Codata(body))) => body,
+                _ => continue,
+            };
+            match &**body {
+                Type::Product(fields) => {
+                    let mut fields: Vec<String> = fields.iter().map(|(name, _)| name.get_name().to_string()).collect();
+                    fields.sort();
+                    codata.records.push(fields);
+                },
+                Type::CoProduct(constructors) => {
+                    codata.constructors.extend(constructors.iter().map(|(name, _)| name.get_name().to_string()));
+                },
+                _ => {},
+            }
+        }
+        codata
+    }
+
+    pub fn is_lazy_constructor(&self, name: &str) -> bool {
+        self.constructors.iter().any(|x| x == name)
+    }
+
+    pub fn is_lazy_record<'b>(&self, fields: impl Iterator<Item = &'b str>) -> bool {
+        let mut fields: Vec<&str> = fields.collect();
+        fields.sort();
+        self.records.iter().any(|x| x.iter().map(|x| x.as_str()).eq(fields.iter().copied()))
+    }
+}
+
+impl Inference {
+    pub fn new() -> Self {
+        Inference {
+            substitution: vec![],
+            definitions: HashMap::new(),
+            constructors: HashMap::new(),
+            globals: HashMap::new(),
+            values: HashMap::new(),
+            locals: vec![],
+            rigid: vec![],
+            cumulative: false,
+            holes: vec![],
+            goals: RefCell::new(vec![]),
+            implicits: vec![],
+            instances: vec![],
+            given: vec![],
+            constraints: vec![],
+            equations: vec![],
+            terminating: vec![],
+            corecursive: vec![],
+            fuel: DEFAULT_FUEL,
+        }
+    }
+
+    // a type in @0 is then also accepted where @1 is expected
+    pub fn cumulative(mut self) -> Self {
+        self.cumulative = true;
+        self
+    }
+
+    // the recursion of these definitions is taken on trust
+    pub fn terminating(mut self, names: Vec<String>) -> Self {
+        self.terminating = names;
+        self
+    }
+
+    pub fn fuel(mut self, fuel: usize) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.substitution.push(None);
+        Ty::Meta(self.substitution.len() - 1)
+    }
+
+    fn resolve(&self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::Meta(id) => match &self.substitution[*id] {
+                Some(solution) => self.resolve(solution),
+                None => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    pub fn zonk(&self, ty: &Ty) -> Ty {
+        self.resolve(ty).map(&mut |x| self.zonk(x))
+    }
+
+    fn metas(&self, ty: &Ty, result: &mut Vec<usize>) {
+        match self.resolve(ty) {
+            Ty::Meta(id) => {
+                if !result.contains(&id) {
+                    result.push(id);
+                }
+            },
+            other => other.components().into_iter().for_each(|x| self.metas(x, result)),
+        }
+    }
+
+    // capture avoiding, a binder that would capture a replacement gets primed
+    fn substitute(ty: &Ty, mapping: &HashMap<String, Ty>) -> Ty {
+        match ty {
+            Ty::Rigid(name) => mapping.get(name).cloned().unwrap_or_else(|| ty.clone()),
+            Ty::Pi(visibility, name, from, to) => {
+                let (name, to) = Inference::substitute_under(std::slice::from_ref(name), to, mapping);
+                Ty::Pi(*visibility, name[0].clone(), Box::new(Inference::substitute(from, mapping)), Box::new(to))
+            },
+            Ty::Lambda(name, body) => {
+                let (name, body) = Inference::substitute_under(std::slice::from_ref(name), body, mapping);
+                Ty::Lambda(name[0].clone(), Box::new(body))
+            },
+            Ty::Match(scrutinee, arms) => Ty::Match(
+                Box::new(Inference::substitute(scrutinee, mapping)),
+                arms.iter().map(|(pattern, body)| {
+                    let binders: Vec<String> = pattern.binders().iter().map(|x| x.get_name().to_string()).collect();
+                    let (renamed, body) = Inference::substitute_under(&binders, body, mapping);
+                    let pattern = binders.iter().zip(&renamed).fold(pattern.clone(), |pattern, (from, to)| {
+                        pattern.rename(from, to)
+                    });
+                    (pattern, body)
+                }).collect(),
+            ),
+            other => other.map(&mut |x| Inference::substitute(x, mapping)),
+        }
+    }
+
+    fn substitute_under(binders: &[String], body: &Ty, mapping: &HashMap<String, Ty>) -> (Vec<String>, Ty) {
+        let mut mapping = mapping.clone();
+        binders.iter().for_each(|x| {
+            mapping.remove(x);
+        });
+        let mut taken = vec![];
+        body.free_variables(&mut taken);
+        let replacements: Vec<Ty> = mapping
+            .iter()
+            .filter(|(name, _)| taken.contains(name))
+            .map(|(_, x)| x.clone())
+            .collect();
+        replacements.iter().for_each(|x| x.free_variables(&mut taken));
+        let mut renamed = vec![];
+        for binder in binders {
+            if !replacements.iter().any(|x| x.mentions(binder)) {
+                renamed.push(binder.clone());
+                continue;
+            }
+            let mut fresh = format!("{}'", binder);
+            while taken.contains(&fresh) {
+                fresh.push('\'');
+            }
+            taken.push(fresh.clone());
+            mapping.insert(binder.clone(), Ty::Rigid(fresh.clone()));
+            renamed.push(fresh);
+        }
+        (renamed, Inference::substitute(body, &mapping))
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Ty {
+        let Scheme(variables, body) = scheme;
+        let mapping = variables.iter().map(|name| (name.clone(), self.fresh())).collect();
+        Inference::substitute(body, &mapping)
+    }
+
+    fn environment_metas(&self) -> Vec<usize> {
+        let mut result = vec![];
+        for Scheme(_, ty) in self.globals.values().chain(self.locals.iter().map(|(_, x)| x)) {
+            self.metas(ty, &mut result);
+        }
+        result
+    }
+
+    fn generalise(&self, ty: &Ty, skip: &[usize]) -> Scheme {
+        let mut free = vec![];
+        self.metas(ty, &mut free);
+        let mapping: HashMap<usize, String> = free
+            .into_iter()
+            .filter(|x| !skip.contains(x) && !self.holes.iter().any(|(_, id)| id == x))
+            .map(|x| (x, format!("t{}", x)))
+            .collect();
+        let mut variables: Vec<String> = mapping.values().cloned().collect();
+        variables.sort();
+        Scheme(variables, self.replace_metas(ty, &mapping))
+    }
+
+    fn replace_metas(&self, ty: &Ty, mapping: &HashMap<usize, String>) -> Ty {
+        match self.zonk(ty) {
+            Ty::Meta(id) => match mapping.get(&id) {
+                Some(name) => Ty::Rigid(name.clone()),
+                None => Ty::Meta(id),
+            },
+            other => other.map(&mut |x| self.replace_metas(x, mapping)),
+        }
+    }
+
+    // names free in an annotation are collected into `free` instead of being rejected
+    fn convert(&self, typ: &Type, bound: &HashMap<String, Ty>, free: &mut Option<Vec<String>>) -> Result<Ty, String> {
+        match typ {
+            // codata is typed like the record or variant it wraps, only its evaluation differs
+            Type::Codata(body) => self.convert(body, bound, free),
+            Type::Product(fields) | Type::Class(fields) => Ok(Ty::Product(
+                fields
+                    .iter()
+                    .map(|(name, x)| Ok((name.get_name().to_string(), self.convert(x, bound, free)?)))
+                    .collect::<Result<_, String>>()?
+            )),
+            Type::OpenProduct(fields, tail) => Ok(extend_row(
+                true,
+                fields
+                    .iter()
+                    .map(|(name, x)| Ok((name.get_name().to_string(), self.convert(x, bound, free)?)))
+                    .collect::<Result<_, String>>()?,
+                Some(self.convert(&Type::TypeVar(tail.clone()), bound, free)?),
+            )),
+            Type::OpenCoProduct(constructors, tail) => Ok(extend_row(
+                false,
+                constructors
+                    .iter()
+                    .map(|(name, x)| Ok((name.get_name().to_string(), self.convert(x, bound, free)?)))
+                    .collect::<Result<_, String>>()?,
+                Some(self.convert(&Type::TypeVar(tail.clone()), bound, free)?),
+            )),
+            Type::CoProduct(constructors) => Ok(Ty::CoProduct(
+                constructors
+                    .iter()
+                    .map(|(name, x)| Ok((name.get_name().to_string(), self.convert(x, bound, free)?)))
+                    .collect::<Result<_, String>>()?
+            )),
+            Type::Function(from, to) => Ok(Ty::Function(
+                Box::new(self.convert(from, bound, free)?),
+                Box::new(self.convert(to, bound, free)?),
+            )),
+            Type::Pi(visibility, name, from, to) => {
+                let mut inner = bound.clone();
+                inner.insert(name.get_name().to_string(), Ty::Rigid(name.get_name().to_string()));
+                Ok(Ty::Pi(
+                    *visibility,
+                    name.get_name().to_string(),
+                    Box::new(self.convert(from, bound, free)?),
+                    Box::new(self.convert(to, &inner, free)?),
+                ))
+            },
+            Type::Application(function, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| match argument {
+                        Value::Type(typ) => self.convert(typ, bound, free),
+                        Value::Var(name) => self.convert(&Type::TypeVar(name.clone()), bound, free),
+                        other => self.to_term(other, bound),
+                    })
+                    .collect::<Result<Vec<Ty>, String>>()?;
+                let name = match &**function {
+                    Type::TypeVar(name) if !bound.contains_key(name.get_name()) => name,
+                    other => {
+                        let function = self.convert(other, bound, free)?;
+                        return Ok(arguments.into_iter().fold(function, |f, x| Ty::Application(Box::new(f), Box::new(x))));
+                    },
+                };
+                match self.definitions.get(name.get_name()) {
+                    Some(definition) if definition.parameters.len() == arguments.len() => {
+                        Ok(Ty::Named(name.get_name().to_string(), arguments))
+                    },
+                    Some(definition) => Err(format!(
+                        "{} expects {} arguments but got {} {}",
+                        name, definition.parameters.len(), arguments.len(), print_span(name.get_span())
+                    )),
+                    None => {
+                        let function = self.convert(function, bound, &mut None)
+                            .map_err(|_| format!("{} is not a type definition {}", name, print_span(name.get_span())))?;
+                        Ok(arguments.into_iter().fold(function, |f, x| Ty::Application(Box::new(f), Box::new(x))))
+                    },
+                }
+            },
+            Type::TypeVar(name) => {
+                let text = name.get_name();
+                if let Some(ty) = bound.get(text) {
+                    return Ok(ty.clone());
+                }
+                if self.rigid.iter().any(|x| x == text) || self.locals.iter().any(|(x, _)| x == text) {
+                    return Ok(Ty::Rigid(text.to_string()));
+                }
+                if let Some(definition) = self.definitions.get(text) {
+                    return if definition.parameters.is_empty() {
+                        Ok(Ty::Named(text.to_string(), vec![]))
+                    } else {
+                        Err(format!(
+                            "{} expects {} arguments {}",
+                            name, definition.parameters.len(), print_span(name.get_span())
+                        ))
+                    };
+                }
+                if self.values.contains_key(text) || self.globals.contains_key(text) {
+                    return Ok(Ty::Global(text.to_string()));
+                }
+                match free {
+                    Some(free) => {
+                        if !free.iter().any(|x| x == text) {
+                            free.push(text.to_string());
+                        }
+                        Ok(Ty::Rigid(text.to_string()))
+                    },
+                    None => Err(format!("unknown type {} {}", name, print_span(name.get_span()))),
+                }
+            },
+            Type::Universe(level) => Ok(Ty::Universe(Inference::convert_level(level))),
+            Type::Atomic(atomic) => Ok(Ty::Atomic(*atomic)),
+            Type::Hole(name) => {
+                self.record_goal(name, None);
+                self.hole(name)
+            },
+            Type::Equal(left, right) => Ok(Ty::Equal(
+                Box::new(self.to_term(left, bound)?),
+                Box::new(self.to_term(right, bound)?),
+            )),
+        }
+    }
+
+    fn hole(&self, name: &Name) -> Result<Ty, String> {
+        match self.holes.iter().find(|(x, _)| x == name.get_name()) {
+            Some((_, id)) => Ok(Ty::Meta(*id)),
+            None => Err(format!("hole ?{} is not part of the program {}", name, print_span(name.get_span()))),
+        }
+    }
+
+    // holes of types have no expected type, they only need to be types
+    fn record_goal(&self, name: &Name, expected: Option<Ty>) {
+        let mut goals = self.goals.borrow_mut();
+        if goals.iter().any(|(x, _, _)| x == name) {
+            return;
+        }
+        let context = self.locals.iter().map(|(name, Scheme(_, ty))| (name.clone(), ty.clone())).collect();
+        goals.push((name.clone(), expected, context));
+    }
+
+    pub fn goals(&self) -> Vec<Goal> {
+        let mut goals: Vec<Goal> = self.goals
+            .borrow()
+            .iter()
+            .map(|(name, expected, context)| {
+                let contents = self.hole(name).map(|x| self.zonk(&x)).ok();
+                let expected = match expected {
+                    Some(ty) => self.zonk(ty),
+                    None => {
+                        let level = contents.as_ref().and_then(|x| self.level_of(x, &HashMap::new()).ok());
+                        Ty::Universe(level.unwrap_or_else(|| UniverseLevel::constant(0)))
+                    },
+                };
+                Goal {
+                    name: name.get_name().to_string(),
+                    span: name.get_span(),
+                    expected,
+                    context: context.iter().map(|(name, ty)| (name.clone(), self.zonk(ty))).collect(),
+                    solution: contents.filter(|x| !matches!(x, Ty::Meta(_))),
+                }
+            })
+            .collect();
+        let position = |goal: &Goal| self.holes.iter().position(|(x, _)| *x == goal.name);
+        goals.sort_by_key(position);
+        goals
+    }
+
+    // a value as it appears inside a type, local lets are inlined
+    fn to_term(&self, value: &Value, bound: &HashMap<String, Ty>) -> Result<Ty, String> {
+        let binding = |names: &mut dyn Iterator<Item = &Name>| {
+            let mut inner = bound.clone();
+            for name in names {
+                inner.insert(name.get_name().to_string(), Ty::Rigid(name.get_name().to_string()));
+            }
+            inner
+        };
+        match value {
+            Value::Var(name) => {
+                let text = name.get_name();
+                match bound.get(text) {
+                    Some(ty) => Ok(ty.clone()),
+                    None if self.definitions.contains_key(text) => {
+                        self.convert(&Type::TypeVar(name.clone()), bound, &mut None)
+                    },
+                    None if self.rigid.iter().any(|x| x == text) || self.locals.iter().any(|(x, _)| x == text) => {
+                        Ok(Ty::Rigid(text.to_string()))
+                    },
+                    None => Ok(Ty::Global(text.to_string())),
+                }
+            },
+            Value::Tuple(items) => Ok(Ty::Record(index_fields(
+                items.iter().map(|x| self.to_term(x, bound)).collect::<Result<_, String>>()?
+            ))),
+            Value::Record(fields) => Ok(Ty::Record(
+                fields
+                    .iter()
+                    .map(|(name, x)| Ok((name.get_name().to_string(), self.to_term(x, bound)?)))
+                    .collect::<Result<_, String>>()?
+            )),
+            Value::Either(name, payload) => Ok(Ty::Either(
+                name.get_name().to_string(),
+                Box::new(self.to_term(payload, bound)?),
+            )),
+            Value::Match(scrutinee, arms) => Ok(Ty::Match(
+                Box::new(self.to_term(scrutinee, bound)?),
+                arms
+                    .iter()
+                    .map(|(pattern, body)| {
+                        let inner = binding(&mut pattern.binders().into_iter());
+                        Ok((pattern.clone(), self.to_term(body, &inner)?))
+                    })
+                    .collect::<Result<_, String>>()?,
+            )),
+            Value::Function(parameters, body) => {
+                let inner = binding(&mut parameters.iter().map(|(name, _)| name));
+                let body = self.expr_term(body, &inner)?;
+                Ok(parameters.iter().rev().fold(body, |body, (name, _)| {
+                    Ty::Lambda(name.get_name().to_string(), Box::new(body))
+                }))
+            },
+            Value::Application(function, arguments) => {
+                if let Value::Var(name) = &**function {
+                    if self.definitions.contains_key(name.get_name()) && !bound.contains_key(name.get_name()) {
+                        let typ = Type::Application(Box::new(Type::TypeVar(name.clone())), arguments.clone());
+                        return self.convert(&typ, bound, &mut None);
+                    }
+                }
+                // implicit arguments are erased
+                let mut arguments = arguments.iter().filter(|x| !matches!(x, Value::Implicit(_)));
+                arguments.try_fold(self.to_term(function, bound)?, |f, x| {
+                    Ok(Ty::Application(Box::new(f), Box::new(self.to_term(x, bound)?)))
+                })
+            },
+            Value::Constant(constant, _) => Ok(Ty::Literal(constant.clone())),
+            Value::Type(typ) => self.convert(typ, bound, &mut None),
+            Value::Hole(name) => self.hole(name),
+            Value::Implicit(_) => Err(format!(
+                "implicit arguments can only be passed to functions {}", print_span(value.get_span())
+            )),
+            Value::Refl => Ok(Ty::Refl),
+        }
+    }
+
+    fn expr_term(&self, Expr(lets, value): &Expr, bound: &HashMap<String, Ty>) -> Result<Ty, String> {
+        let mut inner = bound.clone();
+        for Let(name, value, _) in lets {
+            let term = self.to_term(value, &inner)?;
+            inner.insert(name.get_name().to_string(), term);
+        }
+        self.to_term(value, &inner)
+    }
+
+    fn convert_level(level: &Level) -> UniverseLevel {
+        match level {
+            Level::Constant(n) => UniverseLevel::constant(*n),
+            Level::Variable(name) => UniverseLevel::variable(name.get_name()),
+            Level::Successor(level) => Inference::convert_level(level).successor(),
+            Level::Maximum(levels) => levels
+                .iter()
+                .fold(UniverseLevel::constant(0), |x, level| x.maximum(&Inference::convert_level(level))),
+        }
+    }
+
+    // level variables of the definition are fixed by the levels of the arguments
+    fn instantiate_levels(
+        &self,
+        name: &str,
+        arguments: &[Ty],
+        parameters: &HashMap<String, UniverseLevel>,
+    ) -> Result<HashMap<String, UniverseLevel>, String> {
+        let definition = &self.definitions[name];
+        let mut mapping: HashMap<String, UniverseLevel> = HashMap::new();
+        for (kind, argument) in definition.kinds.iter().zip(arguments) {
+            if let Ty::Universe(UniverseLevel { constant: 0, variables }) = kind {
+                if let [(variable, offset)] = &variables[..] {
+                    // an argument in @(l+1) fixes l one level below its own
+                    let level = self.level_of(argument, parameters)?.predecessor(*offset);
+                    let level = match mapping.get(variable) {
+                        Some(current) => current.maximum(&level),
+                        None => level,
+                    };
+                    mapping.insert(variable.clone(), level);
+                }
+            }
+        }
+        Ok(mapping)
+    }
+
+    // the smallest universe containing the type, rigid variables default to @0
+    fn level_of(&self, ty: &Ty, parameters: &HashMap<String, UniverseLevel>) -> Result<UniverseLevel, String> {
+        match self.normalise(ty)? {
+            Ty::Rigid(name) => Ok(match parameters.get(&name) {
+                Some(level) => level.clone(),
+                None => match self.locals.iter().rev().find(|(x, _)| *x == name) {
+                    Some((_, Scheme(_, ty))) => match self.resolve(ty) {
+                        Ty::Universe(level) => level,
+                        _ => UniverseLevel::constant(0),
+                    },
+                    None => UniverseLevel::constant(0),
+                },
+            }),
+            Ty::Universe(level) => Ok(level.successor()),
+            Ty::Function(from, to) => Ok(self.level_of(&from, parameters)?.maximum(&self.level_of(&to, parameters)?)),
+            Ty::Pi(_, name, from, to) => {
+                let mut inner = parameters.clone();
+                match self.normalise(&from)? {
+                    Ty::Universe(level) => inner.insert(name, level),
+                    _ => inner.remove(&name),
+                };
+                Ok(self.level_of(&from, parameters)?.maximum(&self.level_of(&to, &inner)?))
+            },
+            Ty::Product(fields) | Ty::CoProduct(fields) => fields
+                .iter()
+                .try_fold(UniverseLevel::constant(0), |x, (_, ty)| Ok(x.maximum(&self.level_of(ty, parameters)?))),
+            Ty::OpenProduct(fields, tail) | Ty::OpenCoProduct(fields, tail) => fields
+                .iter()
+                .try_fold(self.level_of(&tail, parameters)?, |x, (_, ty)| Ok(x.maximum(&self.level_of(ty, parameters)?))),
+            Ty::Named(name, arguments) => {
+                let mapping = self.instantiate_levels(&name, &arguments, parameters)?;
+                Ok(self.definitions[&name].level.substitute(&mapping))
+            },
+            _ => Ok(UniverseLevel::constant(0)),
+        }
+    }
+
+    // arguments of named types have to live in the universes their parameters ask for
+    fn check_levels(&self, ty: &Ty, parameters: &HashMap<String, UniverseLevel>) -> Result<(), String> {
+        match self.resolve(ty) {
+            Ty::Named(name, arguments) => {
+                let mapping = self.instantiate_levels(&name, &arguments, parameters)?;
+                for (kind, argument) in self.definitions[&name].kinds.iter().zip(&arguments) {
+                    self.check_levels(argument, parameters)?;
+                    if let Ty::Universe(level) = kind {
+                        let expected = level.substitute(&mapping);
+                        let actual = self.level_of(argument, parameters)?;
+                        if !expected.includes(&actual) {
+                            return Err(format!(
+                                "argument {} of {} lives in @{} but @{} is expected",
+                                self.zonk(argument), name, actual, expected
+                            ));
+                        }
+                    }
+                }
+                Ok(())
+            },
+            Ty::Function(from, to) | Ty::Pi(_, _, from, to) => {
+                self.check_levels(&from, parameters)?;
+                self.check_levels(&to, parameters)
+            },
+            Ty::Product(fields) | Ty::CoProduct(fields) => {
+                fields.iter().try_for_each(|(_, x)| self.check_levels(x, parameters))
+            },
+            Ty::OpenProduct(fields, tail) | Ty::OpenCoProduct(fields, tail) => {
+                fields.iter().try_for_each(|(_, x)| self.check_levels(x, parameters))?;
+                self.check_levels(&tail, parameters)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn unfold(&self, name: &str, arguments: &[Ty]) -> Result<Ty, String> {
+        let definition = self.definitions.get(name).ok_or(format!("unknown type {}", name))?;
+        let bound = definition.parameters.iter().cloned().zip(arguments.iter().cloned()).collect();
+        self.convert(&definition.body, &bound, &mut None)
+    }
+
+    fn normaliser(&self) -> Normaliser<'_> {
+        Normaliser::new(&self.values, &self.substitution)
+            .with_equations(&self.equations)
+            .with_corecursive(&self.corecursive)
+            .with_fuel(self.fuel)
+    }
+
+    // terms that may look different once evaluated
+    fn reducible(&self, ty: &Ty) -> bool {
+        match ty {
+            Ty::Global(_) | Ty::Application(_, _) | Ty::Match(_, _) => true,
+            Ty::Rigid(name) => self.equations.iter().any(|(x, _)| x == name),
+            _ => false,
+        }
+    }
+
+    fn normalise(&self, ty: &Ty) -> Result<Ty, String> {
+        self.normaliser().normalise(ty)
+    }
+
+    // function types are only normalised when they don't show their shape, so messages keep the names
+    fn function_type(&self, ty: &Ty) -> Result<Ty, String> {
+        match self.resolve(ty) {
+            ty @ Ty::Function(_, _) | ty @ Ty::Pi(_, _, _, _) => Ok(ty),
+            ty => self.normalise(&ty),
+        }
+    }
+
+    fn occurs(&self, id: usize, ty: &Ty) -> bool {
+        let mut metas = vec![];
+        self.metas(ty, &mut metas);
+        metas.contains(&id)
+    }
+
+    fn unify_with(&mut self, left: &Ty, right: &Ty, assumptions: &mut Vec<(Ty, Ty)>) -> Result<(), String> {
+        let left = self.resolve(left);
+        let right = self.resolve(right);
+        if self.zonk(&left) == self.zonk(&right) {
+            return Ok(());
+        }
+        let flexible = matches!(left, Ty::Meta(_)) || matches!(right, Ty::Meta(_));
+        if !flexible && (self.reducible(&left) || self.reducible(&right)) {
+            let normaliser = self.normaliser();
+            if normaliser.equal(&left, &right)? {
+                return Ok(());
+            }
+            let (reduced_left, reduced_right) = (normaliser.normalise(&left)?, normaliser.normalise(&right)?);
+            if reduced_left != left || reduced_right != right {
+                return self.unify_with(&reduced_left, &reduced_right, assumptions);
+            }
+        }
+        match (&left, &right) {
+            (Ty::Meta(x), Ty::Meta(y)) if x == y => Ok(()),
+            (Ty::Meta(id), other) | (other, Ty::Meta(id)) => {
+                if self.occurs(*id, other) {
+                    return Err(format!("infinite type {} = {}", Ty::Meta(*id), self.zonk(other)));
+                }
+                self.substitution[*id] = Some(other.clone());
+                Ok(())
+            },
+            (Ty::Atomic(x), Ty::Atomic(y)) if x == y => Ok(()),
+            (Ty::Universe(x), Ty::Universe(y)) if x.includes(y) && y.includes(x) => Ok(()),
+            (Ty::Rigid(x), Ty::Rigid(y)) if x == y => Ok(()),
+            (Ty::Function(from, to), Ty::Function(another_from, another_to))
+            | (Ty::Function(from, to), Ty::Pi(Visibility::Explicit, _, another_from, another_to))
+            | (Ty::Pi(Visibility::Explicit, _, from, to), Ty::Function(another_from, another_to))
+            | (Ty::Application(from, to), Ty::Application(another_from, another_to))
+            | (Ty::Equal(from, to), Ty::Equal(another_from, another_to)) => {
+                self.unify_with(from, another_from, assumptions)?;
+                self.unify_with(to, another_to, assumptions)
+            },
+            (Ty::Pi(visibility, x, from, to), Ty::Pi(another_visibility, y, another_from, another_to))
+                if visibility == another_visibility =>
+            {
+                self.unify_with(from, another_from, assumptions)?;
+                let (to, another_to) = common_binder(x, to, y, another_to);
+                self.unify_with(&to, &another_to, assumptions)
+            },
+            (Ty::Lambda(x, body), Ty::Lambda(y, another_body)) => {
+                let (body, another_body) = common_binder(x, body, y, another_body);
+                self.unify_with(&body, &another_body, assumptions)
+            },
+            // eta: f is the same as x ~> f x
+            (Ty::Lambda(x, body), other) | (other, Ty::Lambda(x, body)) => {
+                let mut name = x.clone();
+                while other.mentions(&name) || (name != *x && body.mentions(&name)) {
+                    name.push('\'');
+                }
+                let mapping = vec![(x.clone(), Ty::Rigid(name.clone()))].into_iter().collect();
+                let applied = Ty::Application(Box::new(other.clone()), Box::new(Ty::Rigid(name)));
+                self.unify_with(&Inference::substitute(body, &mapping), &applied, assumptions)
+            },
+            (Ty::Either(x, payload), Ty::Either(y, another_payload)) if x == y => {
+                self.unify_with(payload, another_payload, assumptions)
+            },
+            (Ty::Match(scrutinee, arms), Ty::Match(another_scrutinee, another_arms))
+                if arms.len() == another_arms.len() && arms.iter().zip(another_arms).all(|(a, b)| a.0 == b.0) =>
+            {
+                self.unify_with(scrutinee, another_scrutinee, assumptions)?;
+                arms.iter().zip(another_arms).try_for_each(|((_, a), (_, b))| self.unify_with(a, b, assumptions))
+            },
+            (Ty::Named(x, arguments), Ty::Named(y, another_arguments)) if x == y => arguments
+                .iter()
+                .zip(another_arguments)
+                .try_for_each(|(a, b)| self.unify_with(a, b, assumptions)),
+            (Ty::Named(name, arguments), _) | (_, Ty::Named(name, arguments)) => {
+                let pair = (self.zonk(&left), self.zonk(&right));
+                if assumptions.contains(&pair) {
+                    return Ok(());
+                }
+                assumptions.push(pair);
+                let unfolded = self.unfold(name, arguments)?;
+                match left {
+                    Ty::Named(_, _) => self.unify_with(&unfolded, &right, assumptions),
+                    _ => self.unify_with(&left, &unfolded, assumptions),
+                }
+            },
+            (Ty::Product(fields), Ty::Product(another_fields))
+            | (Ty::CoProduct(fields), Ty::CoProduct(another_fields))
+            | (Ty::Record(fields), Ty::Record(another_fields)) => {
+                if fields.len() != another_fields.len() {
+                    return Err(format!("{} and {} have different entries", left, right));
+                }
+                for (name, ty) in fields {
+                    match another_fields.iter().find(|(x, _)| x == name) {
+                        Some((_, another_ty)) => self.unify_with(ty, another_ty, assumptions)?,
+                        None => return Err(format!("{} has no entry {}", right, name)),
+                    }
+                }
+                Ok(())
+            },
+            (Ty::OpenProduct(_, _), Ty::Product(_))
+            | (Ty::OpenProduct(_, _), Ty::OpenProduct(_, _))
+            | (Ty::Product(_), Ty::OpenProduct(_, _))
+            | (Ty::OpenCoProduct(_, _), Ty::CoProduct(_))
+            | (Ty::OpenCoProduct(_, _), Ty::OpenCoProduct(_, _))
+            | (Ty::CoProduct(_), Ty::OpenCoProduct(_, _)) => self.unify_rows(&left, &right, assumptions),
+            _ => Err(format!("{} is not {}", left, right)),
+        }
+    }
+
+    // the entries one row lacks have to be in its tail
+    fn unify_rows(&mut self, left: &Ty, right: &Ty, assumptions: &mut Vec<(Ty, Ty)>) -> Result<(), String> {
+        let (left, right) = (self.zonk(left), self.zonk(right));
+        let (product, fields, tail) = row(&left).unwrap();
+        let (_, another_fields, another_tail) = row(&right).unwrap();
+        for (name, ty) in fields {
+            if let Some((_, another_ty)) = another_fields.iter().find(|(x, _)| x == name) {
+                self.unify_with(ty, another_ty, assumptions)?;
+            }
+        }
+        let only = |xs: &[(String, Ty)], ys: &[(String, Ty)]| -> Vec<(String, Ty)> {
+            xs.iter().filter(|(x, _)| !ys.iter().any(|(y, _)| x == y)).cloned().collect()
+        };
+        let (lacking_right, lacking_left) = (only(fields, another_fields), only(another_fields, fields));
+        match (tail, another_tail) {
+            (Some(tail), Some(another_tail)) if tail == another_tail => {
+                match lacking_right.first().or_else(|| lacking_left.first()) {
+                    Some((name, _)) => Err(format!("{} and {} differ in {}", left, right, name)),
+                    None => Ok(()),
+                }
+            },
+            (Some(tail), Some(another_tail)) => {
+                let rest = self.fresh();
+                self.unify_with(tail, &extend_row(product, lacking_left, Some(rest.clone())), assumptions)?;
+                self.unify_with(another_tail, &extend_row(product, lacking_right, Some(rest)), assumptions)
+            },
+            (Some(tail), None) => match lacking_right.first() {
+                Some((name, _)) => Err(format!("{} has no entry {}", right, name)),
+                None => self.unify_with(tail, &extend_row(product, lacking_left, None), assumptions),
+            },
+            (None, Some(another_tail)) => match lacking_left.first() {
+                Some((name, _)) => Err(format!("{} has no entry {}", left, name)),
+                None => self.unify_with(another_tail, &extend_row(product, lacking_right, None), assumptions),
+            },
+            (None, None) => Err(format!("{} is not {}", left, right)),
+        }
+    }
+
+    // every type is below Top and above Bottom, records may have more fields than asked for,
+    // variants fewer constructors, functions take more and give less; unknown types are unified
+    fn subtype(&mut self, sub: &Ty, sup: &Ty, assumptions: &mut Vec<(Ty, Ty)>) -> Result<(), String> {
+        let sub = self.resolve(sub);
+        let sup = self.resolve(sup);
+        if self.zonk(&sub) == self.zonk(&sup) {
+            return Ok(());
+        }
+        if self.reducible(&sub) || self.reducible(&sup) {
+            let (reduced_sub, reduced_sup) = (self.normalise(&sub)?, self.normalise(&sup)?);
+            if reduced_sub != sub || reduced_sup != sup {
+                return self.subtype(&reduced_sub, &reduced_sup, assumptions);
+            }
+        }
+        match (&sub, &sup) {
+            (_, Ty::Atomic(AtomicType::Top)) | (Ty::Atomic(AtomicType::Bottom), _) => Ok(()),
+            (Ty::Meta(_), _) | (_, Ty::Meta(_)) => self.unify_with(&sub, &sup, assumptions),
+            (Ty::Universe(x), Ty::Universe(y)) if self.cumulative && y.includes(x) => Ok(()),
+            (Ty::Function(from, to), Ty::Function(another_from, another_to))
+            | (Ty::Function(from, to), Ty::Pi(Visibility::Explicit, _, another_from, another_to))
+            | (Ty::Pi(Visibility::Explicit, _, from, to), Ty::Function(another_from, another_to)) => {
+                self.subtype(another_from, from, assumptions)?;
+                self.subtype(to, another_to, assumptions)
+            },
+            (Ty::Pi(visibility, x, from, to), Ty::Pi(another_visibility, y, another_from, another_to))
+                if visibility == another_visibility =>
+            {
+                self.subtype(another_from, from, assumptions)?;
+                let (to, another_to) = common_binder(x, to, y, another_to);
+                self.subtype(&to, &another_to, assumptions)
+            },
+            (Ty::Product(fields), Ty::Product(another_fields)) => {
+                for (name, another_ty) in another_fields {
+                    match fields.iter().find(|(x, _)| x == name) {
+                        Some((_, ty)) => self.subtype(ty, another_ty, assumptions)?,
+                        None => return Err(format!("{} has no field {}", sub, name)),
+                    }
+                }
+                Ok(())
+            },
+            (Ty::CoProduct(constructors), Ty::CoProduct(another_constructors)) => {
+                for (name, ty) in constructors {
+                    match another_constructors.iter().find(|(x, _)| x == name) {
+                        Some((_, another_ty)) => self.subtype(ty, another_ty, assumptions)?,
+                        None => return Err(format!("{} has no constructor {}", sup, name)),
+                    }
+                }
+                Ok(())
+            },
+            // a variant with a row, its payloads may be smaller and the rows are unified
+            (Ty::OpenCoProduct(_, _) | Ty::CoProduct(_), Ty::OpenCoProduct(_, _) | Ty::CoProduct(_)) => {
+                let (_, constructors, tail) = row(&sub).unwrap();
+                let (_, another_constructors, _) = row(&sup).unwrap();
+                let mut widened = vec![];
+                for (name, ty) in constructors {
+                    match another_constructors.iter().find(|(x, _)| x == name) {
+                        Some((_, another_ty)) => {
+                            self.subtype(ty, another_ty, assumptions)?;
+                            widened.push((name.clone(), another_ty.clone()));
+                        },
+                        None => widened.push((name.clone(), ty.clone())),
+                    }
+                }
+                self.unify_with(&extend_row(false, widened, tail.cloned()), &sup, assumptions)
+            },
+            // arguments of named types follow the variance of their parameters, unused ones are taken as covariant
+            (Ty::Named(x, arguments), Ty::Named(y, another_arguments)) if x == y => {
+                let variances = self.definitions[x].variances.clone();
+                for ((argument, another), variance) in arguments.iter().zip(another_arguments).zip(variances) {
+                    match variance {
+                        Variance::Contravariant => self.subtype(another, argument, assumptions)?,
+                        Variance::Invariant => self.unify_with(argument, another, assumptions)?,
+                        Variance::Unused | Variance::Covariant => self.subtype(argument, another, assumptions)?,
+                    }
+                }
+                Ok(())
+            },
+            (Ty::Named(name, arguments), _) | (_, Ty::Named(name, arguments)) => {
+                let pair = (self.zonk(&sub), self.zonk(&sup));
+                if assumptions.contains(&pair) {
+                    return Ok(());
+                }
+                assumptions.push(pair);
+                let unfolded = self.unfold(name, arguments)?;
+                match sub {
+                    Ty::Named(_, _) => self.subtype(&unfolded, &sup, assumptions),
+                    _ => self.subtype(&sub, &unfolded, assumptions),
+                }
+            },
+            _ => self.unify_with(&sub, &sup, assumptions),
+        }
+    }
+
+    // the actual type has to fit where the expected one is asked for
+    fn subsume(
+        &mut self,
+        expected: &Ty,
+        actual: &Ty,
+        expected_at: Option<Span>,
+        actual_at: Option<Span>,
+    ) -> Result<(), String> {
+        self.subtype(actual, expected, &mut vec![]).map_err(|reason| {
+            let (expected, actual) = (self.zonk(expected), self.zonk(actual));
+            let error = format!(
+                "{} ({}) is not a subtype of {} ({})",
+                actual, print_span(actual_at), expected, print_span(expected_at),
+            );
+            if reason == format!("{} is not {}", actual, expected) {
+                error
+            } else {
+                format!("{}: {}", error, reason)
+            }
+        })
+    }
+
+    fn expect(
+        &mut self,
+        expected: &Ty,
+        actual: &Ty,
+        expected_at: Option<Span>,
+        actual_at: Option<Span>,
+    ) -> Result<(), String> {
+        if let (Ty::Universe(x), Ty::Universe(y)) = (self.resolve(expected), self.resolve(actual)) {
+            if self.cumulative && x.includes(&y) {
+                return Ok(());
+            }
+        }
+        self.unify_with(expected, actual, &mut vec![]).map_err(|reason| {
+            let (expected, actual) = (self.zonk(expected), self.zonk(actual));
+            let error = format!(
+                "cannot unify {} ({}) with {} ({})",
+                expected, print_span(expected_at), actual, print_span(actual_at),
+            );
+            if reason == format!("{} is not {}", expected, actual) {
+                error
+            } else {
+                format!("{}: {}", error, reason)
+            }
+        })
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<Ty> {
+        let scheme = self
+            .locals
+            .iter()
+            .rev()
+            .find(|(x, _)| x == name)
+            .map(|(_, x)| x.clone())
+            .or_else(|| self.globals.get(name).cloned())
+            .or_else(|| primitive(name).map(|x| Scheme(vec![], primitive_type(x))))?;
+        Some(self.instantiate(&scheme))
+    }
+
+    // constructors of no type definition build open variants
+    fn constructor(&mut self, name: &str) -> Result<(Ty, Ty), String> {
+        let typ = match self.constructors.get(name) {
+            Some(typ) => typ.clone(),
+            None => {
+                let payload = self.fresh();
+                let open = Ty::OpenCoProduct(vec![(name.to_string(), payload.clone())], Box::new(self.fresh()));
+                return Ok((open, payload));
+            },
+        };
+        let definition = &self.definitions[&typ];
+        let arguments: Vec<Ty> = definition.parameters.clone().iter().map(|_| self.fresh()).collect();
+        let payload = match row(&self.unfold(&typ, &arguments)?) {
+            Some((false, constructors, _)) => constructors.iter().find(|(x, _)| x == name).unwrap().1.clone(),
+            _ => unreachable!(),
+        };
+        Ok((Ty::Named(typ, arguments), payload))
+    }
+
+    // the value a pattern of type `ty` matches, when it has one, binders stand for themselves and a payload of .
+    // left out for the empty tuple
+    fn pattern_term(&mut self, pattern: &Pattern, ty: &Ty) -> Result<Option<Ty>, String> {
+        Ok(match pattern {
+            Pattern::Binder(name) => Some(Ty::Rigid(name.get_name().to_string())),
+            Pattern::Wildcard => match self.structure(ty)? {
+                Ty::Atomic(AtomicType::Top) => Some(Ty::Record(vec![])),
+                Ty::Product(fields) if fields.is_empty() => Some(Ty::Record(vec![])),
+                _ => None,
+            },
+            Pattern::Constructor(name, payload) => {
+                // only left out payloads and tuples look at their type
+                let payload_ty = match **payload {
+                    Pattern::Wildcard | Pattern::Tuple(_) => self.constructor(name.get_name())?.1,
+                    _ => ty.clone(),
+                };
+                self.pattern_term(payload, &payload_ty)?.map(|x| Ty::Either(name.get_name().to_string(), Box::new(x)))
+            },
+            Pattern::Tuple(items) => match self.structure(ty)? {
+                Ty::Product(fields) if fields.len() == items.len() => {
+                    let mut terms = vec![];
+                    for (item, (_, ty)) in items.iter().zip(fields) {
+                        match self.pattern_term(item, &ty)? {
+                            Some(term) => terms.push(term),
+                            None => return Ok(None),
+                        }
+                    }
+                    Some(Ty::Record(index_fields(terms)))
+                },
+                _ => None,
+            },
+            Pattern::Literal(value) => Some(Ty::Literal(value.clone())),
+            Pattern::Refl => Some(Ty::Refl),
+            Pattern::Record(_) | Pattern::Or(_) => None,
+        })
+    }
+
+    fn view(&self, ty: &Ty) -> Result<TypeView<Ty>, String> {
+        match self.normalise(ty)? {
+            Ty::Named(name, arguments) => self.view(&self.unfold(&name, &arguments)?),
+            Ty::Product(fields) | Ty::OpenProduct(fields, _) => Ok(TypeView::Product(fields)),
+            Ty::CoProduct(constructors) => Ok(TypeView::Sum(constructors)),
+            Ty::Atomic(AtomicType::Bottom) => Ok(TypeView::Empty),
+            _ => Ok(TypeView::Opaque),
+        }
+    }
+
+    fn structure(&self, ty: &Ty) -> Result<Ty, String> {
+        match self.normalise(ty)? {
+            Ty::Named(name, arguments) => self.structure(&self.unfold(&name, &arguments)?),
+            other => Ok(other),
+        }
+    }
+
+    fn check_pattern(&mut self, pattern: &Pattern, ty: &Ty, bindings: &mut Vec<(String, Ty)>) -> Result<(), String> {
+        match pattern {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Binder(name) => {
+                bindings.push((name.get_name().to_string(), ty.clone()));
+                Ok(())
+            },
+            Pattern::Constructor(name, inner) => {
+                let (typ, payload) = self.constructor(name.get_name())
+                    .map_err(|error| format!("{} {}", error, print_span(name.get_span())))?;
+                self.expect(ty, &typ, None, name.get_span())?;
+                self.check_pattern(inner, &payload, bindings)
+            },
+            Pattern::Tuple(items) => {
+                let types: Vec<Ty> = items.iter().map(|_| self.fresh()).collect();
+                self.expect(ty, &Ty::Product(index_fields(types.clone())), None, None)
+                    .map_err(|error| format!("in pattern {}: {}", pattern, error))?;
+                items.iter().zip(types).try_for_each(|(item, ty)| self.check_pattern(item, &ty, bindings))
+            },
+            Pattern::Record(fields) => {
+                let known = match self.structure(ty)? {
+                    Ty::Product(known) => known,
+                    // records not known to be closed are asked for the fields the pattern names
+                    Ty::Meta(_) | Ty::OpenProduct(_, _) => {
+                        let wanted: Vec<(String, Ty)> = fields
+                            .iter()
+                            .map(|(name, _)| (name.get_name().to_string(), self.fresh()))
+                            .collect();
+                        let open = Ty::OpenProduct(wanted.clone(), Box::new(self.fresh()));
+                        self.expect(ty, &open, None, None).map_err(|error| format!("in pattern {}: {}", pattern, error))?;
+                        wanted
+                    },
+                    other => return Err(format!("record pattern {} can't match {}", pattern, other)),
+                };
+                for (name, inner) in fields {
+                    match known.iter().find(|(x, _)| x == name.get_name()) {
+                        Some((_, field)) => self.check_pattern(inner, &field.clone(), bindings)?,
+                        None => return Err(format!(
+                            "{} has no field {} {}", self.zonk(ty), name, print_span(name.get_span())
+                        )),
+                    }
+                }
+                Ok(())
+            },
+            Pattern::Refl => {
+                let equal = Ty::Equal(Box::new(self.fresh()), Box::new(self.fresh()));
+                self.expect(ty, &equal, None, None).map_err(|error| format!("in pattern refl: {}", error))?;
+                self.assume_equal(ty)
+            },
+            Pattern::Literal(AtomicValue::Int(_)) => self.expect(ty, &Ty::Atomic(AtomicType::Int), None, None),
+            Pattern::Literal(AtomicValue::StringLiteral(_)) => {
+                self.expect(ty, &Ty::Atomic(AtomicType::String), None, None)
+            },
+            Pattern::Or(alternatives) => {
+                let mut first = vec![];
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    let mut current = vec![];
+                    self.check_pattern(alternative, ty, &mut current)?;
+                    if i == 0 {
+                        first = current;
+                        continue;
+                    }
+                    // every alternative binds the same names, whichever one matched the arm sees all of them
+                    let missing = first.iter().any(|(x, _)| !current.iter().any(|(y, _)| x == y));
+                    if missing || current.len() != first.len() {
+                        return Err(format!("alternatives of {} bind different names", pattern));
+                    }
+                    for (name, ty) in current {
+                        let expected = first.iter().find(|(x, _)| *x == name).map(|(_, x)| x.clone());
+                        match expected {
+                            Some(expected) => self.expect(&expected, &ty, None, None)
+                                .map_err(|error| format!("{} in the alternatives of {}: {}", name, pattern, error))?,
+                            None => return Err(format!("alternatives of {} bind different names", pattern)),
+                        }
+                    }
+                }
+                bindings.extend(first);
+                Ok(())
+            },
+        }
+    }
+
+    // in an arm matching refl against a proof of a == b, a variable side stands for the other side
+    fn assume_equal(&mut self, ty: &Ty) -> Result<(), String> {
+        let (left, right) = match self.structure(ty)? {
+            Ty::Equal(left, right) => (self.normalise(&left)?, self.normalise(&right)?),
+            other => return Err(format!("refl can't match {}", other)),
+        };
+        if matches!(left, Ty::Meta(_)) || matches!(right, Ty::Meta(_)) || self.normaliser().equal(&left, &right)? {
+            return self.unify_with(&left, &right, &mut vec![]);
+        }
+        match (&left, &right) {
+            (_, Ty::Rigid(name)) if !left.mentions(name) => self.equations.push((name.clone(), left)),
+            (Ty::Rigid(name), _) if !right.mentions(name) => self.equations.push((name.clone(), right)),
+            _ => {
+                let equal = Ty::Equal(Box::new(left), Box::new(right));
+                return Err(format!("refl can't match a proof of {}, neither side is a variable", equal));
+            },
+        }
+        Ok(())
+    }
+
+    pub fn infer_expr(&mut self, expr: &Expr) -> Result<Ty, String> {
+        let Expr(lets, value) = expr;
+        let (depth, equations) = (self.locals.len(), self.equations.len());
+        let mut result = Ok(());
+        for local in lets {
+            if let Err(error) = self.infer_let(local).and_then(|scheme| self.bind_local(local, scheme)) {
+                result = Err(error);
+                break;
+            }
+        }
+        let result = result.and_then(|_| self.infer(value)).map(|ty| {
+            // local types leaving their scope are replaced by what they are
+            let ty = self.zonk(&ty);
+            self.equations.drain(equations..).rev().fold(ty, |ty, (name, term)| {
+                Inference::substitute(&ty, &vec![(name, term)].into_iter().collect())
+            })
+        });
+        self.locals.truncate(depth);
+        self.equations.truncate(equations);
+        result
+    }
+
+    // a local type is the same as what it is defined to be, like a definition
+    fn bind_local(&mut self, Let(name, value, _): &Let, scheme: Scheme) -> Result<(), String> {
+        let is_type = matches!(self.resolve(&scheme.1), Ty::Universe(_));
+        self.locals.push((name.get_name().to_string(), scheme));
+        if is_type {
+            let term = self.to_term(value, &HashMap::new())?;
+            self.equations.push((name.get_name().to_string(), term));
+        }
+        Ok(())
+    }
+
+    fn annotation(&self, typ: &Type) -> Result<Scheme, String> {
+        let mut free = Some(vec![]);
+        let ty = self.convert(typ, &HashMap::new(), &mut free)?;
+        Ok(Scheme(free.unwrap_or_default(), ty))
+    }
+
+    fn check_against(&mut self, value: &Value, scheme: &Scheme, at: Option<Span>) -> Result<(), String> {
+        let Scheme(variables, expected) = scheme;
+        let (rigid, locals, given) = (self.rigid.len(), self.locals.len(), self.given.len());
+        let constraints = self.constraints.len();
+        self.rigid.extend(variables.iter().cloned());
+        let result = self.check_with_instances(value, expected, at)
+            .and_then(|_| self.solve_constraints(constraints));
+        self.rigid.truncate(rigid);
+        self.locals.truncate(locals);
+        self.given.truncate(given);
+        result
+    }
+
+    // the instance parameters a definition starts with can be used as its instance arguments
+    fn check_with_instances(&mut self, value: &Value, expected: &Ty, at: Option<Span>) -> Result<(), String> {
+        match self.function_type(expected)? {
+            Ty::Pi(Visibility::Instance, name, from, to) => {
+                self.locals.push((name.clone(), Scheme(vec![], (*from).clone())));
+                self.given.push((name, *from));
+                self.check_with_instances(value, &to, at)
+            },
+            Ty::Pi(Visibility::Implicit, name, from, to) => {
+                self.locals.push((name, Scheme(vec![], *from)));
+                self.check_with_instances(value, &to, at)
+            },
+            _ => self.check(value, expected, at),
+        }
+    }
+
+    // lambdas checked against a dependent function type see their parameters in the result type
+    pub fn check(&mut self, value: &Value, expected: &Ty, at: Option<Span>) -> Result<(), String> {
+        if let Value::Hole(name) = value {
+            self.record_goal(name, Some(expected.clone()));
+            return Ok(());
+        }
+        // implicit parameters are bound without being written
+        match self.function_type(expected)? {
+            Ty::Pi(Visibility::Implicit, name, from, to) => {
+                self.locals.push((name, Scheme(vec![], *from)));
+                let result = self.check(value, &to, at);
+                self.locals.pop();
+                return result;
+            },
+            Ty::Pi(Visibility::Instance, name, _, _) => return Err(misplaced_instance(&name, at)),
+            _ => (),
+        }
+        match value {
+            Value::Function(parameters, body) if !parameters.is_empty() => {
+                self.check_function(parameters, body, expected, at)
+            },
+            Value::Match(scrutinee, arms) => self.infer_match(scrutinee, arms, Some((expected, at))).map(|_| ()),
+            // the constructor of the expected type takes its arguments, the payload is checked against them
+            Value::Either(name, payload) if self.constructors.contains_key(name.get_name()) => {
+                let (typ, payload_ty) = self.constructor(name.get_name())?;
+                let wanted = match self.resolve(expected) {
+                    wanted if self.reducible(&wanted) => self.normalise(&wanted)?,
+                    wanted => wanted,
+                };
+                if let (Ty::Named(x, arguments), Ty::Named(y, given)) = (&typ, &wanted) {
+                    if x == y {
+                        for (argument, given) in arguments.iter().zip(given) {
+                            self.unify_with(argument, given, &mut vec![])?;
+                        }
+                    }
+                }
+                self.check(payload, &payload_ty, name.get_span())?;
+                self.subsume(expected, &typ, at, value.get_span())
+            },
+            _ => {
+                let actual = self.infer(value)?;
+                self.subsume(expected, &actual, at, value.get_span())
+            },
+        }
+    }
+
+    fn check_function(
+        &mut self,
+        parameters: &[(Name, Option<Type>)],
+        body: &Expr,
+        expected: &Ty,
+        at: Option<Span>,
+    ) -> Result<(), String> {
+        let ((name, annotation), rest) = match parameters.split_first() {
+            Some(split) => split,
+            None => return self.check_expr(body, expected, at),
+        };
+        let (from, to) = match self.function_type(expected)? {
+            Ty::Pi(Visibility::Implicit, bound, from, to) => {
+                self.locals.push((bound, Scheme(vec![], *from)));
+                let result = self.check_function(parameters, body, &to, at);
+                self.locals.pop();
+                return result;
+            },
+            Ty::Pi(Visibility::Instance, bound, _, _) => return Err(misplaced_instance(&bound, at)),
+            Ty::Pi(Visibility::Explicit, bound, from, to) => {
+                let mapping = vec![(bound, Ty::Rigid(name.get_name().to_string()))].into_iter().collect();
+                (*from, Inference::substitute(&to, &mapping))
+            },
+            Ty::Function(from, to) => (*from, *to),
+            _ => {
+                let actual = self.infer(&Value::Function(parameters.to_vec(), Box::new(body.clone())))?;
+                return self.subsume(expected, &actual, at, name.get_span());
+            },
+        };
+        // a parameter may ask for less than it is given
+        let from = match annotation {
+            Some(typ) => {
+                let declared = self.convert(typ, &HashMap::new(), &mut None)?;
+                self.subsume(&declared, &from, typ.get_span(), at)?;
+                declared
+            },
+            None => from,
+        };
+        self.locals.push((name.get_name().to_string(), Scheme(vec![], from)));
+        let result = self.check_function(rest, body, &to, at);
+        self.locals.pop();
+        result
+    }
+
+    fn check_expr(&mut self, Expr(lets, value): &Expr, expected: &Ty, at: Option<Span>) -> Result<(), String> {
+        let (depth, equations) = (self.locals.len(), self.equations.len());
+        let mut result = Ok(());
+        for local in lets {
+            if let Err(error) = self.infer_let(local).and_then(|scheme| self.bind_local(local, scheme)) {
+                result = Err(error);
+                break;
+            }
+        }
+        let result = result.and_then(|_| self.check(value, expected, at));
+        self.locals.truncate(depth);
+        self.equations.truncate(equations);
+        result
+    }
+
+    // a local function is given its type while it is checked, the calls it makes to itself are checked against it
+    fn infer_let(&mut self, local: &Let) -> Result<Scheme, String> {
+        let Let(name, value, annotation) = local;
+        let depth = self.locals.len();
+        match annotation {
+            Some(typ) => {
+                let scheme = self.annotation(typ)?;
+                if local.is_recursive() {
+                    self.locals.push((name.get_name().to_string(), scheme.clone()));
+                }
+                let result = self.check_against(value, &scheme, typ.get_span().or(name.get_span()));
+                self.locals.truncate(depth);
+                result.map(|_| scheme)
+            },
+            None => {
+                let own = self.fresh();
+                if local.is_recursive() {
+                    self.locals.push((name.get_name().to_string(), Scheme(vec![], own.clone())));
+                }
+                let ty = self.infer(value)
+                    .and_then(|ty| self.expect(&own, &ty, name.get_span(), value.get_span()).map(|_| ty))
+                    .map_err(|error| format!("in {}: {}", name, error));
+                self.locals.truncate(depth);
+                let ty = ty?;
+                let skip = self.environment_metas();
+                Ok(self.generalise(&ty, &skip))
+            },
+        }
+    }
+
+    pub fn infer(&mut self, value: &Value) -> Result<Ty, String> {
+        match value {
+            Value::Var(name) => {
+                let ty = self.lookup_name(name)?;
+                self.insert_implicits(ty, value)
+            },
+            Value::Tuple(items) => {
+                let types = items.iter().map(|x| self.infer(x)).collect::<Result<Vec<Ty>, String>>()?;
+                Ok(Ty::Product(index_fields(types)))
+            },
+            Value::Record(fields) => Ok(Ty::Product(
+                fields
+                    .iter()
+                    .map(|(name, x)| Ok((name.get_name().to_string(), self.infer(x)?)))
+                    .collect::<Result<_, String>>()?
+            )),
+            Value::Either(name, payload) => {
+                let (typ, expected) = self.constructor(name.get_name())
+                    .map_err(|error| format!("{} {}", error, print_span(name.get_span())))?;
+                let actual = self.infer(payload)?;
+                self.subsume(&expected, &actual, name.get_span(), payload.get_span())?;
+                Ok(typ)
+            },
+            Value::Match(scrutinee, arms) => self.infer_match(scrutinee, arms, None),
+            Value::Function(parameters, body) => {
+                // later annotations may mention earlier parameters
+                let depth = self.locals.len();
+                let mut result = Ok(());
+                for (name, annotation) in parameters {
+                    let ty = match annotation {
+                        Some(typ) => match self.convert(typ, &HashMap::new(), &mut None) {
+                            Ok(ty) => ty,
+                            Err(error) => {
+                                result = Err(error);
+                                break;
+                            },
+                        },
+                        None => self.fresh(),
+                    };
+                    self.locals.push((name.get_name().to_string(), Scheme(vec![], ty)));
+                }
+                let result = result.and_then(|_| self.infer_expr(body));
+                let types: Vec<(String, Ty)> = self.locals
+                    .drain(depth..)
+                    .map(|(name, Scheme(_, ty))| (name, ty))
+                    .collect();
+                Ok(types.into_iter().rev().fold(result?, |to, (name, from)| {
+                    if self.zonk(&to).mentions(&name) {
+                        Ty::Pi(Visibility::Explicit, name, Box::new(from), Box::new(to))
+                    } else {
+                        Ty::Function(Box::new(from), Box::new(to))
+                    }
+                }))
+            },
+            Value::Application(function, arguments) => {
+                // implicits are filled in lazily so that `@{...}` can still give them
+                let mut ty = match &**function {
+                    Value::Var(name) => self.lookup_name(name)?,
+                    _ => self.infer(function)?,
+                };
+                for argument in arguments {
+                    if let Value::Implicit(argument) = argument {
+                        match self.function_type(&ty)? {
+                            Ty::Pi(Visibility::Implicit, name, from, to) => {
+                                self.check(argument, &from, function.get_span())?;
+                                let mapping = vec![(name, self.to_term(argument, &HashMap::new())?)].into_iter().collect();
+                                ty = Inference::substitute(&to, &mapping);
+                            },
+                            other => return Err(format!(
+                                "{} of type {} takes no implicit argument {}",
+                                describe(function), other, print_span(argument.get_span())
+                            )),
+                        }
+                        continue;
+                    }
+                    ty = self.insert_implicits(ty, function)?;
+                    if let Ty::Pi(_, name, from, to) = self.function_type(&ty)? {
+                        self.check(argument, &from, function.get_span())?;
+                        let mapping = vec![(name, self.to_term(argument, &HashMap::new())?)].into_iter().collect();
+                        ty = Inference::substitute(&to, &mapping);
+                        continue;
+                    }
+                    let actual = self.infer(argument)?;
+                    ty = match self.function_type(&ty)? {
+                        Ty::Function(from, to) => {
+                            self.subsume(&from, &actual, function.get_span(), argument.get_span())?;
+                            *to
+                        },
+                        other => {
+                            let to = self.fresh();
+                            let expected = Ty::Function(Box::new(actual), Box::new(to.clone()));
+                            self.expect(&other, &expected, function.get_span(), argument.get_span())?;
+                            to
+                        },
+                    };
+                }
+                self.insert_implicits(ty, function)
+            },
+            Value::Constant(AtomicValue::Int(_), _) => Ok(Ty::Atomic(AtomicType::Int)),
+            Value::Constant(AtomicValue::StringLiteral(_), _) => Ok(Ty::Atomic(AtomicType::String)),
+            Value::Type(typ) => {
+                let ty = self.convert(typ, &HashMap::new(), &mut None)?;
+                self.check_levels(&ty, &HashMap::new())
+                    .map_err(|error| format!("{} {}", error, print_span(typ.get_span())))?;
+                Ok(Ty::Universe(self.level_of(&ty, &HashMap::new())?))
+            },
+            Value::Hole(name) => {
+                let expected = self.fresh();
+                self.record_goal(name, Some(expected.clone()));
+                Ok(expected)
+            },
+            Value::Implicit(_) => Err(format!(
+                "implicit arguments can only be passed to functions {}", print_span(value.get_span())
+            )),
+            Value::Refl => {
+                let side = self.fresh();
+                Ok(Ty::Equal(Box::new(side.clone()), Box::new(side)))
+            },
+        }
+    }
+
+    // arms are checked against the expected type when there is one, so that refl can rewrite it
+    fn infer_match(
+        &mut self,
+        scrutinee: &Value,
+        arms: &[(Pattern, Value)],
+        expected: Option<(&Ty, Option<Span>)>,
+    ) -> Result<Ty, String> {
+        let scrutinee_ty = self.infer(scrutinee)?;
+        let result = match expected {
+            Some((expected, _)) => expected.clone(),
+            None => self.fresh(),
+        };
+        let mut result_at = expected.and_then(|(_, at)| at);
+        for (pattern, body) in arms {
+            let (depth, equations) = (self.locals.len(), self.equations.len());
+            let mut bindings = vec![];
+            let checked = self.check_pattern(pattern, &scrutinee_ty, &mut bindings).and_then(|_| {
+                // a matched variable is what the pattern matches in the arm, a proof is refl itself
+                if let Value::Var(matched) = scrutinee {
+                    let name = matched.get_name();
+                    if self.locals.iter().any(|(x, _)| x == name) {
+                        if let Some(term) = self.pattern_term(pattern, &scrutinee_ty)?.filter(|x| !x.mentions(name)) {
+                            self.equations.push((name.to_string(), term));
+                        }
+                    }
+                }
+                self.locals.extend(bindings.into_iter().map(|(name, ty)| (name, Scheme(vec![], ty))));
+                match expected {
+                    Some((expected, at)) => self.check(body, expected, at),
+                    None => {
+                        let body_ty = self.infer(body)?;
+                        self.expect(&result, &body_ty, result_at, body.get_span())
+                    },
+                }
+            });
+            self.locals.truncate(depth);
+            self.equations.truncate(equations);
+            checked?;
+            result_at = result_at.or_else(|| body.get_span());
+        }
+        // without a catch-all arm an open variant is closed to the constructors matched
+        if let Ty::OpenCoProduct(_, tail) = self.structure(&scrutinee_ty)? {
+            let total = arms.iter().any(|(pattern, _)| matches!(pattern, Pattern::Wildcard | Pattern::Binder(_)));
+            if matches!(*tail, Ty::Meta(_)) && !total {
+                self.unify_with(&tail, &Ty::CoProduct(vec![]), &mut vec![])?;
+            }
+        }
+        let patterns: Vec<&Pattern> = arms.iter().map(|(pattern, _)| pattern).collect();
+        check_exhaustiveness(&patterns, &scrutinee_ty, &|x| self.view(x))
+            .map_err(|error| format!("{} {}", error, print_span(scrutinee.get_span())))?;
+        Ok(result)
+    }
+
+    fn lookup_name(&mut self, name: &Name) -> Result<Ty, String> {
+        self.lookup(name.get_name())
+            .ok_or(format!("unbound name {} {}", name, print_span(name.get_span())))
+    }
+
+    // `{A : @0} -> A -> A` used as a value becomes `?m -> ?m`, the meta is solved by unification
+    // `[d : Eq A] -> ...` leaves a constraint, solved by instance search once the definition is checked
+    fn insert_implicits(&mut self, mut ty: Ty, head: &Value) -> Result<Ty, String> {
+        let mut first = true;
+        loop {
+            let (visibility, name, from, to) = match self.function_type(&ty)? {
+                Ty::Pi(visibility, name, from, to) if visibility != Visibility::Explicit => (visibility, name, from, to),
+                _ => return Ok(ty),
+            };
+            let meta = self.fresh();
+            match (visibility, head, &meta) {
+                (Visibility::Instance, Value::Var(site), _) => {
+                    self.constraints.push((site.clone(), first, *from, None));
+                    first = false;
+                },
+                (Visibility::Instance, _, _) => return Err(format!(
+                    "instance arguments are only found for named functions {}", print_span(head.get_span())
+                )),
+                (_, _, Ty::Meta(id)) => self.implicits.push((*id, name.clone(), describe(head), head.get_span())),
+                _ => (),
+            }
+            let mapping = vec![(name, meta)].into_iter().collect();
+            ty = Inference::substitute(&to, &mapping);
+        }
+    }
+
+    // instance arguments are looked for once their types are known, at the end of a definition
+    fn solve_constraints(&mut self, from: usize) -> Result<(), String> {
+        for index in from..self.constraints.len() {
+            if self.constraints[index].3.is_some() {
+                continue;
+            }
+            let (site, _, ty, _) = self.constraints[index].clone();
+            let dictionary = self.find_instance(&ty, 0)
+                .map_err(|error| format!("{} for {} {}", error, site, print_span(site.get_span())))?;
+            self.constraints[index].3 = Some(dictionary);
+        }
+        Ok(())
+    }
+
+    // instance parameters in scope come first, then instances in program order, exactly one must fit
+    fn find_instance(&mut self, ty: &Ty, depth: usize) -> Result<Value, String> {
+        let ty = self.zonk(ty);
+        if depth > INSTANCE_DEPTH {
+            return Err(format!("instance search for {} does not terminate", ty));
+        }
+        let mut metas = vec![];
+        self.metas(&ty, &mut metas);
+        if !metas.is_empty() {
+            return Err(format!("ambiguous instance {}", ty));
+        }
+        let candidates: Vec<(String, bool)> = self.given
+            .iter()
+            .rev()
+            .map(|(name, _)| (name.clone(), true))
+            .chain(self.instances.iter().map(|name| (name.clone(), false)))
+            .collect();
+        let mut found = vec![];
+        for candidate in candidates {
+            let saved = self.substitution.clone();
+            if self.try_instance(&candidate, &ty).is_ok() {
+                found.push(candidate);
+            }
+            self.substitution = saved;
+        }
+        match &found[..] {
+            [] => Err(format!("no instance {}", ty)),
+            [candidate] => {
+                let needed = self.try_instance(candidate, &ty)?;
+                let dictionary = Value::Var(Name::new(candidate.0.clone(), Context::ValueContext));
+                if needed.is_empty() {
+                    return Ok(dictionary);
+                }
+                let arguments = needed
+                    .iter()
+                    .map(|x| self.find_instance(x, depth + 1))
+                    .collect::<Result<Vec<Value>, String>>()?;
+                Ok(Value::Application(Box::new(dictionary), arguments))
+            },
+            _ => {
+                let names: Vec<&str> = found.iter().map(|(name, _)| name.as_str()).collect();
+                Err(format!("overlapping instances {} for {}", names.join(" and "), ty))
+            },
+        }
+    }
+
+    // unifies the candidate with the wanted type, returns the instances the candidate needs
+    fn try_instance(&mut self, (name, given): &(String, bool), wanted: &Ty) -> Result<Vec<Ty>, String> {
+        let mut ty = if *given {
+            self.given.iter().rev().find(|(x, _)| x == name).map(|(_, ty)| ty.clone()).unwrap()
+        } else {
+            let scheme = self.globals[name].clone();
+            self.instantiate(&scheme)
+        };
+        let mut needed = vec![];
+        while let Ty::Pi(visibility, binder, from, to) = self.function_type(&ty)? {
+            match visibility {
+                Visibility::Implicit => (),
+                Visibility::Instance => needed.push(*from),
+                Visibility::Explicit => break,
+            }
+            let mapping = vec![(binder, self.fresh())].into_iter().collect();
+            ty = Inference::substitute(&to, &mapping);
+        }
+        self.unify_with(&ty, wanted, &mut vec![])?;
+        Ok(needed)
+    }
+
+    // the dictionaries found for each use of a name with instance parameters, in checking order
+    pub fn dictionaries(&self) -> Vec<(&Name, Vec<&Value>)> {
+        let mut result: Vec<(&Name, Vec<&Value>)> = vec![];
+        for (site, first, _, dictionary) in &self.constraints {
+            let dictionary = match dictionary {
+                Some(dictionary) => dictionary,
+                None => continue,
+            };
+            match result.last_mut() {
+                Some((_, found)) if !first => found.push(dictionary),
+                _ => result.push((site, vec![dictionary])),
+            }
+        }
+        result
+    }
+
+    // implicits of a definition must be solved, unless they became part of its generalised type
+    fn check_implicits(&self, from: usize, ty: &Ty) -> Result<(), String> {
+        let mut free = vec![];
+        self.metas(ty, &mut free);
+        for (id, name, function, at) in &self.implicits[from..] {
+            if let Ty::Meta(id) = self.zonk(&Ty::Meta(*id)) {
+                if !free.contains(&id) {
+                    return Err(format!(
+                        "cannot infer the implicit argument {} of {} {}", name, function, print_span(*at)
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn register_definition(&mut self, Let(name, value, annotation): &Let) -> Result<bool, String> {
+        if primitive(name.get_name()).is_some() {
+            return Err(format!("{} is the name of a primitive {}", name, print_span(name.get_span())));
+        }
+        let (parameters, body) = match as_type_definition(value) {
+            Some(definition) => definition,
+            None => return Ok(false),
+        };
+        let kind = match annotation {
+            Some(typ) if is_kind(typ) => self.convert(typ, &HashMap::new(), &mut None)?,
+            Some(typ) => return Err(format!("type definition {} can't have type {}", name, typ)),
+            None => parameters.iter().fold(Ty::Universe(UniverseLevel::constant(0)), |to, _| {
+                Ty::Function(Box::new(Ty::Universe(UniverseLevel::constant(0))), Box::new(to))
+            }),
+        };
+        let mut kinds = vec![];
+        let mut result = kind.clone();
+        while let Ty::Function(from, to) = result {
+            kinds.push(*from);
+            result = *to;
+        }
+        let level = match result {
+            Ty::Universe(level) if kinds.len() == parameters.len() => level,
+            _ => return Err(format!(
+                "type definition {} has {} parameters but kind {}", name, parameters.len(), kind
+            )),
+        };
+        let variant = match body {
+            Type::Codata(inner) => inner,
+            other => other,
+        };
+        if let Type::CoProduct(constructors) | Type::OpenCoProduct(constructors, _) = variant {
+            for (constructor, _) in constructors {
+                let previous = self.constructors.insert(constructor.get_name().to_string(), name.get_name().to_string());
+                if let Some(previous) = previous {
+                    return Err(format!(
+                        "constructor {} is defined in both {} and {} {}",
+                        constructor, previous, name, print_span(constructor.get_span())
+                    ));
+                }
+            }
+        }
+        self.definitions.insert(name.get_name().to_string(), TypeDefinition {
+            parameters,
+            kinds,
+            level,
+            body: body.clone(),
+            variances: vec![],
+        });
+        self.globals.insert(name.get_name().to_string(), Scheme(vec![], kind));
+        Ok(true)
+    }
+
+    fn check_definition(&self, name: &str) -> Result<(), String> {
+        let definitions: Definitions = self.definitions
+            .iter()
+            .map(|(name, x)| (name.as_str(), (&x.parameters[..], &x.body)))
+            .collect();
+        check_positivity(name, &definitions)?;
+        let definition = &self.definitions[name];
+        let bound = definition.parameters.iter().map(|x| (x.clone(), Ty::Rigid(x.clone()))).collect();
+        let body = self.convert(&definition.body, &bound, &mut None)?;
+
+        // parameters are measured by the universes they were declared in
+        let levels: HashMap<String, UniverseLevel> = definition.parameters
+            .iter()
+            .zip(&definition.kinds)
+            .filter_map(|(x, kind)| match kind {
+                Ty::Universe(level) => Some((x.clone(), level.clone())),
+                _ => None,
+            })
+            .collect();
+        self.check_levels(&body, &levels)?;
+        let level = self.level_of(&body, &levels)?;
+        let fits = definition.level.includes(&level) && (self.cumulative || level.includes(&definition.level));
+        if !fits {
+            return Err(format!("{} lives in @{} but is declared in @{}", body, level, definition.level));
+        }
+        Ok(())
+    }
+
+    // every parameter starts unused and the occurrences are joined until no definition changes
+    fn derive_variances(&mut self) -> Result<(), String> {
+        let mut bodies = vec![];
+        for (name, definition) in &self.definitions {
+            let bound = definition.parameters.iter().map(|x| (x.clone(), Ty::Rigid(x.clone()))).collect();
+            bodies.push((name.clone(), definition.parameters.clone(), self.convert(&definition.body, &bound, &mut None)?));
+        }
+        let mut table: HashMap<String, Vec<Variance>> = bodies
+            .iter()
+            .map(|(name, parameters, _)| (name.clone(), vec![Variance::Unused; parameters.len()]))
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (name, parameters, body) in &bodies {
+                let variances: Vec<Variance> =
+                    parameters.iter().map(|x| occurrences(body, x, Variance::Covariant, &table)).collect();
+                if table[name] != variances {
+                    table.insert(name.clone(), variances);
+                    changed = true;
+                }
+            }
+        }
+        for (name, variances) in table {
+            self.definitions.get_mut(&name).unwrap().variances = variances;
+        }
+        Ok(())
+    }
+
+    // registers the type definitions and the values types may refer to, returns the values
+    fn register_program<'b>(&mut self, program: &'b [Let]) -> Result<Vec<&'b Let>, String> {
+        for name in holes(program) {
+            if !self.holes.iter().any(|(x, _)| x == name.get_name()) {
+                let id = self.substitution.len();
+                self.substitution.push(None);
+                self.holes.push((name.get_name().to_string(), id));
+            }
+        }
+        let mut values = vec![];
+        for definition in program {
+            if !self.register_definition(definition)? {
+                values.push(definition);
+            }
+        }
+        for name in self.definitions.keys().cloned().collect::<Vec<String>>() {
+            self.check_definition(&name).map_err(|error| format!("in type {}: {}", name, error))?;
+        }
+        self.derive_variances()?;
+        // values that can't be read as terms stay opaque inside types
+        for Let(name, value, _) in &values {
+            if let Ok(term) = self.to_term(value, &HashMap::new()) {
+                self.values.insert(name.get_name().to_string(), term);
+            }
+        }
+        Ok(values)
+    }
+
+    // a definition whose type ends in a class, `{A : @0} -> [d : Eq A] -> Eq (List A)`, is an instance
+    fn is_instance(&self, ty: &Ty) -> bool {
+        match ty {
+            Ty::Pi(Visibility::Implicit, _, _, to) | Ty::Pi(Visibility::Instance, _, _, to) => self.is_instance(to),
+            Ty::Named(name, _) => matches!(self.definitions[name].body, Type::Class(_)),
+            _ => false,
+        }
+    }
+
+    fn is_corecursive(&self, ty: &Ty) -> bool {
+        match ty {
+            Ty::Pi(_, _, _, to) | Ty::Function(_, to) => self.is_corecursive(to),
+            Ty::Named(name, _) => matches!(self.definitions[name].body, Type::Codata(_)),
+            _ => false,
+        }
+    }
+
+    pub fn infer_program(&mut self, program: &[Let]) -> Result<HashMap<String, Scheme>, String> {
+        let program: Vec<Let> = program.iter().cloned().chain(methods(program)).collect();
+        let values = self.register_program(&program)?;
+        for Let(name, _, annotation) in &values {
+            let scheme = match annotation {
+                Some(typ) => self.annotation(typ)?,
+                None => Scheme(vec![], self.fresh()),
+            };
+            if self.is_instance(&scheme.1) {
+                self.instances.push(name.get_name().to_string());
+            }
+            if self.is_corecursive(&scheme.1) {
+                self.corecursive.push(name.get_name().to_string());
+            }
+            self.globals.insert(name.get_name().to_string(), scheme);
+        }
+        for Let(name, value, annotation) in values {
+            let text = name.get_name().to_string();
+            let scheme = self.globals[&text].clone();
+            let implicits = self.implicits.len();
+            let constraints = self.constraints.len();
+            match annotation {
+                // a type written without names is found where it is declared
+                Some(typ) => self.check_against(value, &scheme, typ.get_span().or(name.get_span()))
+                    .and_then(|_| self.check_implicits(implicits, &scheme.1))
+                    .map_err(|error| format!("in {}: {}", name, error))?,
+                None => {
+                    let actual = self.infer(value).map_err(|error| format!("in {}: {}", name, error))?;
+                    self.expect(&scheme.1, &actual, name.get_span(), value.get_span())
+                        .and_then(|_| self.solve_constraints(constraints))
+                        .and_then(|_| self.check_implicits(implicits, &actual))
+                        .map_err(|error| format!("in {}: {}", name, error))?;
+                    self.globals.remove(&text);
+                    let skip = self.environment_metas();
+                    let scheme = self.generalise(&actual, &skip);
+                    self.globals.insert(text, scheme);
+                },
+            }
+        }
+        check_termination(&program, &self.terminating, &self.corecursive)?;
+        Ok(self.globals.iter().map(|(name, Scheme(variables, ty))| {
+            (name.clone(), Scheme(variables.clone(), self.zonk(ty)))
+        }).collect())
+    }
+}
+
+// the checker without pragmas, programs are checked with it in tests
+#[cfg(test)]
+pub fn infer_program(program: &[Let]) -> Result<HashMap<String, Scheme>, String> {
+    Inference::new().infer_program(program)
+}
+
+impl Ty {
+    fn print_argument(&self) -> String {
+        match self {
+            Ty::Named(_, arguments) if !arguments.is_empty() => format!("({})", self),
+            Ty::Either(_, _) | Ty::Application(_, _) => format!("({})", self),
+            Ty::Function(_, _) | Ty::Pi(_, _, _, _) | Ty::Product(_) | Ty::CoProduct(_) => format!("({})", self),
+            Ty::OpenProduct(_, _) | Ty::OpenCoProduct(_, _) => format!("({})", self),
+            Ty::Lambda(_, _) | Ty::Match(_, _) | Ty::Equal(_, _) => format!("({})", self),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // printed like the variable it is named once generalised, ?name is a hole
+            Ty::Meta(id) => write!(f, "t{}", id),
+            Ty::Rigid(name) => write!(f, "{}", name),
+            Ty::Named(name, arguments) => {
+                write!(f, "{}", name)?;
+                arguments.iter().try_for_each(|x| write!(f, " {}", x.print_argument()))
+            },
+            Ty::Function(from, to) => match **from {
+                Ty::Function(_, _) | Ty::Pi(_, _, _, _) => write!(f, "({}) -> {}", from, to),
+                _ => write!(f, "{} -> {}", from, to),
+            },
+            Ty::Pi(Visibility::Explicit, name, from, to) => write!(f, "({} : {}) -> {}", name, from, to),
+            Ty::Pi(Visibility::Implicit, name, from, to) => write!(f, "{{{} : {}}} -> {}", name, from, to),
+            Ty::Pi(Visibility::Instance, name, from, to) => write!(f, "[{} : {}] -> {}", name, from, to),
+            Ty::Product(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, x)| if is_positional(name) {
+                        x.print_argument()
+                    } else {
+                        format!("{} {}", name, x.print_argument())
+                    })
+                    .collect();
+                write!(f, "{}", fields.join(" * "))
+            },
+            Ty::CoProduct(constructors) => {
+                let constructors: Vec<String> = constructors
+                    .iter()
+                    .map(|(name, x)| format!("{} {}", name, x.print_argument()))
+                    .collect();
+                write!(f, "{}", constructors.join(" + "))
+            },
+            Ty::OpenProduct(fields, tail) | Ty::OpenCoProduct(fields, tail) if fields.is_empty() => {
+                write!(f, "..{}", tail.print_argument())
+            },
+            Ty::OpenProduct(fields, tail) => write!(f, "{} * ..{}", Ty::Product(fields.clone()), tail.print_argument()),
+            Ty::OpenCoProduct(fields, tail) => write!(f, "{} + ..{}", Ty::CoProduct(fields.clone()), tail.print_argument()),
+            Ty::Universe(level) => {
+                if level.variables.is_empty() || (level.constant == 0 && matches!(level.variables[..], [(_, 0)])) {
+                    write!(f, "@{}", level)
+                } else {
+                    write!(f, "@({})", level)
+                }
+            },
+            Ty::Atomic(atomic) => write!(f, "{}", atomic),
+            Ty::Global(name) => write!(f, "{}", name),
+            Ty::Lambda(name, body) => write!(f, "{} ~> {}", name, body),
+            Ty::Application(function, argument) => match **function {
+                Ty::Application(_, _) => write!(f, "{} {}", function, argument.print_argument()),
+                _ => write!(f, "{} {}", function.print_argument(), argument.print_argument()),
+            },
+            Ty::Record(fields) if fields.is_empty() => write!(f, "."),
+            Ty::Record(fields) => {
+                let positional = fields.iter().all(|(name, _)| is_positional(name));
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, x)| if positional { x.to_string() } else { format!("{} = {}", name, x) })
+                    .collect();
+                if positional {
+                    write!(f, "({})", fields.join(", "))
+                } else {
+                    write!(f, "{{{}}}", fields.join(", "))
+                }
+            },
+            Ty::Either(name, payload) => write!(f, "{} {}", name, payload.print_argument()),
+            Ty::Match(scrutinee, arms) => {
+                write!(f, "{}", scrutinee.print_argument())?;
+                arms.iter().try_for_each(|(pattern, body)| write!(f, " | {} -> {}", pattern, body))
+            },
+            Ty::Literal(value) => write!(f, "{}", value),
+            Ty::Equal(left, right) => {
+                let side = |x: &Ty| match x {
+                    Ty::Equal(_, _) | Ty::Function(_, _) | Ty::Pi(_, _, _, _) | Ty::Lambda(_, _) | Ty::Match(_, _) => {
+                        format!("({})", x)
+                    },
+                    _ => x.to_string(),
+                };
+                write!(f, "{} == {}", side(left), side(right))
+            },
+            Ty::Refl => write!(f, "refl"),
+        }
+    }
+}
+
+impl fmt::Display for UniverseLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = self.variables
+            .iter()
+            .map(|(name, offset)| if *offset == 0 { name.clone() } else { format!("{}+{}", name, offset) })
+            .collect();
+        if parts.is_empty() || self.constant > 0 {
+            parts.insert(0, self.constant.to_string());
+        }
+        if parts.len() == 1 {
+            write!(f, "{}", parts[0])
+        } else {
+            write!(f, "max {}", parts.join(" "))
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.1)
+    }
+}
+
+#[cfg(test)]
+mod type_inference_tests {
+    use std::collections::HashMap;
+
+    use crate::compiling_process::translating::testing::bare as source;
+    use crate::inner_representation::abstract_syntax_tree::Let;
+    use super::{infer_program, Inference};
+
+    const LIST: &str = "$List: @ -> @ = A ~> Nil . + Cons A * List A;";
+
+    // definitional equality of the values of `left` and `right`, types or not, under the rest of the program
+    fn equal_values(text: &str) -> Result<bool, String> {
+        let program = source(text);
+        let value = |name: &str| program.iter().find(|Let(x, _, _)| x.get_name() == name).unwrap().1.clone();
+        let rest: Vec<Let> = program.iter().filter(|Let(x, _, _)| !["left", "right"].contains(&x.get_name())).cloned().collect();
+        let mut inference = Inference::new();
+        inference.register_program(&rest)?;
+        let left = inference.to_term(&value("left"), &HashMap::new())?;
+        let right = inference.to_term(&value("right"), &HashMap::new())?;
+        inference.normaliser().equal(&left, &right)
+    }
+
+    #[test]
+    fn unit_tests() {
+        let program = source(&format!("{}
+            $id = x ~> x;
+            $pair = (id 1, id /a/);
+            $length = l ~> l | Nil _ -> 0 | Cons (_, rest) -> length rest;
+            $twice: (A -> A) -> A -> A = f x ~> f (f x);
+            $local = u ~> $k = a b ~> a; (k 1 id, k id 1);
+        ", LIST));
+        let types = infer_program(&program).unwrap();
+        for (name, scheme) in &types {
+            println!("{}: {}", name, scheme);
+        }
+        assert_eq!(types["id"].to_string(), types["id"].0[0].clone() + " -> " + &types["id"].0[0]);
+        assert_eq!(types["pair"].to_string(), "Int * String");
+        assert_eq!(types["length"].0.len(), 1);
+        assert!(types["length"].to_string().starts_with("List "));
+        assert!(types["length"].to_string().ends_with(" -> Int"));
+        assert_eq!(types["List"].to_string(), "@0 -> @0");
+    }
+
+    #[test]
+    fn recursive_lets() {
+        // a local function sees itself, it is generalised once checked
+        let program = source("
+            $List: @ -> @ = A ~> Nil . + Cons A * List A;
+            $total: List Int -> Int = xs ~> $go = ys acc ~> ys | Nil -> acc | Cons (y, rest) -> go rest (#add acc y); go xs 0;
+            $count = l ~> $go: {A : @} -> List A -> Int = ys ~> ys | Nil -> 0 | Cons (_, rest) -> #add 1 (go rest); go l;
+            $both = (count (Cons (1, Nil)), count (Cons (/a/, Nil)));
+        ");
+        let types = infer_program(&program).unwrap();
+        assert_eq!(types["total"].to_string(), "List Int -> Int");
+        // other lets only see what comes before them
+        let error = infer_program(&source("$main = n ~> $x = #add x n; x;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("unbound name x"));
+        let error = infer_program(&source("$main = m ~> $go = n ~> #add (go n) /a/; go m;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("in go: String"));
+    }
+
+    #[test]
+    fn universes() {
+        let error = infer_program(&source("$type: @0 = @0;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("@0 lives in @1 but is declared in @0"));
+
+        let program = source("
+            $Small: @1 = @0;
+            $universes: @0 * @0 = Small, Small;
+        ");
+        let error = infer_program(&program).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("@1 * @1 (at 3:35) is not a subtype of @0 * @0 (at 3:14)"));
+        assert!(infer_program(&source("$universes: @1 * @1 = (@0, @0);")).is_ok());
+        assert!(infer_program(&source("$type: @1 = @0;")).is_ok());
+
+        // a type is only in a larger universe with cumulativity
+        let small = source("$small: @1 = Int;");
+        assert!(infer_program(&small).is_err());
+        assert!(Inference::new().cumulative().infer_program(&small).is_ok());
+
+        let error = infer_program(&source(&format!("{} $big: @1 = List @0;", LIST))).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("argument @0 of List lives in @1 but @0 is expected"));
+
+        let error = infer_program(&source("$Big: @0 = Wrap @0;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("lives in @1 but is declared in @0"));
+    }
+
+    #[test]
+    fn level_polymorphism() {
+        let boxing = "$Box: @l -> @l = A ~> Box A;";
+        let types = infer_program(&source(&format!("{} $boxed: @1 = Box @0; $small: @0 = Box Int;", boxing))).unwrap();
+        assert_eq!(types["Box"].to_string(), "@l -> @l");
+        assert!(infer_program(&source(&format!("{} $wrong: @0 = Box @0;", boxing))).is_err());
+
+        // levels written above or as the largest of others
+        let program = source("
+            $Pair: @l -> @k -> @(max l k) = A B ~> Pair (A * B);
+            $Types: @(l+1) -> @(l+1) = A ~> Types A;
+            $pairs: @1 = Pair @0 Int;
+            $types: @1 = Types @0;
+        ");
+        let types = infer_program(&program).unwrap();
+        assert_eq!(types["Pair"].to_string(), "@l -> @k -> @(max k l)");
+        assert_eq!(types["Types"].to_string(), "@(l+1) -> @(l+1)");
+        let error = infer_program(&source("$Pair: @l -> @k -> @(max l k) = A B ~> Pair (A * B); $pairs: @0 = Pair @0 Int;"));
+        assert!(error.is_err());
+        let error = infer_program(&source("$Types: @(l+1) -> @(l+1) = A ~> Types A; $types: @0 = Types Int;"));
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn cumulativity() {
+        let program = source("$Lift: @l -> @(l+1) = A ~> Lift A; $lifted: @1 = Lift Int;");
+        let error = infer_program(&program).unwrap_err();
+        println!("{}", error);
+        let types = Inference::new().cumulative().infer_program(&program).unwrap();
+        assert_eq!(types["Lift"].to_string(), "@l -> @(l+1)");
+    }
+
+    #[test]
+    fn dependent_functions() {
+        let types = infer_program(&source("$id: (A : @0) -> A -> A = A x ~> x; $three: Int = id Int 3;")).unwrap();
+        assert_eq!(types["id"].to_string(), "(A : @0) -> A -> A");
+
+        let program = source("
+            $id: (A : @0) -> A -> A = A x ~> x;
+            $text = /a/;
+            $wrong = id Int text;
+        ");
+        let error = infer_program(&program).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("String (at 4:29) is not a subtype of Int (at 4:22)"));
+
+        let types = infer_program(&source("$poly = (A : @0) (x : A) ~> x;")).unwrap();
+        assert_eq!(types["poly"].to_string(), "(A : @0) -> A -> A");
+
+        // a local type is what it is defined to be
+        let program = source("$loc: Int -> Int = y ~> $T = Int; $g = (B : @) (z : B) ~> z; g T y;");
+        assert!(infer_program(&program).is_ok());
+        let program = source("$loc: Int -> Int = y ~> $T = String; $g = (B : @) (z : B) ~> z; g T y;");
+        assert!(infer_program(&program).is_err());
+        let program = source("$loc = y ~> $T = Int; $x: T = #add y 1; x;");
+        assert_eq!(infer_program(&program).unwrap()["loc"].to_string(), "Int -> Int");
+    }
+
+    #[test]
+    fn implicit_arguments() {
+        let defined = |value: &str| infer_program(&source(&format!("$id: {{A : @0}} -> A -> A = x ~> x; $defined{};", value)));
+        let types = defined(": Int = id 3").unwrap();
+        assert_eq!(types["id"].to_string(), "{A : @0} -> A -> A");
+        assert_eq!(defined(" = id @{Int} 3").unwrap()["defined"].to_string(), "Int");
+        assert_eq!(defined(" = id").unwrap()["defined"].to_string(), "t1 -> t1");
+
+        assert!(defined(" = id @{Int} /a/").unwrap_err().contains("is not a subtype of Int"));
+        assert!(defined(" = 3 @{Int}").unwrap_err().contains("takes no implicit argument"));
+
+        // nothing fixes A
+        let error = infer_program(&source("
+            $id: {A : @} -> A -> A = x ~> x;
+            $defined: Int = (f ~> 3) id;
+        ")).unwrap_err();
+        println!("{}", error);
+        assert_eq!(error, "in defined: cannot infer the implicit argument A of id at 3:38");
+    }
+
+    #[test]
+    fn rows() {
+        // records with more fields than asked for, each with fields of its own
+        let program = source("
+            $getX: {r : @} -> x Int * ..r -> Int = p ~> p | {x} -> x;
+            $wide: Int = getX {y = /a/, x = 1};
+            $named = getX {x = 1, name = /a/};
+            $moved = getX {y = 2, x = 3, z = 4};
+        ");
+        let types = infer_program(&program).unwrap();
+        assert_eq!(types["named"].to_string(), "Int");
+        assert_eq!(types["moved"].to_string(), "Int");
+        let error = infer_program(&source("$getX: {r : @} -> x Int * ..r -> Int = p ~> p | {x} -> x; $y = getX {y = 1};"));
+        assert!(error.unwrap_err().contains("has no entry x"));
+
+        let types = infer_program(&source("$getY = p ~> p | {y} -> y;")).unwrap();
+        assert_eq!(types["getY"].to_string(), "y t3 * ..t4 -> t3");
+
+        // the variant is closed by the match
+        let area = "$area = s ~> s | Circle r -> r | Square a -> a;";
+        let types = infer_program(&source(area)).unwrap();
+        assert_eq!(types["area"].to_string(), "Circle t5 + Square t5 -> t5");
+        let error = infer_program(&source(&format!("{} $other = area (Triangle 1);", area))).unwrap_err();
+        assert!(error.contains("has no entry Triangle"));
+
+        // a wildcard accepts any other constructor
+        let types = infer_program(&source("$round = s ~> s | Circle _ -> 1 | _ -> 0; $other = round (Triangle 1);")).unwrap();
+        assert_eq!(types["round"].to_string(), "Circle t4 + ..t5 -> Int");
+
+        let shapes = "$Shape: @0 -> @0 = r ~> Circle Int + ..r;";
+        assert!(infer_program(&source(&format!("{} $square: Shape (Square Int) = Square 1;", shapes))).is_ok());
+        assert!(infer_program(&source(&format!("{} $wrong: Shape (Square Int) = Square /a/;", shapes))).is_err());
+
+        // Shape (Square Int + Blob .) is the variant extended with two constructors
+        let shapes = |main: &str| source(&format!("
+            $Shape = r ~> Circle Int + ..r;
+            $area: Shape (Square Int + Blob .) -> Int = s ~> s | Circle r -> r | Square a -> #mul a a | Blob -> 0;
+            $main = {};
+        ", main));
+        let types = infer_program(&shapes("(area (Circle 2), area (Square 3), area Blob)")).unwrap();
+        assert_eq!(types["area"].to_string(), "Shape (Square Int + Blob .) -> Int");
+        let error = infer_program(&shapes("area (Triangle 1)")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("has no entry Triangle"));
+        assert!(infer_program(&shapes("area (Square /a/)")).is_err());
+    }
+
+    #[test]
+    fn subtyping() {
+        let ok = |text: &str| infer_program(&source(text)).map(|_| ()).unwrap_or_else(|error| panic!("{}", error));
+        let ignore = "$ignore: . -> Int = v ~> 1;";
+        ok(&format!("$anything: . = 1; $absurd: ! -> Int = v ~> v; {} $used: Int = ignore /a/;", ignore));
+
+        // functions take more and give less
+        ok(&format!("{} $narrow: Int -> . = ignore;", ignore));
+        let error = infer_program(&source("
+            $identity: Int -> Int = x ~> x;
+            $wide: . -> Int = identity;
+        ")).unwrap_err();
+        println!("{}", error);
+        assert_eq!(error, "in wide: Int -> Int (at 3:31) is not a subtype of . -> Int (at 3:14): . is not Int");
+
+        // records may have more fields
+        let get_x = "$getX: x Int * z Int -> Int = p ~> p | {x} -> x;";
+        ok(&format!("{} $x: Int = getX {{x = 1, y = /a/, z = 2}};", get_x));
+        let error = infer_program(&source(&format!("{} $narrow: x Int * z Int = {{z = 1}};", get_x))).unwrap_err();
+        assert!(error.contains("has no field x"));
+
+        // variants may have fewer constructors
+        ok("$small: A Int + C Int = A 1; $big: A Int + B Int + C Int = small;");
+        let error = infer_program(&source("$small: A Int + C Int = A 1; $big: B Int + C Int = small;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("B Int + C Int has no constructor A"));
+
+        // parameters only found in results are covariant, in arguments contravariant, in both invariant
+        let variance = |more: &str| infer_program(&source(&format!("{}
+            $Sink: @ -> @ = A ~> Drain (A -> Int);
+            $Cell: @ -> @ = A ~> Keep (A -> A);
+            $ints: List Int = Cons (1, Nil);
+            $wide: Sink . = Drain (x ~> 1);
+            $cell: Cell Int = Keep (x ~> x);
+            {}
+        ", LIST, more)));
+        let accepted = "$anything: List . = ints; $mixed: List . = Cons (/a/, Cons (1, Nil)); $narrow: Sink Int = wide;";
+        let types = variance(accepted).unwrap();
+        assert_eq!(types["mixed"].to_string(), "List .");
+        let error = variance("$bad: List Int = Cons (/a/, Nil);").unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("String is not Int"));
+        let error = variance("$narrow: Sink Int = wide; $bad: Sink . = narrow;").unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("Sink Int (at 7:54) is not a subtype of Sink . (at 7:45): . is not Int"));
+        let error = variance("$bad: Cell . = cell;").unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("Cell Int (at 7:28) is not a subtype of Cell . (at 7:19): Int is not ."));
+    }
+
+    #[test]
+    fn equality() {
+        let pred = "$pred: Int -> Int = n ~> n | 0 -> 1 | _ -> 2;";
+        let types = infer_program(&source(&format!("{} $computed: pred 0 == 1 = refl;", pred))).unwrap();
+        assert_eq!(types["computed"].to_string(), "pred 0 == 1");
+        let error = infer_program(&source(&format!("{} $computed: pred 0 == 2 = refl;", pred))).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("is not a subtype of pred 0 == 2"));
+
+        let sym = "$sym: (x : Int) -> (y : Int) -> x == y -> y == x = x y p ~>";
+        let types = infer_program(&source(&format!("{} p | refl -> refl;", sym))).unwrap();
+        assert_eq!(types["sym"].to_string(), "(x : Int) -> (y : Int) -> x == y -> y == x");
+        // without matching, x and y stay apart
+        let error = infer_program(&source(&format!("{} refl;", sym))).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("y == x"));
+
+        let subst = "$subst: (P : Int -> @0) -> (x : Int) -> (y : Int) -> x == y -> P x -> P y = P x y p px ~> p | refl -> px;";
+        assert!(infer_program(&source(subst)).is_ok());
+        let j = "
+            $J: (x : Int) -> (P : (y : Int) -> x == y -> @0) -> P x refl -> (y : Int) -> (p : x == y) -> P y p
+                = x P d y p ~> p | refl -> d;
+        ";
+        assert!(infer_program(&source(j)).is_ok());
+
+        // matching on n tells the arms what n is, plus n Z stays folded while n is unknown
+        let plus = "
+            $Nat = Z . + S Nat;
+            $plus: Nat -> Nat -> Nat = m n ~> m | Z -> n | S k -> S (plus k n);
+            $cong: (f : Nat -> Nat) -> (a : Nat) -> (b : Nat) -> a == b -> f a == f b = f a b p ~> p | refl -> refl;
+        ";
+        let error = infer_program(&source(&format!("{} $bad: (n : Nat) -> plus n Z == n = n ~> refl;", plus))).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("plus n (Z .) is not n"));
+        let zero_right = "
+            $zeroRight: (n : Nat) -> plus n Z == n = n ~> n | Z -> refl | S k -> cong (x ~> S x) (plus k Z) k (zeroRight k);
+        ";
+        let types = infer_program(&source(&format!("{} {}", plus, zero_right))).unwrap();
+        assert_eq!(types["zeroRight"].to_string(), "(n : Nat) -> plus n (Z .) == n");
+
+        let stuck = "$stuck: (x : Int) -> pred x == 2 -> Int = x p ~> p | refl -> 0;";
+        let error = infer_program(&source(&format!("{} {}", pred, stuck))).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("neither side is a variable"));
+    }
+
+    #[test]
+    fn codata() {
+        let stream = "$Stream: @ -> @ = A ~> codata head A * tail (Stream A);";
+        let program = source(&format!("{}
+            $ones: Stream Int = {{head = 1, tail = ones}};
+            $second: (ones | {{tail = {{head = h}}}} -> h) == 1 = refl;
+        ", stream));
+        let types = infer_program(&program).unwrap();
+        assert_eq!(types["ones"].to_string(), "Stream Int");
+
+        let error = infer_program(&source(&format!("{}\n$stuck: Stream Int = stuck;", stream))).unwrap_err();
+        println!("{}", error);
+        assert_eq!(error, "in stuck: the corecursive call to stuck at 2:22 is not guarded by a constructor");
+
+        // definitions that only hand on a stream made elsewhere need no guard
+        let program = source(&format!("{}
+            $from: Int -> Stream Int = n ~> {{head = n, tail = from (#add n 1)}};
+            $nats: Stream Int = from 0;
+            $second: (nats | {{tail = {{head = h}}}} -> h) == 1 = refl;
+        ", stream));
+        let types = infer_program(&program).unwrap();
+        assert_eq!(types["nats"].to_string(), "Stream Int");
+
+        // the same record as an ordinary type loops
+        let error = infer_program(&source("$Ones = head Int * tail Ones; $ones: Ones = {head = 1, tail = ones};")).unwrap_err();
+        println!("{}", error);
+        assert!(error.starts_with("in ones: the call to ones"));
+    }
+
+    #[test]
+    fn primitives() {
+        let types = infer_program(&source("$shown = #concat (#show (#add 1 2)) /!/; $sum = #add 1;")).unwrap();
+        assert_eq!(types["shown"].to_string(), "String");
+        assert_eq!(types["sum"].to_string(), "Int -> Int");
+
+        // primitives compute inside types
+        assert!(infer_program(&source("$five: #add 2 3 == 5 = refl;")).is_ok());
+        assert!(infer_program(&source("$five: #add 2 3 == 6 = refl;")).is_err());
+
+        let error = infer_program(&source("$one = 1; $wrong = #length one;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("Int (at 1:28) is not a subtype of String (at 1:20)"));
+
+        assert_eq!(infer_program(&source("$#add = 1;")).unwrap_err(), "#add is the name of a primitive at 1:2");
+    }
+
+    #[test]
+    fn fuel() {
+        // every conversion check gets the whole fuel, enough to count a hundred elements
+        let program = source("
+            $List = A ~> Nil . + Cons A * List A;
+            $length: List Int -> Int = xs ~> xs | Nil -> 0 | Cons (_, rest) -> #add 1 (length rest);
+            $upto: Int -> List Int = n ~> n | 0 -> Nil | _ -> Cons (n, upto (#sub n 1));
+            $xs = upto 100;
+            $hundred: length xs == 100 = refl;
+            $again: length xs == 100 = refl;
+        ");
+        // upto counts down on an Int, the checker is told it terminates
+        let checker = || Inference::new().terminating(vec!["upto".to_string()]);
+        // the list is built with as much stack as the main thread the checker runs on has
+        let checked = std::thread::Builder::new().stack_size(8 << 20).spawn({
+            let program = program.clone();
+            move || checker().infer_program(&program).map(|_| ())
+        });
+        assert_eq!(checked.unwrap().join().unwrap(), Ok(()));
+        let error = checker().fuel(50).infer_program(&program).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("gave up normalising"));
+
+        // a deep reduction runs out of fuel before it runs out of stack
+        let big = (0..11).fold("S Z".to_string(), |x, _| format!("double ({})", x));
+        let program = source(&format!("
+            $Nat = Z . + S Nat;
+            $plus: Nat -> Nat -> Nat = m n ~> m | Z -> n | S k -> S (plus k n);
+            $double: Nat -> Nat = n ~> n | Z -> Z | S k -> S (S (double k));
+            $big: Nat = {};
+            $same: plus big Z == big = refl;
+        ", big));
+        let checked = std::thread::Builder::new().stack_size(8 << 20).spawn(move || infer_program(&program).map(|_| ()));
+        let error = checked.unwrap().join().unwrap().unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("gave up normalising"));
+    }
+
+    #[test]
+    fn type_level_computation() {
+        let picked = |typ: &str, value: &str| infer_program(&source(&format!("
+            $Choose: Int -> @0 = n ~> n | 0 -> Int | _ -> String;
+            $pick: (n : Int) -> Choose n -> Choose n = n x ~> x;
+            $picked: {} = pick {};
+        ", typ, value)));
+        assert!(picked("Int", "0 42").is_ok());
+        assert!(picked("String", "1 /a/").is_ok());
+
+        let error = picked("Int", "1 42").unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("is not a subtype of Choose 1"));
+    }
+
+    #[test]
+    fn definitional_equality() {
+        // List Int is (A ~> List A) Int, and x ~> length x is length
+        assert_eq!(equal_values(&format!("{} $left = List Int; $right = (A ~> List A) Int;", LIST)), Ok(true));
+        assert_eq!(equal_values(&format!("{} $left = List @0; $right = (A ~> List A) Int;", LIST)), Ok(false));
+        assert_eq!(equal_values(&format!("{} $left = x ~> length x; $right = length;", LIST)), Ok(true));
+    }
+
+    #[test]
+    fn errors() {
+        let error = infer_program(&source("$s = /a/; $bad = ((x : Int) ~> x) s;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("String (at 1:35) is not a subtype of Int (at 1:20)"));
+
+        // constants are located like names are
+        let error = infer_program(&source("$one: String = 1;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("Int (at 1:16) is not a subtype of String (at 1:2)"));
+
+        let error = infer_program(&source("$omega = x ~> x x;")).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("infinite type"));
+
+        let error = infer_program(&source(&format!("{} $partial = l ~> l | Nil _ -> 0;", LIST))).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("Cons _ is not covered"));
+
+        // x is not bound when Nil matched
+        let head = |second: &str| infer_program(&source(&format!("{} $head = l ~> l | (Cons (x, _) | {}) -> x;", LIST, second)));
+        let error = head("Nil _").unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("bind different names"));
+        // the same names at types that don't unify
+        let error = head("Cons (_, x)").unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("x in the alternatives of"));
+
+        // loops are only accepted when they are trusted
+        let looping = source("$loop: Int -> Int = x ~>\n    loop x;");
+        let error = infer_program(&looping).unwrap_err();
+        println!("{}", error);
+        assert!(error.contains("in loop: the call to loop at 2:5 may not terminate"));
+        assert!(Inference::new().terminating(vec!["loop".to_string()]).infer_program(&looping).is_ok());
+
+        let error = infer_program(&source("$Bad = Mk (Bad -> Int);")).unwrap_err();
+        println!("{}", error);
+        assert_eq!(error, "in type Bad: Bad is not strictly positive, it occurs to the left of the arrow in Bad -> Int at 1:12");
+    }
+}
@@ -1,6 +1,12 @@
+use crate::inner_representation::abstract_syntax_tree::Span;
 use crate::inner_representation::token::{Token, TokenKind, RawToken, RawTokenKind};
 use crate::utils::tokenizing_constants::{separating_symbols, special_symbols, special_tokens};
 
+// the same number of bytes in spaces, newlines are kept so what comes after stays where it was
+fn blank(text: &str) -> String {
+    text.chars().map(|x| if x == '\n' { "\n".to_string() } else { " ".repeat(x.len_utf8()) }).collect()
+}
+
 fn remove_comments(program_text: String) -> Result<String, String> { // #* *#
     Ok(program_text
         .split("#*")
@@ -8,27 +14,31 @@ fn remove_comments(program_text: String) -> Result<String, String> { // #* *#
         .enumerate()
         .map(|(i, x)| {
             if i == 0 && x.len() == 1 {
-                Ok(x[0])
+                Ok(x[0].to_string())
             } else if x.len() == 2 {
-                Ok(x[1])
+                let open = if i == 0 { "" } else { "  " };
+                Ok(format!("{}{}  {}", open, blank(x[0]), x[1]))
             }else {
                 Err(format!("Wrong comment partition at {:?}", x))}
             }
         )
-        .collect::<Result<Vec<&str>, String>>()?
-        .join(" ")
+        .collect::<Result<Vec<String>, String>>()?
+        .join("")
     )
 }
 
 fn first_step_tokenize(program_test: Result<String, String>) -> Result<Vec<RawToken>, String> {
+    let mut offset = 0;
     let result: Vec<RawToken> = program_test?
         .split("/")
         .enumerate()
         .map(|(i, x)| {
+            let start = offset;
+            offset += x.len() + 1;
             if i % 2 == 0 {
-                RawToken::new(String::from(x), RawTokenKind::DontKnow)
+                RawToken::new(String::from(x), RawTokenKind::DontKnow, start)
             } else {
-                RawToken::new(String::from(x), RawTokenKind::StringLiteral)
+                RawToken::new(String::from(x), RawTokenKind::StringLiteral, start)
             }
         })
         .collect();
@@ -67,39 +77,74 @@ fn token_analyse(raw_token: &str) -> Result<Token, String> { //todo
     }
 }
 
-fn separate_symbols(text: String) -> String {
-    separating_symbols
-        .iter()
-        .fold(text, |text, sym| {
-            text.replace(sym, &format!(" {} ", sym)[..])
-        })
+// the words between separating symbols and whitespace, and the symbols themselves, each with the offset it starts at
+fn separate_symbols(text: &str, offset: usize) -> Vec<(usize, &str)> {
+    let mut result = vec![];
+    let mut word = None;
+    let mut i = 0;
+    while let Some(next) = text[i..].chars().next() {
+        let symbol = separating_symbols.iter().filter(|x| text[i..].starts_with(**x)).map(|x| x.len()).max();
+        if symbol.is_some() || next.is_whitespace() {
+            if let Some(begin) = word.take() {
+                result.push((offset + begin, &text[begin..i]));
+            }
+        }
+        match symbol {
+            Some(length) => {
+                result.push((offset + i, &text[i..i + length]));
+                i += length;
+            },
+            None => {
+                if !next.is_whitespace() && word.is_none() {
+                    word = Some(i);
+                }
+                i += next.len_utf8();
+            },
+        }
+    }
+    if let Some(begin) = word {
+        result.push((offset + begin, &text[begin..]));
+    }
+    result
+}
+
+// the line and column of every offset, from where the lines of the program start
+fn locate(lines: &[usize], text: &str, begin: usize, end: usize) -> Span {
+    let line = lines.partition_point(|x| *x <= begin);
+    let column = text[lines[line - 1]..begin].chars().count() + 1;
+    Span { begin, end, line, column }
 }
 
-fn raw_token_analyse(raw_token: RawToken) -> Result<Vec<Token>, String> {
+fn raw_token_analyse(raw_token: RawToken, lines: &[usize], text: &str) -> Result<Vec<Token>, String> {
     match raw_token.kind {
         RawTokenKind::StringLiteral => {
-            Ok(vec![Token::new(raw_token.text, TokenKind::StringLiteral)])
+            // the slashes around it belong to the literal
+            let span = locate(lines, text, raw_token.offset - 1, raw_token.offset + raw_token.text.len() + 1);
+            Ok(vec![Token::new(raw_token.text, TokenKind::StringLiteral).with_span(span)])
         },
         RawTokenKind::DontKnow => {
-            separate_symbols(raw_token.text)
-                .split_whitespace()
-                .map(token_analyse)
+            separate_symbols(&raw_token.text, raw_token.offset)
+                .into_iter()
+                .map(|(begin, word)| {
+                    token_analyse(word).map(|x| x.with_span(locate(lines, text, begin, begin + word.len())))
+                })
                 .collect()
         }
     }
 }
 
-fn second_step_tokenize(tokens: Result<Vec<RawToken>, String>) -> Result<Vec<Token>, String> {
+fn second_step_tokenize(tokens: Result<Vec<RawToken>, String>, text: &str) -> Result<Vec<Token>, String> {
+    let lines: Vec<usize> = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
     Ok(tokens?
         .into_iter()
-        .map(raw_token_analyse)
+        .map(|x| raw_token_analyse(x, &lines, text))
         .collect::<Result<Vec<Vec<Token>>, String>>()?
         .concat()
     )
 }
 
 pub fn tokenize(program_text: String) -> Result<Vec<Token>, String> {
-    second_step_tokenize(first_step_tokenize(remove_comments(program_text)))
+    second_step_tokenize(first_step_tokenize(remove_comments(program_text.clone())), &program_text)
 }
 
 #[cfg(test)]
@@ -112,6 +157,22 @@ mod tokenizing_tests {
         println!("{:?}", super::tokenize("".to_string()));
         println!("{:?}", super::tokenize("true * false".to_string()) )
     }
+
+    #[test]
+    fn positions() {
+        let tokens = super::tokenize("$x = #* one\n two *# f->/a b/;\n  $yé=1;".to_string()).unwrap();
+        let found: Vec<(String, usize, usize)> = tokens
+            .iter()
+            .map(|x| (x.text().to_string(), x.get_span().unwrap().line, x.get_span().unwrap().column))
+            .collect();
+        let expected = [
+            ("$", 1, 1), ("x", 1, 2), ("=", 1, 4), ("f", 2, 9), ("->", 2, 10), ("a b", 2, 12), (";", 2, 17),
+            ("$", 3, 3), ("yé", 3, 4), ("=", 3, 6), ("1", 3, 7), (";", 3, 8),
+        ];
+        assert_eq!(found, expected.iter().map(|(x, line, column)| (x.to_string(), *line, *column)).collect::<Vec<_>>());
+        let literal = tokens[5].get_span().unwrap();
+        assert_eq!((literal.begin, literal.end), (23, 28));
+    }
 }
 
 /* 
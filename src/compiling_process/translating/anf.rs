@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+
+use crate::compiling_process::pattern_compiling::compile_match;
+use crate::compiling_process::primitives::Primitive;
+use crate::compiling_process::translating::closures::lift;
+use crate::compiling_process::translating::erasure::Term;
+use crate::inner_representation::abstract_syntax_tree::{AtomicValue, Let, Pattern};
+use crate::inner_representation::decision_tree::{Case, DecisionTree, Step};
+
+// programs for native backends, lowered from the erased program: every intermediate value is named, every function is
+// at the top level and reads what it captured from its environment, matches are already switches on forced values
+
+// values that need no computing
+#[derive(Clone, Debug, PartialEq)]
+pub enum Atom {
+    // the parameters of the function come first, then the variables bound in it
+    Local(usize),
+    Captured(usize),
+    Int(i32),
+    // an index into the texts of the program
+    Text(usize),
+    Unit,
+    Type,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Selection {
+    Payload,
+    Index(usize),
+    // an index into the symbols of the program
+    Field(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    // the value of a definition, computed the first time it is asked for
+    Global(usize),
+    Tuple(Vec<Atom>),
+    Record(Vec<(usize, Atom)>),
+    Either(usize, Atom),
+    // a function of the program with the values it captures
+    Closure(usize, Vec<Atom>),
+    Thunk(usize, Vec<Atom>),
+    // a primitive as a value
+    Primitive(Primitive),
+    // a primitive given all its arguments
+    Call(Primitive, Vec<Atom>),
+    Apply(Atom, Vec<Atom>),
+    Force(Atom),
+    // forces the value before selecting from it
+    Select(Atom, Selection),
+    // stops the program with the message
+    Fail(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Test {
+    Constructor(usize),
+    Int(i32),
+    Text(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    Let(usize, Operation),
+    // a match not in tail position, its block ends by jumping here with the value of the local
+    Join(usize, Block),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum End {
+    Return(Atom),
+    // the call is made by the caller, so loops take no stack
+    TailCall(Atom, Vec<Atom>),
+    // on a forced value, the block after the cases is taken when none of them is
+    Switch(Atom, Vec<(Test, Block)>, Box<Block>),
+    // leaves the innermost join
+    Jump(Atom),
+    NoMatch(Atom),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block(pub Vec<Statement>, pub End);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub parameters: usize,
+    pub captured: usize,
+    // parameters included
+    pub locals: usize,
+    pub body: Block,
+}
+
+// definitions are functions without parameters or captures, in the order of the program
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub globals: Vec<(String, usize)>,
+    pub symbols: Vec<String>,
+    pub texts: Vec<String>,
+    pub main: usize,
+}
+
+fn atoms(atoms: &[Atom]) -> String {
+    atoms.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl std::fmt::Display for Atom {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Atom::Local(i) => write!(fmt, "l{}", i),
+            Atom::Captured(i) => write!(fmt, "c{}", i),
+            Atom::Int(n) => write!(fmt, "{}", n),
+            Atom::Text(i) => write!(fmt, "text {}", i),
+            Atom::Unit => write!(fmt, "()"),
+            Atom::Type => write!(fmt, "type"),
+        }
+    }
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Operation::Global(i) => write!(fmt, "global {}", i),
+            Operation::Tuple(items) => write!(fmt, "({})", atoms(items)),
+            Operation::Record(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, x)| format!("{} = {}", name, x)).collect();
+                write!(fmt, "{{{}}}", fields.join(", "))
+            },
+            Operation::Either(constructor, payload) => write!(fmt, "either {} {}", constructor, payload),
+            Operation::Closure(function, captured) => write!(fmt, "closure {} [{}]", function, atoms(captured)),
+            Operation::Thunk(function, captured) => write!(fmt, "thunk {} [{}]", function, atoms(captured)),
+            Operation::Primitive(primitive) => write!(fmt, "{}", primitive.name()),
+            Operation::Call(primitive, arguments) => write!(fmt, "{} ({})", primitive.name(), atoms(arguments)),
+            Operation::Apply(function, arguments) => write!(fmt, "apply {} ({})", function, atoms(arguments)),
+            Operation::Force(atom) => write!(fmt, "force {}", atom),
+            Operation::Select(atom, Selection::Payload) => write!(fmt, "{}.payload", atom),
+            Operation::Select(atom, Selection::Index(i)) => write!(fmt, "{}.{}", atom, i),
+            Operation::Select(atom, Selection::Field(name)) => write!(fmt, "{}.field {}", atom, name),
+            Operation::Fail(message) => write!(fmt, "fail {:?}", message),
+        }
+    }
+}
+
+impl Block {
+    fn print(&self, fmt: &mut std::fmt::Formatter, indent: usize) -> std::fmt::Result {
+        let prefix = "    ".repeat(indent);
+        for statement in &self.0 {
+            match statement {
+                Statement::Let(local, operation) => writeln!(fmt, "{}l{} = {}", prefix, local, operation)?,
+                Statement::Join(local, block) => {
+                    writeln!(fmt, "{}join l{}", prefix, local)?;
+                    block.print(fmt, indent + 1)?;
+                },
+            }
+        }
+        match &self.1 {
+            End::Return(atom) => writeln!(fmt, "{}return {}", prefix, atom),
+            End::TailCall(function, arguments) => writeln!(fmt, "{}tail {} ({})", prefix, function, atoms(arguments)),
+            End::Switch(atom, cases, default) => {
+                writeln!(fmt, "{}switch {}", prefix, atom)?;
+                for (test, block) in cases {
+                    match test {
+                        Test::Constructor(constructor) => writeln!(fmt, "{}| either {}", prefix, constructor)?,
+                        Test::Int(n) => writeln!(fmt, "{}| {}", prefix, n)?,
+                        Test::Text(text) => writeln!(fmt, "{}| text {}", prefix, text)?,
+                    }
+                    block.print(fmt, indent + 1)?;
+                }
+                writeln!(fmt, "{}| _", prefix)?;
+                default.print(fmt, indent + 1)
+            },
+            End::Jump(atom) => writeln!(fmt, "{}jump {}", prefix, atom),
+            End::NoMatch(atom) => writeln!(fmt, "{}no match {}", prefix, atom),
+        }
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (name, function) in &self.globals {
+            writeln!(fmt, "global {} = function {}", name, function)?;
+        }
+        for (i, function) in self.functions.iter().enumerate() {
+            let parameters: Vec<String> = (0..function.parameters).map(|x| format!("l{}", x)).collect();
+            writeln!(fmt, "function {} {}({}) [{}]", i, function.name, parameters.join(", "), function.captured)?;
+            function.body.print(fmt, 1)?;
+        }
+        Ok(())
+    }
+}
+
+// a function being lowered, what it captures is read from its environment
+struct Scope {
+    variables: Vec<(String, Atom)>,
+    // the names captured, with how the function around reads them
+    captures: Vec<(String, Atom)>,
+    locals: usize,
+    // of the block being lowered
+    statements: Vec<Statement>,
+}
+
+struct Lowering {
+    functions: Vec<Function>,
+    scopes: Vec<Scope>,
+    globals: HashMap<String, usize>,
+    symbols: Vec<String>,
+    texts: Vec<String>,
+}
+
+fn position(list: &mut Vec<String>, item: &str) -> usize {
+    match list.iter().position(|x| x == item) {
+        Some(index) => index,
+        None => {
+            list.push(item.to_string());
+            list.len() - 1
+        },
+    }
+}
+
+impl Lowering {
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, operation: Operation) -> Atom {
+        let scope = self.scope();
+        scope.statements.push(Statement::Let(scope.locals, operation));
+        scope.locals += 1;
+        Atom::Local(scope.locals - 1)
+    }
+
+    // a variable of one of the functions around, captured on the way in, constants are never captured
+    fn resolve(&mut self, name: &str, depth: usize) -> Option<Atom> {
+        let scope = &self.scopes[depth];
+        if let Some((_, atom)) = scope.variables.iter().rev().find(|(x, _)| x == name) {
+            return Some(atom.clone());
+        }
+        if let Some(index) = scope.captures.iter().position(|(x, _)| x == name) {
+            return Some(Atom::Captured(index));
+        }
+        if depth == 0 {
+            return None;
+        }
+        let outer = self.resolve(name, depth - 1)?;
+        if !matches!(outer, Atom::Local(_) | Atom::Captured(_)) {
+            return Some(outer);
+        }
+        let captures = &mut self.scopes[depth].captures;
+        captures.push((name.to_string(), outer));
+        Some(Atom::Captured(captures.len() - 1))
+    }
+
+    // values that can neither fail nor loop, computing them now is the same as delaying them
+    fn is_immediate(term: &Term) -> bool {
+        match term {
+            Term::Local(_) | Term::Primitive(_) | Term::Int(_) | Term::Text(_) | Term::Function(_, _) | Term::Erased => true,
+            Term::Tuple(items) => items.iter().all(Self::is_immediate),
+            Term::Record(fields) => fields.iter().all(|(_, x)| Self::is_immediate(x)),
+            Term::Either(_, payload) => Self::is_immediate(payload),
+            _ => false,
+        }
+    }
+
+    // lowers a function of the program and gives its index with the values to capture for it
+    fn function(
+        &mut self,
+        name: &str,
+        parameters: &[String],
+        body: impl FnOnce(&mut Self) -> Result<End, String>,
+    ) -> Result<(usize, Vec<Atom>), String> {
+        self.scopes.push(Scope {
+            variables: parameters.iter().enumerate().map(|(i, x)| (x.clone(), Atom::Local(i))).collect(),
+            captures: vec![],
+            locals: parameters.len(),
+            statements: vec![],
+        });
+        let end = body(self)?;
+        let scope = self.scopes.pop().unwrap();
+        self.functions.push(Function {
+            name: name.to_string(),
+            parameters: parameters.len(),
+            captured: scope.captures.len(),
+            locals: scope.locals,
+            body: Block(scope.statements, end),
+        });
+        Ok((self.functions.len() - 1, scope.captures.into_iter().map(|(_, x)| x).collect()))
+    }
+
+    fn block(&mut self, body: impl FnOnce(&mut Self) -> Result<End, String>) -> Result<Block, String> {
+        let outer = std::mem::take(&mut self.scope().statements);
+        let end = body(self)?;
+        let statements = std::mem::replace(&mut self.scope().statements, outer);
+        Ok(Block(statements, end))
+    }
+
+    fn delayed(&mut self, term: &Term) -> Result<Atom, String> {
+        if Self::is_immediate(term) {
+            return self.atom(term);
+        }
+        let (function, captures) = self.function("thunk", &[], |lowering| lowering.tail(term))?;
+        Ok(self.emit(Operation::Thunk(function, captures)))
+    }
+
+    fn atoms(&mut self, terms: &[Term]) -> Result<Vec<Atom>, String> {
+        terms.iter().map(|x| self.atom(x)).collect()
+    }
+
+    fn bind(&mut self, lets: &[(String, Term)]) -> Result<(), String> {
+        for (name, value) in lets {
+            let atom = self.atom(value)?;
+            self.scope().variables.push((name.clone(), atom));
+        }
+        Ok(())
+    }
+
+    // the term as an atom, the statements computing it are added to the block
+    fn atom(&mut self, term: &Term) -> Result<Atom, String> {
+        Ok(match term {
+            Term::Local(name) => {
+                let depth = self.scopes.len() - 1;
+                self.resolve(name, depth).ok_or_else(|| format!("the local {} is not bound", name))?
+            },
+            Term::Global(name) => {
+                let global = self.globals[name];
+                self.emit(Operation::Global(global))
+            },
+            Term::Primitive(primitive) => self.emit(Operation::Primitive(*primitive)),
+            Term::Call(primitive, arguments) => {
+                let arguments = self.atoms(arguments)?;
+                self.emit(Operation::Call(*primitive, arguments))
+            },
+            Term::Tuple(items) if items.is_empty() => Atom::Unit,
+            Term::Tuple(items) => {
+                let items = self.atoms(items)?;
+                self.emit(Operation::Tuple(items))
+            },
+            Term::Record(fields) => {
+                let mut values = vec![];
+                for (name, value) in fields {
+                    let value = self.atom(value)?;
+                    values.push((position(&mut self.symbols, name), value));
+                }
+                self.emit(Operation::Record(values))
+            },
+            Term::Either(name, payload) => {
+                let payload = self.atom(payload)?;
+                let constructor = position(&mut self.symbols, name);
+                self.emit(Operation::Either(constructor, payload))
+            },
+            Term::Lazy(term) => self.delayed(term)?,
+            Term::Match(scrutinee, arms) => {
+                let scope = self.scope();
+                let result = scope.locals;
+                scope.locals += 1;
+                let block = self.block(|lowering| lowering.matching(scrutinee, arms, true))?;
+                self.scope().statements.push(Statement::Join(result, block));
+                Atom::Local(result)
+            },
+            Term::Function(parameters, body) => {
+                // only a definition is a function, what it captures are the thunks it delays
+                if self.scopes.len() > 1 {
+                    return Err("functions are lifted to definitions before lowering".to_string());
+                }
+                let name = format!("lambda_{}", parameters.join("_"));
+                let (function, captures) = self.function(&name, parameters, |lowering| lowering.tail(body))?;
+                self.emit(Operation::Closure(function, captures))
+            },
+            Term::Apply(function, arguments) => {
+                let function = self.atom(function)?;
+                let arguments = self.atoms(arguments)?;
+                self.emit(Operation::Apply(function, arguments))
+            },
+            Term::Let(lets, body) => {
+                let depth = self.scope().variables.len();
+                self.bind(lets)?;
+                let atom = self.atom(body)?;
+                self.scope().variables.truncate(depth);
+                atom
+            },
+            Term::Int(n) => Atom::Int(*n),
+            Term::Text(text) => Atom::Text(position(&mut self.texts, text)),
+            Term::Erased => Atom::Type,
+            Term::Fail(message) => self.emit(Operation::Fail(message.clone())),
+        })
+    }
+
+    // ends the block with the term, a call at the end is left to the caller
+    fn tail(&mut self, term: &Term) -> Result<End, String> {
+        match term {
+            Term::Match(scrutinee, arms) => self.matching(scrutinee, arms, false),
+            Term::Apply(function, arguments) => {
+                let function = self.atom(function)?;
+                let arguments = self.atoms(arguments)?;
+                Ok(End::TailCall(function, arguments))
+            },
+            Term::Let(lets, body) => {
+                let depth = self.scope().variables.len();
+                self.bind(lets)?;
+                let end = self.tail(body)?;
+                self.scope().variables.truncate(depth);
+                Ok(end)
+            },
+            term => Ok(End::Return(self.atom(term)?)),
+        }
+    }
+
+    // `join` is set for a match that is not in tail position, its arms jump with their values
+    fn matching(&mut self, scrutinee: &Term, arms: &[(Pattern, Term)], join: bool) -> Result<End, String> {
+        let scrutinee = self.atom(scrutinee)?;
+        let patterns: Vec<&Pattern> = arms.iter().map(|(pattern, _)| pattern).collect();
+        let tree = compile_match(&patterns)?;
+        self.tree(&tree, arms, &scrutinee, join)
+    }
+
+    fn select(&mut self, scrutinee: &Atom, occurrence: &[Step]) -> Atom {
+        occurrence.iter().fold(scrutinee.clone(), |atom, step| {
+            let selection = match step {
+                Step::Payload => Selection::Payload,
+                Step::Index(i) => Selection::Index(*i),
+                Step::Field(name) => Selection::Field(position(&mut self.symbols, name)),
+            };
+            self.emit(Operation::Select(atom, selection))
+        })
+    }
+
+    fn tree(&mut self, tree: &DecisionTree, arms: &[(Pattern, Term)], scrutinee: &Atom, join: bool) -> Result<End, String> {
+        match tree {
+            DecisionTree::Fail => Ok(End::NoMatch(scrutinee.clone())),
+            DecisionTree::Leaf(arm, bindings) => {
+                let depth = self.scope().variables.len();
+                for (name, occurrence) in bindings {
+                    let atom = self.select(scrutinee, occurrence);
+                    self.scope().variables.push((name.get_name().to_string(), atom));
+                }
+                let end = match join {
+                    true => End::Jump(self.atom(&arms[*arm].1)?),
+                    false => self.tail(&arms[*arm].1)?,
+                };
+                self.scope().variables.truncate(depth);
+                Ok(end)
+            },
+            DecisionTree::Switch(occurrence, cases, default) => {
+                let selected = self.select(scrutinee, occurrence);
+                let value = self.emit(Operation::Force(selected));
+                let mut blocks = vec![];
+                for (case, tree) in cases {
+                    let test = match case {
+                        Case::Constructor(name) => Test::Constructor(position(&mut self.symbols, name)),
+                        Case::Literal(AtomicValue::Int(n)) => Test::Int(*n),
+                        Case::Literal(AtomicValue::StringLiteral(text)) => Test::Text(position(&mut self.texts, text)),
+                    };
+                    let block = self.block(|lowering| lowering.tree(tree, arms, scrutinee, join))?;
+                    blocks.push((test, block));
+                }
+                let default = self.block(|lowering| match default {
+                    Some(tree) => lowering.tree(tree, arms, scrutinee, join),
+                    None => Ok(End::NoMatch(scrutinee.clone())),
+                })?;
+                Ok(End::Switch(value, blocks, Box::new(default)))
+            },
+        }
+    }
+}
+
+// lowers each definition of the erased program to a function without parameters, `main` has to be one of them
+pub fn lower(program: &[Let]) -> Result<Program, String> {
+    let program = lift(program)?;
+    let main = program.definitions.iter().position(|(name, _)| name == "main").ok_or("unbound name main")?;
+    let mut lowering = Lowering {
+        functions: vec![],
+        scopes: vec![],
+        globals: program.definitions.iter().enumerate().map(|(i, (name, _))| (name.clone(), i)).collect(),
+        symbols: vec![],
+        texts: vec![],
+    };
+    let mut globals = vec![];
+    for (name, term) in &program.definitions {
+        let (function, _) = lowering
+            .function(name, &[], |lowering| lowering.tail(term))
+            .map_err(|error| format!("in {}: {}", name, error))?;
+        globals.push((name.clone(), function));
+    }
+    Ok(Program {
+        functions: lowering.functions,
+        globals,
+        symbols: lowering.symbols,
+        texts: lowering.texts,
+        main,
+    })
+}
+
+#[cfg(test)]
+mod anf_tests {
+    use crate::compiling_process::translating::testing::bare as program;
+    use super::lower;
+
+    #[test]
+    fn unit_tests() {
+        let program = program("
+            $adder = n ~> x ~> #add x n;
+            $main = (adder 1 2, #add (Just 3 | Just x -> x | Nothing -> 0) 1);
+        ");
+        // the function taking `x` is lifted to a definition given `n` first, the inner match is a join the sum continues
+        // after
+        let expected = "\
+global adder$1 = function 1
+global adder = function 3
+global main = function 4
+function 0 lambda_n_x(l0, l1) [0]
+    l2 = #add (l1, l0)
+    return l2
+function 1 adder$1() [0]
+    l0 = closure 0 []
+    return l0
+function 2 lambda_n(l0) [0]
+    l1 = global 0
+    tail l1 (l0)
+function 3 adder() [0]
+    l0 = closure 2 []
+    return l0
+function 4 main() [0]
+    l0 = global 1
+    l1 = apply l0 (1, 2)
+    join l2
+        l3 = either 0 3
+        l4 = force l3
+        switch l4
+        | either 0
+            l5 = l3.payload
+            jump l5
+        | either 1
+            jump 0
+        | _
+            no match l3
+    l6 = #add (l2, 1)
+    l7 = (l1, l6)
+    return l7
+";
+        assert_eq!(lower(&program).unwrap().to_string(), expected);
+        assert_eq!(lower(&self::program("$x = 1;")).unwrap_err(), "unbound name main");
+        // a variable two functions out is a parameter of the inner ones, only thunks capture
+        let nested = lower(&self::program("$main = a ~> b ~> c ~> #add a c;")).unwrap();
+        assert!(nested.functions.iter().all(|x| x.captured == 0));
+        let delayed = lower(&self::program("$Later = codata later Int; $main = a ~> {later = #add a 1};")).unwrap();
+        assert_eq!(delayed.functions.iter().map(|x| x.captured).collect::<Vec<_>>(), [1, 0, 0]);
+    }
+}
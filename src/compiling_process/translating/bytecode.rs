@@ -0,0 +1,1195 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use crate::compiling_process::pattern_compiling::compile_match;
+use crate::compiling_process::primitives::Primitive;
+use crate::compiling_process::translating::closures::lift;
+use crate::compiling_process::translating::erasure::Term;
+use crate::inner_representation::abstract_syntax_tree::{AtomicValue, Let, Pattern};
+use crate::inner_representation::decision_tree::{Case, DecisionTree, Step};
+
+// constructors, fields and messages are numbered, instructions only carry indices into the tables of the program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Int(i32),
+    Text(usize),
+    Type,
+    Local(usize),
+    Captured(usize),
+    Global(usize),
+    Primitive(Primitive),
+    // pops into a local slot
+    Store(usize),
+    Tuple(usize),
+    // the fields are given by a shape, popped in the order they are written
+    Record(usize),
+    Constructor(usize),
+    // a function or a suspended value with the given number of captured values from the stack
+    Closure(usize, usize),
+    Thunk(usize, usize),
+    Call(usize),
+    TailCall(usize),
+    // a primitive given all its arguments
+    Apply(Primitive),
+    Return,
+    Force,
+    Payload,
+    Index(usize),
+    Field(usize),
+    // jumps by the table of the forced value on top
+    Switch(usize),
+    Jump(usize),
+    // no arm matches the scrutinee in the slot
+    NoMatch(usize),
+    Error(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Key {
+    Constructor(usize),
+    Int(i32),
+    Text(String),
+}
+
+#[derive(Clone, Debug)]
+struct Table {
+    cases: Vec<(Key, usize)>,
+    default: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    name: String,
+    arity: usize,
+    locals: usize,
+    code: Vec<Instruction>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bytecode {
+    functions: Vec<Function>,
+    // every global with the function computing it
+    globals: Vec<(String, usize)>,
+    symbols: Vec<String>,
+    texts: Vec<Rc<str>>,
+    shapes: Vec<Vec<usize>>,
+    tables: Vec<Table>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Location {
+    Local(usize),
+    Captured(usize),
+}
+
+// a function being compiled, what it captures is found in the one around it
+struct Scope {
+    function: usize,
+    code: Vec<Instruction>,
+    locals: usize,
+    variables: Vec<(String, usize)>,
+    captures: Vec<(String, Location)>,
+}
+
+struct Compiler {
+    bytecode: Bytecode,
+    scopes: Vec<Scope>,
+    globals: HashMap<String, usize>,
+    symbols: HashMap<String, usize>,
+}
+
+impl Compiler {
+    fn symbol(&mut self, name: &str) -> usize {
+        if let Some(index) = self.symbols.get(name) {
+            return *index;
+        }
+        self.bytecode.symbols.push(name.to_string());
+        self.symbols.insert(name.to_string(), self.bytecode.symbols.len() - 1);
+        self.bytecode.symbols.len() - 1
+    }
+
+    fn text(&mut self, text: &str) -> usize {
+        self.bytecode.texts.push(Rc::from(text));
+        self.bytecode.texts.len() - 1
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.scope().code.push(instruction);
+    }
+
+    fn position(&mut self) -> usize {
+        self.scope().code.len()
+    }
+
+    fn slot(&mut self) -> usize {
+        let scope = self.scope();
+        scope.locals += 1;
+        scope.locals - 1
+    }
+
+    fn bind(&mut self, name: &str, slot: usize) {
+        self.scope().variables.push((name.to_string(), slot));
+    }
+
+    // a local of one of the functions around, captured on the way in
+    fn resolve(&mut self, name: &str, depth: usize) -> Option<Location> {
+        let scope = &self.scopes[depth];
+        if let Some((_, slot)) = scope.variables.iter().rev().find(|(x, _)| x == name) {
+            return Some(Location::Local(*slot));
+        }
+        if let Some(index) = scope.captures.iter().position(|(x, _)| x == name) {
+            return Some(Location::Captured(index));
+        }
+        if depth == 0 {
+            return None;
+        }
+        let outer = self.resolve(name, depth - 1)?;
+        let captures = &mut self.scopes[depth].captures;
+        captures.push((name.to_string(), outer));
+        Some(Location::Captured(captures.len() - 1))
+    }
+
+    // values that can neither fail nor loop, computing them now is the same as delaying them
+    fn is_immediate(term: &Term) -> bool {
+        match term {
+            Term::Local(_) | Term::Primitive(_) | Term::Int(_) | Term::Text(_) | Term::Function(_, _) | Term::Erased => true,
+            Term::Tuple(items) => items.iter().all(Self::is_immediate),
+            Term::Record(fields) => fields.iter().all(|(_, x)| Self::is_immediate(x)),
+            Term::Either(_, payload) => Self::is_immediate(payload),
+            _ => false,
+        }
+    }
+
+    fn load(&mut self, location: Location) {
+        match location {
+            Location::Local(slot) => self.emit(Instruction::Local(slot)),
+            Location::Captured(index) => self.emit(Instruction::Captured(index)),
+        }
+    }
+
+    // compiles the body of a new function and leaves the instruction creating it to the caller
+    fn function(
+        &mut self,
+        name: String,
+        parameters: &[String],
+        body: impl FnOnce(&mut Self) -> Result<(), String>,
+    ) -> Result<(usize, usize), String> {
+        let function = self.bytecode.functions.len();
+        self.bytecode.functions.push(Function {
+            name,
+            arity: parameters.len(),
+            locals: 0,
+            code: vec![],
+        });
+        self.scopes.push(Scope {
+            function,
+            code: vec![],
+            locals: parameters.len(),
+            variables: parameters.iter().enumerate().map(|(i, x)| (x.clone(), i)).collect(),
+            captures: vec![],
+        });
+        body(self)?;
+        let scope = self.scopes.pop().unwrap();
+        let compiled = &mut self.bytecode.functions[scope.function];
+        compiled.locals = scope.locals;
+        compiled.code = scope.code;
+        for (_, location) in &scope.captures {
+            self.load(*location);
+        }
+        Ok((function, scope.captures.len()))
+    }
+
+    fn name(&self) -> String {
+        let function = self.scopes.last().unwrap().function;
+        self.bytecode.functions[function].name.clone()
+    }
+
+    fn delayed(&mut self, term: &Term) -> Result<(), String> {
+        if Self::is_immediate(term) {
+            return self.value(term, false);
+        }
+        let name = format!("{}.thunk", self.name());
+        let (function, captured) = self.function(name, &[], |compiler| compiler.value(term, true))?;
+        self.emit(Instruction::Thunk(function, captured));
+        Ok(())
+    }
+
+    fn error(&mut self, message: String) {
+        let text = self.text(&message);
+        self.emit(Instruction::Error(text));
+    }
+
+    // in tail position the code returns by itself
+    fn value(&mut self, term: &Term, tail: bool) -> Result<(), String> {
+        match term {
+            Term::Local(name) => {
+                let depth = self.scopes.len() - 1;
+                let location = self.resolve(name, depth).ok_or_else(|| format!("the local {} is not bound", name))?;
+                self.load(location);
+            },
+            Term::Global(name) => self.emit(Instruction::Global(self.globals[name])),
+            Term::Primitive(primitive) => self.emit(Instruction::Primitive(*primitive)),
+            Term::Call(primitive, arguments) => {
+                arguments.iter().try_for_each(|x| self.value(x, false))?;
+                self.emit(Instruction::Apply(*primitive));
+            },
+            Term::Tuple(items) => {
+                items.iter().try_for_each(|x| self.value(x, false))?;
+                self.emit(Instruction::Tuple(items.len()));
+            },
+            Term::Record(fields) => {
+                fields.iter().try_for_each(|(_, x)| self.value(x, false))?;
+                let shape = fields.iter().map(|(name, _)| self.symbol(name)).collect();
+                self.bytecode.shapes.push(shape);
+                self.emit(Instruction::Record(self.bytecode.shapes.len() - 1));
+            },
+            Term::Either(name, payload) => {
+                self.value(payload, false)?;
+                let constructor = self.symbol(name);
+                self.emit(Instruction::Constructor(constructor));
+            },
+            Term::Lazy(term) => self.delayed(term)?,
+            Term::Match(scrutinee, arms) => {
+                self.value(scrutinee, false)?;
+                let slot = self.slot();
+                self.emit(Instruction::Store(slot));
+                let patterns: Vec<&Pattern> = arms.iter().map(|(pattern, _)| pattern).collect();
+                let tree = compile_match(&patterns)?;
+                let mut ends = vec![];
+                self.tree(&tree, arms, slot, tail, &mut ends)?;
+                let end = self.position();
+                for jump in ends {
+                    self.scope().code[jump] = Instruction::Jump(end);
+                }
+                return Ok(());
+            },
+            Term::Function(parameters, body) => {
+                // only a definition is a function, what it captures are the thunks it delays
+                if self.scopes.len() > 1 {
+                    return Err("functions are lifted to definitions before lowering".to_string());
+                }
+                let name = format!("{}.lambda", self.name());
+                let (function, captured) = self.function(name, parameters, |compiler| compiler.value(body, true))?;
+                self.emit(Instruction::Closure(function, captured));
+            },
+            Term::Apply(function, arguments) => {
+                self.value(function, false)?;
+                arguments.iter().try_for_each(|x| self.value(x, false))?;
+                if tail {
+                    self.emit(Instruction::TailCall(arguments.len()));
+                    return Ok(());
+                }
+                self.emit(Instruction::Call(arguments.len()));
+            },
+            Term::Let(lets, body) => {
+                let depth = self.scope().variables.len();
+                for (name, value) in lets {
+                    self.value(value, false)?;
+                    let slot = self.slot();
+                    self.emit(Instruction::Store(slot));
+                    self.bind(name, slot);
+                }
+                self.value(body, tail)?;
+                self.scope().variables.truncate(depth);
+                return Ok(());
+            },
+            Term::Int(n) => self.emit(Instruction::Int(*n)),
+            Term::Text(text) => {
+                let text = self.text(text);
+                self.emit(Instruction::Text(text));
+            },
+            Term::Erased => self.emit(Instruction::Type),
+            Term::Fail(message) => self.error(message.clone()),
+        }
+        if tail {
+            self.emit(Instruction::Return);
+        }
+        Ok(())
+    }
+
+    fn select(&mut self, slot: usize, occurrence: &[Step]) {
+        self.emit(Instruction::Local(slot));
+        for step in occurrence {
+            let instruction = match step {
+                Step::Payload => Instruction::Payload,
+                Step::Index(i) => Instruction::Index(*i),
+                Step::Field(name) => Instruction::Field(self.symbol(name)),
+            };
+            self.emit(instruction);
+        }
+    }
+
+    fn tree(
+        &mut self,
+        tree: &DecisionTree,
+        arms: &[(Pattern, Term)],
+        slot: usize,
+        tail: bool,
+        ends: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        match tree {
+            DecisionTree::Fail => self.emit(Instruction::NoMatch(slot)),
+            DecisionTree::Leaf(arm, bindings) => {
+                let depth = self.scope().variables.len();
+                for (name, occurrence) in bindings {
+                    self.select(slot, occurrence);
+                    let binding = self.slot();
+                    self.emit(Instruction::Store(binding));
+                    self.bind(name.get_name(), binding);
+                }
+                self.value(&arms[*arm].1, tail)?;
+                self.scope().variables.truncate(depth);
+                if !tail {
+                    ends.push(self.position());
+                    self.emit(Instruction::Jump(0));
+                }
+            },
+            DecisionTree::Switch(occurrence, cases, default) => {
+                self.select(slot, occurrence);
+                self.emit(Instruction::Force);
+                let table = self.bytecode.tables.len();
+                self.bytecode.tables.push(Table { cases: vec![], default: 0 });
+                self.emit(Instruction::Switch(table));
+                for (case, tree) in cases {
+                    let key = match case {
+                        Case::Constructor(name) => Key::Constructor(self.symbol(name)),
+                        Case::Literal(AtomicValue::Int(n)) => Key::Int(*n),
+                        Case::Literal(AtomicValue::StringLiteral(text)) => Key::Text(text.clone()),
+                    };
+                    let target = self.position();
+                    self.bytecode.tables[table].cases.push((key, target));
+                    self.tree(tree, arms, slot, tail, ends)?;
+                }
+                self.bytecode.tables[table].default = self.position();
+                match default {
+                    Some(tree) => self.tree(tree, arms, slot, tail, ends)?,
+                    None => self.emit(Instruction::NoMatch(slot)),
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+// every definition of the erased program becomes a function without parameters computing it, evaluated the first
+// time it is used
+pub fn translate(program: &[Let]) -> Result<Bytecode, String> {
+    let program = lift(program)?;
+    let mut compiler = Compiler {
+        bytecode: Bytecode::default(),
+        scopes: vec![],
+        globals: program.definitions.iter().enumerate().map(|(i, (name, _))| (name.clone(), i)).collect(),
+        symbols: HashMap::new(),
+    };
+    for (name, term) in &program.definitions {
+        let (function, _) = compiler
+            .function(name.clone(), &[], |compiler| compiler.value(term, true))
+            .map_err(|error| format!("in {}: {}", name, error))?;
+        compiler.bytecode.globals.push((name.clone(), function));
+    }
+    Ok(compiler.bytecode)
+}
+
+#[derive(Clone, Debug)]
+pub enum Object {
+    Int(i32),
+    Text(Rc<str>),
+    Tuple(Rc<[Object]>),
+    Record(Rc<[(usize, Object)]>),
+    Either(usize, Rc<Object>),
+    Closure(Rc<Closure>),
+    // a primitive with the arguments it was given so far
+    Primitive(Primitive, Rc<[Object]>),
+    Type,
+    Thunk(Rc<RefCell<Thunk>>),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    function: usize,
+    captured: Rc<[Object]>,
+    arguments: Vec<Object>,
+}
+
+#[derive(Debug)]
+pub enum Thunk {
+    Delayed(usize, Rc<[Object]>),
+    // keeps what it was delayed with, to be forced again when forcing fails
+    Forcing(usize, Rc<[Object]>),
+    // the value may be a thunk itself, it is forced in turn
+    Forced(Object),
+}
+
+// a long list is constructors nested in each other, the values beneath are taken apart one at a time rather than
+// each dropping the next, which would overflow the stack
+impl Drop for Object {
+    fn drop(&mut self) {
+        let mut objects = vec![];
+        self.take(&mut objects);
+        while let Some(mut object) = objects.pop() {
+            object.take(&mut objects);
+        }
+    }
+}
+
+impl Object {
+    // moves out the values only this one holds
+    fn take(&mut self, objects: &mut Vec<Object>) {
+        match self {
+            Object::Tuple(items) | Object::Primitive(_, items) => {
+                if let Some(items) = Rc::get_mut(items) {
+                    objects.extend(items.iter_mut().map(|x| mem::replace(x, Object::Type)));
+                }
+            },
+            Object::Record(fields) => {
+                if let Some(fields) = Rc::get_mut(fields) {
+                    objects.extend(fields.iter_mut().map(|(_, x)| mem::replace(x, Object::Type)));
+                }
+            },
+            Object::Either(_, payload) => {
+                if let Some(payload) = Rc::get_mut(payload) {
+                    objects.push(mem::replace(payload, Object::Type));
+                }
+            },
+            Object::Closure(closure) => {
+                if let Some(closure) = Rc::get_mut(closure) {
+                    objects.push(Object::Tuple(mem::replace(&mut closure.captured, Rc::from(vec![]))));
+                    objects.append(&mut closure.arguments);
+                }
+            },
+            Object::Thunk(thunk) => {
+                if let Some(thunk) = Rc::get_mut(thunk) {
+                    match thunk.get_mut() {
+                        Thunk::Forced(value) => objects.push(mem::replace(value, Object::Type)),
+                        Thunk::Delayed(_, captured) | Thunk::Forcing(_, captured) => {
+                            objects.push(Object::Tuple(mem::replace(captured, Rc::from(vec![]))));
+                        },
+                    }
+                }
+            },
+            Object::Int(_) | Object::Text(_) | Object::Type => (),
+        }
+    }
+}
+
+enum Global {
+    Pending,
+    InProgress,
+    Done(Object),
+}
+
+// a call in progress, its locals start at `base` on the stack right above the function called,
+// `pending` are the arguments its result is applied to, `update` the thunk it is forcing
+struct Frame {
+    function: usize,
+    pc: usize,
+    base: usize,
+    captured: Rc<[Object]>,
+    pending: Vec<Object>,
+    update: Option<Rc<RefCell<Thunk>>>,
+}
+
+struct State {
+    stack: Vec<Object>,
+    frames: Vec<Frame>,
+    current: Frame,
+}
+
+pub struct VirtualMachine<'a> {
+    bytecode: &'a Bytecode,
+    globals: RefCell<Vec<Global>>,
+}
+
+impl<'a> VirtualMachine<'a> {
+    pub fn new(bytecode: &'a Bytecode) -> Self {
+        VirtualMachine {
+            bytecode,
+            globals: RefCell::new(bytecode.globals.iter().map(|_| Global::Pending).collect()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Object, String> {
+        match self.bytecode.globals.iter().position(|(x, _)| x == name) {
+            Some(global) => self.global(global),
+            None => Err(format!("unbound name {}", name)),
+        }
+    }
+
+    fn global(&self, global: usize) -> Result<Object, String> {
+        if let Global::Done(value) = &self.globals.borrow()[global] {
+            return Ok(value.clone());
+        }
+        let state = mem::replace(&mut self.globals.borrow_mut()[global], Global::InProgress);
+        let value = match state {
+            Global::Done(value) => value,
+            Global::Pending => match self.run(self.bytecode.globals[global].1, Rc::from(vec![]), vec![]) {
+                Ok(value) => value,
+                Err(error) => {
+                    self.globals.borrow_mut()[global] = Global::Pending;
+                    return Err(error);
+                },
+            },
+            Global::InProgress => return Err(format!("{} is defined in terms of itself", self.bytecode.globals[global].0)),
+        };
+        self.globals.borrow_mut()[global] = Global::Done(value.clone());
+        Ok(value)
+    }
+
+    pub fn force(&self, mut value: Object) -> Result<Object, String> {
+        loop {
+            let thunk = match &value {
+                Object::Thunk(thunk) => thunk.clone(),
+                _ => return Ok(value),
+            };
+            let (function, captured) = match &*thunk.borrow() {
+                Thunk::Forced(forced) => {
+                    value = forced.clone();
+                    continue;
+                },
+                Thunk::Delayed(function, captured) => (*function, captured.clone()),
+                Thunk::Forcing(_, _) => return Err(Self::circular()),
+            };
+            thunk.replace(Thunk::Forcing(function, captured.clone()));
+            match self.run(function, captured.clone(), vec![]) {
+                Ok(result) => {
+                    thunk.replace(Thunk::Forced(result.clone()));
+                    value = result;
+                },
+                Err(error) => {
+                    thunk.replace(Thunk::Delayed(function, captured));
+                    return Err(error);
+                },
+            }
+        }
+    }
+
+    fn circular() -> String {
+        "a lazy value is defined in terms of itself".to_string()
+    }
+
+    // the value `depth` below the top of the stack is forced in place, unless a frame had to be entered to force it,
+    // the current instruction then runs again once that frame returns
+    fn demand(&self, state: &mut State, depth: usize) -> Result<bool, String> {
+        let at = state.stack.len() - 1 - depth;
+        loop {
+            let thunk = match &state.stack[at] {
+                Object::Thunk(thunk) => thunk.clone(),
+                _ => return Ok(true),
+            };
+            let (function, captured) = match &*thunk.borrow() {
+                Thunk::Forced(value) => {
+                    state.stack[at] = value.clone();
+                    continue;
+                },
+                Thunk::Delayed(function, captured) => (*function, captured.clone()),
+                Thunk::Forcing(_, _) => return Err(Self::circular()),
+            };
+            thunk.replace(Thunk::Forcing(function, captured.clone()));
+            state.current.pc -= 1;
+            self.enter(state, function, captured, vec![], vec![], false);
+            state.current.update = Some(thunk);
+            return Ok(false);
+        }
+    }
+
+    // forces everything inside as well, which never ends for infinite codata,
+    // the thunks are forced where they are, one after the other in the order they are printed
+    pub fn force_all(&self, value: Object) -> Result<Object, String> {
+        let value = self.force(value)?;
+        let mut objects = vec![value.clone()];
+        while let Some(object) = objects.pop() {
+            match &self.force(object)? {
+                Object::Tuple(items) => objects.extend(items.iter().rev().cloned()),
+                Object::Record(fields) => objects.extend(fields.iter().rev().map(|(_, x)| x.clone())),
+                Object::Either(_, payload) => objects.push((**payload).clone()),
+                _ => (),
+            }
+        }
+        Ok(value)
+    }
+
+    // a tail call reuses the frame of the caller, otherwise the caller is saved to return to
+    fn enter(
+        &self,
+        state: &mut State,
+        function: usize,
+        captured: Rc<[Object]>,
+        arguments: Vec<Object>,
+        pending: Vec<Object>,
+        tail: bool,
+    ) {
+        let base = if tail {
+            state.stack.truncate(state.current.base);
+            state.current.base
+        } else {
+            state.stack.push(Object::Type);
+            state.stack.len()
+        };
+        state.stack.extend(arguments);
+        state.stack.resize(base + self.bytecode.functions[function].locals, Object::Type);
+        let frame = Frame {
+            function,
+            pc: 0,
+            base,
+            captured,
+            pending,
+            update: if tail { state.current.update.take() } else { None },
+        };
+        let caller = mem::replace(&mut state.current, frame);
+        if !tail {
+            state.frames.push(caller);
+        }
+    }
+
+    // either the result right away, or the frame of the function called has been entered
+    fn call(
+        &self,
+        state: &mut State,
+        function: Object,
+        mut arguments: Vec<Object>,
+        tail: bool,
+    ) -> Result<Option<Object>, String> {
+        let function = self.force(function)?;
+        let closure = match &function {
+            Object::Closure(closure) => closure.clone(),
+            Object::Primitive(primitive, collected) => {
+                let (primitive, mut collected) = (*primitive, collected.to_vec());
+                collected.extend(arguments);
+                if collected.len() < primitive.arity() {
+                    return Ok(Some(Object::Primitive(primitive, Rc::from(collected))));
+                }
+                let rest = collected.split_off(primitive.arity());
+                let result = self.primitive(primitive, collected)?;
+                return if rest.is_empty() { Ok(Some(result)) } else { self.call(state, result, rest, tail) };
+            },
+            Object::Type => return Ok(Some(Object::Type)),
+            _ => return Err(format!("{} is not a function", self.show(&function))),
+        };
+        let arity = self.bytecode.functions[closure.function].arity;
+        let mut collected = closure.arguments.clone();
+        if collected.len() + arguments.len() < arity {
+            collected.extend(arguments);
+            return Ok(Some(Object::Closure(Rc::new(Closure {
+                function: closure.function,
+                captured: closure.captured.clone(),
+                arguments: collected,
+            }))));
+        }
+        let mut rest = arguments.split_off(arity - collected.len());
+        collected.extend(arguments);
+        if tail {
+            rest.extend(mem::take(&mut state.current.pending));
+        }
+        self.enter(state, closure.function, closure.captured.clone(), collected, rest, tail);
+        Ok(None)
+    }
+
+    fn primitive(&self, primitive: Primitive, arguments: Vec<Object>) -> Result<Object, String> {
+        let arguments = arguments
+            .into_iter()
+            .map(|x| self.atomic(primitive, x))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self::object(primitive.apply(&arguments)?))
+    }
+
+    fn atomic(&self, primitive: Primitive, value: Object) -> Result<AtomicValue, String> {
+        match &self.force(value)? {
+            Object::Int(n) => Ok(AtomicValue::Int(*n)),
+            Object::Text(text) => Ok(AtomicValue::StringLiteral(text.to_string())),
+            other => Err(format!("{} expects ints and strings but got {}", primitive.name(), self.show(other))),
+        }
+    }
+
+    fn object(value: AtomicValue) -> Object {
+        match value {
+            AtomicValue::Int(n) => Object::Int(n),
+            AtomicValue::StringLiteral(text) => Object::Text(Rc::from(text)),
+        }
+    }
+
+    // ends the current frame, the value comes back once the outermost one has returned
+    fn finish(&self, state: &mut State, mut value: Object) -> Result<Option<Object>, String> {
+        loop {
+            let pending = mem::take(&mut state.current.pending);
+            if !pending.is_empty() {
+                match self.call(state, value, pending, true)? {
+                    Some(result) => value = result,
+                    None => return Ok(None),
+                }
+                continue;
+            }
+            // a forced thunk keeps its value, the instruction that demanded it finds it there
+            let update = state.current.update.take();
+            if let Some(thunk) = &update {
+                thunk.replace(Thunk::Forced(value.clone()));
+            }
+            state.stack.truncate(state.current.base - 1);
+            return match state.frames.pop() {
+                Some(caller) => {
+                    state.current = caller;
+                    if update.is_none() {
+                        state.stack.push(value);
+                    }
+                    Ok(None)
+                },
+                None => Ok(Some(value)),
+            };
+        }
+    }
+
+    // a closure given exactly its arguments is entered where they already are on the stack
+    fn call_directly(&self, state: &mut State, size: usize, tail: bool) -> bool {
+        let at = state.stack.len() - size - 1;
+        let closure = match &state.stack[at] {
+            Object::Closure(closure) if closure.arguments.is_empty() => closure.clone(),
+            _ => return false,
+        };
+        let Function { arity, locals, .. } = self.bytecode.functions[closure.function];
+        if arity != size {
+            return false;
+        }
+        let base = if tail {
+            let base = state.current.base;
+            for i in 0..size {
+                state.stack.swap(base + i, at + 1 + i);
+            }
+            state.stack.truncate(base + size);
+            base
+        } else {
+            at + 1
+        };
+        state.stack.resize(base + locals, Object::Type);
+        let frame = Frame {
+            function: closure.function,
+            pc: 0,
+            base,
+            captured: closure.captured.clone(),
+            pending: if tail { mem::take(&mut state.current.pending) } else { vec![] },
+            update: if tail { state.current.update.take() } else { None },
+        };
+        let caller = mem::replace(&mut state.current, frame);
+        if !tail {
+            state.frames.push(caller);
+        }
+        true
+    }
+
+    fn mismatch(&self, value: &Object) -> String {
+        format!("can't match {} against the pattern", self.show(value))
+    }
+
+    fn fits(key: &Key, value: &Object) -> bool {
+        match (key, value) {
+            (Key::Constructor(expected), Object::Either(name, _)) => expected == name,
+            (Key::Int(expected), Object::Int(n)) => expected == n,
+            (Key::Text(expected), Object::Text(text)) => **expected == **text,
+            _ => false,
+        }
+    }
+
+    fn run(&self, function: usize, captured: Rc<[Object]>, arguments: Vec<Object>) -> Result<Object, String> {
+        let mut state = State {
+            stack: vec![],
+            frames: vec![],
+            current: Frame {
+                function,
+                pc: 0,
+                base: 0,
+                captured: captured.clone(),
+                pending: vec![],
+                update: None,
+            },
+        };
+        self.enter(&mut state, function, captured, arguments, vec![], false);
+        // the frame the machine started with is not returned to
+        state.frames.clear();
+        let result = self.execute(&mut state);
+        if result.is_err() {
+            // the thunks being forced are left to be forced again
+            for frame in state.frames.iter().chain([&state.current]) {
+                if let Some(thunk) = &frame.update {
+                    let delayed = match &*thunk.borrow() {
+                        Thunk::Forcing(function, captured) => Thunk::Delayed(*function, captured.clone()),
+                        _ => continue,
+                    };
+                    thunk.replace(delayed);
+                }
+            }
+        }
+        result
+    }
+
+    fn execute(&self, state: &mut State) -> Result<Object, String> {
+        loop {
+            let code = &self.bytecode.functions[state.current.function].code;
+            let instruction = code[state.current.pc];
+            state.current.pc += 1;
+            // values are forced on the stack of the machine rather than on the one of Rust
+            let ready = match instruction {
+                Instruction::Force | Instruction::Payload | Instruction::Index(_) | Instruction::Field(_) => {
+                    self.demand(state, 0)?
+                },
+                Instruction::Call(size) | Instruction::TailCall(size) => self.demand(state, size)?,
+                Instruction::Apply(primitive) => {
+                    let mut ready = true;
+                    for depth in (0..primitive.arity()).rev() {
+                        ready = self.demand(state, depth)?;
+                        if !ready {
+                            break;
+                        }
+                    }
+                    ready
+                },
+                _ => true,
+            };
+            if !ready {
+                continue;
+            }
+            match instruction {
+                Instruction::Int(n) => state.stack.push(Object::Int(n)),
+                Instruction::Text(text) => state.stack.push(Object::Text(self.bytecode.texts[text].clone())),
+                Instruction::Type => state.stack.push(Object::Type),
+                Instruction::Local(slot) => {
+                    let value = state.stack[state.current.base + slot].clone();
+                    state.stack.push(value);
+                },
+                Instruction::Captured(index) => {
+                    let value = state.current.captured[index].clone();
+                    state.stack.push(value);
+                },
+                Instruction::Global(global) => {
+                    let value = self.global(global)?;
+                    state.stack.push(value);
+                },
+                Instruction::Primitive(primitive) => state.stack.push(Object::Primitive(primitive, Rc::from(vec![]))),
+                Instruction::Store(slot) => {
+                    let value = state.stack.pop().unwrap();
+                    state.stack[state.current.base + slot] = value;
+                },
+                Instruction::Tuple(size) => {
+                    let items = state.stack.split_off(state.stack.len() - size);
+                    state.stack.push(Object::Tuple(Rc::from(items)));
+                },
+                Instruction::Record(shape) => {
+                    let shape = &self.bytecode.shapes[shape];
+                    let values = state.stack.split_off(state.stack.len() - shape.len());
+                    let fields: Vec<(usize, Object)> = shape.iter().cloned().zip(values).collect();
+                    state.stack.push(Object::Record(Rc::from(fields)));
+                },
+                Instruction::Constructor(constructor) => {
+                    let payload = state.stack.pop().unwrap();
+                    state.stack.push(Object::Either(constructor, Rc::new(payload)));
+                },
+                Instruction::Closure(function, size) => {
+                    let captured = state.stack.split_off(state.stack.len() - size);
+                    state.stack.push(Object::Closure(Rc::new(Closure {
+                        function,
+                        captured: Rc::from(captured),
+                        arguments: vec![],
+                    })));
+                },
+                Instruction::Thunk(function, size) => {
+                    let captured = state.stack.split_off(state.stack.len() - size);
+                    state.stack.push(Object::Thunk(Rc::new(RefCell::new(Thunk::Delayed(function, Rc::from(captured))))));
+                },
+                Instruction::Call(size) | Instruction::TailCall(size) => {
+                    let tail = matches!(instruction, Instruction::TailCall(_));
+                    if self.call_directly(state, size, tail) {
+                        continue;
+                    }
+                    let arguments = state.stack.split_off(state.stack.len() - size);
+                    let function = state.stack.pop().unwrap();
+                    match self.call(state, function, arguments, tail)? {
+                        Some(value) if tail => {
+                            if let Some(value) = self.finish(state, value)? {
+                                return Ok(value);
+                            }
+                        },
+                        Some(value) => state.stack.push(value),
+                        None => (),
+                    }
+                },
+                Instruction::Apply(primitive) => {
+                    let start = state.stack.len() - primitive.arity();
+                    let stack = &state.stack;
+                    let argument = |i: usize| self.atomic(primitive, stack[start + i].clone());
+                    // forced from the first like the interpreter does, without allocating for the arguments
+                    let result = match primitive.arity() {
+                        1 => primitive.apply(&[argument(0)?])?,
+                        2 => primitive.apply(&[argument(0)?, argument(1)?])?,
+                        _ => primitive.apply(&[argument(0)?, argument(1)?, argument(2)?])?,
+                    };
+                    state.stack.truncate(start);
+                    state.stack.push(Self::object(result));
+                },
+                Instruction::Return => {
+                    let value = state.stack.pop().unwrap();
+                    if let Some(value) = self.finish(state, value)? {
+                        return Ok(value);
+                    }
+                },
+                Instruction::Force => (),
+                Instruction::Payload => match &state.stack.pop().unwrap() {
+                    Object::Either(_, payload) => state.stack.push((**payload).clone()),
+                    other => return Err(self.mismatch(other)),
+                },
+                Instruction::Index(i) => match &state.stack.pop().unwrap() {
+                    Object::Tuple(items) => {
+                        state.stack.push(items.get(i).cloned().ok_or(format!("tuple has no element {}", i))?);
+                    },
+                    other => return Err(self.mismatch(other)),
+                },
+                Instruction::Field(field) => match &state.stack.pop().unwrap() {
+                    Object::Record(fields) => {
+                        let value = fields.iter().find(|(name, _)| *name == field).map(|(_, x)| x.clone());
+                        state.stack.push(value.ok_or(format!("record has no field {}", self.bytecode.symbols[field]))?);
+                    },
+                    other => return Err(self.mismatch(other)),
+                },
+                Instruction::Switch(table) => {
+                    let value = state.stack.pop().unwrap();
+                    let table = &self.bytecode.tables[table];
+                    state.current.pc = table.cases
+                        .iter()
+                        .find(|(key, _)| Self::fits(key, &value))
+                        .map_or(table.default, |(_, target)| *target);
+                },
+                Instruction::Jump(target) => state.current.pc = target,
+                Instruction::NoMatch(slot) => {
+                    return Err(format!("no pattern matches {}", self.show(&state.stack[state.current.base + slot])));
+                },
+                Instruction::Error(text) => return Err(self.bytecode.texts[text].to_string()),
+            }
+        }
+    }
+
+    fn is_either(value: &Object) -> bool {
+        match value {
+            Object::Either(_, _) => true,
+            Object::Thunk(thunk) => matches!(&*thunk.borrow(), Thunk::Forced(value) if Self::is_either(value)),
+            _ => false,
+        }
+    }
+
+    // printed the way the interpreter prints its values, the parts left to print are kept in reverse
+    pub fn show(&self, value: &Object) -> String {
+        enum Part {
+            Value(Object),
+            Text(String),
+        }
+        let mut shown = String::new();
+        let mut parts = vec![Part::Value(value.clone())];
+        while let Some(part) = parts.pop() {
+            let value = match part {
+                Part::Value(value) => value,
+                Part::Text(text) => {
+                    shown.push_str(&text);
+                    continue;
+                },
+            };
+            match &value {
+                Object::Int(n) => shown.push_str(&n.to_string()),
+                Object::Text(text) => shown.push_str(&format!("/{}/", text)),
+                Object::Tuple(items) => {
+                    shown.push('(');
+                    parts.push(Part::Text(")".to_string()));
+                    for (i, x) in items.iter().enumerate().rev() {
+                        parts.push(Part::Value(x.clone()));
+                        if i > 0 {
+                            parts.push(Part::Text(", ".to_string()));
+                        }
+                    }
+                },
+                Object::Record(fields) => {
+                    shown.push('{');
+                    parts.push(Part::Text("}".to_string()));
+                    for (i, (name, x)) in fields.iter().enumerate().rev() {
+                        parts.push(Part::Value(x.clone()));
+                        parts.push(Part::Text(format!("{} = ", self.bytecode.symbols[*name])));
+                        if i > 0 {
+                            parts.push(Part::Text(", ".to_string()));
+                        }
+                    }
+                },
+                Object::Either(name, payload) => {
+                    shown.push_str(&format!("{} ", self.bytecode.symbols[*name]));
+                    if Self::is_either(payload) {
+                        parts.push(Part::Text(")".to_string()));
+                        parts.push(Part::Value((**payload).clone()));
+                        parts.push(Part::Text("(".to_string()));
+                    } else {
+                        parts.push(Part::Value((**payload).clone()));
+                    }
+                },
+                Object::Closure(_) | Object::Primitive(_, _) => shown.push_str("<function>"),
+                Object::Type => shown.push_str("<type>"),
+                Object::Thunk(thunk) => match &*thunk.borrow() {
+                    Thunk::Forced(value) => parts.push(Part::Value(value.clone())),
+                    _ => shown.push_str("..."),
+                },
+            }
+        }
+        shown
+    }
+}
+
+impl fmt::Display for Bytecode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            writeln!(f, "{} {} ({} parameters, {} locals)", i, function.name, function.arity, function.locals)?;
+            for (pc, instruction) in function.code.iter().enumerate() {
+                writeln!(f, "  {:4} {:?}", pc, instruction)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod bytecode_tests {
+    use crate::compiling_process::translating::testing::{interpret, program};
+    use crate::inner_representation::abstract_syntax_tree::Let;
+    use super::{translate, VirtualMachine};
+
+    // the machine and the interpreter give the same value, or the same error
+    fn compare(program: &[Let], name: &str) -> Result<String, String> {
+        let expected = interpret(program, name);
+        let bytecode = translate(program)?;
+        let machine = VirtualMachine::new(&bytecode);
+        let actual = machine.get(name).and_then(|x| machine.force_all(x)).map(|x| machine.show(&x));
+        assert_eq!(actual, expected);
+        actual
+    }
+
+    #[test]
+    fn unit_tests() {
+        let program = program("
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $sum: List Int -> Int = xs ~> foldr (x n ~> #add x n) 0 xs;
+            $total = sum (map (x ~> #mul x 10) numbers);
+            $shown = #concat (#show total) /!/;
+            $partial = #add 1;
+            $over = (x ~> y ~> #sub x y) 10 3;
+            $curried = (x y ~> #sub x y) 10;
+            $point = {x = 1, y = /two/};
+            $swap = p ~> p | {x, y} -> {x = y, y = x};
+            $swapped = swap point;
+            $classify = n ~> n | (0 | 1) -> /small/ | 2 -> /two/ | _ -> /big/;
+            $classes = map classify (Cons (0, Cons (2, Cons (7, Nil))));
+            $nested = Just (Just (Cons (refl, Nil)));
+            $hole = ?later;
+            $stuck = classify (#div 1 0);
+            $bad = (x ~> x | Just y -> y) Nothing;
+            $loop = loop;
+        ");
+        assert_eq!(compare(&program, "total"), Ok("60".to_string()));
+        assert_eq!(compare(&program, "shown"), Ok("/60!/".to_string()));
+        assert_eq!(compare(&program, "partial"), Ok("<function>".to_string()));
+        assert_eq!(compare(&program, "over"), Ok("7".to_string()));
+        assert_eq!(compare(&program, "curried"), Ok("<function>".to_string()));
+        assert_eq!(compare(&program, "swapped"), Ok("{x = /two/, y = 1}".to_string()));
+        assert_eq!(compare(&program, "classes"), Ok("Cons (/small/, Cons (/two/, Cons (/big/, Nil ())))".to_string()));
+        assert_eq!(compare(&program, "nested"), Ok("Just (Just (Cons ((), Nil ())))".to_string()));
+        assert_eq!(compare(&program, "hole"), Err("reached the unfinished hole ?later".to_string()));
+        assert_eq!(compare(&program, "stuck"), Err("division by zero".to_string()));
+        assert_eq!(compare(&program, "bad"), Err("no pattern matches Nothing ()".to_string()));
+        assert_eq!(compare(&program, "loop"), Err("loop is defined in terms of itself".to_string()));
+        assert_eq!(compare(&program, "missing"), Err("unbound name missing".to_string()));
+    }
+
+    #[test]
+    fn codata() {
+        let program = program("
+            $Stream = A ~> codata head A * tail (Stream A);
+            $from: Int -> Stream Int = n ~> {head = n, tail = from (#add n 1)};
+            $take: {A : @} -> Int -> Stream A -> List A = n s ~> #le n 0
+                | 1 -> Nil
+                | _ -> (s | {head, tail} -> Cons (head, take (#sub n 1) tail));
+            $main = take 5 (from 10);
+            $strict = length (Cons (#div 1 0, Nil));
+        ");
+        assert_eq!(compare(&program, "main"), Ok("Cons (10, Cons (11, Cons (12, Cons (13, Cons (14, Nil ())))))".to_string()));
+        // only the fields of codata wait until they are looked at
+        assert_eq!(compare(&program, "strict"), Err("division by zero".to_string()));
+    }
+
+    #[test]
+    fn lifted() {
+        // functions inside others are definitions given what they capture, a local one calls itself through its own
+        let program = program("
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $total: List Int -> Int = xs ~> $go = ys acc ~> ys | Nil -> acc | Cons (y, rest) -> go rest (#add acc y); go xs 0;
+            $shadow = x ~> $go = n ~> n | 0 -> x | k -> go (#sub k 1); $x = 5; (m ~> (go m, x)) 3;
+            $adders = n ~> map (k ~> #add k n) numbers;
+            $main = (total numbers, shadow 1, adders 10);
+            $delayed = x ~> (~> #add x 1);
+            $given = delayed 2;
+        ");
+        assert_eq!(compare(&program, "main"), Ok("(6, (1, 5), Cons (11, Cons (12, Cons (13, Nil ()))))".to_string()));
+        let bytecode = translate(&program).unwrap();
+        let lambdas: Vec<&str> = bytecode.functions.iter().filter_map(|x| x.name.strip_suffix(".lambda")).collect();
+        assert!(lambdas.iter().all(|x| bytecode.globals.iter().any(|(name, _)| name == x)), "{:?}", lambdas);
+        assert!(lambdas.contains(&"total$1"));
+        // erased a function without parameters is what it computes, the interpreter keeps it a function
+        let machine = VirtualMachine::new(&bytecode);
+        assert_eq!(machine.get("given").and_then(|x| machine.force_all(x)).map(|x| machine.show(&x)), Ok("3".to_string()));
+    }
+
+    #[test]
+    fn erased() {
+        // the machine runs the erased program, types are not passed to functions that only take them to check
+        let program = program("
+            $identity = (A : @) (x : A) ~> x;
+            $twice = (F : @ -> @) (A : @) (f : A -> A) (x : A) ~> f (f x);
+            $Id = A ~> A;
+            $main = (identity Int 3, twice Id Int (x ~> #add x 1) 5, identity (List Int) Nil);
+        ");
+        assert_eq!(compare(&program, "main"), Ok("(3, 7, Nil ())".to_string()));
+        let bytecode = translate(&program).unwrap();
+        assert!(!bytecode.globals.iter().any(|(name, _)| name == "Id"));
+        let lambda = bytecode.functions.iter().find(|x| x.name == "identity.lambda").unwrap();
+        assert_eq!(lambda.arity, 1);
+    }
+
+    #[test]
+    fn tail_calls() {
+        // far deeper than the interpreter's stack allows, the loop runs in constant space
+        let program = program("
+            $count = n acc ~> n | 0 -> acc | _ -> count (#sub n 1) (#add acc 2);
+            $main = count 1000000 0;
+            $deep = n ~> n | 0 -> 0 | _ -> #add 1 (deep (#sub n 1));
+            $built = deep 100000;
+        ");
+        let bytecode = translate(&program).unwrap();
+        let machine = VirtualMachine::new(&bytecode);
+        assert_eq!(machine.get("main").map(|x| machine.show(&x)), Ok("2000000".to_string()));
+        assert_eq!(machine.get("built").map(|x| machine.show(&x)), Ok("100000".to_string()));
+        println!("{}", bytecode.to_string().lines().take(40).collect::<Vec<_>>().join("\n"));
+    }
+
+    #[test]
+    fn long_lists() {
+        // building, folding and dropping a long list happen on the stack of the machine rather than the one of the test
+        let program = program("
+            $upto = n ~> n | 0 -> Nil | _ -> Cons (n, upto (#sub n 1));
+            $size = length (upto 100000);
+            $last = n ~> foldr (x rest ~> rest | Nothing -> Just x | _ -> rest) Nothing (upto n);
+            $first = last 100000;
+            $list = upto 100000;
+        ");
+        let bytecode = translate(&program).unwrap();
+        let machine = VirtualMachine::new(&bytecode);
+        assert_eq!(machine.get("size").map(|x| machine.show(&x)), Ok("100000".to_string()));
+        assert_eq!(machine.get("first").and_then(|x| machine.force_all(x)).map(|x| machine.show(&x)), Ok("Just 1".to_string()));
+        let list = machine.get("list").and_then(|x| machine.force_all(x)).map(|x| machine.show(&x)).unwrap();
+        assert!(list.starts_with("Cons (100000, Cons (99999, "));
+        assert!(list.ends_with(&format!("Cons (1, Nil ()){}", ")".repeat(99999))));
+    }
+}
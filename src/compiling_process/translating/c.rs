@@ -0,0 +1,535 @@
+use std::collections::HashMap;
+
+use crate::compiling_process::pattern_compiling::compile_match;
+use crate::compiling_process::primitives::Primitive;
+use crate::compiling_process::translating::closures::lift;
+use crate::compiling_process::translating::erasure::Term;
+use crate::inner_representation::abstract_syntax_tree::{AtomicValue, Let, Pattern};
+use crate::inner_representation::decision_tree::{Case, DecisionTree, Step};
+
+// allocation, strings, application and printing, the generated code only calls into it
+pub const RUNTIME: &str = include_str!("runtime.c");
+
+// a C function being generated, what it captures is read from `environment`
+struct Scope {
+    code: String,
+    indent: usize,
+    variables: Vec<(String, String)>,
+    // the names captured, with how the function around reads them
+    captures: Vec<(String, String)>,
+}
+
+struct Generator {
+    prototypes: Vec<String>,
+    functions: Vec<String>,
+    shapes: Vec<String>,
+    scopes: Vec<Scope>,
+    globals: HashMap<String, usize>,
+    symbols: Vec<String>,
+    fresh: usize,
+}
+
+fn identifier(name: &str) -> String {
+    name.chars().map(|x| if x.is_ascii_alphanumeric() { x } else { '_' }).collect()
+}
+
+// bytes outside printable ascii are written in octal, `?` is escaped so nothing reads as a trigraph
+fn string_literal(text: &str) -> String {
+    let mut result = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                result.push('\\');
+                result.push(byte as char);
+            },
+            0x20..=0x7e => result.push(byte as char),
+            _ => result.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn int_literal(n: i32) -> String {
+    if n == i32::MIN {
+        "make_int(INT32_MIN)".to_string()
+    } else {
+        format!("make_int({})", n)
+    }
+}
+
+fn primitive_name(primitive: Primitive) -> &'static str {
+    &primitive.name()[1..]
+}
+
+impl Generator {
+    fn symbol(&mut self, name: &str) -> usize {
+        match self.symbols.iter().position(|x| x == name) {
+            Some(index) => index,
+            None => {
+                self.symbols.push(name.to_string());
+                self.symbols.len() - 1
+            },
+        }
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.fresh += 1;
+        format!("{}_{}", prefix, self.fresh)
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn line(&mut self, text: &str) {
+        let scope = self.scope();
+        scope.code.push_str(&"    ".repeat(scope.indent));
+        scope.code.push_str(text);
+        scope.code.push('\n');
+    }
+
+    fn open(&mut self, text: &str) {
+        self.line(text);
+        self.scope().indent += 1;
+    }
+
+    fn close(&mut self, text: &str) {
+        self.scope().indent -= 1;
+        self.line(text);
+    }
+
+    // a new C variable holding the value, so values are computed in the order they are written
+    fn temporary(&mut self, expression: String) -> String {
+        let name = self.fresh("t");
+        self.line(&format!("Value {} = {};", name, expression));
+        name
+    }
+
+    fn bind(&mut self, name: &str, expression: String) {
+        let variable = format!("{}_{}", self.fresh("v"), identifier(name));
+        self.line(&format!("Value {} = {};", variable, expression));
+        self.scope().variables.push((name.to_string(), variable));
+    }
+
+    // a variable of one of the functions around, captured on the way in
+    fn resolve(&mut self, name: &str, depth: usize) -> Option<String> {
+        let scope = &self.scopes[depth];
+        if let Some((_, variable)) = scope.variables.iter().rev().find(|(x, _)| x == name) {
+            return Some(variable.clone());
+        }
+        if let Some(index) = scope.captures.iter().position(|(x, _)| x == name) {
+            return Some(format!("environment[{}]", index));
+        }
+        if depth == 0 {
+            return None;
+        }
+        let outer = self.resolve(name, depth - 1)?;
+        let captures = &mut self.scopes[depth].captures;
+        captures.push((name.to_string(), outer));
+        Some(format!("environment[{}]", captures.len() - 1))
+    }
+
+    // values that can neither fail nor loop, computing them now is the same as delaying them
+    fn is_immediate(term: &Term) -> bool {
+        match term {
+            Term::Local(_) | Term::Primitive(_) | Term::Int(_) | Term::Text(_) | Term::Function(_, _) | Term::Erased => true,
+            Term::Tuple(items) => items.iter().all(Self::is_immediate),
+            Term::Record(fields) => fields.iter().all(|(_, x)| Self::is_immediate(x)),
+            Term::Either(_, payload) => Self::is_immediate(payload),
+            _ => false,
+        }
+    }
+
+    fn array(values: &[String]) -> String {
+        if values.is_empty() {
+            "NULL".to_string()
+        } else {
+            format!("(Value[]){{{}}}", values.join(", "))
+        }
+    }
+
+    // generates a C function and gives its name with the values to capture for it
+    fn function(
+        &mut self,
+        name: &str,
+        parameters: &[String],
+        body: impl FnOnce(&mut Self) -> Result<(), String>,
+    ) -> Result<(String, Vec<String>), String> {
+        let function = format!("{}_{}", self.fresh("function"), identifier(name));
+        self.scopes.push(Scope {
+            code: String::new(),
+            indent: 1,
+            variables: vec![],
+            captures: vec![],
+        });
+        self.line("(void)environment;");
+        self.line("(void)arguments;");
+        for (i, parameter) in parameters.iter().enumerate() {
+            self.bind(parameter, format!("arguments[{}]", i));
+        }
+        body(self)?;
+        let scope = self.scopes.pop().unwrap();
+        let signature = format!("static Value {}(Value *environment, Value *arguments)", function);
+        self.prototypes.push(format!("{};", signature));
+        self.functions.push(format!("{} {{\n{}}}\n", signature, scope.code));
+        Ok((function, scope.captures.into_iter().map(|(_, x)| x).collect()))
+    }
+
+    fn delayed(&mut self, term: &Term) -> Result<String, String> {
+        if Self::is_immediate(term) {
+            return self.value(term);
+        }
+        let (function, captures) = self.function("thunk", &[], |generator| generator.tail(term))?;
+        Ok(self.temporary(format!("make_thunk({}, {}, {})", function, captures.len(), Self::array(&captures))))
+    }
+
+    fn lets(&mut self, lets: &[(String, Term)]) -> Result<usize, String> {
+        let depth = self.scope().variables.len();
+        for (name, value) in lets {
+            let value = self.value(value)?;
+            self.bind(name, value);
+        }
+        Ok(depth)
+    }
+
+    fn values(&mut self, terms: &[Term]) -> Result<Vec<String>, String> {
+        terms.iter().map(|x| self.value(x)).collect()
+    }
+
+    // the term as a C expression, the statements computing it are written before
+    fn value(&mut self, term: &Term) -> Result<String, String> {
+        Ok(match term {
+            Term::Local(name) => {
+                let depth = self.scopes.len() - 1;
+                self.resolve(name, depth).ok_or_else(|| format!("the local {} is not bound", name))?
+            },
+            Term::Global(name) => {
+                let global = self.globals[name];
+                self.temporary(format!("global({})", global))
+            },
+            Term::Primitive(primitive) => {
+                format!("make_closure(code_{}, {}, 0, NULL)", primitive_name(*primitive), primitive.arity())
+            },
+            Term::Call(primitive, arguments) => {
+                let arguments = self.values(arguments)?;
+                self.temporary(format!("primitive_{}({})", primitive_name(*primitive), arguments.join(", ")))
+            },
+            Term::Tuple(items) if items.is_empty() => "UNIT".to_string(),
+            Term::Tuple(items) => {
+                let items = self.values(items)?;
+                self.temporary(format!("make_tuple({}, {})", items.len(), Self::array(&items)))
+            },
+            Term::Record(fields) => {
+                let values = fields.iter().map(|(_, x)| self.value(x)).collect::<Result<Vec<_>, _>>()?;
+                let names: Vec<String> = fields.iter().map(|(name, _)| self.symbol(name).to_string()).collect();
+                let shape = format!("shape_{}", self.shapes.len());
+                let names = if names.is_empty() { "0".to_string() } else { names.join(", ") };
+                self.shapes.push(format!("static const int {}[] = {{{}}};", shape, names));
+                self.temporary(format!("make_record({}, {}, {})", fields.len(), shape, Self::array(&values)))
+            },
+            Term::Either(name, payload) => {
+                let payload = self.value(payload)?;
+                let constructor = self.symbol(name);
+                self.temporary(format!("make_either({}, {})", constructor, payload))
+            },
+            Term::Lazy(term) => self.delayed(term)?,
+            Term::Match(scrutinee, arms) => {
+                let result = self.fresh("result");
+                let end = self.fresh("end");
+                self.line(&format!("Value {};", result));
+                self.matching(scrutinee, arms, Some((&result, &end)))?;
+                self.line(&format!("{}:;", end));
+                result
+            },
+            Term::Function(parameters, body) => {
+                // only a definition is a function, what it captures are the thunks it delays
+                if self.scopes.len() > 1 {
+                    return Err("functions are lifted to definitions before lowering".to_string());
+                }
+                let name = format!("lambda_{}", parameters.join("_"));
+                let (function, captures) = self.function(&name, parameters, |generator| generator.tail(body))?;
+                let arguments = format!("{}, {}, {}", parameters.len(), captures.len(), Self::array(&captures));
+                self.temporary(format!("make_closure({}, {})", function, arguments))
+            },
+            Term::Apply(function, arguments) => {
+                let function = self.value(function)?;
+                let function = self.temporary(function);
+                let arguments = self.values(arguments)?;
+                self.temporary(format!("apply({}, {}, {})", function, arguments.len(), Self::array(&arguments)))
+            },
+            Term::Let(lets, body) => {
+                let depth = self.lets(lets)?;
+                let value = self.value(body)?;
+                self.scope().variables.truncate(depth);
+                value
+            },
+            Term::Int(n) => int_literal(*n),
+            Term::Text(text) => format!("make_text({}, {})", string_literal(text), text.len()),
+            Term::Erased => "TYPE_VALUE".to_string(),
+            Term::Fail(message) => format!("fail({})", string_literal(message)),
+        })
+    }
+
+    // returns the value, a call at the end is left to the caller so loops take no stack
+    fn tail(&mut self, term: &Term) -> Result<(), String> {
+        match term {
+            Term::Match(scrutinee, arms) => self.matching(scrutinee, arms, None),
+            Term::Apply(function, arguments) => {
+                let function = self.value(function)?;
+                let function = self.temporary(function);
+                let arguments = self.values(arguments)?;
+                self.line(&format!("return tail_call({}, {}, {});", function, arguments.len(), Self::array(&arguments)));
+                Ok(())
+            },
+            Term::Let(lets, body) => {
+                let depth = self.lets(lets)?;
+                self.tail(body)?;
+                self.scope().variables.truncate(depth);
+                Ok(())
+            },
+            term => {
+                let value = self.value(term)?;
+                self.line(&format!("return {};", value));
+                Ok(())
+            },
+        }
+    }
+
+    // `target` is where a match that is not in tail position puts its value and jumps to after
+    fn matching(&mut self, scrutinee: &Term, arms: &[(Pattern, Term)], target: Option<(&str, &str)>) -> Result<(), String> {
+        let scrutinee = self.value(scrutinee)?;
+        let scrutinee = self.temporary(scrutinee);
+        let patterns: Vec<&Pattern> = arms.iter().map(|(pattern, _)| pattern).collect();
+        let tree = compile_match(&patterns)?;
+        self.tree(&tree, arms, &scrutinee, target)
+    }
+
+    fn select(&mut self, scrutinee: &str, occurrence: &[Step]) -> String {
+        occurrence.iter().fold(scrutinee.to_string(), |value, step| match step {
+            Step::Payload => format!("select_payload({})", value),
+            Step::Index(i) => format!("select_index({}, {})", value, i),
+            Step::Field(name) => format!("select_field({}, {})", value, self.symbol(name)),
+        })
+    }
+
+    fn tree(
+        &mut self,
+        tree: &DecisionTree,
+        arms: &[(Pattern, Term)],
+        scrutinee: &str,
+        target: Option<(&str, &str)>,
+    ) -> Result<(), String> {
+        match tree {
+            DecisionTree::Fail => self.line(&format!("no_match({});", scrutinee)),
+            DecisionTree::Leaf(arm, bindings) => {
+                let depth = self.scope().variables.len();
+                for (name, occurrence) in bindings {
+                    let value = self.select(scrutinee, occurrence);
+                    self.bind(name.get_name(), value);
+                }
+                match target {
+                    Some((result, end)) => {
+                        let value = self.value(&arms[*arm].1)?;
+                        self.line(&format!("{} = {};", result, value));
+                        self.line(&format!("goto {};", end));
+                    },
+                    None => self.tail(&arms[*arm].1)?,
+                }
+                self.scope().variables.truncate(depth);
+            },
+            DecisionTree::Switch(occurrence, cases, default) => {
+                let value = self.select(scrutinee, occurrence);
+                let value = self.temporary(format!("force({})", value));
+                let constructors = matches!(cases.first(), Some((Case::Constructor(_), _)));
+                if constructors {
+                    self.open(&format!("switch (constructor_of({})) {{", value));
+                }
+                for (i, (case, tree)) in cases.iter().enumerate() {
+                    let test = match case {
+                        Case::Constructor(name) => format!("case {}: {{", self.symbol(name)),
+                        Case::Literal(AtomicValue::Int(n)) => {
+                            format!("is_int({0}) && int_of({0}) == {1}", value, int_literal(*n).replace("make_int", ""))
+                        },
+                        Case::Literal(AtomicValue::StringLiteral(text)) => {
+                            format!("is_text({}, {}, {})", value, string_literal(text), text.len())
+                        },
+                    };
+                    match (constructors, i) {
+                        (true, _) => self.open(&test),
+                        (false, 0) => self.open(&format!("if ({}) {{", test)),
+                        (false, _) => self.open(&format!("}} else if ({}) {{", test)),
+                    }
+                    self.tree(tree, arms, scrutinee, target)?;
+                    if constructors {
+                        self.close("}");
+                    } else {
+                        self.scope().indent -= 1;
+                    }
+                }
+                match (constructors, cases.is_empty()) {
+                    (true, _) => self.open("default: {"),
+                    (false, true) => self.open("{"),
+                    (false, false) => self.open("} else {"),
+                }
+                match default {
+                    Some(tree) => self.tree(tree, arms, scrutinee, target)?,
+                    None => self.line(&format!("no_match({});", scrutinee)),
+                }
+                self.close("}");
+                if constructors {
+                    self.close("}");
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+// the erased program as one C99 file with the runtime, printing `main` when run
+pub fn emit_c(program: &[Let]) -> Result<String, String> {
+    let program = lift(program)?;
+    let program = &program.definitions;
+    let main = program.iter().position(|(name, _)| name == "main").ok_or("unbound name main")?;
+    let mut generator = Generator {
+        prototypes: vec![],
+        functions: vec![],
+        shapes: vec![],
+        scopes: vec![],
+        globals: program.iter().enumerate().map(|(i, (name, _))| (name.clone(), i)).collect(),
+        symbols: vec![],
+        fresh: 0,
+    };
+    let mut globals = vec![];
+    for (name, term) in program {
+        let (function, _) = generator
+            .function(name, &[], |generator| generator.tail(term))
+            .map_err(|error| format!("in {}: {}", name, error))?;
+        globals.push(function);
+    }
+    let names: Vec<String> = program.iter().map(|(name, _)| string_literal(name)).collect();
+    let mut symbols: Vec<String> = generator.symbols.iter().map(|x| string_literal(x)).collect();
+    if symbols.is_empty() {
+        symbols.push("\"\"".to_string());
+    }
+    let mut result = RUNTIME.to_string();
+    result.push_str(&generator.prototypes.join("\n"));
+    result.push_str("\n\n");
+    result.push_str(&generator.shapes.join("\n"));
+    result.push('\n');
+    result.push_str(&generator.functions.join("\n"));
+    result.push_str(&format!("\nconst char *symbols[] = {{{}}};\n", symbols.join(", ")));
+    result.push_str(&format!("const char *global_names[] = {{{}}};\n", names.join(", ")));
+    result.push_str(&format!("Code global_code[] = {{{}}};\n", globals.join(", ")));
+    result.push_str(&format!("Value global_values[{}];\n", program.len()));
+    result.push_str(&format!("char global_states[{}];\n\n", program.len()));
+    result.push_str(&format!("int main(void) {{\n    puts(show(force_all(global({}))));\n    return 0;\n}}\n", main));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod c_tests {
+    use std::fs;
+    use std::process::Command;
+
+    use crate::compiling_process::translating::testing::{directory, interpret, program, run, succeeds};
+    use crate::inner_representation::abstract_syntax_tree::Let;
+    use super::{emit_c, string_literal};
+
+    // what the compiled program prints, or its error
+    fn compile(name: &str, program: &[Let]) -> Result<String, String> {
+        let directory = directory("c");
+        let source = directory.join(format!("{}.c", name));
+        let executable = directory.join(name);
+        fs::write(&source, emit_c(program).unwrap()).unwrap();
+        succeeds(Command::new("cc").arg("-std=c99").arg("-O2").arg("-o").arg(&executable).arg(&source));
+        run(&mut Command::new(&executable))
+    }
+
+    fn values() -> Vec<Let> {
+        program("
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $sum: List Int -> Int = xs ~> foldr (x n ~> #add x n) 0 xs;
+            $point = {x = 1, y = /two/};
+            $swap = p ~> p | {x, y} -> {x = y, y = x};
+            $classify = n ~> n | (0 | 1) -> /small/ | 2 -> /two/ | _ -> /big/;
+            $words = s ~> s | /one/ -> 1 | /two/ -> 2 | _ -> 0;
+            $over = (x ~> y ~> #sub x y) 10 3;
+            $main = (
+                sum (map (x ~> #mul x 10) numbers),
+                #concat (#show (#div -7 2)) (#slice /?hello?/ 1 6),
+                swap point,
+                map classify (Cons (0, Cons (2, Cons (7, Nil)))),
+                (words /two/, words /three/),
+                (over, #add 2147483647 1, Just (Just refl), #add 1)
+            );
+        ")
+    }
+
+    fn codata() -> Vec<Let> {
+        program("
+            $Stream = A ~> codata head A * tail (Stream A);
+            $from: Int -> Stream Int = n ~> {head = n, tail = from (#add n 1)};
+            $take: {A : @} -> Int -> Stream A -> List A = n s ~> #le n 0
+                | 1 -> Nil
+                | _ -> (s | {head, tail} -> Cons (head, take (#sub n 1) tail));
+            $count = n acc ~> n | 0 -> acc | _ -> count (#sub n 1) (#add acc 2);
+            $main = (take 3 (from 10), count 10000000 0);
+        ")
+    }
+
+    #[test]
+    fn unit_tests() {
+        assert_eq!(string_literal("a\"b\\c??=\n"), "\"a\\\"b\\\\c\\?\\?=\\012\"");
+        assert_eq!(string_literal("é"), "\"\\303\\251\"");
+        assert_eq!(emit_c(&program("$x = 1;")).unwrap_err(), "unbound name main");
+
+        let code = emit_c(&values()).unwrap();
+        assert!(code.contains("switch (constructor_of("));
+        assert!(code.contains("make_text(\"two\", 3)"));
+        assert!(code.contains("int main(void) {\n    puts(show(force_all(global("));
+        let code = emit_c(&codata()).unwrap();
+        assert!(code.contains("make_thunk("));
+        assert!(code.contains("return tail_call("));
+    }
+
+    #[test]
+    #[ignore = "runs cc"]
+    fn compiled() {
+        let program = values();
+        assert_eq!(compile("unit_tests", &program), interpret(&program, "main"));
+    }
+
+    #[test]
+    #[ignore = "runs cc"]
+    fn lifted() {
+        // a local function calls itself through the definition it is lifted to, given the x it captures
+        let program = program("
+            $total: List Int -> Int = xs ~> $go = ys acc ~> ys | Nil -> acc | Cons (y, rest) -> go rest (#add acc y); go xs 0;
+            $shadow = x ~> $go = n ~> n | 0 -> x | k -> go (#sub k 1); $x = 5; (m ~> (go m, x)) 3;
+            $main = (total (Cons (1, Cons (2, Cons (3, Nil)))), shadow 1);
+        ");
+        assert_eq!(compile("lifted", &program), interpret(&program, "main"));
+    }
+
+    #[test]
+    #[ignore = "runs cc"]
+    fn codata_and_loops() {
+        assert_eq!(compile("codata_and_loops", &codata()), Ok("(Cons (10, Cons (11, Cons (12, Nil ()))), 20000000)".to_string()));
+        // the list functions of the prelude go through long lists without growing the stack
+        let lists = program("
+            $replicate = n ~> $go = k acc ~> #le k 0 | 1 -> acc | _ -> go (#sub k 1) (Cons (k, acc)); go n Nil;
+            $small = x ~> bool (#le x 5);
+            $both = append (replicate 100000) (replicate 3);
+            $main = (length (replicate 100000), length (map (x ~> #add x 1) (filter small both)));
+        ");
+        assert_eq!(compile("long_lists", &lists), Ok("(100000, 8)".to_string()));
+
+        let failing = |name: &str, text: &str| compile(name, &program(text));
+        let expected = |text: &str| Err(text.to_string());
+        assert_eq!(failing("division", "$main = #mod 1 0;"), expected("division by zero"));
+        assert_eq!(failing("hole", "$main = #add 1 ?later;"), expected("reached the unfinished hole ?later"));
+        assert_eq!(failing("no_match", "$main = Nothing | Just x -> x;"), expected("no pattern matches Nothing ()"));
+        assert_eq!(failing("loop", "$main = main;"), expected("main is defined in terms of itself"));
+    }
+}
@@ -0,0 +1,497 @@
+use crate::compiling_process::translating::erasure::{erase, Program, Term};
+use crate::inner_representation::abstract_syntax_tree::{Context, Expr, Let, Name, Pattern, Type, Value, Visibility};
+
+// programs where functions are only ever definitions: a function inside another one is lifted to a definition of its
+// own taking the variables it captures before its parameters, and where it was there is that definition given them
+
+fn name(text: &str) -> Name {
+    Name::new(text.to_string(), Context::ValueContext)
+}
+
+// every binding gets an id of its own and the names referring to it carry that id, names that are not bound locally
+// are left at 0, a binding hiding another local of the same name is renamed so that none is hidden once functions
+// are lifted
+struct Resolution {
+    scopes: Vec<(String, Name)>,
+    next: usize,
+}
+
+fn renamed(name: &Name, text: &str) -> Name {
+    let result = Name::new(text.to_string(), name.get_context());
+    match name.get_span() {
+        Some(span) => result.with_span(span),
+        None => result,
+    }
+}
+
+impl Resolution {
+    fn binder(&mut self, name: &Name) -> Name {
+        self.next += 1;
+        let hides = self.scopes.iter().any(|(x, _)| x == name.get_name());
+        let text = if hides { format!("{}$local{}", name.get_name(), self.next) } else { name.get_name().to_string() };
+        let resolved = renamed(name, &text).with_id(self.next);
+        self.scopes.push((name.get_name().to_string(), resolved.clone()));
+        resolved
+    }
+
+    fn reference(&self, name: &Name) -> Name {
+        Self::lookup(&self.scopes, name)
+    }
+
+    fn lookup(scopes: &[(String, Name)], name: &Name) -> Name {
+        match scopes.iter().rev().find(|(x, _)| x == name.get_name()) {
+            Some((_, resolved)) => renamed(name, resolved.get_name()).with_id(resolved.get_id()),
+            None => name.clone().with_id(0),
+        }
+    }
+
+    // the alternatives of an or-pattern bind the same names, they are given the ids of the first one
+    fn pattern(&mut self, pattern: &Pattern, first: Option<usize>) -> Pattern {
+        match pattern {
+            Pattern::Binder(name) => match first {
+                Some(depth) => Pattern::Binder(Self::lookup(&self.scopes[depth..], name)),
+                None => Pattern::Binder(self.binder(name)),
+            },
+            Pattern::Constructor(name, inner) => Pattern::Constructor(name.clone(), Box::new(self.pattern(inner, first))),
+            Pattern::Tuple(items) => Pattern::Tuple(items.iter().map(|x| self.pattern(x, first)).collect()),
+            Pattern::Record(fields) => {
+                Pattern::Record(fields.iter().map(|(name, x)| (name.clone(), self.pattern(x, first))).collect())
+            },
+            Pattern::Or(alternatives) => {
+                let depth = self.scopes.len();
+                let mut resolved = vec![];
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    resolved.push(self.pattern(alternative, if i == 0 { first } else { first.or(Some(depth)) }));
+                }
+                Pattern::Or(resolved)
+            },
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Refl => pattern.clone(),
+        }
+    }
+
+    fn expr(&mut self, Expr(lets, value): &Expr) -> Expr {
+        let depth = self.scopes.len();
+        let mut resolved = vec![];
+        for local in lets {
+            let Let(name, value, typ) = local;
+            let (name, value) = if local.is_recursive() {
+                let name = self.binder(name);
+                (name, self.value(value))
+            } else {
+                let value = self.value(value);
+                (self.binder(name), value)
+            };
+            resolved.push(Let(name, value, typ.clone()));
+        }
+        let value = self.value(value);
+        self.scopes.truncate(depth);
+        Expr(resolved, value)
+    }
+
+    // types are left as they are, they are never computed with
+    fn value(&mut self, value: &Value) -> Value {
+        match value {
+            Value::Var(name) => Value::Var(self.reference(name)),
+            Value::Tuple(items) => Value::Tuple(items.iter().map(|x| self.value(x)).collect()),
+            Value::Record(fields) => Value::Record(fields.iter().map(|(name, x)| (name.clone(), self.value(x))).collect()),
+            Value::Either(name, payload) => Value::Either(name.clone(), Box::new(self.value(payload))),
+            Value::Match(scrutinee, arms) => {
+                let scrutinee = self.value(scrutinee);
+                let mut resolved = vec![];
+                for (pattern, arm) in arms {
+                    let depth = self.scopes.len();
+                    let pattern = self.pattern(pattern, None);
+                    resolved.push((pattern, self.value(arm)));
+                    self.scopes.truncate(depth);
+                }
+                Value::Match(Box::new(scrutinee), resolved)
+            },
+            Value::Function(parameters, body) => {
+                let depth = self.scopes.len();
+                let parameters = parameters.iter().map(|(name, typ)| (self.binder(name), typ.clone())).collect();
+                let body = self.expr(body);
+                self.scopes.truncate(depth);
+                Value::Function(parameters, Box::new(body))
+            },
+            Value::Application(function, arguments) => {
+                Value::Application(Box::new(self.value(function)), arguments.iter().map(|x| self.value(x)).collect())
+            },
+            Value::Implicit(value) => Value::Implicit(Box::new(self.value(value))),
+            Value::Constant(_, _) | Value::Type(_) | Value::Hole(_) | Value::Refl => value.clone(),
+        }
+    }
+}
+
+// the local functions that see themselves, by id, with the definition each was lifted to and the variables it captures
+type Lifted = [(usize, Name, Vec<Name>)];
+
+// a lifted local function used here uses what it captures
+fn references(value: &Value, lifted: &Lifted, bound: &mut Vec<usize>, result: &mut Vec<Name>) {
+    let mut refer = |name: &Name, bound: &[usize]| {
+        let known = result.iter().any(|x| x.get_id() == name.get_id() && x.get_name() == name.get_name());
+        if !bound.contains(&name.get_id()) && !known {
+            result.push(name.clone());
+        }
+    };
+    match value {
+        Value::Var(name) => match lifted.iter().find(|(id, _, _)| name.get_id() != 0 && *id == name.get_id()) {
+            Some((_, _, captured)) => captured.iter().for_each(|x| refer(x, bound)),
+            None => refer(name, bound),
+        },
+        Value::Tuple(items) => items.iter().for_each(|x| references(x, lifted, bound, result)),
+        Value::Record(fields) => fields.iter().for_each(|(_, x)| references(x, lifted, bound, result)),
+        Value::Either(_, payload) | Value::Implicit(payload) => references(payload, lifted, bound, result),
+        Value::Match(scrutinee, arms) => {
+            references(scrutinee, lifted, bound, result);
+            for (pattern, arm) in arms {
+                let depth = bound.len();
+                bound.extend(pattern.binders().into_iter().map(|x| x.get_id()));
+                references(arm, lifted, bound, result);
+                bound.truncate(depth);
+            }
+        },
+        Value::Function(parameters, body) => {
+            let depth = bound.len();
+            bound.extend(parameters.iter().map(|(x, _)| x.get_id()));
+            let Expr(lets, value) = &**body;
+            for local in lets {
+                let Let(name, value, _) = local;
+                if local.is_recursive() {
+                    bound.push(name.get_id());
+                }
+                references(value, lifted, bound, result);
+                if !local.is_recursive() {
+                    bound.push(name.get_id());
+                }
+            }
+            references(value, lifted, bound, result);
+            bound.truncate(depth);
+        },
+        Value::Application(function, arguments) => {
+            references(function, lifted, bound, result);
+            arguments.iter().for_each(|x| references(x, lifted, bound, result));
+        },
+        // types are left as they are, they are never computed with
+        Value::Constant(_, _) | Value::Type(_) | Value::Hole(_) | Value::Refl => {},
+    }
+}
+
+// the parameters take their types from the annotation of the local function when they have none of their own
+fn annotated(parameters: &[(Name, Option<Type>)], annotation: Option<&Type>) -> Vec<(Name, Option<Type>)> {
+    let mut annotation = annotation;
+    let mut result = vec![];
+    for (name, typ) in parameters {
+        let (from, to) = match annotation {
+            Some(Type::Function(from, to)) | Some(Type::Pi(Visibility::Explicit, _, from, to)) => (Some(&**from), Some(&**to)),
+            _ => (None, None),
+        };
+        annotation = to;
+        result.push((name.clone(), typ.clone().or(from.cloned())));
+    }
+    result
+}
+
+struct Conversion {
+    // the definition being converted, lifted functions are named after it
+    definition: String,
+    count: usize,
+    lifted: Vec<Let>,
+    recursive: Vec<(usize, Name, Vec<Name>)>,
+}
+
+impl Conversion {
+    fn expr(&mut self, Expr(lets, value): &Expr) -> Expr {
+        let mut converted = vec![];
+        for local in lets {
+            let Let(name, value, typ) = local;
+            match value {
+                // uses of it are replaced by the definition it is lifted to
+                Value::Function(parameters, body) if local.is_recursive() => {
+                    let parameters = annotated(parameters, typ.as_ref());
+                    self.function(Some(name), &parameters, body);
+                },
+                value => converted.push(Let(name.clone(), self.value(value), typ.clone())),
+            }
+        }
+        Expr(converted, self.value(value))
+    }
+
+    // what a lifted local function stands for where it is used
+    fn replaced(&self, name: &Name, arguments: Vec<Value>) -> Option<Value> {
+        let (_, lifted, captured) = self.recursive.iter().find(|(id, _, _)| name.get_id() != 0 && *id == name.get_id())?;
+        let mut given: Vec<Value> = captured.iter().map(|x| Value::Var(x.clone())).collect();
+        given.extend(arguments);
+        Some(match given.is_empty() {
+            true => Value::Var(lifted.clone()),
+            false => Value::Application(Box::new(Value::Var(lifted.clone())), given),
+        })
+    }
+
+    // a function without parameters is lifted as well, its definition is what it computes once given the captures
+    fn function(&mut self, own: Option<&Name>, parameters: &[(Name, Option<Type>)], body: &Expr) -> Value {
+        let mut bound: Vec<usize> = own.iter().map(|x| x.get_id()).collect();
+        let mut captured = vec![];
+        references(&Value::Function(parameters.to_vec(), Box::new(body.clone())), &self.recursive, &mut bound, &mut captured);
+        captured.retain(|x| x.get_id() != 0);
+        self.count += 1;
+        let lifted = name(&format!("{}${}", self.definition, self.count));
+        if let Some(own) = own {
+            self.recursive.push((own.get_id(), lifted.clone(), captured.clone()));
+        }
+        let body = self.expr(body);
+        let mut lifted_parameters: Vec<(Name, Option<Type>)> = captured.iter().map(|x| (x.clone(), None)).collect();
+        lifted_parameters.extend(parameters.iter().cloned());
+        self.lifted.push(Let(lifted.clone(), Value::Function(lifted_parameters, Box::new(body)), None));
+        match captured.is_empty() {
+            true => Value::Var(lifted),
+            false => Value::Application(Box::new(Value::Var(lifted)), captured.into_iter().map(Value::Var).collect()),
+        }
+    }
+
+    fn value(&mut self, value: &Value) -> Value {
+        match value {
+            Value::Var(name) => self.replaced(name, vec![]).unwrap_or_else(|| value.clone()),
+            Value::Tuple(items) => Value::Tuple(items.iter().map(|x| self.value(x)).collect()),
+            Value::Record(fields) => Value::Record(fields.iter().map(|(name, x)| (name.clone(), self.value(x))).collect()),
+            Value::Either(name, payload) => Value::Either(name.clone(), Box::new(self.value(payload))),
+            Value::Match(scrutinee, arms) => Value::Match(
+                Box::new(self.value(scrutinee)),
+                arms.iter().map(|(pattern, arm)| (pattern.clone(), self.value(arm))).collect(),
+            ),
+            // the calls anonymous functions get are not known, none of their parameters can be erased
+            Value::Function(parameters, body) => {
+                let parameters: Vec<(Name, Option<Type>)> = parameters.iter().map(|(x, _)| (x.clone(), None)).collect();
+                self.function(None, &parameters, body)
+            },
+            Value::Application(function, arguments) => {
+                let arguments: Vec<Value> = arguments.iter().map(|x| self.value(x)).collect();
+                match &**function {
+                    Value::Var(name) => match self.replaced(name, arguments.clone()) {
+                        Some(replaced) => replaced,
+                        None => Value::Application(function.clone(), arguments),
+                    },
+                    function => Value::Application(Box::new(self.value(function)), arguments),
+                }
+            },
+            Value::Implicit(value) => Value::Implicit(Box::new(self.value(value))),
+            Value::Constant(_, _) | Value::Type(_) | Value::Hole(_) | Value::Refl => value.clone(),
+        }
+    }
+}
+
+// every function of the program as a definition, the ones lifted out of a definition come right before it
+pub fn convert_closures(program: &[Let]) -> Vec<Let> {
+    let mut resolution = Resolution { scopes: vec![], next: 0 };
+    let mut result = vec![];
+    for Let(name, value, typ) in program {
+        let mut conversion = Conversion {
+            definition: name.get_name().to_string(),
+            count: 0,
+            lifted: vec![],
+            recursive: vec![],
+        };
+        // a definition that is a function already is where it has to be
+        let value = match resolution.value(value) {
+            Value::Function(parameters, body) => Value::Function(parameters, Box::new(conversion.expr(&body))),
+            value => conversion.value(&value),
+        };
+        result.extend(conversion.lifted);
+        result.push(Let(name.clone(), value, typ.clone()));
+    }
+    result
+}
+
+fn is_closed(term: &Term) -> bool {
+    match term {
+        Term::Function(_, _) => false,
+        Term::Call(_, items) | Term::Tuple(items) => items.iter().all(is_closed),
+        Term::Record(fields) => fields.iter().all(|(_, x)| is_closed(x)),
+        Term::Either(_, payload) | Term::Lazy(payload) => is_closed(payload),
+        Term::Match(scrutinee, arms) => is_closed(scrutinee) && arms.iter().all(|(_, x)| is_closed(x)),
+        Term::Apply(function, arguments) => is_closed(function) && arguments.iter().all(is_closed),
+        Term::Let(lets, body) => lets.iter().all(|(_, x)| is_closed(x)) && is_closed(body),
+        Term::Local(_) | Term::Global(_) | Term::Primitive(_) | Term::Int(_) | Term::Text(_) => true,
+        Term::Erased | Term::Fail(_) => true,
+    }
+}
+
+// what backends without closures of their own are given: the erased program where every function is a definition,
+// only what they delay still captures variables
+pub fn lift(program: &[Let]) -> Result<Program, String> {
+    let program = erase(&convert_closures(program), true)?;
+    for (name, term) in &program.definitions {
+        let closed = match term {
+            Term::Function(_, body) => is_closed(body),
+            term => is_closed(term),
+        };
+        if !closed {
+            return Err(format!("in {}: a function is left inside another one", name));
+        }
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod closures_tests {
+    use crate::compiling_process::translating::testing::{interpret, program};
+    use crate::inner_representation::abstract_syntax_tree::{Expr, Let, Value};
+    use super::{convert_closures, lift, references, Resolution};
+
+    // the names a value uses and does not bind, definitions and primitives included, in the order they are first used
+    fn free_variables(value: &Value) -> Vec<String> {
+        let mut result = vec![];
+        let value = Resolution { scopes: vec![], next: 0 }.value(value);
+        references(&value, &[], &mut vec![], &mut result);
+        result.into_iter().map(|x| x.get_name().to_string()).collect()
+    }
+
+    // how many functions there are that are not a definition
+    fn nested(value: &Value) -> usize {
+        match value {
+            Value::Tuple(items) => items.iter().map(nested).sum(),
+            Value::Record(fields) => fields.iter().map(|(_, x)| nested(x)).sum(),
+            Value::Either(_, payload) | Value::Implicit(payload) => nested(payload),
+            Value::Match(scrutinee, arms) => nested(scrutinee) + arms.iter().map(|(_, x)| nested(x)).sum::<usize>(),
+            Value::Function(_, body) => 1 + lets(body),
+            Value::Application(function, arguments) => nested(function) + arguments.iter().map(nested).sum::<usize>(),
+            Value::Var(_) | Value::Constant(_, _) | Value::Type(_) | Value::Hole(_) | Value::Refl => 0,
+        }
+    }
+
+    fn lets(Expr(lets, value): &Expr) -> usize {
+        lets.iter().map(|Let(_, x, _)| nested(x)).sum::<usize>() + nested(value)
+    }
+
+    fn is_converted(program: &[Let]) -> bool {
+        program.iter().all(|Let(_, value, _)| match value {
+            Value::Function(_, body) => lets(body) == 0,
+            value => nested(value) == 0,
+        })
+    }
+
+    // the converted program gives every definition the same value as the program
+    fn compare(program: &[Let], names: &[&str]) {
+        let converted = convert_closures(program);
+        assert!(is_converted(&converted));
+        for name in names {
+            let expected = interpret(program, name);
+            assert!(expected.is_ok(), "{}: {:?}", name, expected);
+            assert_eq!(interpret(&converted, name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn unit_tests() {
+        let program = program("
+            $adder = x ~> y ~> #add x y;
+            $main = adder 1 2;
+        ");
+        let converted = convert_closures(&program);
+        let names: Vec<&str> = converted.iter().map(|Let(name, _, _)| name.get_name()).collect();
+        assert_eq!(&names[names.len() - 3..], ["adder$1", "adder", "main"]);
+        let Let(_, lifted, _) = &converted[converted.len() - 3];
+        let Value::Function(parameters, body) = lifted else { panic!("adder$1 is not a function") };
+        let parameters: Vec<&str> = parameters.iter().map(|(x, _)| x.get_name()).collect();
+        // the captured x comes before y
+        assert_eq!(parameters, ["x", "y"]);
+        assert!(body.0.is_empty());
+        assert_eq!(free_variables(lifted), ["#add"]);
+
+        let program = self::program("
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $scaled = n ~> map (x ~> #mul x n) numbers;
+            $curried = x ~> y ~> z ~> #sub (#add x y) z;
+            $shadowed = x ~> (x ~> #mul x 2) (#add x 1);
+            $lets = x ~> $y = #add x 1; $f = z ~> #add y z; f x;
+            $matched = m ~> m | Just v -> (w ~> #add v w) | Nothing -> (w ~> w);
+            $record = n ~> {get = (_ ~> n), add = (k ~> #add k n)};
+            $stream: Int -> Stream Int = n ~> Step (n, (_ ~> stream (#add n 1)));
+            $main = (
+                foldr (x n ~> #add x n) 0 (scaled 10),
+                curried 10 3 2,
+                shadowed 4,
+                lets 5,
+                map (f ~> f 1) (Cons (matched (Just 3), Cons (matched Nothing, Nil))),
+                (record 7 | {get, add} -> (get (), add 1)),
+                (stream 3 | Step (x, rest) -> rest () | Step (y, _) -> (x, y)),
+                (x ~> (y ~> #concat x y)) /a/ /b/
+            );
+        ");
+        compare(&program, &["main", "numbers"]);
+    }
+
+    fn definition<'a>(program: &'a [Let], name: &str) -> &'a Value {
+        &program.iter().find(|Let(x, _, _)| x.get_name() == name).unwrap().1
+    }
+
+    #[test]
+    fn shadowing() {
+        // go captures the first x, the function it is used in the second one, which is renamed as it hides the first
+        let program = program("
+            $shadow = x ~> $go = n ~> n | 0 -> x | k -> go (#sub k 1); $x = 5; (m ~> (go m, x)) 3;
+            $main = shadow 1;
+        ");
+        let converted = convert_closures(&program);
+        let Value::Function(outer, _) = definition(&converted, "shadow") else { panic!("shadow is not a function") };
+        let Value::Function(parameters, _) = definition(&converted, "shadow$2") else { panic!("shadow$2 is not a function") };
+        let names: Vec<&str> = parameters.iter().map(|(x, _)| x.get_name()).collect();
+        assert_eq!(names[0], "x");
+        assert!(names[1].starts_with("x$local"));
+        assert_eq!(names[2], "m");
+        assert_eq!(parameters[0].0.get_id(), outer[0].0.get_id());
+        assert_ne!(parameters[1].0.get_id(), outer[0].0.get_id());
+        compare(&program, &["main"]);
+        assert_eq!(interpret(&converted, "main"), Ok("(1, 5)".to_string()));
+    }
+
+    #[test]
+    fn without_parameters() {
+        // the function is lifted as well, given x it is what it computes
+        let program = program("$f = x ~> (~> #add x 1); $main = f 2;");
+        let converted = convert_closures(&program);
+        assert!(is_converted(&converted));
+        let Value::Function(parameters, _) = definition(&converted, "f$1") else { panic!("f$1 is not a function") };
+        assert_eq!(parameters.iter().map(|(x, _)| x.get_name()).collect::<Vec<_>>(), ["x"]);
+        assert_eq!(interpret(&converted, "main"), Ok("3".to_string()));
+        assert!(lift(&program).is_ok());
+    }
+
+    #[test]
+    fn recursive_lets() {
+        // a local function sees itself, its uses are the definition it is lifted to given what it captures
+        let program = program("
+            $total: List Int -> Int = xs ~> $go = ys acc ~> ys | Nil -> acc | Cons (y, rest) -> go rest (#add acc y); go xs 0;
+            $scaled = k xs ~> $go: List Int -> List Int = ys ~> ys | Nil -> Nil | Cons (y, rest) -> Cons (#mul k y, go rest);
+                go xs;
+            $count = n ~> $down = m ~> m | 0 -> Nil | _ -> Cons (m, down (#sub m 1)); $down = map (x ~> #add x n) (down n); down;
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $main = (total numbers, scaled 10 numbers, count 3);
+        ");
+        let converted = convert_closures(&program);
+        let Value::Function(parameters, _) = definition(&converted, "scaled$1") else { panic!("scaled$1 is not a function") };
+        assert_eq!(parameters.iter().map(|(x, _)| x.get_name()).collect::<Vec<_>>(), ["k", "ys"]);
+        assert!(parameters[1].1.is_some());
+        compare(&program, &["main"]);
+        assert!(lift(&program).is_ok());
+    }
+
+    #[test]
+    fn free_variables_in_order() {
+        let program = program("$main = x ~> $y = #add x z; (p | (a, b) -> #mul a (w ~> #add y w) | c -> c);");
+        let Let(_, value, _) = program.last().unwrap();
+        assert_eq!(free_variables(value), ["#add", "z", "p", "#mul"]);
+    }
+
+    #[test]
+    fn failures_are_kept() {
+        let program = program("
+            $hole = x ~> (y ~> ?later) x;
+            $bad = n ~> (x ~> x | Just y -> #add y n) Nothing;
+            $main = bad 1;
+        ");
+        let converted = convert_closures(&program);
+        assert!(is_converted(&converted));
+        assert!(interpret(&converted, "main").is_err());
+        assert_eq!(interpret(&converted, "main"), interpret(&program, "main"));
+    }
+}
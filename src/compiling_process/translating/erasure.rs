@@ -0,0 +1,559 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::compiling_process::primitives::{primitive, Primitive};
+use crate::compiling_process::static_analysis::type_inference::{as_type_definition, Codata};
+use crate::inner_representation::abstract_syntax_tree::{AtomicValue, Expr, Let, Name, Pattern, Type, Value, Visibility};
+
+// untyped programs, what backends are given instead of the checked AST: names are resolved to locals, definitions and
+// primitives, implicit arguments and holes are gone, and once types are erased so are type definitions, type
+// parameters with their arguments and proofs given to functions
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    // bound by a function, a let or a pattern
+    Local(String),
+    // a definition of the program
+    Global(String),
+    // a primitive as a value
+    Primitive(Primitive),
+    // a primitive given all its arguments
+    Call(Primitive, Vec<Term>),
+    Int(i32),
+    Text(String),
+    // the empty tuple is also what proofs are
+    Tuple(Vec<Term>),
+    Record(Vec<(String, Term)>),
+    Either(String, Box<Term>),
+    // a field of codata, computed the first time it is looked at
+    Lazy(Box<Term>),
+    // the patterns of the checked program, they never look at types
+    Match(Box<Term>, Vec<(Pattern, Term)>),
+    // never without parameters
+    Function(Vec<String>, Box<Term>),
+    // never without arguments
+    Apply(Box<Term>, Vec<Term>),
+    // computed in order, each one sees the ones before it and a function sees itself
+    Let(Vec<(String, Term)>, Box<Term>),
+    // a type still passed around, nothing ever looks inside it
+    Erased,
+    // stops the program with the message
+    Fail(String),
+}
+
+// the definitions in the order of the program
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub definitions: Vec<(String, Term)>,
+}
+
+fn terms(terms: &[Term]) -> String {
+    terms.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+impl std::fmt::Display for Term {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Term::Local(name) => write!(fmt, "{}", name),
+            Term::Global(name) => write!(fmt, "${}", name),
+            Term::Primitive(primitive) => write!(fmt, "{}", primitive.name()),
+            Term::Call(primitive, arguments) => write!(fmt, "{} ({})", primitive.name(), terms(arguments)),
+            Term::Int(n) => write!(fmt, "{}", n),
+            Term::Text(text) => write!(fmt, "/{}/", text),
+            Term::Tuple(items) => write!(fmt, "({})", terms(items)),
+            Term::Record(fields) => {
+                let fields: Vec<String> = fields.iter().map(|(name, x)| format!("{} = {}", name, x)).collect();
+                write!(fmt, "{{{}}}", fields.join(", "))
+            },
+            Term::Either(name, payload) => write!(fmt, "{} {}", name, payload),
+            Term::Lazy(term) => write!(fmt, "lazy {}", term),
+            Term::Match(scrutinee, arms) => {
+                let arms: Vec<String> = arms.iter().map(|(pattern, x)| format!("{} -> {}", pattern, x)).collect();
+                write!(fmt, "match {} [{}]", scrutinee, arms.join(" | "))
+            },
+            Term::Function(parameters, body) => write!(fmt, "({} ~> {})", parameters.join(" "), body),
+            Term::Apply(function, arguments) => write!(fmt, "apply {} ({})", function, terms(arguments)),
+            Term::Let(lets, body) => {
+                let lets: Vec<String> = lets.iter().map(|(name, x)| format!("{} = {}; ", name, x)).collect();
+                write!(fmt, "let {}in {}", lets.concat(), body)
+            },
+            Term::Erased => write!(fmt, "erased"),
+            Term::Fail(message) => write!(fmt, "fail {:?}", message),
+        }
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (name, term) in &self.definitions {
+            writeln!(fmt, "${} = {}", name, term)?;
+        }
+        Ok(())
+    }
+}
+
+// parameters standing for types, @ or a function giving @
+pub fn is_kind(typ: &Type) -> bool {
+    match typ {
+        Type::Universe(_) => true,
+        Type::Function(_, result) | Type::Pi(_, _, _, result) => is_kind(result),
+        _ => false,
+    }
+}
+
+// a type given to a parameter, or a proof it is not used as a value
+fn erased_parameter(typ: Option<&Type>) -> Option<Term> {
+    match typ {
+        Some(typ) if is_kind(typ) => Some(Term::Erased),
+        Some(Type::Equal(_, _)) => Some(Term::Tuple(vec![])),
+        _ => None,
+    }
+}
+
+// what erasing types leaves out of the parameters of a function and gives for them, the parameters are annotated or
+// take their types from the annotation of the definition, up to the first one that is not explicit
+pub fn erased_parameters(parameters: &[(Name, Option<Type>)], annotation: Option<&Type>) -> Vec<Option<Term>> {
+    let mut annotation = annotation;
+    let mut result = vec![];
+    for (_, typ) in parameters {
+        let (from, to) = match annotation {
+            Some(Type::Function(from, to)) | Some(Type::Pi(Visibility::Explicit, _, from, to)) => {
+                (Some(&**from), Some(&**to))
+            },
+            _ => (None, None),
+        };
+        annotation = to;
+        result.push(erased_parameter(typ.as_ref().or(from)));
+    }
+    result
+}
+
+// what a name stands for while erasing
+#[derive(Clone)]
+enum Binding {
+    Value,
+    // a type or a proof, uses of it are the term
+    Erased(Term),
+    // a function some parameters of which are erased, they are left out of the calls to it
+    Function(Vec<Option<Term>>),
+}
+
+struct Erasure {
+    erase_types: bool,
+    codata: Codata,
+    globals: HashMap<String, Binding>,
+    variables: Vec<(String, Binding)>,
+    fresh: usize,
+    // the definition being erased, and the ones made for it that come before it
+    definition: String,
+    wrappers: Vec<(String, Term)>,
+}
+
+impl Erasure {
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        match self.variables.iter().rev().find(|(x, _)| x == name) {
+            Some((_, binding)) => Some(binding),
+            None => self.globals.get(name),
+        }
+    }
+
+    fn fresh(&mut self) -> String {
+        self.fresh += 1;
+        format!("${}", self.fresh)
+    }
+
+    fn binding(&self, value: &Value, annotation: Option<&Type>) -> Binding {
+        if !self.erase_types {
+            return Binding::Value;
+        }
+        if as_type_definition(value).is_some() {
+            return Binding::Erased(Term::Erased);
+        }
+        match value {
+            Value::Function(parameters, _) => {
+                let erased = erased_parameters(parameters, annotation);
+                if erased.iter().any(|x| x.is_some()) { Binding::Function(erased) } else { Binding::Value }
+            },
+            _ => Binding::Value,
+        }
+    }
+
+    fn variable(&self, name: &str) -> Term {
+        if self.variables.iter().any(|(x, _)| x == name) {
+            Term::Local(name.to_string())
+        } else if self.globals.contains_key(name) {
+            Term::Global(name.to_string())
+        } else {
+            Term::Fail(format!("unbound name {}", name))
+        }
+    }
+
+    // only definitions leave their erased parameters out, nothing is known of the calls to anonymous functions
+    fn function(&mut self, parameters: &[(Name, Option<Type>)], body: &Expr, erased: &[Option<Term>]) -> Term {
+        let depth = self.variables.len();
+        let mut kept = vec![];
+        for (i, (name, _)) in parameters.iter().enumerate() {
+            match erased.get(i).cloned().flatten() {
+                Some(term) => self.variables.push((name.get_name().to_string(), Binding::Erased(term))),
+                None => {
+                    kept.push(name.get_name().to_string());
+                    self.variables.push((name.get_name().to_string(), Binding::Value));
+                },
+            }
+        }
+        let body = self.body(body);
+        self.variables.truncate(depth);
+        if kept.is_empty() {
+            body
+        } else {
+            Term::Function(kept, Box::new(body))
+        }
+    }
+
+    fn definition(&mut self, binding: &Binding, value: &Value) -> Term {
+        match (binding, value) {
+            (Binding::Function(erased), Value::Function(parameters, body)) => self.function(parameters, body, erased),
+            _ => self.term(value),
+        }
+    }
+
+    fn body(&mut self, Expr(lets, value): &Expr) -> Term {
+        let depth = self.variables.len();
+        let mut terms = vec![];
+        for local in lets {
+            let Let(name, value, annotation) = local;
+            let binding = self.binding(value, annotation.as_ref());
+            if local.is_recursive() {
+                self.variables.push((name.get_name().to_string(), binding.clone()));
+            }
+            if !matches!(binding, Binding::Erased(_)) {
+                terms.push((name.get_name().to_string(), self.definition(&binding, value)));
+            }
+            if !local.is_recursive() {
+                self.variables.push((name.get_name().to_string(), binding));
+            }
+        }
+        let value = self.term(value);
+        self.variables.truncate(depth);
+        if terms.is_empty() {
+            value
+        } else {
+            Term::Let(terms, Box::new(value))
+        }
+    }
+
+    // a call to a function with erased parameters, the ones it is not given make it a function taking them as well
+    fn direct(&mut self, name: &str, erased: &[Option<Term>], arguments: &[&Value]) -> Term {
+        let function = self.variable(name);
+        let mut kept = vec![];
+        for (i, argument) in arguments.iter().enumerate() {
+            if !matches!(erased.get(i), Some(Some(_))) {
+                kept.push(self.term(argument));
+            }
+        }
+        let (given, last) = (arguments.len(), erased.iter().rposition(|x| x.is_some()).unwrap_or(0));
+        if given > last {
+            return if kept.is_empty() { function } else { Term::Apply(Box::new(function), kept) };
+        }
+        let lets: Vec<(String, Term)> = kept.into_iter().map(|x| (self.fresh(), x)).collect();
+        let mut arguments: Vec<Term> = lets.iter().map(|(name, _)| Term::Local(name.clone())).collect();
+        let mut parameters = vec![];
+        for erased in &erased[given..=last] {
+            parameters.push(self.fresh());
+            if erased.is_none() {
+                arguments.push(Term::Local(parameters.last().unwrap().clone()));
+            }
+        }
+        let body = if arguments.is_empty() { function.clone() } else { Term::Apply(Box::new(function.clone()), arguments) };
+        if let Term::Global(_) = function {
+            // the function is a definition of its own, given the arguments that are kept
+            let fresh = self.fresh();
+            let name = format!("{}$erased{}", self.definition, &fresh[1..]);
+            let mut names: Vec<String> = lets.iter().map(|(name, _)| name.clone()).collect();
+            names.extend(parameters);
+            self.wrappers.push((name.clone(), Term::Function(names, Box::new(body))));
+            let kept: Vec<Term> = lets.into_iter().map(|(_, x)| x).collect();
+            return if kept.is_empty() { Term::Global(name) } else { Term::Apply(Box::new(Term::Global(name)), kept) };
+        }
+        let result = Term::Function(parameters, Box::new(body));
+        if lets.is_empty() {
+            result
+        } else {
+            Term::Let(lets, Box::new(result))
+        }
+    }
+
+    fn application(&mut self, function: &Value, arguments: &[&Value]) -> Term {
+        if let Value::Var(name) = function {
+            match self.lookup(name.get_name()).cloned() {
+                // a type applied to anything is a type
+                Some(Binding::Erased(term)) => return term,
+                Some(Binding::Function(erased)) => return self.direct(name.get_name(), &erased, arguments),
+                Some(Binding::Value) => {},
+                None => match primitive(name.get_name()) {
+                    Some(primitive) if primitive.arity() == arguments.len() => {
+                        return Term::Call(primitive, arguments.iter().map(|x| self.term(x)).collect());
+                    },
+                    Some(primitive) if arguments.is_empty() => return Term::Primitive(primitive),
+                    Some(primitive) => {
+                        let arguments = arguments.iter().map(|x| self.term(x)).collect();
+                        return Term::Apply(Box::new(Term::Primitive(primitive)), arguments);
+                    },
+                    None => {},
+                },
+            }
+        }
+        let function = match function {
+            Value::Var(name) => self.variable(name.get_name()),
+            function => self.term(function),
+        };
+        if arguments.is_empty() {
+            return function;
+        }
+        Term::Apply(Box::new(function), arguments.iter().map(|x| self.term(x)).collect())
+    }
+
+    fn contents(&mut self, lazy: bool, value: &Value) -> Term {
+        let term = self.term(value);
+        if lazy { Term::Lazy(Box::new(term)) } else { term }
+    }
+
+    fn term(&mut self, value: &Value) -> Term {
+        match value {
+            Value::Var(_) => self.application(value, &[]),
+            Value::Tuple(items) => Term::Tuple(items.iter().map(|x| self.term(x)).collect()),
+            Value::Record(fields) => {
+                let lazy = self.codata.is_lazy_record(fields.iter().map(|(name, _)| name.get_name()));
+                Term::Record(fields.iter().map(|(name, x)| (name.get_name().to_string(), self.contents(lazy, x))).collect())
+            },
+            Value::Either(name, payload) => {
+                let payload = self.contents(self.codata.is_lazy_constructor(name.get_name()), payload);
+                Term::Either(name.get_name().to_string(), Box::new(payload))
+            },
+            Value::Match(scrutinee, arms) => {
+                let scrutinee = self.term(scrutinee);
+                let mut terms = vec![];
+                for (pattern, value) in arms {
+                    let depth = self.variables.len();
+                    let binders = pattern.binders().into_iter().map(|x| (x.get_name().to_string(), Binding::Value));
+                    self.variables.extend(binders);
+                    terms.push((pattern.clone(), self.term(value)));
+                    self.variables.truncate(depth);
+                }
+                Term::Match(Box::new(scrutinee), terms)
+            },
+            Value::Function(parameters, body) => self.function(parameters, body, &[]),
+            Value::Application(function, arguments) => {
+                // implicit arguments only matter to the type checker
+                let arguments: Vec<&Value> = arguments.iter().filter(|x| !matches!(x, Value::Implicit(_))).collect();
+                self.application(function, &arguments)
+            },
+            Value::Constant(AtomicValue::Int(n), _) => Term::Int(*n),
+            Value::Constant(AtomicValue::StringLiteral(text), _) => Term::Text(text.clone()),
+            Value::Type(_) => Term::Erased,
+            Value::Hole(name) => Term::Fail(format!("reached the unfinished hole ?{}", name)),
+            Value::Implicit(_) => Term::Fail("implicit arguments can only be passed to functions".to_string()),
+            // proofs carry no information
+            Value::Refl => Term::Tuple(vec![]),
+        }
+    }
+}
+
+// the untyped program, with `erase_types` types are only left where they are passed to functions not known here
+pub fn erase(program: &[Let], erase_types: bool) -> Result<Program, String> {
+    let mut erasure = Erasure {
+        erase_types,
+        codata: Codata::new(program),
+        globals: HashMap::new(),
+        variables: vec![],
+        fresh: 0,
+        definition: String::new(),
+        wrappers: vec![],
+    };
+    let mut bindings = vec![];
+    for Let(name, value, annotation) in program {
+        let binding = erasure.binding(value, annotation.as_ref());
+        erasure.globals.insert(name.get_name().to_string(), binding.clone());
+        bindings.push(binding);
+    }
+    let mut definitions = vec![];
+    for (i, (Let(name, value, _), binding)) in program.iter().zip(bindings).enumerate() {
+        // types may be defined again, every use is of the last definition
+        let hidden = program[i + 1..].iter().any(|Let(x, _, _)| x.get_name() == name.get_name());
+        if !hidden && !matches!(binding, Binding::Erased(_)) {
+            erasure.definition = name.get_name().to_string();
+            let term = erasure.definition(&binding, value);
+            definitions.append(&mut erasure.wrappers);
+            definitions.push((name.get_name().to_string(), term));
+        }
+    }
+    let program = Program { definitions };
+    verify(&program)?;
+    Ok(program)
+}
+
+fn verify_pattern(pattern: &Pattern) -> bool {
+    match pattern {
+        Pattern::Wildcard | Pattern::Binder(_) | Pattern::Refl => true,
+        Pattern::Or(alternatives) => alternatives.iter().all(verify_pattern),
+        _ => false,
+    }
+}
+
+fn verify_terms<'a>(
+    terms: impl IntoIterator<Item = &'a Term>,
+    globals: &HashSet<&str>,
+    locals: &mut Vec<String>,
+) -> Result<(), String> {
+    terms.into_iter().try_for_each(|x| verify_term(x, globals, locals))
+}
+
+fn verify_term(term: &Term, globals: &HashSet<&str>, locals: &mut Vec<String>) -> Result<(), String> {
+    match term {
+        Term::Local(name) if !locals.contains(name) => Err(format!("the local {} is not bound", name)),
+        Term::Global(name) if !globals.contains(name.as_str()) => Err(format!("there is no definition {}", name)),
+        Term::Local(_) | Term::Global(_) | Term::Primitive(_) | Term::Int(_) | Term::Text(_) => Ok(()),
+        Term::Erased | Term::Fail(_) => Ok(()),
+        Term::Call(primitive, arguments) if primitive.arity() != arguments.len() => {
+            Err(format!("{} takes {} arguments but is given {}", primitive.name(), primitive.arity(), arguments.len()))
+        },
+        Term::Call(primitive, arguments) if arguments.contains(&Term::Erased) => {
+            Err(format!("{} is given an erased type", primitive.name()))
+        },
+        Term::Call(_, arguments) | Term::Tuple(arguments) => verify_terms(arguments, globals, locals),
+        Term::Record(fields) => verify_terms(fields.iter().map(|(_, x)| x), globals, locals),
+        Term::Either(_, payload) | Term::Lazy(payload) => verify_term(payload, globals, locals),
+        Term::Match(scrutinee, arms) => {
+            if **scrutinee == Term::Erased && !arms.iter().all(|(pattern, _)| verify_pattern(pattern)) {
+                return Err("an erased type is matched on".to_string());
+            }
+            verify_term(scrutinee, globals, locals)?;
+            for (pattern, arm) in arms {
+                let depth = locals.len();
+                locals.extend(pattern.binders().into_iter().map(|x| x.get_name().to_string()));
+                verify_term(arm, globals, locals)?;
+                locals.truncate(depth);
+            }
+            Ok(())
+        },
+        Term::Function(parameters, _) if parameters.is_empty() => Err("a function has no parameters".to_string()),
+        Term::Function(parameters, body) => {
+            let depth = locals.len();
+            locals.extend(parameters.iter().cloned());
+            verify_term(body, globals, locals)?;
+            locals.truncate(depth);
+            Ok(())
+        },
+        Term::Apply(_, arguments) if arguments.is_empty() => Err("a function is applied to nothing".to_string()),
+        Term::Apply(function, _) if **function == Term::Erased => Err("an erased type is applied".to_string()),
+        Term::Apply(function, arguments) => verify_terms(std::iter::once(&**function).chain(arguments), globals, locals),
+        Term::Let(lets, body) => {
+            let depth = locals.len();
+            for (name, value) in lets {
+                let recursive = matches!(value, Term::Function(_, _));
+                if recursive {
+                    locals.push(name.clone());
+                }
+                verify_term(value, globals, locals)?;
+                if !recursive {
+                    locals.push(name.clone());
+                }
+            }
+            verify_term(body, globals, locals)?;
+            locals.truncate(depth);
+            Ok(())
+        },
+    }
+}
+
+// every name is bound, definitions are unique, functions and applications are not empty, primitives are given what
+// they take and erased types are never looked at
+pub fn verify(program: &Program) -> Result<(), String> {
+    let mut globals = HashSet::new();
+    for (name, _) in &program.definitions {
+        if !globals.insert(name.as_str()) {
+            return Err(format!("{} is defined twice", name));
+        }
+    }
+    for (name, term) in &program.definitions {
+        verify_term(term, &globals, &mut vec![]).map_err(|error| format!("in {}: {}", name, error))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod erasure_tests {
+    use crate::compiling_process::primitives::Primitive;
+    use crate::compiling_process::translating::testing::bare as program;
+    use super::{erase, verify, Program, Term};
+
+    #[test]
+    fn unit_tests() {
+        let program = program("
+            $Id = A ~> A;
+            $identity = (A : @) (x : A) ~> x;
+            $twice: (F : @ -> @) -> (A : @) -> (A -> A) -> A -> A = F A f x ~> f (f x);
+            $use = (n : Int) (p : n == n) ~> #add n 1;
+            $main = (identity Int 3, twice Id Int (x ~> #add x 1) 5, use 1 refl, identity, twice Id, (A : @) ~> A);
+        ");
+        // the erased parameters a function is not given make it a function taking them, anonymous functions keep theirs
+        let expected = "\
+$identity = (x ~> x)
+$twice = (f x ~> apply f (apply f (x)))
+$use = (n ~> #add (n, 1))
+$main$erased2 = ($1 ~> $identity)
+$main$erased4 = ($3 ~> $twice)
+$main = (apply $identity (3), apply $twice ((x ~> #add (x, 1)), 5), apply $use (1), $main$erased2, $main$erased4, (A ~> A))
+";
+        assert_eq!(erase(&program, true).unwrap().to_string(), expected);
+        // without erasure types are values like any other
+        let kept = erase(&program, false).unwrap().to_string();
+        assert!(kept.contains("$Id = (A ~> erased)\n"));
+        assert!(kept.contains("$use = (n p ~> #add (n, 1))\n"));
+        assert!(kept.contains("apply $identity (erased, 3)"));
+
+        let partial = erase(&self::program("$pair = (x : Int) (A : @) (y : A) ~> (x, y); $main = pair (#add 1 2);"), true);
+        // the function taking the rest is a definition, given what is kept
+        assert_eq!(
+            partial.unwrap().to_string(),
+            "$pair = (x y ~> (x, y))\n$main$erased3 = ($1 $2 ~> apply $pair ($1))\n$main = apply $main$erased3 (#add (1, 2))\n"
+        );
+    }
+
+    #[test]
+    fn names() {
+        let program = program("
+            $T = Int;
+            $f = (A : @) (x : A) ~> x;
+            $main = x ~> (f, (f ~> f Int x) (y ~> y), #add x, #sub 1 2, T, nothing, ?later);
+        ");
+        let expected = "\
+$f = (x ~> x)
+$main$erased2 = ($1 ~> $f)
+$main = (x ~> ($main$erased2, apply (f ~> apply f (erased, x)) ((y ~> y)), apply #add (x), #sub (1, 2), erased, \
+fail \"unbound name nothing\", fail \"reached the unfinished hole ?later\"))
+";
+        assert_eq!(erase(&program, true).unwrap().to_string(), expected);
+    }
+
+    #[test]
+    fn verification() {
+        let program = |term: Term| Program {
+            definitions: vec![("main".to_string(), term)],
+        };
+        let local = |name: &str| Term::Local(name.to_string());
+        let apply = |function: Term, arguments: Vec<Term>| Term::Apply(Box::new(function), arguments);
+        assert_eq!(verify(&program(Term::Function(vec!["x".to_string()], Box::new(local("x"))))), Ok(()));
+        assert_eq!(verify(&program(local("x"))), Err("in main: the local x is not bound".to_string()));
+        assert_eq!(verify(&program(Term::Global("f".to_string()))), Err("in main: there is no definition f".to_string()));
+        let applied = apply(Term::Erased, vec![Term::Int(1)]);
+        assert_eq!(verify(&program(applied)), Err("in main: an erased type is applied".to_string()));
+        assert_eq!(
+            verify(&program(Term::Call(Primitive::Add, vec![Term::Int(1)]))),
+            Err("in main: #add takes 2 arguments but is given 1".to_string())
+        );
+        let twice = Program {
+            definitions: vec![("x".to_string(), Term::Int(1)), ("x".to_string(), Term::Int(2))],
+        };
+        assert_eq!(verify(&twice), Err("x is defined twice".to_string()));
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::compiling_process::pattern_compiling::compile_match;
+use crate::compiling_process::primitives::Primitive;
+use crate::compiling_process::translating::erasure::{erase, Term};
+use crate::inner_representation::abstract_syntax_tree::{AtomicValue, Let, Pattern};
+use crate::inner_representation::decision_tree::{Case, DecisionTree, Step};
+
+// thunks, application, primitives and printing, the generated code only calls into it
+pub const RUNTIME: &str = include_str!("runtime.js");
+
+const RESERVED: &[&str] = &[
+    "arguments", "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default", "delete", "do",
+    "else", "enum", "eval", "export", "extends", "false", "finally", "for", "function", "if", "implements", "import",
+    "in", "instanceof", "interface", "let", "module", "new", "null", "package", "private", "protected", "public",
+    "require", "return", "static", "super", "switch", "this", "throw", "true", "try", "typeof", "undefined", "var",
+    "void", "while", "with", "yield",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Global {
+    // a function or a constant, defined once and used as it is
+    Plain,
+    // computed the first time it is used
+    Lazy,
+}
+
+struct Generator {
+    globals: HashMap<String, (String, Global)>,
+    // the JS names of the variables in scope
+    variables: Vec<(String, String)>,
+    used: HashSet<String>,
+}
+
+fn identifier(name: &str) -> String {
+    let name: String = name.chars().map(|x| if x.is_ascii_alphanumeric() || x == '_' { x } else { '_' }).collect();
+    if RESERVED.contains(&name.as_str()) || name.starts_with(|x: char| x.is_ascii_digit()) {
+        format!("{}_", name)
+    } else {
+        name
+    }
+}
+
+fn string_literal(text: &str) -> String {
+    let mut result = String::from("\"");
+    for x in text.chars() {
+        match x {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\u{20}'..='\u{7e}' => result.push(x),
+            x if (x as u32) < 0x20 || x == '\u{7f}' || x == '\u{2028}' || x == '\u{2029}' => {
+                result.push_str(&format!("\\u{:04x}", x as u32))
+            },
+            x => result.push(x),
+        }
+    }
+    result.push('"');
+    result
+}
+
+// record fields are written as properties, quoted when they are not identifiers
+fn key(name: &str) -> String {
+    if identifier(name) == name {
+        name.to_string()
+    } else {
+        string_literal(name)
+    }
+}
+
+fn property(name: &str) -> String {
+    if identifier(name) == name {
+        format!(".{}", name)
+    } else {
+        format!("[{}]", string_literal(name))
+    }
+}
+
+// `#add` is `$add` in the runtime, `#show` is `$showInt` not to be taken for printing values
+fn function_name(primitive: Primitive) -> String {
+    match primitive {
+        Primitive::Show => "$showInt".to_string(),
+        _ => primitive.name().replace('#', "$"),
+    }
+}
+
+fn indentation(indent: usize) -> String {
+    "    ".repeat(indent)
+}
+
+impl Generator {
+    // a JS name for a variable, not taken by anything else in the same definition
+    fn declare(&mut self, name: &str) -> String {
+        let base = identifier(name);
+        let mut result = base.clone();
+        let mut n = 0;
+        while self.used.contains(&result) {
+            n += 1;
+            result = format!("{}_{}", base, n);
+        }
+        self.used.insert(result.clone());
+        self.variables.push((name.to_string(), result.clone()));
+        result
+    }
+
+    fn variable(&self, name: &str) -> String {
+        let variable = self.variables.iter().rev().find(|(x, _)| x == name);
+        variable.map_or_else(|| identifier(name), |(_, variable)| variable.clone())
+    }
+
+    // values that can neither fail nor loop, computing them now is the same as delaying them
+    fn is_immediate(&self, term: &Term) -> bool {
+        match term {
+            Term::Local(_) | Term::Primitive(_) | Term::Int(_) | Term::Text(_) | Term::Function(_, _) | Term::Erased => true,
+            Term::Global(name) => matches!(self.globals.get(name), Some((_, Global::Plain))),
+            Term::Tuple(items) => items.iter().all(|x| self.is_immediate(x)),
+            Term::Record(fields) => fields.iter().all(|(_, x)| self.is_immediate(x)),
+            Term::Either(_, payload) => self.is_immediate(payload),
+            _ => false,
+        }
+    }
+
+    fn delayed(&mut self, term: &Term, indent: usize) -> Result<String, String> {
+        if self.is_immediate(term) {
+            return self.expression(term, indent);
+        }
+        Ok(format!("$lazy({})", self.arrow("", term, indent)?))
+    }
+
+    fn list(&mut self, terms: &[Term], indent: usize) -> Result<String, String> {
+        let terms = terms.iter().map(|x| self.expression(x, indent)).collect::<Result<Vec<_>, _>>()?;
+        Ok(terms.join(", "))
+    }
+
+    // `(x, y) => value`, or a block when the body needs statements
+    fn arrow(&mut self, parameters: &str, body: &Term, indent: usize) -> Result<String, String> {
+        if !matches!(body, Term::Let(_, _) | Term::Match(_, _)) {
+            let result = self.tail(body, indent)?;
+            return Ok(if result.starts_with('{') {
+                format!("({}) => ({})", parameters, result)
+            } else {
+                format!("({}) => {}", parameters, result)
+            });
+        }
+        let body = self.body(body, indent + 1, true)?;
+        Ok(format!("({}) => {{\n{}{}}}", parameters, body, indentation(indent)))
+    }
+
+    // the value of a term, the same as a function body but computed in place
+    fn immediately(&mut self, term: &Term, indent: usize) -> Result<String, String> {
+        let body = self.body(term, indent + 1, false)?;
+        Ok(format!("(() => {{\n{}{}}})()", body, indentation(indent)))
+    }
+
+    fn function(&mut self, parameters: &[String], body: &Term, indent: usize) -> Result<String, String> {
+        let depth = self.variables.len();
+        let names: Vec<String> = parameters.iter().map(|x| self.declare(x)).collect();
+        let result = self.arrow(&names.join(", "), body, indent);
+        self.variables.truncate(depth);
+        result
+    }
+
+    fn expression(&mut self, term: &Term, indent: usize) -> Result<String, String> {
+        Ok(match term {
+            Term::Local(name) => self.variable(name),
+            Term::Global(name) => match &self.globals[name] {
+                (global, Global::Plain) => global.clone(),
+                (global, Global::Lazy) => format!("$force({})", global),
+            },
+            Term::Primitive(primitive) => function_name(*primitive),
+            Term::Call(primitive, arguments) => format!("{}({})", function_name(*primitive), self.list(arguments, indent)?),
+            Term::Tuple(items) => {
+                let items = items.iter().map(|x| self.expression(x, indent)).collect::<Result<Vec<_>, _>>()?;
+                format!("[{}]", items.join(", "))
+            },
+            Term::Record(fields) if fields.is_empty() => "{}".to_string(),
+            Term::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(name, x)| Ok(format!("{}: {}", key(name), self.expression(x, indent)?)))
+                    .collect::<Result<Vec<_>, String>>()?;
+                format!("{{ {} }}", fields.join(", "))
+            },
+            Term::Either(name, payload) => {
+                let payload = self.expression(payload, indent)?;
+                format!("{{ $tag: {}, value: {} }}", string_literal(name), payload)
+            },
+            Term::Lazy(term) => self.delayed(term, indent)?,
+            Term::Match(_, _) | Term::Let(_, _) => self.immediately(term, indent)?,
+            Term::Function(parameters, body) => self.function(parameters, body, indent)?,
+            Term::Apply(function, arguments) => {
+                format!("$apply({}, [{}])", self.expression(function, indent)?, self.list(arguments, indent)?)
+            },
+            Term::Int(n) => n.to_string(),
+            Term::Text(text) => string_literal(text),
+            Term::Erased => "$type".to_string(),
+            Term::Fail(message) => format!("$fail({})", string_literal(message)),
+        })
+    }
+
+    // the value at the end of a function, a call there is left to the caller so loops take no stack
+    fn tail(&mut self, term: &Term, indent: usize) -> Result<String, String> {
+        if let Term::Apply(function, arguments) = term {
+            let function = self.expression(function, indent)?;
+            return Ok(format!("$tail({}, [{}])", function, self.list(arguments, indent)?));
+        }
+        self.expression(term, indent)
+    }
+
+    // statements ending with a return on every path
+    fn body(&mut self, term: &Term, indent: usize, tail: bool) -> Result<String, String> {
+        let Term::Let(lets, value) = term else {
+            return self.returning(term, indent, tail);
+        };
+        let depth = self.variables.len();
+        let mut result = String::new();
+        for (name, value) in lets {
+            // a function sees itself, it is only called once the constant is set
+            let (name, value) = match value {
+                Term::Function(_, _) => (self.declare(name), self.expression(value, indent)?),
+                value => {
+                    let value = self.expression(value, indent)?;
+                    (self.declare(name), value)
+                },
+            };
+            result.push_str(&format!("{}const {} = {};\n", indentation(indent), name, value));
+        }
+        result.push_str(&self.returning(value, indent, tail)?);
+        self.variables.truncate(depth);
+        Ok(result)
+    }
+
+    fn returning(&mut self, term: &Term, indent: usize, tail: bool) -> Result<String, String> {
+        match term {
+            Term::Match(scrutinee, arms) => {
+                let value = self.expression(scrutinee, indent)?;
+                let depth = self.variables.len();
+                let scrutinee = self.declare("scrutinee");
+                self.variables.truncate(depth);
+                let patterns: Vec<&Pattern> = arms.iter().map(|(pattern, _)| pattern).collect();
+                let tree = compile_match(&patterns)?;
+                let mut result = format!("{}const {} = {};\n", indentation(indent), scrutinee, value);
+                result.push_str(&self.tree(&tree, arms, &scrutinee, indent, tail)?);
+                Ok(result)
+            },
+            Term::Let(_, _) => self.body(term, indent, tail),
+            term => {
+                let value = if tail { self.tail(term, indent)? } else { self.expression(term, indent)? };
+                Ok(format!("{}return {};\n", indentation(indent), value))
+            },
+        }
+    }
+
+    fn select(scrutinee: &str, occurrence: &[Step]) -> String {
+        occurrence.iter().fold(scrutinee.to_string(), |value, step| match step {
+            Step::Payload => format!("$force({}).value", value),
+            Step::Index(i) => format!("$force({})[{}]", value, i),
+            Step::Field(name) => format!("$force({}){}", value, property(name)),
+        })
+    }
+
+    fn tree(
+        &mut self,
+        tree: &DecisionTree,
+        arms: &[(Pattern, Term)],
+        scrutinee: &str,
+        indent: usize,
+        tail: bool,
+    ) -> Result<String, String> {
+        let prefix = indentation(indent);
+        Ok(match tree {
+            DecisionTree::Fail => format!("{}return $noMatch({});\n", prefix, scrutinee),
+            DecisionTree::Leaf(arm, bindings) => {
+                let depth = self.variables.len();
+                let mut result = String::new();
+                for (name, occurrence) in bindings {
+                    let value = Self::select(scrutinee, occurrence);
+                    let name = self.declare(name.get_name());
+                    result.push_str(&format!("{}const {} = {};\n", prefix, name, value));
+                }
+                result.push_str(&self.returning(&arms[*arm].1, indent, tail)?);
+                self.variables.truncate(depth);
+                result
+            },
+            DecisionTree::Switch(occurrence, cases, default) => {
+                let value = format!("$force({})", Self::select(scrutinee, occurrence));
+                let constructors = matches!(cases.first(), Some((Case::Constructor(_), _)));
+                let mut result = if constructors {
+                    format!("{}switch ({}.$tag) {{\n", prefix, value)
+                } else {
+                    format!("{}switch ({}) {{\n", prefix, value)
+                };
+                for (case, tree) in cases {
+                    let label = match case {
+                        Case::Constructor(name) => string_literal(name),
+                        Case::Literal(AtomicValue::Int(n)) => n.to_string(),
+                        Case::Literal(AtomicValue::StringLiteral(text)) => string_literal(text),
+                    };
+                    result.push_str(&format!("{}    case {}: {{\n", prefix, label));
+                    result.push_str(&self.tree(tree, arms, scrutinee, indent + 2, tail)?);
+                    result.push_str(&format!("{}    }}\n", prefix));
+                }
+                result.push_str(&format!("{}    default: {{\n", prefix));
+                match default {
+                    Some(tree) => result.push_str(&self.tree(tree, arms, scrutinee, indent + 2, tail)?),
+                    None => result.push_str(&format!("{}        return $noMatch({});\n", prefix, scrutinee)),
+                }
+                result.push_str(&format!("{}    }}\n{}}}\n", prefix, prefix));
+                result
+            },
+        })
+    }
+}
+
+// the program as an ES2015 script with the runtime, it prints `main` when run and exports its definitions
+pub fn emit_js(program: &[Let], erase_types: bool) -> Result<String, String> {
+    let program = erase(program, erase_types)?;
+    let mut generator = Generator {
+        globals: HashMap::new(),
+        variables: vec![],
+        used: HashSet::new(),
+    };
+    for (name, term) in &program.definitions {
+        let kind = match term {
+            Term::Function(_, _) | Term::Int(_) | Term::Text(_) | Term::Erased => Global::Plain,
+            _ => Global::Lazy,
+        };
+        let global = generator.declare(name);
+        generator.globals.insert(name.clone(), (global, kind));
+    }
+    generator.variables.clear();
+    let names = generator.used.clone();
+    let mut result = RUNTIME.to_string();
+    let mut exports = vec![];
+    for (name, term) in &program.definitions {
+        let (global, kind) = generator.globals[name].clone();
+        generator.used = names.clone();
+        let definition = match kind {
+            Global::Plain => generator.expression(term, 0),
+            Global::Lazy => generator.arrow("", term, 0).map(|code| format!("$lazy({}, {})", code, string_literal(name))),
+        };
+        let definition = definition.map_err(|error| format!("in {}: {}", name, error))?;
+        result.push_str(&format!("\nconst {} = {};\n", global, definition));
+        exports.push(if &global == name { global } else { format!("{}: {}", key(name), global) });
+    }
+    exports.extend(["$force", "$apply", "$show"].iter().map(|x| x.to_string()));
+    result.push_str(&format!(
+        "\nif (typeof module !== \"undefined\") {{\n    module.exports = {{ {} }};\n}}\n",
+        exports.join(", ")
+    ));
+    if let Some((main, _)) = generator.globals.get("main") {
+        result.push_str(&format!(
+            "\nif (typeof module === \"undefined\" || require.main === module) {{\n    $run({});\n}}\n",
+            main
+        ));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod js_tests {
+    use std::fs;
+    use std::process::Command;
+
+    use crate::compiling_process::translating::testing::{self, directory, interpret, program};
+    use crate::inner_representation::abstract_syntax_tree::Let;
+    use super::{emit_js, identifier, string_literal};
+
+    // what node prints for the program, or its error
+    fn run(name: &str, program: &[Let], erase: bool) -> Result<String, String> {
+        let source = directory("js").join(format!("{}.js", name));
+        fs::write(&source, emit_js(program, erase).unwrap()).unwrap();
+        testing::run(Command::new("node").arg(&source))
+    }
+
+    fn values() -> Vec<Let> {
+        program("
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $sum: List Int -> Int = xs ~> foldr (x n ~> #add x n) 0 xs;
+            $point = {x = 1, y = /two/};
+            $swap = p ~> p | {x, y} -> {x = y, y = x};
+            $classify = n ~> n | (0 | 1) -> /small/ | 2 -> /two/ | _ -> /big/;
+            $words = s ~> s | /one/ -> 1 | /two/ -> 2 | _ -> 0;
+            $over = (x ~> y ~> #sub x y) 10 3;
+            $total: List Int -> Int = xs ~> $go = ys acc ~> ys | Nil -> acc | Cons (y, rest) -> go rest (#add acc y); go xs 0;
+            $new = x ~> (x | 0 -> 1 | n -> n, x | 1 -> 2 | _ -> 3);
+            $main = (
+                sum (map (x ~> #mul x 10) numbers),
+                #concat (#show (#div -7 2)) (#slice /héllo/ 1 4),
+                swap point,
+                map classify (Cons (0, Cons (2, Cons (7, Nil)))),
+                (words /two/, words /three/, new 1, total numbers),
+                (over, #add 2147483647 1, #mul 65536 65536, Just (Just refl), #add 1, #length /é/)
+            );
+        ")
+    }
+
+    fn codata() -> Vec<Let> {
+        program("
+            $Stream = A ~> codata head A * tail (Stream A);
+            $from: Int -> Stream Int = n ~> {head = n, tail = from (#add n 1)};
+            $take: {A : @} -> Int -> Stream A -> List A = n s ~> #le n 0
+                | 1 -> Nil
+                | _ -> (s | {head, tail} -> Cons (head, take (#sub n 1) tail));
+            $count = n acc ~> n | 0 -> acc | _ -> count (#sub n 1) (#add acc 2);
+            $main = (take 3 (from 10), count 1000000 0);
+        ")
+    }
+
+    fn erased() -> Vec<Let> {
+        program("
+            $identity = (A : @) (x : A) ~> x;
+            $twice = (F : @ -> @) (A : @) (f : A -> A) (x : A) ~> f (f x);
+            $Id = A ~> A;
+            $main = (identity Int 3, twice Id Int (x ~> #add x 1) 5, identity (List Int) Nil);
+        ")
+    }
+
+    #[test]
+    fn unit_tests() {
+        assert_eq!(identifier("new"), "new_");
+        assert_eq!(identifier("x'"), "x_");
+        assert_eq!(string_literal("a\"b\\c\n\u{1}é"), "\"a\\\"b\\\\c\\n\\u0001é\"");
+
+        let code = emit_js(&values(), false).unwrap();
+        assert!(code.contains("const swap = (p) => {"));
+        assert!(code.contains("const point = $lazy(() => ({ x: 1, y: \"two\" }), \"point\");"));
+        assert!(code.contains("const new_ = (x) => "));
+        let code = emit_js(&codata(), false).unwrap();
+        assert!(code.contains("tail: $lazy(() => $tail(from, [$add(n, 1)]))"));
+    }
+
+    #[test]
+    fn erasure() {
+        let program = erased();
+        let erased = emit_js(&program, true).unwrap();
+        assert!(erased.contains("const identity = (x) => x;"));
+        assert!(erased.contains("$apply(identity, [3])"));
+        assert!(erased.contains("const twice = (f, x) => $tail(f, [$apply(f, [x])]);"));
+        assert!(!erased.contains("const Id ="));
+        assert!(!emit_js(&program, false).unwrap().contains("const identity = (x) => x;"));
+    }
+
+    #[test]
+    #[ignore = "runs node"]
+    fn run_by_node() {
+        let program = values();
+        assert_eq!(run("unit_tests", &program, false), interpret(&program, "main"));
+        for erase in [false, true].iter() {
+            assert_eq!(run(&format!("erasure_{}", erase), &erased(), *erase), Ok("(3, 7, Nil ())".to_string()));
+        }
+    }
+
+    #[test]
+    #[ignore = "runs node"]
+    fn codata_and_loops() {
+        let expected = Ok("(Cons (10, Cons (11, Cons (12, Nil ()))), 2000000)".to_string());
+        assert_eq!(run("codata_and_loops", &codata(), false), expected);
+
+        let failing = |name: &str, text: &str| run(name, &program(text), false);
+        let expected = |text: &str| Err(text.to_string());
+        assert_eq!(failing("division", "$main = #mod 1 0;"), expected("division by zero"));
+        assert_eq!(failing("hole", "$main = #add 1 ?later;"), expected("reached the unfinished hole ?later"));
+        assert_eq!(failing("no_match", "$main = Nothing | Just x -> x;"), expected("no pattern matches Nothing ()"));
+        assert_eq!(failing("loop", "$main = main;"), expected("main is defined in terms of itself"));
+    }
+}
@@ -0,0 +1,518 @@
+use crate::compiling_process::primitives::Primitive;
+use crate::compiling_process::translating::anf::{
+    lower, Atom, Block, End, Function, Operation, Program, Selection, Statement, Test,
+};
+use crate::inner_representation::abstract_syntax_tree::Let;
+
+// the C runtime with what the generated code calls exported, compiled and linked next to the module
+pub const RUNTIME: &str = concat!("#define EXPORT\n", include_str!("runtime.c"));
+
+// every value is an `i8*` in the representation of the C runtime, sizes are `i64` so the module is for 64 bit targets
+const DECLARATIONS: &str = "\
+@type_object = external global i8
+@unit_object = external global i8
+
+declare i8* @fail(i8*, ...)
+declare i8* @make_tuple(i64, i8**)
+declare i8* @make_record(i64, i32*, i8**)
+declare i8* @make_either(i32, i8*)
+declare i8* @make_closure(i8* (i8**, i8**)*, i64, i64, i8**)
+declare i8* @make_thunk(i8* (i8**, i8**)*, i64, i8**)
+declare i8* @tail_call(i8*, i64, i8**)
+declare i8* @apply(i8*, i64, i8**)
+declare i8* @force(i8*)
+declare i8* @global(i64)
+declare i8* @select_payload(i8*)
+declare i8* @select_index(i8*, i64)
+declare i8* @select_field(i8*, i32)
+declare i32 @constructor_of(i8*)
+declare i32 @is_text(i8*, i8*, i64)
+declare i8* @no_match(i8*)
+declare i8* @show(i8*)
+declare i8* @force_all(i8*)
+declare i32 @puts(i8*)
+";
+
+const PRIMITIVES: [(Primitive, &str); 12] = [
+    (Primitive::Add, "add"),
+    (Primitive::Subtract, "sub"),
+    (Primitive::Multiply, "mul"),
+    (Primitive::Divide, "div"),
+    (Primitive::Remainder, "mod"),
+    (Primitive::Equal, "eq"),
+    (Primitive::Less, "lt"),
+    (Primitive::LessOrEqual, "le"),
+    (Primitive::Length, "length"),
+    (Primitive::Concatenate, "concat"),
+    (Primitive::Slice, "slice"),
+    (Primitive::Show, "show"),
+];
+
+fn primitive_name(primitive: Primitive) -> &'static str {
+    &primitive.name()[1..]
+}
+
+fn identifier(name: &str) -> String {
+    name.chars().map(|x| if x.is_ascii_alphanumeric() { x } else { '_' }).collect()
+}
+
+// bytes outside printable ascii are written in hex, as are quotes and backslashes
+fn bytes_literal(bytes: &[u8]) -> String {
+    let mut result = String::from("c\"");
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => result.push_str(&format!("\\{:02X}", byte)),
+            0x20..=0x7e => result.push(byte as char),
+            _ => result.push_str(&format!("\\{:02X}", byte)),
+        }
+    }
+    result.push('"');
+    result
+}
+
+// ints are kept in the pointer itself with the lowest bit set
+fn int_constant(n: i32) -> String {
+    format!("inttoptr (i64 {} to i8*)", ((n as u32 as u64) << 1) | 1)
+}
+
+fn function_name(index: usize, function: &Function) -> String {
+    format!("@function_{}_{}", index, identifier(&function.name))
+}
+
+fn array_pointer(name: &str, size: usize, element: &str) -> String {
+    format!("getelementptr inbounds ([{0} x {1}], [{0} x {1}]* {2}, i64 0, i64 0)", size, element, name)
+}
+
+// the constants of the module, shared by its functions
+struct Constants {
+    definitions: Vec<String>,
+    strings: Vec<String>,
+}
+
+impl Constants {
+    // a zero terminated string for the runtime
+    fn string(&mut self, text: &str) -> String {
+        let index = match self.strings.iter().position(|x| x == text) {
+            Some(index) => index,
+            None => {
+                let mut bytes = text.as_bytes().to_vec();
+                bytes.push(0);
+                let constant = format!("@string_{}", self.strings.len());
+                self.definitions.push(format!(
+                    "{} = private unnamed_addr constant [{} x i8] {}",
+                    constant,
+                    bytes.len(),
+                    bytes_literal(&bytes)
+                ));
+                self.strings.push(text.to_string());
+                self.strings.len() - 1
+            },
+        };
+        array_pointer(&format!("@string_{}", index), text.len() + 1, "i8")
+    }
+
+    fn shape(&mut self, names: &[usize]) -> String {
+        if names.is_empty() {
+            return "null".to_string();
+        }
+        let constant = format!("@shape_{}", self.definitions.len());
+        let names: Vec<String> = names.iter().map(|x| format!("i32 {}", x)).collect();
+        let definition = format!("{} = private unnamed_addr constant [{} x i32] [{}]", constant, names.len(), names.join(", "));
+        self.definitions.push(definition);
+        array_pointer(&constant, names.len(), "i32")
+    }
+}
+
+// a function being written, locals are `%l`, captured values `%c`
+struct Emitter<'a> {
+    program: &'a Program,
+    constants: &'a mut Constants,
+    allocas: Vec<String>,
+    lines: Vec<String>,
+    fresh: usize,
+    // the basic block being written
+    label: String,
+    // for each join around, its label and the values jumping to it
+    joins: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl Emitter<'_> {
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.fresh += 1;
+        format!("{}_{}", prefix, self.fresh)
+    }
+
+    fn line(&mut self, text: String) {
+        self.lines.push(format!("  {}", text));
+    }
+
+    fn start(&mut self, label: String) {
+        self.lines.push(format!("{}:", label));
+        self.label = label;
+    }
+
+    fn atom(&self, atom: &Atom) -> String {
+        match atom {
+            Atom::Local(i) => format!("%l{}", i),
+            Atom::Captured(i) => format!("%c{}", i),
+            Atom::Int(n) => int_constant(*n),
+            Atom::Text(i) => format!("bitcast ({{ i32, {{ i64, i8* }} }}* @text_{} to i8*)", i),
+            Atom::Unit => "@unit_object".to_string(),
+            Atom::Type => "@type_object".to_string(),
+        }
+    }
+
+    // the values in an array on the stack, given as an `i8**`
+    fn array(&mut self, atoms: &[Atom]) -> String {
+        if atoms.is_empty() {
+            return "null".to_string();
+        }
+        let array = format!("%{}", self.fresh("array"));
+        self.allocas.push(format!("  {} = alloca [{} x i8*]", array, atoms.len()));
+        for (i, atom) in atoms.iter().enumerate() {
+            let pointer = format!("%{}", self.fresh("item"));
+            let element = format!("getelementptr inbounds [{1} x i8*], [{1} x i8*]* {0}, i64 0, i64 {2}", array, atoms.len(), i);
+            self.line(format!("{} = {}", pointer, element));
+            let atom = self.atom(atom);
+            self.line(format!("store i8* {}, i8** {}", atom, pointer));
+        }
+        let first = format!("%{}", self.fresh("items"));
+        self.line(format!("{} = getelementptr inbounds [{2} x i8*], [{2} x i8*]* {1}, i64 0, i64 0", first, array, atoms.len()));
+        first
+    }
+
+    fn code(&self, function: usize) -> String {
+        function_name(function, &self.program.functions[function])
+    }
+
+    fn operation(&mut self, operation: &Operation) -> String {
+        match operation {
+            Operation::Global(index) => format!("call i8* @global(i64 {})", index),
+            Operation::Tuple(items) => {
+                let array = self.array(items);
+                format!("call i8* @make_tuple(i64 {}, i8** {})", items.len(), array)
+            },
+            Operation::Record(fields) => {
+                let names: Vec<usize> = fields.iter().map(|(name, _)| *name).collect();
+                let values: Vec<Atom> = fields.iter().map(|(_, value)| value.clone()).collect();
+                let shape = self.constants.shape(&names);
+                let values = self.array(&values);
+                format!("call i8* @make_record(i64 {}, i32* {}, i8** {})", fields.len(), shape, values)
+            },
+            Operation::Either(constructor, payload) => {
+                format!("call i8* @make_either(i32 {}, i8* {})", constructor, self.atom(payload))
+            },
+            Operation::Closure(function, captured) => {
+                let parameters = self.program.functions[*function].parameters;
+                let environment = self.array(captured);
+                let code = self.code(*function);
+                let arguments = format!("i64 {}, i64 {}, i8** {}", parameters, captured.len(), environment);
+                format!("call i8* @make_closure(i8* (i8**, i8**)* {}, {})", code, arguments)
+            },
+            Operation::Thunk(function, captured) => {
+                let environment = self.array(captured);
+                let code = self.code(*function);
+                format!("call i8* @make_thunk(i8* (i8**, i8**)* {}, i64 {}, i8** {})", code, captured.len(), environment)
+            },
+            Operation::Primitive(primitive) => {
+                let code = format!("@code_{}", primitive_name(*primitive));
+                format!("call i8* @make_closure(i8* (i8**, i8**)* {}, i64 {}, i64 0, i8** null)", code, primitive.arity())
+            },
+            Operation::Call(primitive, arguments) => {
+                let arguments: Vec<String> = arguments.iter().map(|x| format!("i8* {}", self.atom(x))).collect();
+                format!("call i8* @primitive_{}({})", primitive_name(*primitive), arguments.join(", "))
+            },
+            Operation::Apply(function, arguments) => {
+                let array = self.array(arguments);
+                format!("call i8* @apply(i8* {}, i64 {}, i8** {})", self.atom(function), arguments.len(), array)
+            },
+            Operation::Force(atom) => format!("call i8* @force(i8* {})", self.atom(atom)),
+            Operation::Select(atom, Selection::Payload) => format!("call i8* @select_payload(i8* {})", self.atom(atom)),
+            Operation::Select(atom, Selection::Index(i)) => format!("call i8* @select_index(i8* {}, i64 {})", self.atom(atom), i),
+            Operation::Select(atom, Selection::Field(name)) => {
+                format!("call i8* @select_field(i8* {}, i32 {})", self.atom(atom), name)
+            },
+            Operation::Fail(message) => format!("call i8* (i8*, ...) @fail(i8* {})", self.constants.string(message)),
+        }
+    }
+
+    fn block(&mut self, Block(statements, end): &Block) {
+        for statement in statements {
+            match statement {
+                Statement::Let(local, operation) => {
+                    let operation = self.operation(operation);
+                    self.line(format!("%l{} = {}", local, operation));
+                },
+                Statement::Join(local, block) => {
+                    let label = self.fresh("join");
+                    self.joins.push((label.clone(), vec![]));
+                    self.block(block);
+                    let (label, incoming) = self.joins.pop().unwrap();
+                    self.start(label);
+                    if incoming.is_empty() {
+                        // every arm fails, nothing gets here
+                        self.line(format!("%l{} = bitcast i8* null to i8*", local));
+                    } else {
+                        let incoming: Vec<String> =
+                            incoming.iter().map(|(value, label)| format!("[ {}, %{} ]", value, label)).collect();
+                        self.line(format!("%l{} = phi i8* {}", local, incoming.join(", ")));
+                    }
+                },
+            }
+        }
+        self.end(end);
+    }
+
+    fn end(&mut self, end: &End) {
+        match end {
+            End::Return(atom) => {
+                let atom = self.atom(atom);
+                self.line(format!("ret i8* {}", atom));
+            },
+            End::TailCall(function, arguments) => {
+                let array = self.array(arguments);
+                let result = self.fresh("%tail");
+                let function = self.atom(function);
+                self.line(format!("{} = call i8* @tail_call(i8* {}, i64 {}, i8** {})", result, function, arguments.len(), array));
+                self.line(format!("ret i8* {}", result));
+            },
+            End::Jump(atom) => {
+                let atom = self.atom(atom);
+                let label = self.label.clone();
+                let join = self.joins.last_mut().unwrap();
+                join.1.push((atom, label));
+                let target = join.0.clone();
+                self.line(format!("br label %{}", target));
+            },
+            End::NoMatch(atom) => {
+                let atom = self.atom(atom);
+                self.line(format!("call i8* @no_match(i8* {})", atom));
+                self.line("unreachable".to_string());
+            },
+            End::Switch(atom, cases, default) => {
+                let value = self.atom(atom);
+                let labels: Vec<String> = cases.iter().map(|_| self.fresh("case")).collect();
+                let otherwise = self.fresh("default");
+                let constructors = matches!(cases.first(), Some((Test::Constructor(_), _)));
+                if constructors {
+                    let constructor = self.fresh("%constructor");
+                    self.line(format!("{} = call i32 @constructor_of(i8* {})", constructor, value));
+                    let targets: Vec<String> = cases
+                        .iter()
+                        .zip(&labels)
+                        .map(|((test, _), label)| match test {
+                            Test::Constructor(constructor) => format!("i32 {}, label %{}", constructor, label),
+                            _ => unreachable!(),
+                        })
+                        .collect();
+                    self.line(format!("switch i32 {}, label %{} [ {} ]", constructor, otherwise, targets.join(" ")));
+                } else {
+                    // literals are tested one after the other
+                    for (i, ((test, _), label)) in cases.iter().zip(&labels).enumerate() {
+                        let found = self.fresh("%found");
+                        match test {
+                            Test::Int(n) => self.line(format!("{} = icmp eq i8* {}, {}", found, value, int_constant(*n))),
+                            Test::Text(text) => {
+                                let length = self.program.texts[*text].len();
+                                let bytes = array_pointer(&format!("@text_{}.bytes", text), length, "i8");
+                                let equal = self.fresh("%equal");
+                                self.line(format!("{} = call i32 @is_text(i8* {}, i8* {}, i64 {})", equal, value, bytes, length));
+                                self.line(format!("{} = icmp ne i32 {}, 0", found, equal));
+                            },
+                            Test::Constructor(_) => unreachable!(),
+                        }
+                        let next = if i + 1 == cases.len() { otherwise.clone() } else { self.fresh("next") };
+                        self.line(format!("br i1 {}, label %{}, label %{}", found, label, next));
+                        if i + 1 < cases.len() {
+                            self.start(next);
+                        }
+                    }
+                    if cases.is_empty() {
+                        self.line(format!("br label %{}", otherwise));
+                    }
+                }
+                for ((_, block), label) in cases.iter().zip(labels) {
+                    self.start(label);
+                    self.block(block);
+                }
+                self.start(otherwise);
+                self.block(default);
+            },
+        }
+    }
+}
+
+fn emit_function(program: &Program, constants: &mut Constants, index: usize) -> String {
+    let function = &program.functions[index];
+    let mut emitter = Emitter {
+        program,
+        constants,
+        allocas: vec![],
+        lines: vec![],
+        fresh: 0,
+        label: "entry".to_string(),
+        joins: vec![],
+    };
+    let mut entry = vec![];
+    for (name, source, count) in [("l", "arguments", function.parameters), ("c", "environment", function.captured)] {
+        for i in 0..count {
+            entry.push(format!("  %{}{}.pointer = getelementptr inbounds i8*, i8** %{}, i64 {}", name, i, source, i));
+            entry.push(format!("  %{0}{1} = load i8*, i8** %{0}{1}.pointer", name, i));
+        }
+    }
+    emitter.block(&function.body);
+    format!(
+        "define internal i8* {}(i8** %environment, i8** %arguments) {{\nentry:\n{}{}{}\n}}\n",
+        function_name(index, function),
+        emitter.allocas.iter().map(|x| format!("{}\n", x)).collect::<String>(),
+        entry.iter().map(|x| format!("{}\n", x)).collect::<String>(),
+        emitter.lines.join("\n"),
+    )
+}
+
+fn table(name: &str, element: &str, items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|x| format!("{} {}", element, x)).collect();
+    format!("@{} = global [{} x {}] [{}]", name, items.len(), element, items.join(", "))
+}
+
+// the program as an LLVM module printing `main` when linked with the runtime and run
+pub fn emit_llvm(program: &[Let]) -> Result<String, String> {
+    let program = lower(program)?;
+    let mut constants = Constants { definitions: vec![], strings: vec![] };
+    let mut texts = vec![];
+    for (i, text) in program.texts.iter().enumerate() {
+        let length = text.len();
+        let bytes = bytes_literal(text.as_bytes());
+        texts.push(format!("@text_{}.bytes = private unnamed_addr constant [{} x i8] {}", i, length, bytes));
+        texts.push(format!(
+            "@text_{} = private unnamed_addr constant {{ i32, {{ i64, i8* }} }} {{ i32 0, {{ i64, i8* }} {{ i64 {}, i8* {} }} }}",
+            i,
+            length,
+            array_pointer(&format!("@text_{}.bytes", i), length, "i8")
+        ));
+    }
+    let functions: Vec<String> = (0..program.functions.len()).map(|i| emit_function(&program, &mut constants, i)).collect();
+    let symbols: Vec<String> = program.symbols.iter().map(|x| constants.string(x)).collect();
+    let names: Vec<String> = program.globals.iter().map(|(name, _)| constants.string(name)).collect();
+    let code: Vec<String> =
+        program.globals.iter().map(|(_, function)| function_name(*function, &program.functions[*function])).collect();
+    let primitives: Vec<String> = PRIMITIVES
+        .iter()
+        .map(|(primitive, name)| {
+            let parameters = vec!["i8*"; primitive.arity()].join(", ");
+            format!("declare i8* @primitive_{}({})\ndeclare i8* @code_{}(i8**, i8**)\n", name, parameters, name)
+        })
+        .collect();
+    let mut result = String::from("; generated from a checked program, link it with the runtime written next to it\n\n");
+    result.push_str(DECLARATIONS);
+    result.push_str(&primitives.concat());
+    result.push('\n');
+    result.push_str(&texts.iter().map(|x| format!("{}\n", x)).collect::<String>());
+    result.push_str(&constants.definitions.iter().map(|x| format!("{}\n", x)).collect::<String>());
+    result.push('\n');
+    result.push_str(&functions.join("\n"));
+    result.push('\n');
+    result.push_str(&format!("{}\n", table("symbols", "i8*", &symbols)));
+    result.push_str(&format!("{}\n", table("global_names", "i8*", &names)));
+    result.push_str(&format!("{}\n", table("global_code", "i8* (i8**, i8**)*", &code)));
+    result.push_str(&format!("@global_values = global [{} x i8*] zeroinitializer\n", program.globals.len()));
+    result.push_str(&format!("@global_states = global [{} x i8] zeroinitializer\n\n", program.globals.len()));
+    result.push_str(&format!(
+        "define i32 @main() {{\nentry:\n  %main = call i8* @global(i64 {})\n  %value = call i8* @force_all(i8* %main)\n  \
+         %text = call i8* @show(i8* %value)\n  call i32 @puts(i8* %text)\n  ret i32 0\n}}\n",
+        program.main
+    ));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod llvm_tests {
+    use std::fs;
+    use std::process::Command;
+
+    use crate::compiling_process::translating::testing::{directory, interpret, program, run, succeeds};
+    use crate::inner_representation::abstract_syntax_tree::Let;
+    use super::{bytes_literal, emit_llvm, int_constant, RUNTIME};
+
+    // what the compiled program prints, or its error, once llc and a C compiler have built it
+    fn compile(name: &str, program: &[Let]) -> Result<String, String> {
+        let directory = directory("llvm");
+        let module = directory.join(format!("{}.ll", name));
+        let object = directory.join(format!("{}.o", name));
+        let runtime = directory.join("runtime.c");
+        let executable = directory.join(name);
+        fs::write(&module, emit_llvm(program).unwrap()).unwrap();
+        fs::write(&runtime, RUNTIME).unwrap();
+        succeeds(Command::new("llc").args(["-O2", "-filetype=obj", "-relocation-model=pic", "-o"]).arg(&object).arg(&module));
+        succeeds(Command::new("cc").arg("-O2").arg("-o").arg(&executable).arg(&object).arg(&runtime));
+        run(&mut Command::new(&executable))
+    }
+
+    fn values() -> Vec<Let> {
+        program("
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $sum: List Int -> Int = xs ~> foldr (x n ~> #add x n) 0 xs;
+            $point = {x = 1, y = /two/};
+            $swap = p ~> p | {x, y} -> {x = y, y = x};
+            $classify = n ~> n | (0 | 1) -> /small/ | 2 -> /two/ | _ -> /big/;
+            $words = s ~> s | /one/ -> 1 | /two/ -> 2 | _ -> 0;
+            $over = (x ~> y ~> #sub x y) 10 3;
+            $main = (
+                sum (map (x ~> #mul x 10) numbers),
+                #concat (#show (#div -7 2)) (#slice /\"hello\"/ 1 6),
+                swap point,
+                map classify (Cons (0, Cons (2, Cons (7, Nil)))),
+                (words /two/, words /three/, #add (numbers | Cons (x, _) -> x | Nil -> 0) 1),
+                (over, #add 2147483647 1, Just (Just refl), #add 1)
+            );
+        ")
+    }
+
+    fn codata() -> Vec<Let> {
+        program("
+            $Stream = A ~> codata head A * tail (Stream A);
+            $from: Int -> Stream Int = n ~> {head = n, tail = from (#add n 1)};
+            $take: {A : @} -> Int -> Stream A -> List A = n s ~> #le n 0
+                | 1 -> Nil
+                | _ -> (s | {head, tail} -> Cons (head, take (#sub n 1) tail));
+            $count = n acc ~> n | 0 -> acc | _ -> count (#sub n 1) (#add acc 2);
+            $main = (take 3 (from 10), count 10000000 0);
+        ")
+    }
+
+    #[test]
+    fn unit_tests() {
+        assert_eq!(bytes_literal("a\"b\\c\n".as_bytes()), "c\"a\\22b\\5Cc\\0A\"");
+        assert_eq!(int_constant(-1), "inttoptr (i64 8589934591 to i8*)");
+        assert_eq!(emit_llvm(&program("$x = 1;")).unwrap_err(), "unbound name main");
+
+        let module = emit_llvm(&values()).unwrap();
+        assert!(module.contains("define internal i8* @function_"));
+        assert!(module.contains("  switch i32 %constructor_"));
+        assert!(module.contains("define i32 @main() {"));
+        let module = emit_llvm(&codata()).unwrap();
+        assert!(module.contains("call i8* @make_thunk("));
+        assert!(module.contains("call i8* @tail_call("));
+    }
+
+    #[test]
+    #[ignore = "runs llc and cc"]
+    fn compiled() {
+        let program = values();
+        assert_eq!(compile("unit_tests", &program), interpret(&program, "main"));
+    }
+
+    #[test]
+    #[ignore = "runs llc and cc"]
+    fn codata_and_loops() {
+        assert_eq!(compile("codata_and_loops", &codata()), Ok("(Cons (10, Cons (11, Cons (12, Nil ()))), 20000000)".to_string()));
+
+        let failing = |name: &str, text: &str| compile(name, &program(text));
+        let expected = |text: &str| Err(text.to_string());
+        assert_eq!(failing("division", "$main = #mod 1 0;"), expected("division by zero"));
+        assert_eq!(failing("hole", "$main = #add 1 ?later;"), expected("reached the unfinished hole ?later"));
+        assert_eq!(failing("no_match", "$main = Nothing | Just x -> x;"), expected("no pattern matches Nothing ()"));
+        assert_eq!(failing("inner_no_match", "$main = #add (1 | 2 -> 3) 1;"), expected("no pattern matches 1"));
+        assert_eq!(failing("loop", "$main = main;"), expected("main is defined in terms of itself"));
+    }
+}
@@ -0,0 +1,12 @@
+pub mod anf;
+pub mod bytecode;
+pub mod c;
+pub mod closures;
+pub mod erasure;
+pub mod js;
+pub mod llvm;
+pub mod rust;
+pub mod scheme;
+pub mod wat;
+#[cfg(test)]
+pub mod testing;
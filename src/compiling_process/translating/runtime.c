@@ -0,0 +1,534 @@
+/* the runtime every generated program starts with */
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* what the generated code calls is static when the program is in the same file, and exported when it is linked in */
+#ifndef EXPORT
+#define EXPORT static
+#endif
+
+typedef struct Object *Value;
+typedef Value (*Code)(Value *environment, Value *arguments);
+
+/* ints are kept in the value itself with the lowest bit set, everything else points to an object */
+enum Tag { TEXT, TUPLE, RECORD, EITHER, CLOSURE, TYPE, THUNK };
+
+struct Object {
+    enum Tag tag;
+    union {
+        struct { size_t length; const char *bytes; } text;
+        struct { size_t size; Value *items; } tuple;
+        struct { size_t size; const int *names; Value *values; } record;
+        struct { int constructor; Value payload; } either;
+        /* a function with the values it captured and the arguments it was given so far */
+        struct { Code code; size_t arity; size_t collected; Value *environment; Value *arguments; } closure;
+        /* 0 while delayed, 1 while being forced, 2 once the value is known */
+        struct { int state; Code code; Value *environment; Value value; } thunk;
+    } as;
+};
+
+/* the tables of the program, given after the runtime */
+extern const char *symbols[];
+extern const char *global_names[];
+extern Code global_code[];
+extern Value global_values[];
+extern char global_states[];
+
+/* memory is taken from large blocks and never given back */
+static char *block;
+static size_t block_left;
+
+static void *allocate(size_t size) {
+    void *result;
+    size = (size + 15) & ~(size_t)15;
+    if (size > block_left) {
+        size_t length = size > (1 << 20) ? size : (1 << 20);
+        block = malloc(length);
+        if (block == NULL) {
+            fputs("out of memory\n", stderr);
+            exit(1);
+        }
+        block_left = length;
+    }
+    result = block;
+    block += size;
+    block_left -= size;
+    return result;
+}
+
+static Value *copy_values(size_t size, const Value *values) {
+    Value *result = size == 0 ? NULL : allocate(size * sizeof(Value));
+    if (size != 0) {
+        memcpy(result, values, size * sizeof(Value));
+    }
+    return result;
+}
+
+/* the text of values, grown as needed */
+typedef struct { char *bytes; size_t length; size_t capacity; } Buffer;
+
+static void write_bytes(Buffer *buffer, const char *bytes, size_t length) {
+    if (buffer->length + length + 1 > buffer->capacity) {
+        buffer->capacity = (buffer->length + length + 1) * 2;
+        buffer->bytes = realloc(buffer->bytes, buffer->capacity);
+    }
+    memcpy(buffer->bytes + buffer->length, bytes, length);
+    buffer->length += length;
+    buffer->bytes[buffer->length] = 0;
+}
+
+static void write_text(Buffer *buffer, const char *text) {
+    write_bytes(buffer, text, strlen(text));
+}
+
+static void write_value(Buffer *buffer, Value value);
+
+EXPORT const char *show(Value value) {
+    Buffer buffer = { NULL, 0, 0 };
+    write_value(&buffer, value);
+    return buffer.bytes;
+}
+
+EXPORT Value fail(const char *format, ...) {
+    va_list arguments;
+    va_start(arguments, format);
+    vfprintf(stderr, format, arguments);
+    va_end(arguments);
+    fputc('\n', stderr);
+    exit(1);
+    return NULL;
+}
+
+static int is_int(Value value) {
+    return ((uintptr_t)value & 1) != 0;
+}
+
+static Value make_int(int32_t n) {
+    return (Value)(((uintptr_t)(uint32_t)n << 1) | 1);
+}
+
+static int32_t int_of(Value value) {
+    return (int32_t)(uint32_t)((uintptr_t)value >> 1);
+}
+
+EXPORT struct Object type_object = { TYPE, { { 0, NULL } } };
+EXPORT struct Object unit_object = { TUPLE, { { 0, NULL } } };
+/* returned instead of a value when a function ends with a call, the call is made by whoever called it */
+static struct Object tail_object = { TYPE, { { 0, NULL } } };
+#define TYPE_VALUE (&type_object)
+#define UNIT (&unit_object)
+#define TAIL (&tail_object)
+
+static Value pending_function;
+static size_t pending_size;
+static Value *pending_arguments;
+static size_t pending_capacity;
+
+EXPORT Value tail_call(Value function, size_t size, Value *arguments) {
+    if (size > pending_capacity) {
+        pending_capacity = size * 2;
+        pending_arguments = realloc(pending_arguments, pending_capacity * sizeof(Value));
+    }
+    memcpy(pending_arguments, arguments, size * sizeof(Value));
+    pending_function = function;
+    pending_size = size;
+    return TAIL;
+}
+
+static Value make_text(const char *bytes, size_t length) {
+    Value result = allocate(sizeof(struct Object));
+    result->tag = TEXT;
+    result->as.text.bytes = bytes;
+    result->as.text.length = length;
+    return result;
+}
+
+EXPORT Value make_tuple(size_t size, Value *items) {
+    Value result;
+    if (size == 0) {
+        return UNIT;
+    }
+    result = allocate(sizeof(struct Object));
+    result->tag = TUPLE;
+    result->as.tuple.size = size;
+    result->as.tuple.items = copy_values(size, items);
+    return result;
+}
+
+EXPORT Value make_record(size_t size, const int *names, Value *values) {
+    Value result = allocate(sizeof(struct Object));
+    result->tag = RECORD;
+    result->as.record.size = size;
+    result->as.record.names = names;
+    result->as.record.values = copy_values(size, values);
+    return result;
+}
+
+EXPORT Value make_either(int constructor, Value payload) {
+    Value result = allocate(sizeof(struct Object));
+    result->tag = EITHER;
+    result->as.either.constructor = constructor;
+    result->as.either.payload = payload;
+    return result;
+}
+
+EXPORT Value make_closure(Code code, size_t arity, size_t captured, Value *environment) {
+    Value result = allocate(sizeof(struct Object));
+    result->tag = CLOSURE;
+    result->as.closure.code = code;
+    result->as.closure.arity = arity;
+    result->as.closure.collected = 0;
+    result->as.closure.environment = copy_values(captured, environment);
+    result->as.closure.arguments = NULL;
+    return result;
+}
+
+EXPORT Value make_thunk(Code code, size_t captured, Value *environment) {
+    Value result = allocate(sizeof(struct Object));
+    result->tag = THUNK;
+    result->as.thunk.state = 0;
+    result->as.thunk.code = code;
+    result->as.thunk.environment = copy_values(captured, environment);
+    result->as.thunk.value = NULL;
+    return result;
+}
+
+EXPORT Value apply(Value function, size_t size, Value *arguments);
+
+/* the value a function returned, making the call it ended with if there is one */
+static Value finish(Value result) {
+    if (result == TAIL) {
+        Value *arguments = copy_values(pending_size, pending_arguments);
+        return apply(pending_function, pending_size, arguments);
+    }
+    return result;
+}
+
+EXPORT Value force(Value value) {
+    Value result;
+    if (is_int(value) || value->tag != THUNK) {
+        return value;
+    }
+    switch (value->as.thunk.state) {
+    case 2:
+        return value->as.thunk.value;
+    case 1:
+        return fail("a lazy value is defined in terms of itself");
+    }
+    value->as.thunk.state = 1;
+    result = force(finish(value->as.thunk.code(value->as.thunk.environment, NULL)));
+    value->as.thunk.state = 2;
+    value->as.thunk.value = result;
+    return result;
+}
+
+EXPORT Value apply(Value function, size_t size, Value *arguments) {
+    for (;;) {
+        Value result, *given;
+        size_t arity, collected;
+        function = force(function);
+        if (!is_int(function) && function->tag == TYPE) {
+            return function;
+        }
+        if (is_int(function) || function->tag != CLOSURE) {
+            return fail("%s is not a function", show(function));
+        }
+        arity = function->as.closure.arity;
+        collected = function->as.closure.collected;
+        if (collected + size < arity) {
+            Value partial = allocate(sizeof(struct Object));
+            *partial = *function;
+            partial->as.closure.collected = collected + size;
+            partial->as.closure.arguments = allocate((collected + size) * sizeof(Value));
+            memcpy(partial->as.closure.arguments, function->as.closure.arguments, collected * sizeof(Value));
+            memcpy(partial->as.closure.arguments + collected, arguments, size * sizeof(Value));
+            return partial;
+        }
+        given = arguments;
+        if (collected != 0) {
+            given = allocate(arity * sizeof(Value));
+            memcpy(given, function->as.closure.arguments, collected * sizeof(Value));
+            memcpy(given + collected, arguments, (arity - collected) * sizeof(Value));
+        }
+        result = function->as.closure.code(function->as.closure.environment, given);
+        arguments += arity - collected;
+        size -= arity - collected;
+        if (result == TAIL) {
+            /* the arguments left over go to the result of the call made in tail position */
+            Value *all = allocate((pending_size + size) * sizeof(Value));
+            memcpy(all, pending_arguments, pending_size * sizeof(Value));
+            memcpy(all + pending_size, arguments, size * sizeof(Value));
+            function = pending_function;
+            arguments = all;
+            size += pending_size;
+            continue;
+        }
+        if (size == 0) {
+            return result;
+        }
+        function = result;
+    }
+}
+
+/* a definition is computed the first time it is used */
+EXPORT Value global(size_t index) {
+    Value result;
+    switch (global_states[index]) {
+    case 2:
+        return global_values[index];
+    case 1:
+        return fail("%s is defined in terms of itself", global_names[index]);
+    }
+    global_states[index] = 1;
+    result = finish(global_code[index](NULL, NULL));
+    global_states[index] = 2;
+    global_values[index] = result;
+    return result;
+}
+
+EXPORT Value select_payload(Value value) {
+    value = force(value);
+    if (is_int(value) || value->tag != EITHER) {
+        return fail("can't match %s against the pattern", show(value));
+    }
+    return value->as.either.payload;
+}
+
+EXPORT Value select_index(Value value, size_t index) {
+    value = force(value);
+    if (is_int(value) || value->tag != TUPLE) {
+        return fail("can't match %s against the pattern", show(value));
+    }
+    if (index >= value->as.tuple.size) {
+        return fail("tuple has no element %u", (unsigned)index);
+    }
+    return value->as.tuple.items[index];
+}
+
+EXPORT Value select_field(Value value, int name) {
+    size_t i;
+    value = force(value);
+    if (is_int(value) || value->tag != RECORD) {
+        return fail("can't match %s against the pattern", show(value));
+    }
+    for (i = 0; i < value->as.record.size; i++) {
+        if (value->as.record.names[i] == name) {
+            return value->as.record.values[i];
+        }
+    }
+    return fail("record has no field %s", symbols[name]);
+}
+
+/* the constructor of a forced value, -1 for anything else */
+EXPORT int constructor_of(Value value) {
+    return is_int(value) || value->tag != EITHER ? -1 : value->as.either.constructor;
+}
+
+EXPORT int is_text(Value value, const char *bytes, size_t length) {
+    return !is_int(value) && value->tag == TEXT && value->as.text.length == length
+        && memcmp(value->as.text.bytes, bytes, length) == 0;
+}
+
+EXPORT Value no_match(Value value) {
+    return fail("no pattern matches %s", show(value));
+}
+
+static int32_t int_argument(const char *primitive, Value value) {
+    value = force(value);
+    if (!is_int(value)) {
+        fail("%s expects ints and strings but got %s", primitive, show(value));
+    }
+    return int_of(value);
+}
+
+static Value text_argument(const char *primitive, Value value) {
+    value = force(value);
+    if (is_int(value) || value->tag != TEXT) {
+        fail("%s expects ints and strings but got %s", primitive, show(value));
+    }
+    return value;
+}
+
+/* ints wrap around, comparisons give 1 or 0, lengths and slices count bytes */
+EXPORT Value primitive_add(Value a, Value b) {
+    return make_int((int32_t)((uint32_t)int_argument("#add", a) + (uint32_t)int_argument("#add", b)));
+}
+
+EXPORT Value primitive_sub(Value a, Value b) {
+    return make_int((int32_t)((uint32_t)int_argument("#sub", a) - (uint32_t)int_argument("#sub", b)));
+}
+
+EXPORT Value primitive_mul(Value a, Value b) {
+    return make_int((int32_t)((uint32_t)int_argument("#mul", a) * (uint32_t)int_argument("#mul", b)));
+}
+
+EXPORT Value primitive_div(Value a, Value b) {
+    int32_t x = int_argument("#div", a), y = int_argument("#div", b);
+    if (y == 0) {
+        return fail("division by zero");
+    }
+    return make_int(x == INT32_MIN && y == -1 ? INT32_MIN : x / y);
+}
+
+EXPORT Value primitive_mod(Value a, Value b) {
+    int32_t x = int_argument("#mod", a), y = int_argument("#mod", b);
+    if (y == 0) {
+        return fail("division by zero");
+    }
+    return make_int(y == -1 ? 0 : x % y);
+}
+
+EXPORT Value primitive_eq(Value a, Value b) {
+    return make_int(int_argument("#eq", a) == int_argument("#eq", b));
+}
+
+EXPORT Value primitive_lt(Value a, Value b) {
+    return make_int(int_argument("#lt", a) < int_argument("#lt", b));
+}
+
+EXPORT Value primitive_le(Value a, Value b) {
+    return make_int(int_argument("#le", a) <= int_argument("#le", b));
+}
+
+EXPORT Value primitive_length(Value a) {
+    return make_int((int32_t)text_argument("#length", a)->as.text.length);
+}
+
+EXPORT Value primitive_concat(Value a, Value b) {
+    Value x = text_argument("#concat", a), y = text_argument("#concat", b);
+    char *bytes = allocate(x->as.text.length + y->as.text.length + 1);
+    memcpy(bytes, x->as.text.bytes, x->as.text.length);
+    memcpy(bytes + x->as.text.length, y->as.text.bytes, y->as.text.length);
+    return make_text(bytes, x->as.text.length + y->as.text.length);
+}
+
+static size_t clamp(int32_t n, size_t length) {
+    return n < 0 ? 0 : (size_t)n > length ? length : (size_t)n;
+}
+
+EXPORT Value primitive_slice(Value a, Value b, Value c) {
+    Value text = text_argument("#slice", a);
+    size_t from = clamp(int_argument("#slice", b), text->as.text.length);
+    size_t to = clamp(int_argument("#slice", c), text->as.text.length);
+    return make_text(text->as.text.bytes + from, from < to ? to - from : 0);
+}
+
+EXPORT Value primitive_show(Value a) {
+    char *bytes = allocate(16);
+    sprintf(bytes, "%ld", (long)int_argument("#show", a));
+    return make_text(bytes, strlen(bytes));
+}
+
+/* the primitives as functions, for when they are passed around */
+EXPORT Value code_add(Value *environment, Value *arguments) { (void)environment; return primitive_add(arguments[0], arguments[1]); }
+EXPORT Value code_sub(Value *environment, Value *arguments) { (void)environment; return primitive_sub(arguments[0], arguments[1]); }
+EXPORT Value code_mul(Value *environment, Value *arguments) { (void)environment; return primitive_mul(arguments[0], arguments[1]); }
+EXPORT Value code_div(Value *environment, Value *arguments) { (void)environment; return primitive_div(arguments[0], arguments[1]); }
+EXPORT Value code_mod(Value *environment, Value *arguments) { (void)environment; return primitive_mod(arguments[0], arguments[1]); }
+EXPORT Value code_eq(Value *environment, Value *arguments) { (void)environment; return primitive_eq(arguments[0], arguments[1]); }
+EXPORT Value code_lt(Value *environment, Value *arguments) { (void)environment; return primitive_lt(arguments[0], arguments[1]); }
+EXPORT Value code_le(Value *environment, Value *arguments) { (void)environment; return primitive_le(arguments[0], arguments[1]); }
+EXPORT Value code_length(Value *environment, Value *arguments) { (void)environment; return primitive_length(arguments[0]); }
+EXPORT Value code_concat(Value *environment, Value *arguments) { (void)environment; return primitive_concat(arguments[0], arguments[1]); }
+EXPORT Value code_slice(Value *environment, Value *arguments) {
+    (void)environment;
+    return primitive_slice(arguments[0], arguments[1], arguments[2]);
+}
+EXPORT Value code_show(Value *environment, Value *arguments) { (void)environment; return primitive_show(arguments[0]); }
+
+static int is_either(Value value) {
+    if (is_int(value)) {
+        return 0;
+    }
+    if (value->tag == THUNK) {
+        return value->as.thunk.state == 2 && is_either(value->as.thunk.value);
+    }
+    return value->tag == EITHER;
+}
+
+/* printed the way the interpreter prints its values */
+static void write_value(Buffer *buffer, Value value) {
+    size_t i;
+    char number[16];
+    if (is_int(value)) {
+        sprintf(number, "%ld", (long)int_of(value));
+        write_text(buffer, number);
+        return;
+    }
+    switch (value->tag) {
+    case TEXT:
+        write_text(buffer, "/");
+        write_bytes(buffer, value->as.text.bytes, value->as.text.length);
+        write_text(buffer, "/");
+        break;
+    case TUPLE:
+        write_text(buffer, "(");
+        for (i = 0; i < value->as.tuple.size; i++) {
+            write_text(buffer, i == 0 ? "" : ", ");
+            write_value(buffer, value->as.tuple.items[i]);
+        }
+        write_text(buffer, ")");
+        break;
+    case RECORD:
+        write_text(buffer, "{");
+        for (i = 0; i < value->as.record.size; i++) {
+            write_text(buffer, i == 0 ? "" : ", ");
+            write_text(buffer, symbols[value->as.record.names[i]]);
+            write_text(buffer, " = ");
+            write_value(buffer, value->as.record.values[i]);
+        }
+        write_text(buffer, "}");
+        break;
+    case EITHER:
+        write_text(buffer, symbols[value->as.either.constructor]);
+        write_text(buffer, is_either(value->as.either.payload) ? " (" : " ");
+        write_value(buffer, value->as.either.payload);
+        write_text(buffer, is_either(value->as.either.payload) ? ")" : "");
+        break;
+    case CLOSURE:
+        write_text(buffer, "<function>");
+        break;
+    case TYPE:
+        write_text(buffer, "<type>");
+        break;
+    case THUNK:
+        if (value->as.thunk.state == 2) {
+            write_value(buffer, value->as.thunk.value);
+        } else {
+            write_text(buffer, "...");
+        }
+        break;
+    }
+}
+
+/* forces everything inside as well, which never ends for infinite codata */
+EXPORT Value force_all(Value value) {
+    size_t i;
+    value = force(value);
+    if (is_int(value)) {
+        return value;
+    }
+    switch (value->tag) {
+    case TUPLE:
+        for (i = 0; i < value->as.tuple.size; i++) {
+            value->as.tuple.items[i] = force_all(value->as.tuple.items[i]);
+        }
+        break;
+    case RECORD:
+        for (i = 0; i < value->as.record.size; i++) {
+            value->as.record.values[i] = force_all(value->as.record.values[i]);
+        }
+        break;
+    case EITHER:
+        value->as.either.payload = force_all(value->as.either.payload);
+        break;
+    default:
+        break;
+    }
+    return value;
+}
+
+/* the program itself */
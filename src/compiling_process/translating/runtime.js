@@ -0,0 +1,195 @@
+// the runtime every generated program starts with
+"use strict";
+
+class $Failure extends Error {}
+
+function $fail(message) {
+    throw new $Failure(message);
+}
+
+// values of types, only ever passed around when types are not erased
+const $type = Object.freeze({ $type: true });
+
+// a value computed the first time it is needed, `name` is given for definitions
+class $Thunk {
+    constructor(code, name) {
+        this.code = code;
+        this.name = name;
+        this.state = "delayed";
+        this.value = undefined;
+    }
+}
+
+function $lazy(code, name) {
+    return new $Thunk(code, name);
+}
+
+function $force(value) {
+    if (!(value instanceof $Thunk)) {
+        return value;
+    }
+    if (value.state === "forcing") {
+        const name = value.name === undefined ? "a lazy value" : value.name;
+        $fail(`${name} is defined in terms of itself`);
+    }
+    if (value.state === "delayed") {
+        value.state = "forcing";
+        value.value = $force($finish(value.code()));
+        value.state = "forced";
+        value.code = undefined;
+    }
+    return value.value;
+}
+
+// returned instead of a value when a function ends with a call, the call is made by whoever called it
+class $Tail {
+    constructor(f, args) {
+        this.f = f;
+        this.args = args;
+    }
+}
+
+function $tail(f, args) {
+    return new $Tail(f, args);
+}
+
+function $finish(result) {
+    return result instanceof $Tail ? $apply(result.f, result.args) : result;
+}
+
+// functions take their parameters together, the arguments are collected until there are enough of them
+function $apply(f, args) {
+    for (;;) {
+        f = $force(f);
+        if (f === $type) {
+            return f;
+        }
+        if (typeof f !== "function") {
+            $fail(`${$show(f)} is not a function`);
+        }
+        if (args.length < f.length) {
+            const given = args;
+            const partial = (...rest) => f(...given, ...rest);
+            return Object.defineProperty(partial, "length", { value: f.length - given.length });
+        }
+        const result = f(...args.slice(0, f.length));
+        const rest = args.slice(f.length);
+        if (result instanceof $Tail) {
+            f = result.f;
+            args = result.args.concat(rest);
+        } else if (rest.length === 0) {
+            return result;
+        } else {
+            f = result;
+            args = rest;
+        }
+    }
+}
+
+function $noMatch(value) {
+    $fail(`no pattern matches ${$show(value)}`);
+}
+
+// ints wrap around at 32 bits, comparisons give 1 or 0, lengths and slices count utf-8 bytes
+function $int(name, value) {
+    value = $force(value);
+    return typeof value === "number" ? value : $fail(`${name} expects ints and strings but got ${$show(value)}`);
+}
+
+function $text(name, value) {
+    value = $force(value);
+    return typeof value === "string" ? value : $fail(`${name} expects ints and strings but got ${$show(value)}`);
+}
+
+function $divisor(name, value) {
+    const divisor = $int(name, value);
+    return divisor === 0 ? $fail("division by zero") : divisor;
+}
+
+const $bytes = (text) => new TextEncoder().encode(text);
+const $clamp = (n, length) => Math.min(Math.max(n, 0), length);
+
+const $add = (a, b) => ($int("#add", a) + $int("#add", b)) | 0;
+const $sub = (a, b) => ($int("#sub", a) - $int("#sub", b)) | 0;
+const $mul = (a, b) => Math.imul($int("#mul", a), $int("#mul", b));
+const $div = (a, b) => ($int("#div", a) / $divisor("#div", b)) | 0;
+const $mod = (a, b) => ($int("#mod", a) % $divisor("#mod", b)) | 0;
+const $eq = (a, b) => ($int("#eq", a) === $int("#eq", b) ? 1 : 0);
+const $lt = (a, b) => ($int("#lt", a) < $int("#lt", b) ? 1 : 0);
+const $le = (a, b) => ($int("#le", a) <= $int("#le", b) ? 1 : 0);
+const $length = (a) => $bytes($text("#length", a)).length;
+const $concat = (a, b) => $text("#concat", a) + $text("#concat", b);
+const $slice = (a, b, c) => {
+    const bytes = $bytes($text("#slice", a));
+    const from = $clamp($int("#slice", b), bytes.length);
+    const to = $clamp($int("#slice", c), bytes.length);
+    return from < to ? new TextDecoder().decode(bytes.subarray(from, to)) : "";
+};
+const $showInt = (a) => String($int("#show", a));
+
+function $isEither(value) {
+    if (value instanceof $Thunk) {
+        return value.state === "forced" && $isEither(value.value);
+    }
+    return typeof value === "object" && value !== null && "$tag" in value;
+}
+
+// printed the way the interpreter prints its values
+function $show(value) {
+    if (value instanceof $Thunk) {
+        return value.state === "forced" ? $show(value.value) : "...";
+    }
+    if (typeof value === "number") {
+        return String(value);
+    }
+    if (typeof value === "string") {
+        return `/${value}/`;
+    }
+    if (typeof value === "function") {
+        return "<function>";
+    }
+    if (value === $type) {
+        return "<type>";
+    }
+    if (Array.isArray(value)) {
+        return `(${value.map($show).join(", ")})`;
+    }
+    if ("$tag" in value) {
+        return $isEither(value.value) ? `${value.$tag} (${$show(value.value)})` : `${value.$tag} ${$show(value.value)}`;
+    }
+    return `{${Object.keys(value).map((name) => `${name} = ${$show(value[name])}`).join(", ")}}`;
+}
+
+// forces everything inside as well, which never ends for infinite codata
+function $forceAll(value) {
+    value = $force(value);
+    if (Array.isArray(value)) {
+        return value.map($forceAll);
+    }
+    if (typeof value !== "object" || value === $type) {
+        return value;
+    }
+    if ("$tag" in value) {
+        return { $tag: value.$tag, value: $forceAll(value.value) };
+    }
+    const result = {};
+    for (const name of Object.keys(value)) {
+        result[name] = $forceAll(value[name]);
+    }
+    return result;
+}
+
+// prints main, failures go to stderr with exit code 1 under node and are thrown elsewhere
+function $run(main) {
+    try {
+        console.log($show($forceAll(main)));
+    } catch (error) {
+        if (!(error instanceof $Failure) || typeof process === "undefined") {
+            throw error;
+        }
+        console.error(error.message);
+        process.exit(1);
+    }
+}
+
+// the program itself
@@ -0,0 +1,260 @@
+// the runtime every generated Rust module carries as its `rt` module, it is not compiled as part of this crate
+use std::cell::RefCell;
+use std::fmt::Write;
+
+pub use std::rc::Rc;
+
+// strings are shared, so copying a value never copies its text
+pub type Text = Rc<str>;
+pub type Function<A, B> = Rc<dyn Fn(A) -> B>;
+
+// what the type parameters of definitions stand for
+pub trait Value: Clone + Show + 'static {}
+
+impl<T: Clone + Show + 'static> Value for T {}
+
+pub fn lambda<A, B>(code: impl Fn(A) -> B + 'static) -> Function<A, B> {
+    Rc::new(code)
+}
+
+pub fn call<A, B>(function: Function<A, B>, argument: A) -> B {
+    function(argument)
+}
+
+// failures end the program with the message on stderr, the way the other backends do
+pub fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1)
+}
+
+pub fn no_match<A: Show>(value: &A) -> ! {
+    fail(&format!("no pattern matches {}", show(value)))
+}
+
+enum State<A> {
+    Delayed(Box<dyn FnOnce() -> A>),
+    Forcing,
+    Forced(A),
+}
+
+// a field of a record, computed the first time it is read so codata can go on forever
+pub struct Lazy<A>(Rc<RefCell<State<A>>>);
+
+impl<A> Clone for Lazy<A> {
+    fn clone(&self) -> Self {
+        Lazy(self.0.clone())
+    }
+}
+
+pub fn delay<A>(code: impl FnOnce() -> A + 'static) -> Lazy<A> {
+    Lazy(Rc::new(RefCell::new(State::Delayed(Box::new(code)))))
+}
+
+pub fn force<A: Clone>(lazy: &Lazy<A>) -> A {
+    let state = std::mem::replace(&mut *lazy.0.borrow_mut(), State::Forcing);
+    let value = match state {
+        State::Forced(value) => value,
+        State::Forcing => fail("a lazy value is defined in terms of itself"),
+        State::Delayed(code) => code(),
+    };
+    *lazy.0.borrow_mut() = State::Forced(value.clone());
+    value
+}
+
+// a field of codata is read by computing it, the first time
+impl<A: Clone> Lazy<A> {
+    pub fn read(&self) -> A {
+        force(self)
+    }
+}
+
+// the fields of other records hold their values, reading one copies it
+pub trait Read: Clone {
+    fn read(&self) -> Self {
+        self.clone()
+    }
+}
+
+impl<A: Clone> Read for A {}
+
+pub fn text(bytes: &str) -> Text {
+    Rc::from(bytes)
+}
+
+// ints wrap around, comparisons give 1 or 0, lengths and slices count bytes
+pub fn add(a: i32, b: i32) -> i32 {
+    a.wrapping_add(b)
+}
+
+pub fn subtract(a: i32, b: i32) -> i32 {
+    a.wrapping_sub(b)
+}
+
+pub fn multiply(a: i32, b: i32) -> i32 {
+    a.wrapping_mul(b)
+}
+
+fn divisor(b: i32) -> i32 {
+    if b == 0 {
+        fail("division by zero")
+    }
+    b
+}
+
+pub fn divide(a: i32, b: i32) -> i32 {
+    a.wrapping_div(divisor(b))
+}
+
+pub fn remainder(a: i32, b: i32) -> i32 {
+    a.wrapping_rem(divisor(b))
+}
+
+pub fn equal(a: i32, b: i32) -> i32 {
+    (a == b) as i32
+}
+
+pub fn less(a: i32, b: i32) -> i32 {
+    (a < b) as i32
+}
+
+pub fn less_or_equal(a: i32, b: i32) -> i32 {
+    (a <= b) as i32
+}
+
+pub fn length(a: Text) -> i32 {
+    a.len() as i32
+}
+
+pub fn concatenate(a: Text, b: Text) -> Text {
+    Rc::from(format!("{}{}", a, b))
+}
+
+pub fn slice(a: Text, b: i32, c: i32) -> Text {
+    let bytes = a.as_bytes();
+    let clamp = |n: i32| (n.max(0) as usize).min(bytes.len());
+    let (from, to) = (clamp(b), clamp(c));
+    Rc::from(String::from_utf8_lossy(if from < to { &bytes[from..to] } else { &[] }))
+}
+
+pub fn show_int(a: i32) -> Text {
+    Rc::from(a.to_string())
+}
+
+// values are written the way the interpreter prints them
+pub trait Show {
+    fn write(&self, out: &mut String);
+
+    // a constructor given to another one is written in parentheses
+    fn is_either(&self) -> bool {
+        false
+    }
+}
+
+pub fn show<A: Show>(value: &A) -> String {
+    let mut out = String::new();
+    value.write(&mut out);
+    out
+}
+
+pub fn write_either<A: Show>(out: &mut String, constructor: &str, payload: &A) {
+    out.push_str(constructor);
+    if payload.is_either() {
+        out.push_str(" (");
+        payload.write(out);
+        out.push(')');
+    } else {
+        out.push(' ');
+        payload.write(out);
+    }
+}
+
+pub fn write_field<A: Show>(out: &mut String, first: bool, name: &str, value: &A) {
+    out.push_str(if first { "" } else { ", " });
+    out.push_str(name);
+    out.push_str(" = ");
+    value.write(out);
+}
+
+impl Show for i32 {
+    fn write(&self, out: &mut String) {
+        let _ = write!(out, "{}", self);
+    }
+}
+
+impl Show for Text {
+    fn write(&self, out: &mut String) {
+        out.push('/');
+        out.push_str(self);
+        out.push('/');
+    }
+}
+
+impl<A, B> Show for Function<A, B> {
+    fn write(&self, out: &mut String) {
+        out.push_str("<function>");
+    }
+}
+
+impl<A: Clone + Show> Show for Lazy<A> {
+    fn write(&self, out: &mut String) {
+        force(self).write(out);
+    }
+
+    fn is_either(&self) -> bool {
+        force(self).is_either()
+    }
+}
+
+// a value given where `.` is expected, nothing is done with it but printing
+#[derive(Clone)]
+pub struct Top(Rc<dyn Show>);
+
+pub fn top<A: Show + 'static>(value: A) -> Top {
+    Top(Rc::new(value))
+}
+
+impl Show for Top {
+    fn write(&self, out: &mut String) {
+        self.0.write(out);
+    }
+
+    fn is_either(&self) -> bool {
+        self.0.is_either()
+    }
+}
+
+impl Show for () {
+    fn write(&self, out: &mut String) {
+        out.push_str("()");
+    }
+}
+
+macro_rules! tuples {
+    ($(($($item:ident $index:tt),+))*) => {
+        $(impl<$($item: Show),+> Show for ($($item,)+) {
+            fn write(&self, out: &mut String) {
+                out.push('(');
+                $(
+                    out.push_str(if $index == 0 { "" } else { ", " });
+                    self.$index.write(out);
+                )+
+                out.push(')');
+            }
+        })*
+    };
+}
+
+tuples! {
+    (A 0)
+    (A 0, B 1)
+    (A 0, B 1, C 2)
+    (A 0, B 1, C 2, D 3)
+    (A 0, B 1, C 2, D 3, E 4)
+    (A 0, B 1, C 2, D 3, E 4, F 5)
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6)
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7)
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8)
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9)
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10)
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11)
+}
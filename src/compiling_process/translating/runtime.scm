@@ -0,0 +1,172 @@
+;; the runtime every generated program starts with, the program only calls the names starting with $
+(import (scheme base) (scheme write) (scheme process-context))
+
+(define ($fail message)
+  (let ((port (current-error-port)))
+    (write-string message port)
+    (newline port)
+    (exit 1)))
+
+;; the value of types, passed around like any other
+(define $type '$type)
+
+;; a value computed the first time it is needed, `name` is given for definitions
+(define-record-type $thunk
+  ($make-thunk state code name)
+  $thunk?
+  (state $thunk-state $set-thunk-state!)
+  (code $thunk-code $set-thunk-code!)
+  (name $thunk-name))
+
+(define ($lazy code name)
+  ($make-thunk 'delayed code name))
+
+(define ($force value)
+  (if ($thunk? value)
+      (case ($thunk-state value)
+        ((forced) ($thunk-code value))
+        ((forcing)
+         ($fail (string-append (or ($thunk-name value) "a lazy value") " is defined in terms of itself")))
+        (else
+         (let ((code ($thunk-code value)))
+           ($set-thunk-state! value 'forcing)
+           (let ((result ($force (code))))
+             ($set-thunk-code! value result)
+             ($set-thunk-state! value 'forced)
+             result))))
+      value))
+
+;; tuples are vectors, constructors pairs tagged with their name and records lists of fields
+(define-record-type $record-type
+  ($make-record fields)
+  $record?
+  (fields $record-fields))
+
+(define ($tuple . items)
+  (list->vector items))
+
+(define ($record . entries)
+  (let loop ((entries entries) (fields '()))
+    (if (null? entries)
+        ($make-record (reverse fields))
+        (loop (cddr entries) (cons (cons (car entries) (cadr entries)) fields)))))
+
+(define ($either tag payload)
+  (cons tag payload))
+
+(define ($tag value)
+  (car ($force value)))
+
+(define ($payload value)
+  (cdr ($force value)))
+
+(define ($index value i)
+  (vector-ref ($force value) i))
+
+(define ($field value name)
+  (cdr (assoc name ($record-fields ($force value)))))
+
+(define ($same-text? value text)
+  (string=? ($force value) text))
+
+;; functions take one argument at a time, the last one is given in tail position so loops take no stack
+(define ($apply f . arguments)
+  (let loop ((f ($force f)) (arguments arguments))
+    (cond ((null? arguments) f)
+          ((eq? f $type) f)
+          ((not (procedure? f)) ($fail (string-append ($show f) " is not a function")))
+          ((null? (cdr arguments)) (f (car arguments)))
+          (else (loop ($force (f (car arguments))) (cdr arguments))))))
+
+;; a primitive passed around as a value
+(define ($curry f arity)
+  (let collect ((arity arity) (given '()))
+    (if (= arity 0)
+        (apply f (reverse given))
+        (lambda (x) (collect (- arity 1) (cons x given))))))
+
+(define ($no-match value)
+  ($fail (string-append "no pattern matches " ($show value))))
+
+;; ints wrap around at 32 bits, comparisons give 1 or 0, lengths and slices count utf-8 bytes
+(define ($int name value)
+  (let ((value ($force value)))
+    (if (exact-integer? value)
+        value
+        ($fail (string-append name " expects ints and strings but got " ($show value))))))
+
+(define ($text name value)
+  (let ((value ($force value)))
+    (if (string? value)
+        value
+        ($fail (string-append name " expects ints and strings but got " ($show value))))))
+
+(define ($wrap n)
+  (- (modulo (+ n 2147483648) 4294967296) 2147483648))
+
+(define ($divisor name value)
+  (let ((divisor ($int name value)))
+    (if (= divisor 0) ($fail "division by zero") divisor)))
+
+(define ($add a b) ($wrap (+ ($int "#add" a) ($int "#add" b))))
+(define ($sub a b) ($wrap (- ($int "#sub" a) ($int "#sub" b))))
+(define ($mul a b) ($wrap (* ($int "#mul" a) ($int "#mul" b))))
+(define ($div a b) ($wrap (truncate-quotient ($int "#div" a) ($divisor "#div" b))))
+(define ($mod a b) ($wrap (truncate-remainder ($int "#mod" a) ($divisor "#mod" b))))
+(define ($eq a b) (if (= ($int "#eq" a) ($int "#eq" b)) 1 0))
+(define ($lt a b) (if (< ($int "#lt" a) ($int "#lt" b)) 1 0))
+(define ($le a b) (if (<= ($int "#le" a) ($int "#le" b)) 1 0))
+(define ($length a) (bytevector-length (string->utf8 ($text "#length" a))))
+(define ($concat a b) (string-append ($text "#concat" a) ($text "#concat" b)))
+(define ($show-int a) (number->string ($int "#show" a)))
+
+(define ($clamp n length)
+  (min (max n 0) length))
+
+;; a slice cutting a character in two is an error, where the interpreter would replace it
+(define ($slice a b c)
+  (let* ((bytes (string->utf8 ($text "#slice" a)))
+         (from ($clamp ($int "#slice" b) (bytevector-length bytes)))
+         (to ($clamp ($int "#slice" c) (bytevector-length bytes))))
+    (if (< from to) (utf8->string bytes from to) "")))
+
+(define ($is-either value)
+  (if ($thunk? value)
+      (and (eq? ($thunk-state value) 'forced) ($is-either ($thunk-code value)))
+      (pair? value)))
+
+(define ($join texts)
+  (if (null? texts)
+      ""
+      (let loop ((result (car texts)) (texts (cdr texts)))
+        (if (null? texts) result (loop (string-append result ", " (car texts)) (cdr texts))))))
+
+;; printed the way the interpreter prints its values
+(define ($show value)
+  (cond (($thunk? value) (if (eq? ($thunk-state value) 'forced) ($show ($thunk-code value)) "..."))
+        ((exact-integer? value) (number->string value))
+        ((string? value) (string-append "/" value "/"))
+        ((procedure? value) "<function>")
+        ((eq? value $type) "<type>")
+        ((vector? value) (string-append "(" ($join (map $show (vector->list value))) ")"))
+        (($record? value)
+         (let ((field (lambda (field) (string-append (car field) " = " ($show (cdr field))))))
+           (string-append "{" ($join (map field ($record-fields value))) "}")))
+        (($is-either (cdr value))
+         (string-append (symbol->string (car value)) " (" ($show (cdr value)) ")"))
+        (else (string-append (symbol->string (car value)) " " ($show (cdr value))))))
+
+;; forces everything inside as well, which never ends for infinite codata
+(define ($force-all value)
+  (let ((value ($force value)))
+    (cond ((vector? value) (vector-map $force-all value))
+          (($record? value)
+           ($make-record (map (lambda (field) (cons (car field) ($force-all (cdr field)))) ($record-fields value))))
+          ((pair? value) (cons (car value) ($force-all (cdr value))))
+          (else value))))
+
+(define ($run main)
+  (write-string ($show ($force-all main)))
+  (newline))
+
+;; the program itself, its definitions are local so they may take the names of standard procedures
@@ -0,0 +1,1433 @@
+  ;; the runtime every generated module starts with
+  ;; values are pointers to objects in memory whose first word is their tag
+  ;;   0 int [tag, n]                           1 text [tag, length, bytes]
+  ;;   2 tuple [tag, size, items...]            3 record [tag, size, name, value, name, value...]
+  ;;   4 either [tag, constructor, payload]     5 closure [tag, code, arity, captured, 0, captured...]
+  ;;   6 partial [tag, closure, count, arguments...]
+  ;;   7 type [tag]                             8 thunk [tag, code, state, captured, value, captured...]
+  ;;   9 tail [tag], returned instead of a value when a function ends with a call
+  ;; memory below 1024 is the runtime's own, the program's data comes after it and the heap after that
+  (type $code (func (param i32 i32) (result i32)))
+
+  (memory (export "memory") 1)
+
+  (data (i32.const 8) "\07")
+  (data (i32.const 16) "\02")
+  (data (i32.const 32) "\09")
+  (data (i32.const 256) "division by zero")
+  (data (i32.const 288) "no pattern matches ")
+  (data (i32.const 320) " is defined in terms of itself")
+  (data (i32.const 352) "a lazy value")
+  (data (i32.const 384) " is not a function")
+  (data (i32.const 416) "a primitive was given ")
+  (data (i32.const 448) "<function>")
+  (data (i32.const 464) "<type>")
+  (data (i32.const 480) "...")
+
+  ;; the message of the failure that stopped the program, read by the host after the trap
+  (global $error (mut i32) (i32.const 0))
+  ;; the call a function ended with
+  (global $pending_function (mut i32) (i32.const 0))
+  (global $pending_arguments (mut i32) (i32.const 0))
+  (global $pending_count (mut i32) (i32.const 0))
+  ;; where show writes
+  (global $buffer (mut i32) (i32.const 0))
+  (global $buffer_length (mut i32) (i32.const 0))
+  (global $buffer_capacity (mut i32) (i32.const 0))
+
+  (func $error (export "error") (result i32)
+    global.get $error)
+
+  ;; a bump allocator, memory grows when the heap reaches its end and is never freed
+  (func $alloc (param $size i32) (result i32)
+    (local $result i32)
+    global.get $heap
+    local.set $result
+    global.get $heap
+    local.get $size
+    i32.add
+    i32.const 3
+    i32.add
+    i32.const -4
+    i32.and
+    global.set $heap
+    block $enough
+      loop $grow
+        global.get $heap
+        memory.size
+        i32.const 16
+        i32.shl
+        i32.le_u
+        br_if $enough
+        memory.size
+        memory.grow
+        i32.const -1
+        i32.eq
+        if
+          unreachable
+        end
+        br $grow
+      end
+    end
+    local.get $result)
+
+  (func $copy (param $target i32) (param $source i32) (param $count i32)
+    block $done
+      loop $next
+        local.get $count
+        i32.eqz
+        br_if $done
+        local.get $target
+        local.get $source
+        i32.load
+        i32.store
+        local.get $target
+        i32.const 4
+        i32.add
+        local.set $target
+        local.get $source
+        i32.const 4
+        i32.add
+        local.set $source
+        local.get $count
+        i32.const 1
+        i32.sub
+        local.set $count
+        br $next
+      end
+    end)
+
+  (func $copy_bytes (param $target i32) (param $source i32) (param $count i32)
+    block $done
+      loop $next
+        local.get $count
+        i32.eqz
+        br_if $done
+        local.get $target
+        local.get $source
+        i32.load8_u
+        i32.store8
+        local.get $target
+        i32.const 1
+        i32.add
+        local.set $target
+        local.get $source
+        i32.const 1
+        i32.add
+        local.set $source
+        local.get $count
+        i32.const 1
+        i32.sub
+        local.set $count
+        br $next
+      end
+    end)
+
+  ;; two arrays of values as one
+  (func $join (param $first i32) (param $first_count i32) (param $second i32) (param $second_count i32) (result i32)
+    (local $result i32)
+    local.get $second_count
+    i32.eqz
+    if
+      local.get $first
+      return
+    end
+    local.get $first_count
+    i32.eqz
+    if
+      local.get $second
+      return
+    end
+    local.get $first_count
+    local.get $second_count
+    i32.add
+    i32.const 4
+    i32.mul
+    call $alloc
+    local.set $result
+    local.get $result
+    local.get $first
+    local.get $first_count
+    call $copy
+    local.get $result
+    local.get $first_count
+    i32.const 4
+    i32.mul
+    i32.add
+    local.get $second
+    local.get $second_count
+    call $copy
+    local.get $result)
+
+  (func $fail (param $message i32) (result i32)
+    local.get $message
+    global.set $error
+    unreachable)
+
+  (func $int (param $n i32) (result i32)
+    (local $result i32)
+    i32.const 8
+    call $alloc
+    local.set $result
+    local.get $result
+    i32.const 0
+    i32.store
+    local.get $result
+    local.get $n
+    i32.store offset=4
+    local.get $result)
+
+  (func $text (param $bytes i32) (param $length i32) (result i32)
+    (local $result i32)
+    i32.const 12
+    call $alloc
+    local.set $result
+    local.get $result
+    i32.const 1
+    i32.store
+    local.get $result
+    local.get $length
+    i32.store offset=4
+    local.get $result
+    local.get $bytes
+    i32.store offset=8
+    local.get $result)
+
+  ;; the objects are made here and their fields stored by the generated code
+  (func $tuple (param $size i32) (result i32)
+    (local $result i32)
+    local.get $size
+    i32.eqz
+    if
+      i32.const 16
+      return
+    end
+    local.get $size
+    i32.const 4
+    i32.mul
+    i32.const 8
+    i32.add
+    call $alloc
+    local.set $result
+    local.get $result
+    i32.const 2
+    i32.store
+    local.get $result
+    local.get $size
+    i32.store offset=4
+    local.get $result)
+
+  (func $record (param $size i32) (result i32)
+    (local $result i32)
+    local.get $size
+    i32.const 8
+    i32.mul
+    i32.const 8
+    i32.add
+    call $alloc
+    local.set $result
+    local.get $result
+    i32.const 3
+    i32.store
+    local.get $result
+    local.get $size
+    i32.store offset=4
+    local.get $result)
+
+  (func $either (param $constructor i32) (param $payload i32) (result i32)
+    (local $result i32)
+    i32.const 12
+    call $alloc
+    local.set $result
+    local.get $result
+    i32.const 4
+    i32.store
+    local.get $result
+    local.get $constructor
+    i32.store offset=4
+    local.get $result
+    local.get $payload
+    i32.store offset=8
+    local.get $result)
+
+  (func $closure (param $code i32) (param $arity i32) (param $captured i32) (result i32)
+    (local $result i32)
+    local.get $captured
+    i32.const 4
+    i32.mul
+    i32.const 20
+    i32.add
+    call $alloc
+    local.set $result
+    local.get $result
+    i32.const 5
+    i32.store
+    local.get $result
+    local.get $code
+    i32.store offset=4
+    local.get $result
+    local.get $arity
+    i32.store offset=8
+    local.get $result
+    local.get $captured
+    i32.store offset=12
+    local.get $result)
+
+  (func $thunk (param $code i32) (param $captured i32) (result i32)
+    (local $result i32)
+    local.get $captured
+    i32.const 4
+    i32.mul
+    i32.const 20
+    i32.add
+    call $alloc
+    local.set $result
+    local.get $result
+    i32.const 8
+    i32.store
+    local.get $result
+    local.get $code
+    i32.store offset=4
+    local.get $result
+    i32.const 0
+    i32.store offset=8
+    local.get $result
+    local.get $captured
+    i32.store offset=12
+    local.get $result)
+
+  (func $arguments (param $count i32) (result i32)
+    local.get $count
+    i32.const 4
+    i32.mul
+    call $alloc)
+
+  (func $tail_call (param $function i32) (param $arguments i32) (param $count i32) (result i32)
+    local.get $function
+    global.set $pending_function
+    local.get $arguments
+    global.set $pending_arguments
+    local.get $count
+    global.set $pending_count
+    i32.const 32)
+
+  ;; the value a function returned, making the call it ended with if there is one
+  (func $finish (param $result i32) (result i32)
+    local.get $result
+    i32.const 32
+    i32.eq
+    if
+      global.get $pending_function
+      global.get $pending_arguments
+      global.get $pending_count
+      call $apply
+      return
+    end
+    local.get $result)
+
+  (func $force (param $value i32) (result i32)
+    (local $result i32)
+    local.get $value
+    i32.load
+    i32.const 8
+    i32.ne
+    if
+      local.get $value
+      return
+    end
+    local.get $value
+    i32.load offset=8
+    i32.const 2
+    i32.eq
+    if
+      local.get $value
+      i32.load offset=16
+      return
+    end
+    local.get $value
+    i32.load offset=8
+    i32.const 1
+    i32.eq
+    if
+      i32.const 352
+      i32.const 12
+      call $text
+      i32.const 320
+      i32.const 30
+      call $text
+      call $concat
+      call $fail
+      return
+    end
+    local.get $value
+    i32.const 1
+    i32.store offset=8
+    local.get $value
+    i32.const 0
+    local.get $value
+    i32.load offset=4
+    call_indirect (type $code)
+    call $finish
+    call $force
+    local.set $result
+    local.get $value
+    i32.const 2
+    i32.store offset=8
+    local.get $value
+    local.get $result
+    i32.store offset=16
+    local.get $result)
+
+  ;; arguments are collected until there are as many as the function takes, the rest go to what it gives
+  (func $apply (param $function i32) (param $arguments i32) (param $count i32) (result i32)
+    (local $arity i32)
+    (local $result i32)
+    loop $next
+      local.get $function
+      call $force
+      local.set $function
+      local.get $function
+      i32.load
+      i32.const 7
+      i32.eq
+      if
+        local.get $function
+        return
+      end
+      local.get $function
+      i32.load
+      i32.const 6
+      i32.eq
+      if
+        local.get $function
+        i32.const 12
+        i32.add
+        local.get $function
+        i32.load offset=8
+        local.get $arguments
+        local.get $count
+        call $join
+        local.set $arguments
+        local.get $count
+        local.get $function
+        i32.load offset=8
+        i32.add
+        local.set $count
+        local.get $function
+        i32.load offset=4
+        local.set $function
+        br $next
+      end
+      local.get $function
+      i32.load
+      i32.const 5
+      i32.ne
+      if
+        local.get $function
+        call $show
+        i32.const 384
+        i32.const 18
+        call $text
+        call $concat
+        call $fail
+        return
+      end
+      local.get $function
+      i32.load offset=8
+      local.set $arity
+      local.get $count
+      local.get $arity
+      i32.lt_u
+      if
+        local.get $count
+        i32.const 4
+        i32.mul
+        i32.const 12
+        i32.add
+        call $alloc
+        local.set $result
+        local.get $result
+        i32.const 6
+        i32.store
+        local.get $result
+        local.get $function
+        i32.store offset=4
+        local.get $result
+        local.get $count
+        i32.store offset=8
+        local.get $result
+        i32.const 12
+        i32.add
+        local.get $arguments
+        local.get $count
+        call $copy
+        local.get $result
+        return
+      end
+      local.get $function
+      local.get $arguments
+      local.get $function
+      i32.load offset=4
+      call_indirect (type $code)
+      local.set $result
+      local.get $arguments
+      local.get $arity
+      i32.const 4
+      i32.mul
+      i32.add
+      local.set $arguments
+      local.get $count
+      local.get $arity
+      i32.sub
+      local.set $count
+      local.get $result
+      i32.const 32
+      i32.eq
+      if
+        global.get $pending_arguments
+        global.get $pending_count
+        local.get $arguments
+        local.get $count
+        call $join
+        local.set $arguments
+        global.get $pending_count
+        local.get $count
+        i32.add
+        local.set $count
+        global.get $pending_function
+        local.set $function
+        br $next
+      end
+      local.get $count
+      i32.eqz
+      if
+        local.get $result
+        return
+      end
+      local.get $result
+      local.set $function
+      br $next
+    end
+    unreachable)
+
+  ;; a definition is computed the first time it is used, its code comes after the primitives in the table
+  (func $global (param $index i32) (result i32)
+    (local $state i32)
+    (local $result i32)
+    global.get $global_states
+    local.get $index
+    i32.add
+    i32.load8_u
+    local.set $state
+    local.get $state
+    i32.const 2
+    i32.eq
+    if
+      global.get $global_values
+      local.get $index
+      i32.const 4
+      i32.mul
+      i32.add
+      i32.load
+      return
+    end
+    local.get $state
+    i32.const 1
+    i32.eq
+    if
+      global.get $global_names
+      local.get $index
+      i32.const 4
+      i32.mul
+      i32.add
+      i32.load
+      i32.const 320
+      i32.const 30
+      call $text
+      call $concat
+      call $fail
+      return
+    end
+    global.get $global_states
+    local.get $index
+    i32.add
+    i32.const 1
+    i32.store8
+    i32.const 0
+    i32.const 0
+    local.get $index
+    i32.const 12
+    i32.add
+    call_indirect (type $code)
+    call $finish
+    local.set $result
+    global.get $global_states
+    local.get $index
+    i32.add
+    i32.const 2
+    i32.store8
+    global.get $global_values
+    local.get $index
+    i32.const 4
+    i32.mul
+    i32.add
+    local.get $result
+    i32.store
+    local.get $result)
+
+  ;; the parts of a value a pattern looks at, the checker made sure they are there
+  (func $payload (param $value i32) (result i32)
+    local.get $value
+    call $force
+    i32.load offset=8)
+
+  (func $index (param $value i32) (param $index i32) (result i32)
+    local.get $value
+    call $force
+    local.get $index
+    i32.const 4
+    i32.mul
+    i32.add
+    i32.load offset=8)
+
+  (func $field (param $value i32) (param $name i32) (result i32)
+    local.get $value
+    call $force
+    local.set $value
+    loop $next
+      local.get $value
+      i32.load offset=8
+      local.get $name
+      i32.ne
+      if
+        local.get $value
+        i32.const 8
+        i32.add
+        local.set $value
+        br $next
+      end
+    end
+    local.get $value
+    i32.load offset=12)
+
+  ;; the constructor of a value, -1 for anything else
+  (func $constructor (param $value i32) (result i32)
+    local.get $value
+    call $force
+    local.set $value
+    local.get $value
+    i32.load
+    i32.const 4
+    i32.eq
+    if
+      local.get $value
+      i32.load offset=4
+      return
+    end
+    i32.const -1)
+
+  (func $int_is (param $value i32) (param $n i32) (result i32)
+    local.get $value
+    call $force
+    local.set $value
+    local.get $value
+    i32.load
+    i32.eqz
+    if
+      local.get $value
+      i32.load offset=4
+      local.get $n
+      i32.eq
+      return
+    end
+    i32.const 0)
+
+  (func $text_is (param $value i32) (param $bytes i32) (param $length i32) (result i32)
+    (local $other i32)
+    local.get $value
+    call $force
+    local.set $value
+    local.get $value
+    i32.load
+    i32.const 1
+    i32.ne
+    if
+      i32.const 0
+      return
+    end
+    local.get $value
+    i32.load offset=4
+    local.get $length
+    i32.ne
+    if
+      i32.const 0
+      return
+    end
+    local.get $value
+    i32.load offset=8
+    local.set $other
+    block $done
+      loop $next
+        local.get $length
+        i32.eqz
+        br_if $done
+        local.get $other
+        i32.load8_u
+        local.get $bytes
+        i32.load8_u
+        i32.ne
+        if
+          i32.const 0
+          return
+        end
+        local.get $other
+        i32.const 1
+        i32.add
+        local.set $other
+        local.get $bytes
+        i32.const 1
+        i32.add
+        local.set $bytes
+        local.get $length
+        i32.const 1
+        i32.sub
+        local.set $length
+        br $next
+      end
+    end
+    i32.const 1)
+
+  (func $no_match (param $value i32) (result i32)
+    i32.const 288
+    i32.const 19
+    call $text
+    local.get $value
+    call $show
+    call $concat
+    call $fail)
+
+  ;; ints wrap around, comparisons give 1 or 0, lengths and slices count bytes
+  (func $int_value (param $value i32) (result i32)
+    local.get $value
+    call $force
+    local.set $value
+    local.get $value
+    i32.load
+    if
+      i32.const 416
+      i32.const 22
+      call $text
+      local.get $value
+      call $show
+      call $concat
+      call $fail
+      return
+    end
+    local.get $value
+    i32.load offset=4)
+
+  (func $text_value (param $value i32) (result i32)
+    local.get $value
+    call $force
+    local.set $value
+    local.get $value
+    i32.load
+    i32.const 1
+    i32.ne
+    if
+      i32.const 416
+      i32.const 22
+      call $text
+      local.get $value
+      call $show
+      call $concat
+      call $fail
+      return
+    end
+    local.get $value)
+
+  (func $divisor (param $value i32) (result i32)
+    local.get $value
+    call $int_value
+    local.set $value
+    local.get $value
+    i32.eqz
+    if
+      i32.const 256
+      i32.const 16
+      call $text
+      call $fail
+      return
+    end
+    local.get $value)
+
+  (func $add (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    call $int_value
+    local.get $b
+    call $int_value
+    i32.add
+    call $int)
+
+  (func $sub (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    call $int_value
+    local.get $b
+    call $int_value
+    i32.sub
+    call $int)
+
+  (func $mul (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    call $int_value
+    local.get $b
+    call $int_value
+    i32.mul
+    call $int)
+
+  ;; the smallest int divided by -1 would trap, it wraps around to itself instead
+  (func $div (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    call $int_value
+    local.set $a
+    local.get $b
+    call $divisor
+    local.set $b
+    local.get $b
+    i32.const -1
+    i32.eq
+    if
+      i32.const 0
+      local.get $a
+      i32.sub
+      call $int
+      return
+    end
+    local.get $a
+    local.get $b
+    i32.div_s
+    call $int)
+
+  (func $mod (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    call $int_value
+    local.get $b
+    call $divisor
+    i32.rem_s
+    call $int)
+
+  (func $eq (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    call $int_value
+    local.get $b
+    call $int_value
+    i32.eq
+    call $int)
+
+  (func $lt (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    call $int_value
+    local.get $b
+    call $int_value
+    i32.lt_s
+    call $int)
+
+  (func $le (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    call $int_value
+    local.get $b
+    call $int_value
+    i32.le_s
+    call $int)
+
+  (func $length (param $a i32) (result i32)
+    local.get $a
+    call $text_value
+    i32.load offset=4
+    call $int)
+
+  (func $concat (param $a i32) (param $b i32) (result i32)
+    (local $bytes i32)
+    local.get $a
+    call $text_value
+    local.set $a
+    local.get $b
+    call $text_value
+    local.set $b
+    local.get $a
+    i32.load offset=4
+    local.get $b
+    i32.load offset=4
+    i32.add
+    call $alloc
+    local.set $bytes
+    local.get $bytes
+    local.get $a
+    i32.load offset=8
+    local.get $a
+    i32.load offset=4
+    call $copy_bytes
+    local.get $bytes
+    local.get $a
+    i32.load offset=4
+    i32.add
+    local.get $b
+    i32.load offset=8
+    local.get $b
+    i32.load offset=4
+    call $copy_bytes
+    local.get $bytes
+    local.get $a
+    i32.load offset=4
+    local.get $b
+    i32.load offset=4
+    i32.add
+    call $text)
+
+  (func $clamp (param $n i32) (param $length i32) (result i32)
+    local.get $n
+    i32.const 0
+    i32.lt_s
+    if
+      i32.const 0
+      return
+    end
+    local.get $n
+    local.get $length
+    i32.gt_s
+    if
+      local.get $length
+      return
+    end
+    local.get $n)
+
+  (func $slice (param $a i32) (param $b i32) (param $c i32) (result i32)
+    (local $from i32)
+    (local $to i32)
+    local.get $a
+    call $text_value
+    local.set $a
+    local.get $b
+    call $int_value
+    local.get $a
+    i32.load offset=4
+    call $clamp
+    local.set $from
+    local.get $c
+    call $int_value
+    local.get $a
+    i32.load offset=4
+    call $clamp
+    local.set $to
+    local.get $to
+    local.get $from
+    i32.lt_s
+    if
+      local.get $from
+      local.set $to
+    end
+    local.get $a
+    i32.load offset=8
+    local.get $from
+    i32.add
+    local.get $to
+    local.get $from
+    i32.sub
+    call $text)
+
+  (func $show_int (param $a i32) (result i32)
+    i32.const 16
+    call $start
+    local.get $a
+    call $int_value
+    call $write_int
+    call $finish_text)
+
+  ;; the primitives as functions, for when they are passed around, they are the first entries of the table
+  (func $code_add (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    call $add)
+
+  (func $code_sub (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    call $sub)
+
+  (func $code_mul (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    call $mul)
+
+  (func $code_div (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    call $div)
+
+  (func $code_mod (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    call $mod)
+
+  (func $code_eq (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    call $eq)
+
+  (func $code_lt (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    call $lt)
+
+  (func $code_le (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    call $le)
+
+  (func $code_length (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    call $length)
+
+  (func $code_concat (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    call $concat)
+
+  (func $code_slice (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    local.get $arguments
+    i32.load offset=4
+    local.get $arguments
+    i32.load offset=8
+    call $slice)
+
+  (func $code_show (param $environment i32) (param $arguments i32) (result i32)
+    local.get $arguments
+    i32.load
+    call $show_int)
+
+  ;; the text being written, it moves to a bigger place when it fills up
+  (func $start (param $capacity i32)
+    local.get $capacity
+    call $alloc
+    global.set $buffer
+    i32.const 0
+    global.set $buffer_length
+    local.get $capacity
+    global.set $buffer_capacity)
+
+  (func $write_bytes (param $bytes i32) (param $length i32)
+    (local $capacity i32)
+    (local $bigger i32)
+    global.get $buffer_length
+    local.get $length
+    i32.add
+    global.get $buffer_capacity
+    i32.gt_u
+    if
+      global.get $buffer_capacity
+      i32.const 2
+      i32.mul
+      local.get $length
+      i32.add
+      local.set $capacity
+      local.get $capacity
+      call $alloc
+      local.set $bigger
+      local.get $bigger
+      global.get $buffer
+      global.get $buffer_length
+      call $copy_bytes
+      local.get $bigger
+      global.set $buffer
+      local.get $capacity
+      global.set $buffer_capacity
+    end
+    global.get $buffer
+    global.get $buffer_length
+    i32.add
+    local.get $bytes
+    local.get $length
+    call $copy_bytes
+    global.get $buffer_length
+    local.get $length
+    i32.add
+    global.set $buffer_length)
+
+  ;; single characters go through the scratch byte at 48
+  (func $write_byte (param $byte i32)
+    i32.const 48
+    local.get $byte
+    i32.store8
+    i32.const 48
+    i32.const 1
+    call $write_bytes)
+
+  (func $write_text (param $text i32)
+    local.get $text
+    i32.load offset=8
+    local.get $text
+    i32.load offset=4
+    call $write_bytes)
+
+  ;; the digits are put together backwards in the scratch space from 64 to 80
+  (func $write_int (param $n i32)
+    (local $position i32)
+    local.get $n
+    i32.const 0
+    i32.lt_s
+    if
+      i32.const 45
+      call $write_byte
+      i32.const 0
+      local.get $n
+      i32.sub
+      local.set $n
+    end
+    i32.const 80
+    local.set $position
+    loop $next
+      local.get $position
+      i32.const 1
+      i32.sub
+      local.set $position
+      local.get $position
+      local.get $n
+      i32.const 10
+      i32.rem_u
+      i32.const 48
+      i32.add
+      i32.store8
+      local.get $n
+      i32.const 10
+      i32.div_u
+      local.set $n
+      local.get $n
+      br_if $next
+    end
+    local.get $position
+    i32.const 80
+    local.get $position
+    i32.sub
+    call $write_bytes)
+
+  (func $finish_text (result i32)
+    global.get $buffer
+    global.get $buffer_length
+    call $text)
+
+  (func $is_either (param $value i32) (result i32)
+    local.get $value
+    i32.load
+    i32.const 8
+    i32.eq
+    if
+      local.get $value
+      i32.load offset=8
+      i32.const 2
+      i32.ne
+      if
+        i32.const 0
+        return
+      end
+      local.get $value
+      i32.load offset=16
+      call $is_either
+      return
+    end
+    local.get $value
+    i32.load
+    i32.const 4
+    i32.eq)
+
+  ;; printed the way the interpreter prints its values
+  (func $write_value (param $value i32)
+    (local $tag i32)
+    (local $i i32)
+    local.get $value
+    i32.load
+    local.set $tag
+    local.get $tag
+    i32.eqz
+    if
+      local.get $value
+      i32.load offset=4
+      call $write_int
+      return
+    end
+    local.get $tag
+    i32.const 1
+    i32.eq
+    if
+      i32.const 47
+      call $write_byte
+      local.get $value
+      call $write_text
+      i32.const 47
+      call $write_byte
+      return
+    end
+    local.get $tag
+    i32.const 2
+    i32.eq
+    if
+      i32.const 40
+      call $write_byte
+      block $done
+        loop $next
+          local.get $i
+          local.get $value
+          i32.load offset=4
+          i32.ge_u
+          br_if $done
+          local.get $i
+          if
+            i32.const 44
+            call $write_byte
+            i32.const 32
+            call $write_byte
+          end
+          local.get $value
+          local.get $i
+          i32.const 4
+          i32.mul
+          i32.add
+          i32.load offset=8
+          call $write_value
+          local.get $i
+          i32.const 1
+          i32.add
+          local.set $i
+          br $next
+        end
+      end
+      i32.const 41
+      call $write_byte
+      return
+    end
+    local.get $tag
+    i32.const 3
+    i32.eq
+    if
+      i32.const 123
+      call $write_byte
+      block $done
+        loop $next
+          local.get $i
+          local.get $value
+          i32.load offset=4
+          i32.ge_u
+          br_if $done
+          local.get $i
+          if
+            i32.const 44
+            call $write_byte
+            i32.const 32
+            call $write_byte
+          end
+          global.get $symbols
+          local.get $value
+          local.get $i
+          i32.const 8
+          i32.mul
+          i32.add
+          i32.load offset=8
+          i32.const 4
+          i32.mul
+          i32.add
+          i32.load
+          call $write_text
+          i32.const 32
+          call $write_byte
+          i32.const 61
+          call $write_byte
+          i32.const 32
+          call $write_byte
+          local.get $value
+          local.get $i
+          i32.const 8
+          i32.mul
+          i32.add
+          i32.load offset=12
+          call $write_value
+          local.get $i
+          i32.const 1
+          i32.add
+          local.set $i
+          br $next
+        end
+      end
+      i32.const 125
+      call $write_byte
+      return
+    end
+    local.get $tag
+    i32.const 4
+    i32.eq
+    if
+      global.get $symbols
+      local.get $value
+      i32.load offset=4
+      i32.const 4
+      i32.mul
+      i32.add
+      i32.load
+      call $write_text
+      i32.const 32
+      call $write_byte
+      local.get $value
+      i32.load offset=8
+      call $is_either
+      if
+        i32.const 40
+        call $write_byte
+        local.get $value
+        i32.load offset=8
+        call $write_value
+        i32.const 41
+        call $write_byte
+        return
+      end
+      local.get $value
+      i32.load offset=8
+      call $write_value
+      return
+    end
+    local.get $tag
+    i32.const 7
+    i32.eq
+    if
+      i32.const 464
+      i32.const 6
+      call $write_bytes
+      return
+    end
+    local.get $tag
+    i32.const 8
+    i32.eq
+    if
+      local.get $value
+      i32.load offset=8
+      i32.const 2
+      i32.eq
+      if
+        local.get $value
+        i32.load offset=16
+        call $write_value
+        return
+      end
+      i32.const 480
+      i32.const 3
+      call $write_bytes
+      return
+    end
+    i32.const 448
+    i32.const 10
+    call $write_bytes)
+
+  (func $show (param $value i32) (result i32)
+    i32.const 64
+    call $start
+    local.get $value
+    call $write_value
+    call $finish_text)
+
+  ;; forces everything inside as well, which never ends for infinite codata
+  (func $force_all (param $value i32) (result i32)
+    (local $i i32)
+    (local $place i32)
+    local.get $value
+    call $force
+    local.set $value
+    local.get $value
+    i32.load
+    i32.const 4
+    i32.eq
+    if
+      local.get $value
+      local.get $value
+      i32.load offset=8
+      call $force_all
+      i32.store offset=8
+      local.get $value
+      return
+    end
+    local.get $value
+    i32.load
+    i32.const 2
+    i32.eq
+    local.get $value
+    i32.load
+    i32.const 3
+    i32.eq
+    i32.or
+    i32.eqz
+    if
+      local.get $value
+      return
+    end
+    block $done
+      loop $next
+        local.get $i
+        local.get $value
+        i32.load offset=4
+        i32.ge_u
+        br_if $done
+        local.get $value
+        i32.load
+        i32.const 2
+        i32.eq
+        if
+          local.get $value
+          local.get $i
+          i32.const 4
+          i32.mul
+          i32.add
+          i32.const 8
+          i32.add
+          local.set $place
+        else
+          local.get $value
+          local.get $i
+          i32.const 8
+          i32.mul
+          i32.add
+          i32.const 12
+          i32.add
+          local.set $place
+        end
+        local.get $place
+        local.get $place
+        i32.load
+        call $force_all
+        i32.store
+        local.get $i
+        i32.const 1
+        i32.add
+        local.set $i
+        br $next
+      end
+    end
+    local.get $value)
+
+  ;; the program itself
@@ -0,0 +1,1410 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::compiling_process::pattern_compiling::compile_match;
+use crate::compiling_process::primitives::Primitive;
+use crate::compiling_process::static_analysis::classes::elaborate_typed;
+use crate::compiling_process::static_analysis::type_inference::{as_type_definition, Inference, Scheme, Ty, UniverseLevel};
+use crate::compiling_process::translating::closures::{convert_closures, lift};
+use crate::compiling_process::translating::erasure::{self, erased_parameters, Term};
+use crate::inner_representation::abstract_syntax_tree::{
+    is_positional, AtomicType, AtomicValue, Let, Name, Pattern, Type, Value, Visibility,
+};
+use crate::inner_representation::decision_tree::{Case, DecisionTree, Step};
+
+// closures, lazy fields, primitives and printing, written into the generated module as `rt`
+pub const RUNTIME: &str = include_str!("runtime.rs");
+
+// keywords, and the names the generated code uses unqualified
+const RESERVED: &[&str] = &[
+    "Clone", "Self", "String", "_", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+    "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "i32", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "rt", "self",
+    "static", "std", "struct", "super", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Shape {
+    // a variant, one constructor per case of an enum
+    Enum,
+    // a codata record, a struct whose fields are computed when read
+    Struct,
+    // anything else, replaced by its body wherever it is used
+    Alias,
+}
+
+struct TypeDefinition {
+    name: String,
+    parameters: Vec<String>,
+    shape: Shape,
+    body: Ty,
+}
+
+
+// a definition and the Rust function computing it
+struct Global {
+    name: String,
+    // which parameters of the definition erasing types leaves out
+    erased: Vec<bool>,
+    // the parameters of the Rust function
+    arity: usize,
+}
+
+struct Generator<'a> {
+    types: &'a HashMap<String, Scheme>,
+    definitions: HashMap<String, TypeDefinition>,
+    // the type definition each constructor belongs to
+    constructors: HashMap<String, String>,
+    globals: HashMap<String, Global>,
+    // the functions erasing types makes for calls missing type arguments, they have no type and are written where
+    // they are used
+    wrappers: HashMap<String, Term>,
+    // the structs written for records that are not codata, by their sorted field names
+    records: Vec<(Vec<String>, String)>,
+    type_names: HashSet<String>,
+    // the type variables of the definition being written
+    generics: Vec<String>,
+    // the Rust names of the locals in scope
+    variables: Vec<(String, String)>,
+    used: HashSet<String>,
+    unfolding: Vec<String>,
+    // the definition being written and the Rust names of its parameters when it calls itself last, those calls go
+    // back to its start instead
+    looping: Option<(String, Vec<String>)>,
+}
+
+fn identifier(name: &str) -> String {
+    let name: String = name.chars().map(|x| if x.is_ascii_alphanumeric() || x == '_' { x } else { '_' }).collect();
+    if RESERVED.contains(&name.as_str()) || name.is_empty() || name.starts_with(|x: char| x.is_ascii_digit()) {
+        format!("{}_", name)
+    } else {
+        name
+    }
+}
+
+fn string_literal(text: &str) -> String {
+    format!("{:?}", text)
+}
+
+fn int_literal(n: i32) -> String {
+    if n == i32::MIN {
+        "i32::MIN".to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+fn indentation(indent: usize) -> String {
+    "    ".repeat(indent)
+}
+
+// `{ lines; value }` with the value written for `indent + 1`, or just the value
+fn block(lines: &[String], value: String, indent: usize) -> String {
+    if lines.is_empty() {
+        return value;
+    }
+    let mut result = "{\n".to_string();
+    for line in lines {
+        result.push_str(&format!("{}{}\n", indentation(indent + 1), line));
+    }
+    result.push_str(&format!("{}{}\n{}}}", indentation(indent + 1), value, indentation(indent)));
+    result
+}
+
+fn runtime_name(primitive: Primitive) -> &'static str {
+    match primitive {
+        Primitive::Add => "add",
+        Primitive::Subtract => "subtract",
+        Primitive::Multiply => "multiply",
+        Primitive::Divide => "divide",
+        Primitive::Remainder => "remainder",
+        Primitive::Equal => "equal",
+        Primitive::Less => "less",
+        Primitive::LessOrEqual => "less_or_equal",
+        Primitive::Length => "length",
+        Primitive::Concatenate => "concatenate",
+        Primitive::Slice => "slice",
+        Primitive::Show => "show_int",
+    }
+}
+
+fn atomic_type(typ: AtomicType) -> &'static str {
+    match typ {
+        AtomicType::Int => "i32",
+        AtomicType::String => "rt::Text",
+        AtomicType::Top => "rt::Top",
+        AtomicType::Bottom => "()",
+    }
+}
+
+// parameters standing for types, @ or a function giving @
+fn is_kind(ty: &Ty) -> bool {
+    match ty {
+        Ty::Universe(_) => true,
+        Ty::Function(_, result) | Ty::Pi(_, _, _, result) => is_kind(result),
+        _ => false,
+    }
+}
+
+// whether the Rust text mentions the identifier
+fn mentions(text: &str, name: &str) -> bool {
+    text.split(|x: char| !x.is_ascii_alphanumeric() && x != '_').any(|x| x == name)
+}
+
+fn substitute(ty: &Ty, mapping: &HashMap<String, Ty>) -> Ty {
+    let fields = |fields: &[(String, Ty)]| fields.iter().map(|(name, x)| (name.clone(), substitute(x, mapping))).collect();
+    match ty {
+        Ty::Rigid(name) => mapping.get(name).cloned().unwrap_or_else(|| ty.clone()),
+        Ty::Named(name, arguments) => Ty::Named(name.clone(), arguments.iter().map(|x| substitute(x, mapping)).collect()),
+        Ty::Function(from, to) => Ty::Function(Box::new(substitute(from, mapping)), Box::new(substitute(to, mapping))),
+        Ty::Pi(visibility, name, from, to) => {
+            Ty::Pi(*visibility, name.clone(), Box::new(substitute(from, mapping)), Box::new(substitute(to, mapping)))
+        },
+        Ty::Product(x) => Ty::Product(fields(x)),
+        Ty::CoProduct(x) => Ty::CoProduct(fields(x)),
+        Ty::OpenProduct(x, tail) => Ty::OpenProduct(fields(x), tail.clone()),
+        Ty::OpenCoProduct(x, tail) => Ty::OpenCoProduct(fields(x), tail.clone()),
+        _ => ty.clone(),
+    }
+}
+
+// the type of a global as seen by its users, its own type variables unknown to them
+fn instantiate(Scheme(variables, ty): &Scheme) -> Ty {
+    let mut mapping: HashMap<String, Ty> = variables.iter().map(|x| (x.clone(), Ty::Meta(0))).collect();
+    let mut spine = ty;
+    while let Ty::Function(_, to) | Ty::Pi(_, _, _, to) = spine {
+        if let Ty::Pi(_, name, _, _) = spine {
+            mapping.insert(name.clone(), Ty::Meta(0));
+        }
+        spine = to;
+    }
+    substitute(ty, &mapping)
+}
+
+// what of the type of a definition the Rust backend can't write, records with fields it does not know are only given
+// to the definition, where the fields it names are picked out of them, and only the definition takes types
+fn unsupported(ty: &Ty, spine: bool, open: bool) -> Option<&'static str> {
+    match ty {
+        _ if is_kind(ty) => None,
+        Ty::CoProduct(_) | Ty::OpenCoProduct(_, _) => Some("variants that are not named by a type definition"),
+        Ty::OpenProduct(_, _) if !open => Some("records with fields it does not know, other than as parameters of definitions"),
+        Ty::Product(fields) | Ty::OpenProduct(fields, _) => fields.iter().find_map(|(_, x)| unsupported(x, false, false)),
+        Ty::Pi(Visibility::Implicit, _, _, _) | Ty::Pi(Visibility::Instance, _, _, _) if !spine => Some("higher-rank parameters"),
+        Ty::Function(from, _) | Ty::Pi(_, _, from, _) if !spine && is_kind(from) => Some("higher-rank parameters"),
+        Ty::Function(from, to) | Ty::Pi(_, _, from, to) => unsupported(from, false, spine).or(unsupported(to, spine, false)),
+        Ty::Named(_, arguments) => arguments.iter().find_map(|x| unsupported(x, false, false)),
+        _ => None,
+    }
+}
+
+// a definition taking records with any fields is only written for the calls giving them
+fn unapplied(name: &str) -> String {
+    format!("{} takes records with any fields, the Rust backend only writes calls giving them", name)
+}
+
+// the largest tuples Rust clones, and so the largest the runtime prints
+const LARGEST_TUPLE: usize = 12;
+
+fn too_large(items: usize) -> String {
+    format!("tuples of {} items are not supported by the Rust backend, it writes at most {}", items, LARGEST_TUPLE)
+}
+
+// whether a decision tree looks inside the part of the scrutinee at the occurrence
+fn reads(tree: &DecisionTree, occurrence: &[Step]) -> bool {
+    match tree {
+        DecisionTree::Fail => false,
+        DecisionTree::Leaf(_, bindings) => bindings.iter().any(|(_, x)| x.starts_with(occurrence)),
+        DecisionTree::Switch(x, cases, default) => {
+            x.starts_with(occurrence)
+                || cases.iter().any(|(_, tree)| reads(tree, occurrence))
+                || default.as_ref().is_some_and(|tree| reads(tree, occurrence))
+        },
+    }
+}
+
+
+// the locals a term uses without binding them, in order of first use
+fn free_locals(term: &Term, bound: &mut Vec<String>, result: &mut Vec<String>) {
+    let depth = bound.len();
+    match term {
+        Term::Local(name) => {
+            if !bound.contains(name) && !result.contains(name) {
+                result.push(name.clone());
+            }
+        },
+        Term::Call(_, items) | Term::Tuple(items) => items.iter().for_each(|x| free_locals(x, bound, result)),
+        Term::Record(fields) => fields.iter().for_each(|(_, x)| free_locals(x, bound, result)),
+        Term::Either(_, payload) | Term::Lazy(payload) => free_locals(payload, bound, result),
+        Term::Match(scrutinee, arms) => {
+            free_locals(scrutinee, bound, result);
+            for (pattern, arm) in arms {
+                bound.extend(pattern.binders().into_iter().map(|x| x.get_name().to_string()));
+                free_locals(arm, bound, result);
+                bound.truncate(depth);
+            }
+        },
+        Term::Function(parameters, body) => {
+            bound.extend(parameters.iter().cloned());
+            free_locals(body, bound, result);
+        },
+        Term::Apply(function, arguments) => {
+            free_locals(function, bound, result);
+            arguments.iter().for_each(|x| free_locals(x, bound, result));
+        },
+        Term::Let(lets, body) => {
+            for (name, value) in lets {
+                free_locals(value, bound, result);
+                bound.push(name.clone());
+            }
+            free_locals(body, bound, result);
+        },
+        Term::Global(_) | Term::Primitive(_) | Term::Int(_) | Term::Text(_) | Term::Erased | Term::Fail(_) => {},
+    }
+    bound.truncate(depth);
+}
+
+// whether a definition calls itself with all its parameters where nothing is left to do once the call returns
+fn calls_itself_last(term: &Term, name: &str, arity: usize) -> bool {
+    match term {
+        Term::Apply(function, arguments) => matches!(&**function, Term::Global(x) if x == name) && arguments.len() == arity,
+        Term::Match(_, arms) => arms.iter().any(|(_, arm)| calls_itself_last(arm, name, arity)),
+        Term::Let(_, body) => calls_itself_last(body, name, arity),
+        _ => false,
+    }
+}
+
+// what erasing types makes for a call missing type arguments, a function giving its parameters to a definition
+fn is_wrapper(term: &Term) -> bool {
+    match term {
+        Term::Function(_, body) => matches!(&**body, Term::Global(_) | Term::Apply(_, _)),
+        _ => false,
+    }
+}
+
+impl<'a> Generator<'a> {
+    // a Rust name not taken by anything else in the same definition
+    fn fresh(&mut self, name: &str) -> String {
+        let base = identifier(name);
+        let mut result = base.clone();
+        let mut n = 0;
+        while self.used.contains(&result) {
+            n += 1;
+            result = format!("{}_{}", base, n);
+        }
+        self.used.insert(result.clone());
+        result
+    }
+
+    fn declare(&mut self, name: &str) -> String {
+        let result = self.fresh(name);
+        self.variables.push((name.to_string(), result.clone()));
+        result
+    }
+
+    fn type_name(&mut self, name: &str) -> String {
+        let base = identifier(name);
+        let mut result = base.clone();
+        let mut n = 0;
+        while self.type_names.contains(&result) {
+            n += 1;
+            result = format!("{}_{}", base, n);
+        }
+        self.type_names.insert(result.clone());
+        result
+    }
+
+    fn variable(&self, name: &str) -> Option<&String> {
+        self.variables.iter().rev().find(|(x, _)| x == name).map(|(_, variable)| variable)
+    }
+
+    // the type a type definition or an annotation stands for
+    fn convert(&self, typ: &Type) -> Result<Ty, String> {
+        let fields = |fields: &[(Name, Type)]| -> Result<Vec<(String, Ty)>, String> {
+            fields.iter().map(|(name, x)| Ok((name.get_name().to_string(), self.convert(x)?))).collect()
+        };
+        let constructors = |constructors: &[(Name, Box<Type>)]| -> Result<Vec<(String, Ty)>, String> {
+            constructors.iter().map(|(name, x)| Ok((name.get_name().to_string(), self.convert(x)?))).collect()
+        };
+        Ok(match typ {
+            Type::Codata(body) => self.convert(body)?,
+            Type::Product(x) => Ty::Product(fields(x)?),
+            Type::CoProduct(x) => Ty::CoProduct(constructors(x)?),
+            Type::OpenProduct(x, tail) => Ty::OpenProduct(fields(x)?, Box::new(Ty::Rigid(tail.get_name().to_string()))),
+            Type::OpenCoProduct(x, tail) => {
+                Ty::OpenCoProduct(constructors(x)?, Box::new(Ty::Rigid(tail.get_name().to_string())))
+            },
+            Type::Function(from, to) => Ty::Function(Box::new(self.convert(from)?), Box::new(self.convert(to)?)),
+            Type::Pi(visibility, name, from, to) => Ty::Pi(
+                *visibility,
+                name.get_name().to_string(),
+                Box::new(self.convert(from)?),
+                Box::new(self.convert(to)?),
+            ),
+            Type::Application(function, arguments) => {
+                let name = match &**function {
+                    Type::TypeVar(name) if self.definitions.contains_key(name.get_name()) => name.get_name().to_string(),
+                    other => return Err(format!("{} is not a type definition", other)),
+                };
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| match argument {
+                        Value::Type(typ) => self.convert(typ),
+                        Value::Var(name) => self.convert(&Type::TypeVar(name.clone())),
+                        other => Err(format!("types depending on values like {:?} are not supported", other)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ty::Named(name, arguments)
+            },
+            Type::TypeVar(name) if self.definitions.contains_key(name.get_name()) => {
+                Ty::Named(name.get_name().to_string(), vec![])
+            },
+            Type::TypeVar(name) => Ty::Rigid(name.get_name().to_string()),
+            Type::Universe(_) => Ty::Universe(UniverseLevel::constant(0)),
+            Type::Atomic(typ) => Ty::Atomic(*typ),
+            Type::Equal(_, _) => Ty::Equal(Box::new(Ty::Refl), Box::new(Ty::Refl)),
+            Type::Hole(name) => return Err(format!("the type has an unfinished hole ?{}", name)),
+            Type::Class(_) => return Err("classes are not supported by the Rust backend".to_string()),
+        })
+    }
+
+    // the body of a type definition given its arguments
+    fn unfold(&self, name: &str, arguments: &[Ty]) -> Option<Ty> {
+        let definition = self.definitions.get(name)?;
+        let mapping = definition.parameters.iter().cloned().zip(arguments.iter().cloned()).collect();
+        Some(substitute(&definition.body, &mapping))
+    }
+
+    // the Rust type of values of a type, parameters standing for types are erased
+    fn rust_type(&mut self, ty: &Ty) -> Result<String, String> {
+        Ok(match ty {
+            Ty::Atomic(AtomicType::Bottom) => return Err("the empty type has no values to translate".to_string()),
+            Ty::Atomic(typ) => atomic_type(*typ).to_string(),
+            Ty::Rigid(name) if self.generics.contains(name) => identifier(name),
+            Ty::Rigid(name) => return Err(format!("{} is not a type parameter of the definition", name)),
+            // what nothing constrains is left to rustc
+            Ty::Meta(_) => "_".to_string(),
+            Ty::Named(name, arguments) => {
+                let (shape, rust) = match self.definitions.get(name) {
+                    Some(definition) => (definition.shape, definition.name.clone()),
+                    None => return Err(format!("unknown type {}", name)),
+                };
+                if shape == Shape::Alias {
+                    if self.unfolding.contains(name) {
+                        return Err(format!("{} is a recursive record, it has to be codata", name));
+                    }
+                    self.unfolding.push(name.clone());
+                    let body = self.unfold(name, arguments).unwrap();
+                    let result = self.rust_type(&body);
+                    self.unfolding.pop();
+                    return result;
+                }
+                if arguments.is_empty() {
+                    return Ok(rust);
+                }
+                let arguments = arguments.iter().map(|x| self.rust_type(x)).collect::<Result<Vec<_>, _>>()?;
+                format!("{}<{}>", rust, arguments.join(", "))
+            },
+            Ty::Function(from, to) | Ty::Pi(_, _, from, to) if is_kind(from) => self.rust_type(to)?,
+            Ty::Pi(Visibility::Instance, _, _, _) => return Err("classes are not supported by the Rust backend".to_string()),
+            Ty::Pi(Visibility::Implicit, name, _, _) => {
+                return Err(format!("the implicit parameter {} has to be a type", name));
+            },
+            Ty::Function(from, to) | Ty::Pi(_, _, from, to) => {
+                format!("rt::Function<{}, {}>", self.rust_type(from)?, self.rust_type(to)?)
+            },
+            Ty::Product(fields) | Ty::OpenProduct(fields, _) if fields.iter().all(|(name, _)| is_positional(name)) => {
+                let mut items = fields.clone();
+                items.sort_by_key(|(name, _)| name.parse::<usize>().unwrap());
+                if items.len() > LARGEST_TUPLE {
+                    return Err(too_large(items.len()));
+                }
+                let items = items.iter().map(|(_, x)| self.rust_type(x)).collect::<Result<Vec<_>, _>>()?;
+                match items.len() {
+                    0 => "()".to_string(),
+                    1 => format!("({},)", items[0]),
+                    _ => format!("({})", items.join(", ")),
+                }
+            },
+            // a function taking a record with more fields is given exactly the ones it names
+            Ty::Product(fields) | Ty::OpenProduct(fields, _) => {
+                let mut fields = fields.clone();
+                fields.sort_by(|(x, _), (y, _)| x.cmp(y));
+                let name = self.record(fields.iter().map(|(name, _)| name.clone()).collect());
+                let fields = fields.iter().map(|(_, x)| self.rust_type(x)).collect::<Result<Vec<_>, _>>()?;
+                format!("{}<{}>", name, fields.join(", "))
+            },
+            Ty::CoProduct(_) | Ty::OpenCoProduct(_, _) => {
+                return Err(format!("the variant {} has to be named by a type definition", ty));
+            },
+            // types and proofs carry no information
+            Ty::Universe(_) | Ty::Equal(_, _) => "()".to_string(),
+            other => return Err(format!("the type {} cannot be written in Rust", other)),
+        })
+    }
+
+    fn record(&mut self, fields: Vec<String>) -> String {
+        if let Some((_, name)) = self.records.iter().find(|(x, _)| *x == fields) {
+            return name.clone();
+        }
+        let name = self.type_name(&format!("Record_{}", fields.iter().map(|x| identifier(x)).collect::<Vec<_>>().join("_")));
+        self.records.push((fields, name.clone()));
+        name
+    }
+
+    // the codata definition a record is built for and its arguments, others are anonymous records
+    fn nominal(&self, expected: Option<&Ty>) -> Option<(String, Vec<Ty>)> {
+        match expected? {
+            Ty::Named(name, arguments) => match self.definitions.get(name)?.shape {
+                Shape::Struct => Some((name.clone(), arguments.clone())),
+                Shape::Alias if !self.unfolding.contains(name) => self.nominal(self.unfold(name, arguments).as_ref()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn fields(&self, expected: Option<&Ty>) -> Vec<(String, Ty)> {
+        match expected {
+            Some(Ty::Product(fields)) | Some(Ty::OpenProduct(fields, _)) => fields.clone(),
+            Some(Ty::Named(name, arguments)) => match self.definitions.get(name) {
+                Some(definition) if definition.shape != Shape::Enum => self.fields(self.unfold(name, arguments).as_ref()),
+                _ => vec![],
+            },
+            _ => vec![],
+        }
+    }
+
+    // the codata definition a record with these fields is built for, when nothing else tells
+    fn codata(&self, fields: &[&str]) -> Option<String> {
+        let mut fields = fields.to_vec();
+        fields.sort();
+        self.definitions
+            .iter()
+            .filter(|(_, definition)| definition.shape == Shape::Struct)
+            .filter(|(_, definition)| {
+                let mut names: Vec<&str> = match &definition.body {
+                    Ty::Product(x) => x.iter().map(|(name, _)| name.as_str()).collect(),
+                    _ => vec![],
+                };
+                names.sort();
+                names == fields
+            })
+            .map(|(name, _)| name.clone())
+            .min()
+    }
+
+    // the types of the parameters of the Rust function for a global and of what it gives, read off the type of the
+    // global past the parameters erasing types leaves out
+    fn signature(&self, name: &str, ty: &Ty) -> Result<(Vec<Ty>, Ty), String> {
+        let mut ty = ty;
+        let mut parameters = vec![];
+        for erased in &self.globals[name].erased {
+            while let Ty::Pi(Visibility::Implicit, _, _, to) = ty {
+                ty = to;
+            }
+            let (from, to) = match ty {
+                Ty::Function(from, to) | Ty::Pi(_, _, from, to) => (from, to),
+                other => return Err(format!("the type {} of {} does not take all its parameters", other, name)),
+            };
+            if !erased {
+                parameters.push((**from).clone());
+            }
+            ty = to;
+        }
+        Ok((parameters, ty.clone()))
+    }
+
+    // the types of the parameters and of the result of a global where it is used
+    fn instantiated(&self, name: &str) -> Option<(Vec<Ty>, Ty)> {
+        self.signature(name, &instantiate(self.types.get(name)?)).ok()
+    }
+
+    // how many arguments a call to the global has to give for it to be given every record with fields it does not know
+    fn open_parameters(&self, name: &str) -> usize {
+        match self.instantiated(name) {
+            Some((parameters, _)) => parameters.iter().rposition(|x| matches!(x, Ty::OpenProduct(_, _))).map_or(0, |i| i + 1),
+            None => 0,
+        }
+    }
+
+    // the type of what a term computes, known for the definitions given all their parameters
+    fn result_type(&self, term: &Term) -> Option<Ty> {
+        let (name, given) = match term {
+            Term::Global(name) => (name, 0),
+            Term::Apply(function, arguments) => match &**function {
+                Term::Global(name) => (name, arguments.len()),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        match self.globals.get(name) {
+            Some(global) if global.arity == given => self.instantiated(name).map(|(_, result)| result),
+            _ => None,
+        }
+    }
+
+    // a record given where one with some of its fields is expected, it is copied with those only, fields of codata
+    // are read on the way
+    fn projected(&mut self, term: &Term, fields: &[(String, Ty)], indent: usize) -> Result<String, String> {
+        let mut names: Vec<String> = fields.iter().map(|(name, _)| name.clone()).collect();
+        names.sort();
+        let exact = match term {
+            Term::Record(given) => {
+                let mut given: Vec<&str> =
+                    given.iter().filter(|(_, x)| !matches!(x, Term::Lazy(_))).map(|(x, _)| x.as_str()).collect();
+                given.sort();
+                given == names
+            },
+            _ => false,
+        };
+        if exact || names.iter().all(|x| is_positional(x)) {
+            return self.expression(term, Some(&Ty::Product(fields.to_vec())), false, indent);
+        }
+        let code = self.expression(term, None, false, indent)?;
+        let record = self.fresh("record");
+        let copies: Vec<String> =
+            names.iter().map(|x| format!("{}: {}.{}.read()", identifier(x), record, identifier(x))).collect();
+        Ok(format!("{{ let {} = {}; {} {{ {} }} }}", record, code, self.record(names), copies.join(", ")))
+    }
+
+    // a primitive passed around as a value, curried like any other function
+    fn primitive_function(primitive: Primitive) -> String {
+        let (arguments, _) = primitive.signature();
+        let names: Vec<String> = (0..arguments.len()).map(|i| ((b'a' + i as u8) as char).to_string()).collect();
+        let call = names.iter().map(|x| format!("Clone::clone(&{})", x)).collect::<Vec<_>>().join(", ");
+        let call = format!("rt::{}({})", runtime_name(primitive), call);
+        names
+            .iter()
+            .zip(arguments)
+            .rev()
+            .fold(call, |body, (name, typ)| format!("rt::lambda(move |{}: {}| {})", name, atomic_type(typ), body))
+    }
+
+    // the Rust names of the locals a closure takes from the scope, the ones declared from `limit` on are its own
+    fn captures(&self, term: &Term, limit: usize) -> Vec<String> {
+        let mut names = vec![];
+        free_locals(term, &mut vec![], &mut names);
+        let scope = &self.variables[..limit];
+        names
+            .iter()
+            .filter_map(|name| scope.iter().rev().find(|(x, _)| x == name).map(|(_, variable)| variable.clone()))
+            .collect()
+    }
+
+    // closures own copies of what they use, the scope keeps its own
+    fn closure(captures: &[String], code: String) -> String {
+        if captures.is_empty() {
+            return code;
+        }
+        let copies: Vec<String> = captures.iter().map(|x| format!("let {} = Clone::clone(&{});", x, x)).collect();
+        format!("{{ {} {} }}", copies.join(" "), code)
+    }
+
+    fn delayed(&mut self, term: &Term, expected: Option<&Ty>, indent: usize) -> Result<String, String> {
+        let captures = self.captures(term, self.variables.len());
+        let code = self.expression(term, expected, false, indent)?;
+        Ok(Self::closure(&captures, format!("rt::delay(move || {})", code)))
+    }
+
+    // the functions left in definitions once they are lifted are the ones erasing types makes, they only pass their
+    // parameters on
+    fn function(&mut self, parameters: &[String], body: &Term, indent: usize) -> Result<String, String> {
+        let depth = self.variables.len();
+        let mut declared = vec![];
+        for parameter in parameters {
+            let position = self.variables.len();
+            declared.push((position, self.declare(parameter)));
+        }
+        let mut result = self.expression(body, None, false, indent)?;
+        for (i, (position, variable)) in declared.into_iter().enumerate().rev() {
+            let captures = self.captures(&Term::Function(parameters[i..].to_vec(), Box::new(body.clone())), position);
+            result = Self::closure(&captures, format!("rt::lambda(move |{}| {})", variable, result));
+        }
+        self.variables.truncate(depth);
+        Ok(result)
+    }
+
+    fn body(
+        &mut self,
+        lets: &[(String, Term)],
+        body: &Term,
+        expected: Option<&Ty>,
+        tail: bool,
+        indent: usize,
+    ) -> Result<String, String> {
+        let depth = self.variables.len();
+        let mut lines = vec![];
+        for (name, value) in lets {
+            let code = self.expression(value, None, false, indent + 1)?;
+            let variable = self.declare(name);
+            lines.push(format!("let {} = {};", variable, code));
+        }
+        let result = self.expression(body, expected, tail, indent + 1)?;
+        self.variables.truncate(depth);
+        Ok(block(&lines, result, indent))
+    }
+
+    // a definition given some arguments: called once it is given all its Rust function takes, a function taking the
+    // rest before
+    fn global(&mut self, name: &str, arguments: &[Term], tail: bool, indent: usize) -> Result<String, String> {
+        if let Some(wrapper) = self.wrappers.get(name).cloned() {
+            let mut result = self.expression(&wrapper, None, false, indent)?;
+            for argument in arguments {
+                result = format!("rt::call({}, {})", result, self.expression(argument, None, false, indent)?);
+            }
+            return Ok(result);
+        }
+        if arguments.len() < self.open_parameters(name) {
+            return Err(unapplied(name));
+        }
+        let (rust, arity) = (self.globals[name].name.clone(), self.globals[name].arity);
+        let parameters = self.instantiated(name).map(|(parameters, _)| parameters).unwrap_or_default();
+        let mut given = vec![];
+        for (i, argument) in arguments.iter().enumerate() {
+            given.push(match parameters.get(i).filter(|_| i < arity) {
+                Some(Ty::OpenProduct(fields, _)) => self.projected(argument, &fields.clone(), indent)?,
+                ty => self.expression(argument, ty.cloned().as_ref(), false, indent)?,
+            });
+        }
+        // the definition being written calling itself last starts again with the arguments as its parameters
+        if let Some((_, variables)) = self.looping.clone().filter(|(x, _)| tail && x == name && given.len() == arity) {
+            let mut lines = vec![];
+            let mut assignments = vec![];
+            for (variable, code) in variables.iter().zip(given) {
+                let next = self.fresh(&format!("next_{}", variable));
+                lines.push(format!("let {} = {};", next, code));
+                assignments.push(format!("{} = {};", variable, next));
+            }
+            lines.extend(assignments);
+            return Ok(block(&lines, "continue".to_string(), indent));
+        }
+        if given.len() < arity {
+            let written: Vec<Option<String>> =
+                parameters[given.len()..].iter().map(|x| self.rust_type(x).ok().filter(|x| x != "_")).collect();
+            return Ok(self.curried(&rust, given, &written, indent));
+        }
+        let rest = given.split_off(arity);
+        let mut result = format!("{}({})", rust, given.join(", "));
+        for argument in rest {
+            result = format!("rt::call({}, {})", result, argument);
+        }
+        Ok(result)
+    }
+
+    // a Rust function given some of its arguments, as closures taking the others with their types when they are known
+    fn curried(&mut self, rust: &str, given: Vec<String>, parameters: &[Option<String>], indent: usize) -> String {
+        let mut lines = vec![];
+        let mut captures = vec![];
+        for code in given {
+            let variable = self.fresh("argument");
+            lines.push(format!("let {} = {};", variable, code));
+            captures.push(variable);
+        }
+        let names: Vec<String> = parameters.iter().map(|_| self.fresh("parameter")).collect();
+        let all: Vec<String> = captures.iter().chain(&names).map(|x| format!("Clone::clone(&{})", x)).collect();
+        let mut result = format!("{}({})", rust, all.join(", "));
+        for (i, (name, written)) in names.iter().zip(parameters).enumerate().rev() {
+            let parameter = match written {
+                Some(written) => format!("{}: {}", name, written),
+                None => name.clone(),
+            };
+            let code = format!("rt::lambda(move |{}| {})", parameter, result);
+            // the outer closure owns the arguments, the ones inside it take copies
+            result = match i {
+                0 => code,
+                _ => Self::closure(&[captures.clone(), names[..i].to_vec()].concat(), code),
+            };
+        }
+        block(&lines, result, indent)
+    }
+
+    fn expression(&mut self, term: &Term, expected: Option<&Ty>, tail: bool, indent: usize) -> Result<String, String> {
+        // anything can be given where `.` is expected, it is kept to be printed
+        let top = Some(Ty::Atomic(AtomicType::Top));
+        if expected == top.as_ref() && self.result_type(term) != top {
+            // nothing else tells rustc what the parameters of a constructor's type are
+            let ty = match term {
+                Term::Either(constructor, _) => self.constructors.get(constructor).map(|name| {
+                    Ty::Named(name.clone(), vec![Ty::Atomic(AtomicType::Top); self.definitions[name].parameters.len()])
+                }),
+                _ => None,
+            };
+            let code = self.expression(term, ty.as_ref(), false, indent)?;
+            return Ok(match ty {
+                Some(ty) => format!("rt::top::<{}>({})", self.rust_type(&ty)?, code),
+                None => format!("rt::top({})", code),
+            });
+        }
+        Ok(match term {
+            Term::Local(name) => match self.variable(name) {
+                Some(variable) => format!("Clone::clone(&{})", variable),
+                None => return Err(format!("the local {} is not in scope", name)),
+            },
+            Term::Global(name) => self.global(name, &[], tail, indent)?,
+            Term::Primitive(primitive) => Self::primitive_function(*primitive),
+            Term::Call(primitive, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|x| self.expression(x, None, false, indent))
+                    .collect::<Result<Vec<_>, _>>()?;
+                format!("rt::{}({})", runtime_name(*primitive), arguments.join(", "))
+            },
+            Term::Int(n) => int_literal(*n),
+            Term::Text(text) => format!("rt::text({})", string_literal(text)),
+            Term::Tuple(items) if items.is_empty() => "()".to_string(),
+            Term::Tuple(items) if items.len() > LARGEST_TUPLE => return Err(too_large(items.len())),
+            Term::Tuple(items) => {
+                let fields = self.fields(expected);
+                let items = items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| {
+                        let ty = fields.iter().find(|(name, _)| *name == i.to_string()).map(|(_, ty)| ty.clone());
+                        self.expression(x, ty.as_ref(), false, indent)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if items.len() == 1 {
+                    format!("({},)", items[0])
+                } else {
+                    format!("({})", items.join(", "))
+                }
+            },
+            // the fields of codata are delayed, its records are built as the struct of its definition
+            Term::Record(fields) => {
+                let names: Vec<&str> = fields.iter().map(|(name, _)| name.as_str()).collect();
+                let codata = match fields.iter().all(|(_, x)| matches!(x, Term::Lazy(_))) && !fields.is_empty() {
+                    true => self.nominal(expected).map(|(name, _)| name).or_else(|| self.codata(&names)),
+                    false => None,
+                };
+                let (name, extra) = match codata {
+                    Some(name) => {
+                        let rust = self.definitions[&name].name.clone();
+                        let phantom = self.has_phantom(&name)?;
+                        (rust, if phantom { ", _phantom: std::marker::PhantomData" } else { "" })
+                    },
+                    None => {
+                        let mut names: Vec<String> = names.iter().map(|x| x.to_string()).collect();
+                        names.sort();
+                        (self.record(names), "")
+                    },
+                };
+                let types = self.fields(expected);
+                let fields = fields
+                    .iter()
+                    .map(|(field, x)| {
+                        let ty = types.iter().find(|(name, _)| name == field).map(|(_, ty)| ty.clone());
+                        Ok(format!("{}: {}", identifier(field), self.expression(x, ty.as_ref(), false, indent)?))
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                format!("{} {{ {}{} }}", name, fields.join(", "), extra)
+            },
+            Term::Either(constructor, payload) => {
+                let definition = self
+                    .constructors
+                    .get(constructor)
+                    .ok_or_else(|| format!("{} is not a constructor of a type definition", constructor))?
+                    .clone();
+                // without an expected type the arguments of the definition are left unknown
+                let arguments = match expected {
+                    Some(Ty::Named(name, arguments)) if *name == definition => arguments.clone(),
+                    _ => vec![Ty::Meta(0); self.definitions[&definition].parameters.len()],
+                };
+                let ty = match self.unfold(&definition, &arguments) {
+                    Some(Ty::CoProduct(constructors)) => {
+                        constructors.into_iter().find(|(x, _)| x == constructor).map(|(_, ty)| ty)
+                    },
+                    _ => None,
+                };
+                let payload = self.expression(payload, ty.as_ref(), false, indent)?;
+                let name = &self.definitions[&definition].name;
+                format!("{}::{}(rt::Rc::new({}))", name, identifier(constructor), payload)
+            },
+            Term::Lazy(term) => self.delayed(term, expected, indent)?,
+            Term::Match(scrutinee, arms) => self.matching(scrutinee, arms, expected, tail, indent)?,
+            Term::Function(parameters, body) => self.function(parameters, body, indent)?,
+            Term::Apply(function, arguments) => match &**function {
+                Term::Global(name) => self.global(name, arguments, tail, indent)?,
+                function => {
+                    let mut result = self.expression(function, None, false, indent)?;
+                    for argument in arguments {
+                        result = format!("rt::call({}, {})", result, self.expression(argument, None, false, indent)?);
+                    }
+                    result
+                },
+            },
+            Term::Let(lets, body) => self.body(lets, body, expected, tail, indent)?,
+            Term::Erased => "()".to_string(),
+            Term::Fail(message) => format!("rt::fail({})", string_literal(message)),
+        })
+    }
+
+    // whether a codata definition has parameters its fields do not mention, its structs then carry a marker
+    fn has_phantom(&mut self, name: &str) -> Result<bool, String> {
+        let (parameters, body) = match self.definitions.get(name) {
+            Some(definition) => (definition.parameters.clone(), definition.body.clone()),
+            None => return Ok(false),
+        };
+        let generics = std::mem::replace(&mut self.generics, parameters);
+        let written = self.fields(Some(&body)).iter().map(|(_, x)| self.rust_type(x)).collect::<Result<Vec<_>, _>>();
+        let result = written.map(|x| self.phantom(&x).is_some());
+        self.generics = generics;
+        result
+    }
+
+    fn matching(
+        &mut self,
+        scrutinee: &Term,
+        arms: &[(Pattern, Term)],
+        expected: Option<&Ty>,
+        tail: bool,
+        indent: usize,
+    ) -> Result<String, String> {
+        let value = self.expression(scrutinee, None, false, indent + 1)?;
+        let root = self.fresh("scrutinee");
+        let patterns: Vec<&Pattern> = arms.iter().map(|(pattern, _)| pattern).collect();
+        let tree = compile_match(&patterns)?;
+        let code = self.tree(&tree, arms, vec![(vec![], root.clone())], expected, tail, indent + 1)?;
+        Ok(block(&[format!("let {} = {};", root, value)], code, indent))
+    }
+
+    // the variable holding a part of the scrutinee, read into one when it is first needed
+    fn place(
+        &mut self,
+        occurrence: &[Step],
+        places: &mut Vec<(Vec<Step>, String)>,
+        lines: &mut Vec<String>,
+    ) -> Result<String, String> {
+        if let Some((_, place)) = places.iter().find(|(x, _)| x == occurrence) {
+            return Ok(place.clone());
+        }
+        let (last, parent) = occurrence.split_last().ok_or("the scrutinee has no variable")?;
+        let parent = self.place(parent, places, lines)?;
+        let (place, value) = match last {
+            Step::Index(i) => (self.fresh("item"), format!("Clone::clone(&{}.{})", parent, i)),
+            Step::Field(name) => (self.fresh(name), format!("{}.{}.read()", parent, identifier(name))),
+            Step::Payload => return Err("a payload is read before its constructor is known".to_string()),
+        };
+        lines.push(format!("let {} = {};", place, value));
+        places.push((occurrence.to_vec(), place.clone()));
+        Ok(place)
+    }
+
+    fn tree(
+        &mut self,
+        tree: &DecisionTree,
+        arms: &[(Pattern, Term)],
+        mut places: Vec<(Vec<Step>, String)>,
+        expected: Option<&Ty>,
+        tail: bool,
+        indent: usize,
+    ) -> Result<String, String> {
+        let root = places[0].1.clone();
+        let mut lines = vec![];
+        Ok(match tree {
+            DecisionTree::Fail => format!("rt::no_match(&{})", root),
+            DecisionTree::Leaf(arm, bindings) => {
+                let depth = self.variables.len();
+                for (name, occurrence) in bindings {
+                    let place = self.place(occurrence, &mut places, &mut lines)?;
+                    let variable = self.declare(name.get_name());
+                    lines.push(format!("let {} = Clone::clone(&{});", variable, place));
+                }
+                let inner = if lines.is_empty() { indent } else { indent + 1 };
+                let value = self.expression(&arms[*arm].1, expected, tail, inner)?;
+                self.variables.truncate(depth);
+                block(&lines, value, indent)
+            },
+            DecisionTree::Switch(occurrence, cases, default) => {
+                let place = self.place(occurrence, &mut places, &mut lines)?;
+                let inner = if lines.is_empty() { indent } else { indent + 1 };
+                let prefix = indentation(inner + 1);
+                let subject = match cases.first() {
+                    Some((Case::Constructor(_), _)) => format!("&{}", place),
+                    Some((Case::Literal(AtomicValue::StringLiteral(_)), _)) => format!("&*{}", place),
+                    _ => place.clone(),
+                };
+                let mut result = format!("match {} {{\n", subject);
+                for (case, tree) in cases {
+                    let code = match case {
+                        // the payload is matched by reference and copied out of its Rc
+                        Case::Constructor(name) => {
+                            let definition = self
+                                .constructors
+                                .get(name)
+                                .ok_or_else(|| format!("{} is not a constructor of a type definition", name))?;
+                            let enumeration = self.definitions[definition].name.clone();
+                            let mut occurrence = occurrence.clone();
+                            occurrence.push(Step::Payload);
+                            if !reads(tree, &occurrence) {
+                                let code = self.tree(tree, arms, places.clone(), expected, tail, inner + 1)?;
+                                format!("{}::{}(_) => {}", enumeration, identifier(name), code)
+                            } else {
+                                let (reference, payload) = (self.fresh("reference"), self.fresh("payload"));
+                                let mut places = places.clone();
+                                places.push((occurrence, payload.clone()));
+                                let copy = format!("let {} = Clone::clone(&**{});", payload, reference);
+                                let code = self.tree(tree, arms, places, expected, tail, inner + 2)?;
+                                let pattern = format!("{}::{}({})", enumeration, identifier(name), reference);
+                                format!("{} => {}", pattern, block(&[copy], code, inner + 1))
+                            }
+                        },
+                        Case::Literal(literal) => {
+                            let pattern = match literal {
+                                AtomicValue::Int(n) => int_literal(*n),
+                                AtomicValue::StringLiteral(text) => string_literal(text),
+                            };
+                            format!("{} => {}", pattern, self.tree(tree, arms, places.clone(), expected, tail, inner + 1)?)
+                        },
+                    };
+                    result.push_str(&format!("{}{},\n", prefix, code));
+                }
+                let default = match default {
+                    Some(tree) => self.tree(tree, arms, places.clone(), expected, tail, inner + 1)?,
+                    None => format!("rt::no_match(&{})", root),
+                };
+                result.push_str(&format!("{}_ => {},\n{}}}", prefix, default, indentation(inner)));
+                block(&lines, result, indent)
+            },
+        })
+    }
+
+    fn parameters(&self, bound: bool) -> String {
+        if self.generics.is_empty() {
+            return String::new();
+        }
+        let parameters: Vec<String> = self
+            .generics
+            .iter()
+            .map(|x| if bound { format!("{}: rt::Value", identifier(x)) } else { identifier(x) })
+            .collect();
+        format!("<{}>", parameters.join(", "))
+    }
+
+    // the marker for parameters of a type definition none of its parts mention
+    fn phantom(&self, written: &[String]) -> Option<String> {
+        let written = written.join(", ");
+        if self.generics.iter().all(|x| mentions(&written, &identifier(x))) {
+            return None;
+        }
+        let parameters: Vec<String> = self.generics.iter().map(|x| identifier(x)).collect();
+        Some(format!("std::marker::PhantomData<({},)>", parameters.join(", ")))
+    }
+
+    fn enumeration(&mut self, name: &str) -> Result<String, String> {
+        let definition = &self.definitions[name];
+        let (rust, constructors) = match &definition.body {
+            Ty::CoProduct(constructors) => (definition.name.clone(), constructors.clone()),
+            _ => return Err(format!("{} is not a variant", name)),
+        };
+        self.generics = definition.parameters.clone();
+        let payloads = constructors.iter().map(|(_, x)| self.rust_type(x)).collect::<Result<Vec<_>, _>>()?;
+        let mut result = format!("#[derive(Clone)]\npub enum {}{} {{\n", rust, self.parameters(false));
+        let mut arms = String::new();
+        for ((constructor, _), payload) in constructors.iter().zip(&payloads) {
+            let variant = identifier(constructor);
+            result.push_str(&format!("    {}(rt::Rc<{}>),\n", variant, payload));
+            arms.push_str(&format!(
+                "            {}::{}(payload) => rt::write_either(out, {}, &**payload),\n",
+                rust, variant, string_literal(constructor)
+            ));
+        }
+        if let Some(phantom) = self.phantom(&payloads) {
+            result.push_str(&format!("    _Phantom({}),\n", phantom));
+            arms.push_str(&format!("            {}::_Phantom(_) => unreachable!(),\n", rust));
+        }
+        result.push_str(&format!(
+            "}}\n\nimpl{} rt::Show for {}{} {{\n    fn write(&self, out: &mut String) {{\n        match self {{\n{}",
+            self.parameters(true), rust, self.parameters(false), arms
+        ));
+        result.push_str("        }\n    }\n\n    fn is_either(&self) -> bool {\n        true\n    }\n}\n");
+        Ok(result)
+    }
+
+    // the struct of a codata definition, its fields computed when they are first read, or of the records with the
+    // same fields
+    fn structure(&mut self, rust: &str, fields: &[(String, Ty)], lazy: bool) -> Result<String, String> {
+        let written = fields.iter().map(|(_, x)| self.rust_type(x)).collect::<Result<Vec<_>, _>>()?;
+        let mut result = format!("#[derive(Clone)]\npub struct {}{} {{\n", rust, self.parameters(false));
+        let mut lines = String::new();
+        for (i, ((field, _), typ)) in fields.iter().zip(&written).enumerate() {
+            let typ = if lazy { format!("rt::Lazy<{}>", typ) } else { typ.clone() };
+            result.push_str(&format!("    pub {}: {},\n", identifier(field), typ));
+            lines.push_str(&format!(
+                "        rt::write_field(out, {}, {}, &self.{});\n",
+                i == 0, string_literal(field), identifier(field)
+            ));
+        }
+        if let Some(phantom) = self.phantom(&written) {
+            result.push_str(&format!("    pub _phantom: {},\n", phantom));
+        }
+        result.push_str(&format!(
+            "}}\n\nimpl{} rt::Show for {}{} {{\n    fn write(&self, out: &mut String) {{\n        out.push('{{');\n{}",
+            self.parameters(true), rust, self.parameters(false), lines
+        ));
+        result.push_str("        out.push('}');\n    }\n}\n");
+        Ok(result)
+    }
+
+    fn definition(&mut self, name: &str, term: &Term) -> Result<String, String> {
+        let Scheme(variables, ty) = self.types.get(name).ok_or_else(|| format!("no type was inferred for {}", name))?;
+        // the type variables of the definition and the types it takes become its Rust type parameters
+        let mut generics = variables.clone();
+        let mut spine = ty;
+        while let Ty::Function(_, to) | Ty::Pi(_, _, _, to) = spine {
+            if let Ty::Pi(_, binder, from, _) = spine {
+                if is_kind(from) {
+                    generics.push(binder.clone());
+                }
+            }
+            spine = to;
+        }
+        self.generics = generics;
+        let (parameters, result) = self.signature(name, ty)?;
+        let types = parameters.iter().map(|x| self.rust_type(x)).collect::<Result<Vec<_>, _>>()?;
+        let written = self.rust_type(&result)?;
+        // the others could not be inferred where the definition is used
+        let mentioned = format!("{} {}", types.join(", "), written);
+        self.generics.retain(|x| mentions(&mentioned, &identifier(x)));
+        let (names, body) = match term {
+            Term::Function(names, body) => (names.clone(), &**body),
+            term => (vec![], term),
+        };
+        if names.len() != parameters.len() {
+            return Err(format!("its type gives {} parameters where it has {}", parameters.len(), names.len()));
+        }
+        let variables: Vec<String> = names.iter().map(|x| self.declare(x)).collect();
+        let looping = calls_itself_last(body, name, variables.len());
+        self.looping = if looping { Some((name.to_string(), variables.clone())) } else { None };
+        let code = self.expression(body, Some(&result), true, if looping { 2 } else { 1 })?;
+        let code = if looping { format!("loop {{\n        return {};\n    }}", code) } else { code };
+        let declared: Vec<String> = variables
+            .iter()
+            .zip(&types)
+            .map(|(x, typ)| format!("{}{}: {}", if looping { "mut " } else { "" }, x, typ))
+            .collect();
+        let global = &self.globals[name].name;
+        Ok(format!("pub fn {}{}({}) -> {} {{\n    {}\n}}\n", global, self.parameters(true), declared.join(", "), written, code))
+    }
+}
+
+fn shape(name: &Name, typ: &Type) -> Result<Shape, String> {
+    Ok(match typ {
+        Type::Codata(body) if matches!(&**body, Type::Product(_)) => Shape::Struct,
+        Type::Codata(_) => return Err(format!("{} is codata that is not a record, the Rust backend only has lazy records", name)),
+        Type::CoProduct(_) => Shape::Enum,
+        Type::Class(_) => return Err("classes are not supported by the Rust backend".to_string()),
+        _ => Shape::Alias,
+    })
+}
+// the elaborated program with its local functions lifted to definitions, and the types of all its definitions: it is
+// checked as it is written, then again once they are lifted with the second checker, which takes their recursion on
+// trust as it was checked already
+pub fn typed(inference: Inference, lifted: Inference, program: &[Let]) -> Result<(Vec<Let>, HashMap<String, Scheme>), String> {
+    let mut inference = inference;
+    inference.infer_program(program)?;
+    let program = convert_closures(program);
+    let names = program.iter().map(|Let(name, _, _)| name.get_name().to_string()).collect();
+    elaborate_typed(lifted.terminating(names), &program)
+}
+
+// the program as a Rust module with its runtime, every definition a function computing its value from its parameters,
+// types erased and parameterised types generic, the program is the one `typed` gives
+pub fn emit_rust(program: &[Let], types: &HashMap<String, Scheme>) -> Result<String, String> {
+    let lifted = lift(program)?;
+    let mut generator = Generator {
+        types,
+        definitions: HashMap::new(),
+        constructors: HashMap::new(),
+        globals: HashMap::new(),
+        wrappers: HashMap::new(),
+        records: vec![],
+        type_names: HashSet::new(),
+        generics: vec![],
+        variables: vec![],
+        used: HashSet::new(),
+        unfolding: vec![],
+        looping: None,
+    };
+    let mut erased = HashMap::new();
+    for Let(name, value, annotation) in program {
+        match as_type_definition(value) {
+            Some((parameters, typ)) => {
+                if let Value::Function(parameters, _) = value {
+                    if parameters.iter().any(|(_, typ)| typ.as_ref().is_some_and(|x| !erasure::is_kind(x))) {
+                        return Err(format!("in {}: types taking values are not supported by the Rust backend", name));
+                    }
+                }
+                let definition = TypeDefinition {
+                    name: generator.type_name(name.get_name()),
+                    parameters,
+                    shape: shape(name, typ)?,
+                    body: Ty::Product(vec![]),
+                };
+                generator.definitions.insert(name.get_name().to_string(), definition);
+            },
+            None => {
+                let parameters = match value {
+                    Value::Function(parameters, _) => erased_parameters(parameters, annotation.as_ref()),
+                    _ => vec![],
+                };
+                erased.insert(name.get_name(), parameters.iter().map(Option::is_some).collect::<Vec<_>>());
+            },
+        }
+    }
+    for (name, term) in &lifted.definitions {
+        if types.contains_key(name) {
+            let global = Global {
+                name: generator.fresh(name),
+                erased: erased.get(name.as_str()).cloned().unwrap_or_default(),
+                arity: if let Term::Function(parameters, _) = term { parameters.len() } else { 0 },
+            };
+            generator.globals.insert(name.clone(), global);
+        } else if is_wrapper(term) {
+            generator.wrappers.insert(name.clone(), term.clone());
+        } else {
+            return Err(format!("in {}: there is no type for it, local functions have to be lifted before checking", name));
+        }
+    }
+    for Let(name, value, _) in program {
+        if let Some((_, typ)) = as_type_definition(value) {
+            let body = generator.convert(typ).map_err(|error| format!("in {}: {}", name, error))?;
+            if let Ty::CoProduct(constructors) = &body {
+                for (constructor, _) in constructors {
+                    generator.constructors.insert(constructor.clone(), name.get_name().to_string());
+                }
+            }
+            generator.definitions.get_mut(name.get_name()).unwrap().body = body;
+        }
+    }
+    for Let(name, value, _) in program {
+        let what = match types.get(name.get_name()) {
+            Some(Scheme(_, ty)) if as_type_definition(value).is_none() => unsupported(ty, true, false),
+            _ => None,
+        };
+        if let Some(what) = what {
+            return Err(format!("in {}: the Rust backend does not support {}", name, what));
+        }
+    }
+    let mut items = vec![];
+    for Let(name, _, _) in program {
+        let item = match generator.definitions.get(name.get_name()) {
+            Some(definition) if definition.shape == Shape::Enum => generator.enumeration(name.get_name()),
+            Some(definition) if definition.shape == Shape::Struct => {
+                let (rust, body) = (definition.name.clone(), definition.body.clone());
+                generator.generics = definition.parameters.clone();
+                match body {
+                    Ty::Product(fields) => generator.structure(&rust, &fields, true),
+                    _ => Err(format!("{} is not a record", name)),
+                }
+            },
+            _ => continue,
+        };
+        items.push(item.map_err(|error| format!("in {}: {}", name, error))?);
+    }
+    let names = generator.used.clone();
+    let mut functions = vec![];
+    for (name, term) in &lifted.definitions {
+        if generator.globals.contains_key(name) {
+            generator.used = names.clone();
+            generator.variables.clear();
+            functions.push(generator.definition(name, term).map_err(|error| format!("in {}: {}", name, error))?);
+        }
+    }
+    for (fields, rust) in generator.records.clone() {
+        generator.generics = (0..fields.len()).map(|i| format!("T{}", i)).collect();
+        let fields: Vec<(String, Ty)> = fields.into_iter().zip(generator.generics.iter().map(|x| Ty::Rigid(x.clone()))).collect();
+        items.push(generator.structure(&rust, &fields, false)?);
+    }
+    let mut result = "// generated from a checked program, every definition is a function computing its value,\n\
+        // `rt::show(&main())` is what running the program prints\n\
+        #![allow(dead_code, non_camel_case_types, non_snake_case, unused, unreachable_patterns, clippy::all)]\n\n\
+        pub mod rt {\n"
+        .to_string();
+    for line in RUNTIME.lines() {
+        result.push_str(&if line.is_empty() { "\n".to_string() } else { format!("    {}\n", line) });
+    }
+    result.push_str("}\n\n// fields of records are read with `read`, the ones of codata computed when they are first read\n");
+    result.push_str("use self::rt::Read;\n");
+    for item in items.into_iter().chain(functions) {
+        result.push('\n');
+        result.push_str(&item);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod rust_tests {
+    use std::fs;
+    use std::process::Command;
+
+    use crate::compiling_process::static_analysis::type_inference::Inference;
+    use crate::compiling_process::translating::testing::{directory, interpret, program, run, succeeds};
+    use crate::inner_representation::abstract_syntax_tree::Let;
+    use super::{emit_rust, identifier, typed};
+
+    // `count`, `sum` and the local function of `replicate` count down, the checker is told they terminate
+    fn emit(program: &[Let]) -> Result<String, String> {
+        let trusted = ["count", "sum", "replicate"].iter().map(|x| x.to_string()).collect();
+        let inference = Inference::new().terminating(trusted);
+        let (program, types) = typed(inference, Inference::new(), program)?;
+        emit_rust(&program, &types)
+    }
+
+    // what the compiled program prints, or its error
+    fn compile(name: &str, program: &[Let]) -> Result<String, String> {
+        let directory = directory("rust").join(name);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("program.rs"), emit(program).unwrap()).unwrap();
+        let main = directory.join("main.rs");
+        let wrapper = "mod program;\nfn main() {\n    println!(\"{}\", program::rt::show(&program::main()));\n}\n";
+        fs::write(&main, wrapper).unwrap();
+        let executable = directory.join("main");
+        succeeds(Command::new("rustc").args(["--edition", "2021", "-O", "-o"]).arg(&executable).arg(&main));
+        run(&mut Command::new(&executable))
+    }
+
+    fn values() -> Vec<Let> {
+        program("
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $sum: List Int -> Int = xs ~> foldr (x n ~> #add x n) 0 xs;
+            $point = {x = 1, y = /two/};
+            $swap = p ~> p | {x, y} -> {x = y, y = x};
+            $classify = n ~> n | (0 | 1) -> /small/ | 2 -> /two/ | _ -> /big/;
+            $words = s ~> s | /one/ -> 1 | /two/ -> 2 | _ -> 0;
+            $type = x ~> $y = #add x 1; $z: Int * String = (y, /s/); z;
+            $main = (
+                sum (map (x ~> #mul x 10) numbers),
+                #concat (#show (#div -7 2)) (#slice /héllo/ 1 4),
+                swap point,
+                map classify (Cons (0, Cons (2, Cons (7, Nil)))),
+                (words /two/, words /three/, type 4, fst (pair 1 /a/)),
+                (#add 2147483647 1, #div -2147483648 -1, Just (Just refl), #add 1, #length /é/)
+            );
+        ")
+    }
+
+    fn codata() -> Vec<Let> {
+        program("
+            $Stream = A ~> codata head A * tail (Stream A);
+            $Tagged = A ~> codata tag Int;
+            $from: Int -> Stream Int = n ~> {head = n, tail = from (#add n 1)};
+            $take: {A : @} -> Int -> Stream A -> List A = n s ~> #le n 0
+                | 1 -> Nil
+                | _ -> (s | {head, tail} -> Cons (head, take (#sub n 1) tail));
+            $tagged: Tagged String = {tag = 3};
+            $count = n acc ~> n | 0 -> acc | _ -> count (#sub n 1) (#add acc 2);
+            $sum = n acc ~> n | 0 -> acc | _ -> sum (#sub n 1) (#add acc n);
+            $main = (take 3 (from 10), tagged, count 10000 0, sum 1000000 0);
+        ")
+    }
+
+    fn erased() -> Vec<Let> {
+        program("
+            $identity = (A : @) (x : A) ~> x;
+            $twice = (F : @ -> @) (A : @) (f : A -> A) (x : A) ~> f (f x);
+            $Id = A ~> A;
+            $main = (identity Int 3, twice Id Int (x ~> #add x 1) 5, identity (List Int) Nil);
+        ")
+    }
+
+    fn extensions() -> Vec<Let> {
+        program("
+            $getX: {r : @} -> x Int * ..r -> Int = p ~> p | {x} -> x;
+            $getY = p ~> p | {y} -> y;
+            $ignore: . -> Int = v ~> 1;
+            $anything: . = 3;
+            $Eq: @ -> @ = A ~> class eq (A -> A -> Int);
+            $eqInt: Eq Int = {eq = x y ~> #sub x y};
+            $eqText: Eq String = {eq = x y ~> #sub (#length x) (#length y)};
+            $same: {A : @} -> [d : Eq A] -> A -> A -> Int = x y ~> eq x y;
+            $both = n ~> $id = x ~> x; $add = x ~> #add x n; (id n, id /a/, map add (Cons (1, Nil)));
+            $total = xs ~> $go = ys ~> ys | Nil -> 0 | Cons (y, rest) -> #add y (go rest); go xs;
+            $main = (
+                (getX {x = 1, y = /a/}, getY {y = 2, z = 3}),
+                (ignore 5, ignore (#add 1 2), ignore Nil, anything),
+                (same 1 2, eq /abc/ /a/),
+                both 10,
+                total (Cons (1, Cons (2, Nil)))
+            );
+        ")
+    }
+
+    #[test]
+    fn unit_tests() {
+        assert_eq!(identifier("type"), "type_");
+        assert_eq!(identifier("x'"), "x_");
+        assert_eq!(identifier("Clone"), "Clone_");
+
+        let source = emit(&values()).unwrap();
+        assert!(source.contains("pub enum List<A> {\n    Nil(rt::Rc<rt::Top>),\n    Cons(rt::Rc<(A, List<A>)>),\n}"));
+        assert!(source.contains("pub struct Record_x_y<T0, T1> {\n    pub x: T0,\n    pub y: T1,\n}"));
+        assert!(source.contains("pub fn type_(x: i32) -> (i32, rt::Text) {"));
+    }
+
+    #[test]
+    fn lazy_records() {
+        let source = emit(&codata()).unwrap();
+        assert!(source.contains("pub struct Stream<A> {\n    pub head: rt::Lazy<A>,\n    pub tail: rt::Lazy<Stream<A>>,\n}"));
+        assert!(source.contains("pub _phantom: std::marker::PhantomData<(A,)>,"));
+        // calls a definition makes to itself last go back to its start
+        assert!(source.contains("pub fn sum(mut n: i32, mut acc: i32) -> i32 {\n    loop {\n        return {"));
+        let unsupported = self::program("$Colour = codata Red . + Green .; $main = 1;");
+        let error = "Colour is codata that is not a record, the Rust backend only has lazy records";
+        assert_eq!(emit(&unsupported).unwrap_err(), error);
+    }
+
+    #[test]
+    fn erasure() {
+        let source = emit(&erased()).unwrap();
+        assert!(source.contains("pub fn identity<A: rt::Value>(x: A) -> A {\n    Clone::clone(&x)\n}"));
+        assert!(source.contains("pub fn twice<A: rt::Value>(f: rt::Function<A, A>, x: A) -> A {"));
+        assert!(source.contains("identity(3)"));
+        assert!(!source.contains("Id"));
+    }
+
+    #[test]
+    fn extensions_and_limits() {
+        let source = emit(&extensions()).unwrap();
+        assert!(source.contains("pub fn anything() -> rt::Top {\n    rt::top(3)\n}"));
+        assert!(source.contains("Record_x { x: record.x.read() }"));
+        // local functions are definitions of their own, generic when they are used at several types
+        assert!(source.contains("pub fn both_1<"));
+        assert!(source.contains("pub fn total_1(ys: List<i32>) -> i32 {"));
+
+        let rejected = |text: &str| emit(&self::program(text)).unwrap_err();
+        let variants = "in area: the Rust backend does not support variants that are not named by a type definition";
+        assert_eq!(rejected("$area = s ~> s | Circle r -> r | Square a -> #mul a a; $main = area (Square 3);"), variants);
+        let rank = "in apply: the Rust backend does not support higher-rank parameters";
+        assert_eq!(rejected("$apply: ((A : @) -> A -> A) -> Int = f ~> f Int 1; $main = apply (A x ~> x);"), rank);
+        let open = "in widen: the Rust backend does not support records with fields it does not know, other than as parameters \
+            of definitions";
+        assert_eq!(rejected("$widen = p ~> p | {x} -> p; $main = widen {x = 1};"), open);
+        let unapplied = "in main: getX takes records with any fields, the Rust backend only writes calls giving them";
+        assert_eq!(rejected("$getX = p ~> p | {x} -> x; $main = map getX Nil;"), unapplied);
+        let large = "in main: tuples of 13 items are not supported by the Rust backend, it writes at most 12";
+        assert_eq!(rejected("$main = (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13);"), large);
+    }
+
+    #[test]
+    #[ignore = "runs rustc"]
+    fn compiled() {
+        let program = values();
+        assert_eq!(compile("unit_tests", &program), interpret(&program, "main"));
+        let streams = "(Cons (10, Cons (11, Cons (12, Nil ()))), {tag = 3}, 20000, 1784293664)";
+        assert_eq!(compile("codata", &codata()), Ok(streams.to_string()));
+        assert_eq!(compile("erasure", &erased()), Ok("(3, 7, Nil ())".to_string()));
+        let printed = "((1, 2), (1, 1, 1, 3), (-1, 2), (10, /a/, Cons (11, Nil ())), 3)";
+        assert_eq!(compile("extensions", &extensions()), Ok(printed.to_string()));
+        // the list functions of the prelude and a local function calling itself last go through long lists
+        let lists = self::program("
+            $replicate = n ~> $go = k acc ~> #le k 0 | 1 -> acc | _ -> go (#sub k 1) (Cons (k, acc)); go n Nil;
+            $main = (length (replicate 100000), length (map (x ~> #add x 1) (replicate 3)));
+        ");
+        assert_eq!(compile("long_lists", &lists), Ok("(100000, 3)".to_string()));
+
+        let failing = |name: &str, text: &str| compile(name, &self::program(text));
+        let expected = |text: &str| Err(text.to_string());
+        assert_eq!(failing("division", "$main = #mod 1 0;"), expected("division by zero"));
+        assert_eq!(failing("hole", "$main = #add 1 ?later;"), expected("reached the unfinished hole ?later"));
+    }
+}
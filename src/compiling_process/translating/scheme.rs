@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+
+use crate::compiling_process::pattern_compiling::compile_match;
+use crate::compiling_process::primitives::Primitive;
+use crate::compiling_process::translating::erasure::{erase, Term};
+use crate::inner_representation::abstract_syntax_tree::{AtomicValue, Let, Pattern};
+use crate::inner_representation::decision_tree::{Case, DecisionTree, Step};
+
+// thunks, application, primitives and printing, the generated code only calls into it
+pub const RUNTIME: &str = include_str!("runtime.scm");
+
+// the syntax the generated code is written with, a variable of the same name would hide it
+const RESERVED: &[&str] = &[
+    "=>", "_", "and", "begin", "case", "cond", "define", "else", "if", "lambda", "let", "let*", "letrec", "letrec*", "or",
+    "quasiquote", "quote", "set!", "unquote",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Global {
+    // a function or a constant, defined once and used as it is
+    Plain,
+    // computed the first time it is used
+    Lazy,
+}
+
+struct Generator {
+    globals: HashMap<String, Global>,
+}
+
+fn is_simple(name: &str) -> bool {
+    let initial = |x: char| x.is_ascii_alphabetic() || "!$%&*/:<=>?^_~".contains(x);
+    name.starts_with(initial) && name.chars().all(|x| initial(x) || x.is_ascii_digit() || "+-.@".contains(x))
+}
+
+// a symbol as it is written, between bars when it has characters identifiers cannot
+fn symbol(name: &str) -> String {
+    if is_simple(name) {
+        name.to_string()
+    } else {
+        format!("|{}|", name.replace('\\', "\\\\").replace('|', "\\|"))
+    }
+}
+
+fn identifier(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        symbol(name)
+    }
+}
+
+fn string_literal(text: &str) -> String {
+    let mut result = String::from("\"");
+    for x in text.chars() {
+        match x {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            x if (x as u32) < 0x20 || x == '\u{7f}' => result.push_str(&format!("\\x{:x};", x as u32)),
+            x => result.push(x),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn function_name(primitive: Primitive) -> String {
+    match primitive {
+        Primitive::Show => "$show-int".to_string(),
+        _ => primitive.name().replace('#', "$"),
+    }
+}
+
+fn indentation(indent: usize) -> String {
+    "  ".repeat(indent)
+}
+
+impl Generator {
+    // values that can neither fail nor loop, computing them now is the same as delaying them
+    fn is_immediate(&self, term: &Term) -> bool {
+        match term {
+            Term::Local(_) | Term::Primitive(_) | Term::Int(_) | Term::Text(_) | Term::Function(_, _) | Term::Erased => true,
+            Term::Global(name) => self.globals[name] == Global::Plain,
+            Term::Tuple(items) => items.iter().all(|x| self.is_immediate(x)),
+            Term::Record(fields) => fields.iter().all(|(_, x)| self.is_immediate(x)),
+            Term::Either(_, payload) => self.is_immediate(payload),
+            _ => false,
+        }
+    }
+
+    fn delayed(&mut self, term: &Term, indent: usize) -> Result<String, String> {
+        if self.is_immediate(term) {
+            return self.expression(term, indent);
+        }
+        Ok(format!("($lazy (lambda () {}) #f)", self.expression(term, indent)?))
+    }
+
+    fn list(&mut self, terms: &[Term], indent: usize) -> Result<String, String> {
+        let terms = terms.iter().map(|x| self.expression(x, indent)).collect::<Result<Vec<_>, _>>()?;
+        Ok(terms.join(" "))
+    }
+
+    // one `lambda` for each parameter, functions are curried
+    fn function(&mut self, parameters: &[String], body: &Term, indent: usize) -> Result<String, String> {
+        let mut result = self.expression(body, indent + parameters.len())?;
+        for (i, parameter) in parameters.iter().enumerate().rev() {
+            result = format!("(lambda ({})\n{}{})", identifier(parameter), indentation(indent + i + 1), result);
+        }
+        Ok(result)
+    }
+
+    // local definitions are computed in order, each one sees the ones before it and a function also sees itself
+    fn lets(&mut self, lets: &[(String, Term)], value: &Term, indent: usize) -> Result<String, String> {
+        let recursive = matches!(lets[0].1, Term::Function(_, _));
+        let group = if recursive { 1 } else { lets.iter().take_while(|(_, x)| !matches!(x, Term::Function(_, _))).count() };
+        let mut bindings = vec![];
+        for (name, value) in &lets[..group] {
+            bindings.push(format!("({} {})", identifier(name), self.expression(value, indent + 2)?));
+        }
+        let value = match &lets[group..] {
+            [] => self.expression(value, indent + 1)?,
+            rest => self.lets(rest, value, indent + 1)?,
+        };
+        let separator = format!("\n{}", indentation(indent + 2));
+        let form = if recursive { "letrec" } else { "let*" };
+        Ok(format!("({} ({})\n{}{})", form, bindings.join(&separator), indentation(indent + 1), value))
+    }
+
+    fn expression(&mut self, term: &Term, indent: usize) -> Result<String, String> {
+        Ok(match term {
+            Term::Local(name) => identifier(name),
+            Term::Global(name) => match self.globals[name] {
+                Global::Plain => identifier(name),
+                Global::Lazy => format!("($force {})", identifier(name)),
+            },
+            Term::Primitive(primitive) => format!("($curry {} {})", function_name(*primitive), primitive.arity()),
+            Term::Call(primitive, arguments) => format!("({} {})", function_name(*primitive), self.list(arguments, indent)?),
+            Term::Tuple(items) => {
+                let items = items.iter().map(|x| self.expression(x, indent)).collect::<Result<Vec<_>, _>>()?;
+                if items.is_empty() {
+                    "($tuple)".to_string()
+                } else {
+                    format!("($tuple {})", items.join(" "))
+                }
+            },
+            Term::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(name, x)| Ok(format!(" {} {}", string_literal(name), self.expression(x, indent)?)))
+                    .collect::<Result<Vec<_>, String>>()?;
+                format!("($record{})", fields.concat())
+            },
+            Term::Either(name, payload) => format!("($either '{} {})", symbol(name), self.expression(payload, indent)?),
+            Term::Lazy(term) => self.delayed(term, indent)?,
+            Term::Match(scrutinee, arms) => {
+                let value = self.expression(scrutinee, indent + 2)?;
+                let patterns: Vec<&Pattern> = arms.iter().map(|(pattern, _)| pattern).collect();
+                let tree = compile_match(&patterns)?;
+                let tree = self.tree(&tree, arms, indent + 1)?;
+                format!("(let (($scrutinee {}))\n{}{})", value, indentation(indent + 1), tree)
+            },
+            Term::Function(parameters, body) => self.function(parameters, body, indent)?,
+            Term::Apply(function, arguments) => {
+                format!("($apply {} {})", self.expression(function, indent)?, self.list(arguments, indent)?)
+            },
+            Term::Let(lets, value) => self.lets(lets, value, indent)?,
+            Term::Int(n) => n.to_string(),
+            Term::Text(text) => string_literal(text),
+            Term::Erased => "$type".to_string(),
+            Term::Fail(message) => format!("($fail {})", string_literal(message)),
+        })
+    }
+
+    fn select(occurrence: &[Step]) -> String {
+        occurrence.iter().fold("$scrutinee".to_string(), |value, step| match step {
+            Step::Payload => format!("($payload {})", value),
+            Step::Index(i) => format!("($index {} {})", value, i),
+            Step::Field(name) => format!("($field {} {})", value, string_literal(name)),
+        })
+    }
+
+    // a `case` on the tag or the int, strings are compared one after the other
+    fn tree(&mut self, tree: &DecisionTree, arms: &[(Pattern, Term)], indent: usize) -> Result<String, String> {
+        let prefix = indentation(indent + 1);
+        Ok(match tree {
+            DecisionTree::Fail => "($no-match $scrutinee)".to_string(),
+            DecisionTree::Leaf(arm, bindings) => {
+                let bindings: Vec<String> = bindings
+                    .iter()
+                    .map(|(name, occurrence)| format!("({} {})", identifier(name.get_name()), Self::select(occurrence)))
+                    .collect();
+                let result = if bindings.is_empty() {
+                    self.expression(&arms[*arm].1, indent)?
+                } else {
+                    let value = self.expression(&arms[*arm].1, indent + 1)?;
+                    format!("(let ({})\n{}{})", bindings.join(" "), prefix, value)
+                };
+                result
+            },
+            DecisionTree::Switch(occurrence, cases, default) => {
+                let value = Self::select(occurrence);
+                let mut result = match cases.first() {
+                    Some((Case::Constructor(_), _)) => format!("(case ($tag {})", value),
+                    Some((Case::Literal(AtomicValue::StringLiteral(_)), _)) => "(cond".to_string(),
+                    _ => format!("(case ($force {})", value),
+                };
+                for (case, tree) in cases {
+                    let label = match case {
+                        Case::Constructor(name) => format!("({})", symbol(name)),
+                        Case::Literal(AtomicValue::Int(n)) => format!("({})", n),
+                        Case::Literal(AtomicValue::StringLiteral(text)) => {
+                            format!("($same-text? {} {})", value, string_literal(text))
+                        },
+                    };
+                    result.push_str(&format!("\n{}({}\n{} {})", prefix, label, prefix, self.tree(tree, arms, indent + 1)?));
+                }
+                let default = match default {
+                    Some(tree) => self.tree(tree, arms, indent + 1)?,
+                    None => "($no-match $scrutinee)".to_string(),
+                };
+                result.push_str(&format!("\n{}(else {}))", prefix, default));
+                result
+            },
+        })
+    }
+}
+
+// the program as an R7RS program with the runtime, it prints `main` when run
+pub fn emit_scheme(program: &[Let]) -> Result<String, String> {
+    let program = erase(program, true)?;
+    let mut generator = Generator {
+        globals: HashMap::new(),
+    };
+    for (name, term) in &program.definitions {
+        let kind = match term {
+            Term::Function(_, _) | Term::Int(_) | Term::Text(_) | Term::Erased => Global::Plain,
+            _ => Global::Lazy,
+        };
+        generator.globals.insert(name.clone(), kind);
+    }
+    let mut result = RUNTIME.to_string();
+    result.push_str("(let ()");
+    for (name, term) in &program.definitions {
+        let definition = generator.expression(term, 2).map_err(|error| format!("in {}: {}", name, error))?;
+        let definition = match generator.globals[name] {
+            Global::Plain => definition,
+            Global::Lazy => format!("($lazy (lambda () {}) {})", definition, string_literal(name)),
+        };
+        result.push_str(&format!("\n  (define {}\n    {})", identifier(name), definition));
+    }
+    // internal definitions end with an expression, printing main or nothing
+    result.push_str(if generator.globals.contains_key("main") { "\n  ($run main))\n" } else { "\n  $type)\n" });
+    Ok(result)
+}
+
+#[cfg(test)]
+mod scheme_tests {
+    use std::fs;
+    use std::process::Command;
+
+    use crate::compiling_process::translating::testing::{self, directory, interpret, program};
+    use crate::inner_representation::abstract_syntax_tree::Let;
+    use super::{emit_scheme, identifier, string_literal, symbol};
+
+    // what chibi-scheme prints for the program, or its error
+    fn run(name: &str, program: &[Let]) -> Result<String, String> {
+        let source = directory("scheme").join(format!("{}.scm", name));
+        fs::write(&source, emit_scheme(program).unwrap()).unwrap();
+        testing::run(Command::new("chibi-scheme").arg(&source))
+    }
+
+    fn is_balanced(code: &str) -> bool {
+        let (mut depth, mut text, mut escaped) = (0, false, false);
+        for x in code.lines().flat_map(|line| line.split(';').next()).collect::<Vec<_>>().join("\n").chars() {
+            match x {
+                _ if escaped => escaped = false,
+                '\\' if text => escaped = true,
+                '"' => text = !text,
+                '(' if !text => depth += 1,
+                ')' if !text => depth -= 1,
+                _ => {},
+            }
+            if depth < 0 {
+                return false;
+            }
+        }
+        depth == 0 && !text
+    }
+
+    fn values() -> Vec<Let> {
+        program("
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $sum: List Int -> Int = xs ~> foldr (x n ~> #add x n) 0 xs;
+            $point = {x = 1, y = /two/};
+            $swap = p ~> p | {x, y} -> {x = y, y = x};
+            $classify = n ~> n | (0 | 1) -> /small/ | 2 -> /two/ | _ -> /big/;
+            $words = s ~> s | /one/ -> 1 | /two/ -> 2 | _ -> 0;
+            $over = (x ~> y ~> #sub x y) 10 3;
+            $total: List Int -> Int = xs ~> $go = ys acc ~> ys | Nil -> acc | Cons (y, rest) -> go rest (#add acc y); go xs 0;
+            $main = (
+                sum (map (x ~> #mul x 10) numbers),
+                #concat (#show (#div -7 2)) (#slice /héllo/ 1 4),
+                swap point,
+                map classify (Cons (0, Cons (2, Cons (7, Nil)))),
+                (words /two/, words /three/, total numbers),
+                (over, #add 2147483647 1, #mul 65536 65536, Just (Just refl), #add 1, #length /é/)
+            );
+        ")
+    }
+
+    fn codata() -> Vec<Let> {
+        program("
+            $Stream = A ~> codata head A * tail (Stream A);
+            $from: Int -> Stream Int = n ~> {head = n, tail = from (#add n 1)};
+            $take: {A : @} -> Int -> Stream A -> List A = n s ~> #le n 0
+                | 1 -> Nil
+                | _ -> (s | {head, tail} -> Cons (head, take (#sub n 1) tail));
+            $count = n acc ~> n | 0 -> acc | _ -> count (#sub n 1) (#add acc 2);
+            $main = (take 3 (from 10), count 1000000 0);
+        ")
+    }
+
+    fn clashing() -> Vec<Let> {
+        program("
+            $list = (1, 2);
+            $if = x ~> x;
+            $main = (if list, length (Cons (1, Nil)));
+        ")
+    }
+
+    #[test]
+    fn unit_tests() {
+        assert_eq!(identifier("lambda"), "lambda_");
+        assert_eq!(identifier("map"), "map");
+        assert_eq!(identifier("x'"), "|x'|");
+        assert_eq!(symbol("a|b"), "|a\\|b|");
+        assert_eq!(string_literal("a\"b\\c\n\u{1}é"), "\"a\\\"b\\\\c\\n\\x1;é\"");
+
+        let code = emit_scheme(&values()).unwrap();
+        assert!(is_balanced(&code));
+        assert!(code.contains("(define sum\n    (lambda (xs)"));
+        assert!(code.contains("(define point\n    ($lazy (lambda () ($record \"x\" 1 \"y\" \"two\")) \"point\"))"));
+        assert!(code.contains("(case ($tag $scrutinee)"));
+        assert!(code.contains("(letrec ((go (lambda (ys)"));
+        assert!(code.contains("(case ($force $scrutinee)\n"));
+        assert!(code.contains("(($same-text? $scrutinee \"one\")"));
+        assert!(code.contains("($either 'Cons ($tuple 1 "));
+        assert!(code.ends_with("($run main))\n"));
+    }
+
+    #[test]
+    fn lazy_fields() {
+        let code = emit_scheme(&codata()).unwrap();
+        assert!(code.contains("($record \"head\" n \"tail\" ($lazy (lambda () ($apply from ($add n 1))) #f))"));
+    }
+
+    #[test]
+    fn names() {
+        let code = emit_scheme(&clashing()).unwrap();
+        assert!(code.contains("(define list\n    ($lazy (lambda () ($tuple 1 2)) \"list\"))"));
+        assert!(code.contains("(define if_\n    (lambda (x)"));
+        assert!(code.contains("($apply if_ ($force list))"));
+        assert!(!emit_scheme(&self::program("$lambda = 1; $x = lambda;")).unwrap().contains("(define lambda "));
+        assert!(emit_scheme(&self::program("$x = 1;")).unwrap().ends_with("\n  $type)\n"));
+    }
+
+    #[test]
+    #[ignore = "runs chibi-scheme"]
+    fn run_by_scheme() {
+        let program = values();
+        assert_eq!(run("unit_tests", &program), interpret(&program, "main"));
+        assert_eq!(run("codata", &codata()), Ok("(Cons (10, Cons (11, Cons (12, Nil ()))), 2000000)".to_string()));
+        assert_eq!(run("names", &clashing()), Ok("((1, 2), 1)".to_string()));
+
+        let failing = |name: &str, text: &str| run(name, &self::program(text));
+        let expected = |text: &str| Err(text.to_string());
+        assert_eq!(failing("division", "$main = #mod 1 0;"), expected("division by zero"));
+        assert_eq!(failing("hole", "$main = #add 1 ?later;"), expected("reached the unfinished hole ?later"));
+        assert_eq!(failing("no_match", "$main = Nothing | Just x -> x;"), expected("no pattern matches Nothing ()"));
+        assert_eq!(failing("loop", "$main = main;"), expected("main is defined in terms of itself"));
+    }
+}
@@ -0,0 +1,52 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::compiling_process::executing_compiler_extructions::Loader;
+use crate::compiling_process::interpreting::Interpreter;
+use crate::compiling_process::parsing::parse_program;
+use crate::inner_representation::abstract_syntax_tree::{CompilerCommand, Let};
+
+// what the tests share: programs read from source, what the interpreter makes of them and running what the backends
+// translated
+
+// the program after the prelude
+pub fn program(text: &str) -> Vec<Let> {
+    let mut loader = Loader::new();
+    loader.execute(&CompilerCommand::Include("prelude".to_string()), Path::new(".")).unwrap();
+    let mut program = loader.definitions().to_vec();
+    program.extend(parse_program(text.to_string(), &loader.types()).unwrap().get_program().iter().cloned());
+    program
+}
+
+// the program on its own, for tests that look at everything that is translated
+pub fn bare(text: &str) -> Vec<Let> {
+    parse_program(text.to_string(), &[]).unwrap().get_program().to_vec()
+}
+
+// the value the backends are compared against, printed the way their runtimes print
+pub fn interpret(program: &[Let], name: &str) -> Result<String, String> {
+    let interpreter = Interpreter::new(program);
+    interpreter.get(name).and_then(|x| interpreter.force_all(x)).map(|x| x.to_string())
+}
+
+// where a backend writes what it translated, one directory each
+pub fn directory(backend: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("test-language-{}", backend));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+// what the command prints, or its error, the tests that run one are ignored unless asked for as the command may not
+// be installed
+pub fn run(command: &mut Command) -> Result<String, String> {
+    let output = command.output().unwrap_or_else(|error| panic!("{:?} does not run: {}", command.get_program(), error));
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim_end().to_string();
+    if output.status.success() { Ok(text(&output.stdout)) } else { Err(text(&output.stderr)) }
+}
+
+// the tool succeeds with what it was given
+pub fn succeeds(command: &mut Command) {
+    let output = command.output().unwrap_or_else(|error| panic!("{:?} does not run: {}", command.get_program(), error));
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
@@ -0,0 +1,787 @@
+use std::collections::HashMap;
+
+use crate::compiling_process::pattern_compiling::compile_match;
+use crate::compiling_process::primitives::{Primitive, PRIMITIVES};
+use crate::compiling_process::translating::closures::lift;
+use crate::compiling_process::translating::erasure::Term;
+use crate::inner_representation::abstract_syntax_tree::{AtomicValue, Let, Pattern};
+use crate::inner_representation::decision_tree::{Case, DecisionTree, Step};
+
+// allocation, application, primitives and printing, written by hand in the text format
+pub const RUNTIME: &str = include_str!("runtime.wat");
+
+// the runtime keeps the memory below this to itself
+const DATA_START: usize = 1024;
+
+// where the runtime keeps the type value and the empty tuple
+const TYPE_VALUE: usize = 8;
+const UNIT: usize = 16;
+
+// the objects known before the program runs, laid out the way the runtime reads them
+struct Data {
+    bytes: Vec<u8>,
+    texts: HashMap<String, usize>,
+    ints: HashMap<i32, usize>,
+}
+
+impl Data {
+    fn address(&self) -> usize {
+        DATA_START + self.bytes.len()
+    }
+
+    fn align(&mut self) {
+        while !self.bytes.len().is_multiple_of(4) {
+            self.bytes.push(0);
+        }
+    }
+
+    fn words(&mut self, words: &[u32]) -> usize {
+        self.align();
+        let address = self.address();
+        for word in words {
+            self.bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        address
+    }
+
+    fn text(&mut self, text: &str) -> usize {
+        if let Some(address) = self.texts.get(text) {
+            return *address;
+        }
+        let bytes = self.address();
+        self.bytes.extend_from_slice(text.as_bytes());
+        let address = self.words(&[1, text.len() as u32, bytes as u32]);
+        self.texts.insert(text.to_string(), address);
+        address
+    }
+
+    fn int(&mut self, n: i32) -> usize {
+        if let Some(address) = self.ints.get(&n) {
+            return *address;
+        }
+        let address = self.words(&[0, n as u32]);
+        self.ints.insert(n, address);
+        address
+    }
+
+    fn literal(&self) -> String {
+        let mut result = String::from("\"");
+        for byte in &self.bytes {
+            match byte {
+                b'"' | b'\\' => result.push_str(&format!("\\{:02x}", byte)),
+                0x20..=0x7e => result.push(*byte as char),
+                _ => result.push_str(&format!("\\{:02x}", byte)),
+            }
+        }
+        result.push('"');
+        result
+    }
+}
+
+// a function being generated, what it captures is read from the closure it is called with
+struct Scope {
+    code: String,
+    indent: usize,
+    locals: Vec<String>,
+    // how the variables are read, a local or a load from the environment
+    variables: Vec<(String, String)>,
+    captures: Vec<(String, String)>,
+}
+
+struct Generator {
+    functions: Vec<String>,
+    table: Vec<String>,
+    scopes: Vec<Scope>,
+    globals: HashMap<String, usize>,
+    symbols: Vec<String>,
+    data: Data,
+    fresh: usize,
+}
+
+fn identifier(name: &str) -> String {
+    name.chars().map(|x| if x.is_ascii_alphanumeric() { x } else { '_' }).collect()
+}
+
+// the function of the runtime computing a primitive, and its place in the table
+fn runtime_function(primitive: Primitive) -> &'static str {
+    match primitive {
+        Primitive::Show => "show_int",
+        _ => &primitive.name()[1..],
+    }
+}
+
+fn table_index(primitive: Primitive) -> usize {
+    PRIMITIVES.iter().position(|(_, x)| *x == primitive).unwrap()
+}
+
+impl Generator {
+    fn symbol(&mut self, name: &str) -> usize {
+        match self.symbols.iter().position(|x| x == name) {
+            Some(index) => index,
+            None => {
+                self.symbols.push(name.to_string());
+                self.symbols.len() - 1
+            },
+        }
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.fresh += 1;
+        format!("${}_{}", prefix, self.fresh)
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, instructions: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        for instruction in instructions.lines() {
+            scope.code.push_str(&"  ".repeat(scope.indent));
+            scope.code.push_str(instruction);
+            scope.code.push('\n');
+        }
+    }
+
+    fn open(&mut self, instruction: &str) {
+        self.emit(instruction);
+        self.scope().indent += 1;
+    }
+
+    fn close(&mut self) {
+        self.scope().indent -= 1;
+        self.emit("end");
+    }
+
+    fn local(&mut self, name: &str) -> String {
+        let local = self.fresh(&identifier(name));
+        self.scope().locals.push(local.clone());
+        local
+    }
+
+    // takes the value on the stack
+    fn bind(&mut self, name: &str) {
+        let local = self.local(name);
+        self.emit(&format!("local.set {}", local));
+        self.scope().variables.push((name.to_string(), format!("local.get {}", local)));
+    }
+
+    fn fail(&mut self, message: &str) {
+        let text = self.data.text(message);
+        self.emit(&format!("i32.const {}\ncall $fail", text));
+    }
+
+    // a variable of one of the functions around, captured on the way in
+    fn resolve(&mut self, name: &str, depth: usize) -> Option<String> {
+        let scope = &self.scopes[depth];
+        if let Some((_, access)) = scope.variables.iter().rev().find(|(x, _)| x == name) {
+            return Some(access.clone());
+        }
+        if let Some(index) = scope.captures.iter().position(|(x, _)| x == name) {
+            return Some(format!("local.get $environment\ni32.load offset={}", 20 + 4 * index));
+        }
+        if depth == 0 {
+            return None;
+        }
+        let outer = self.resolve(name, depth - 1)?;
+        let captures = &mut self.scopes[depth].captures;
+        captures.push((name.to_string(), outer));
+        Some(format!("local.get $environment\ni32.load offset={}", 20 + 4 * (captures.len() - 1)))
+    }
+
+    // values that can neither fail nor loop, computing them now is the same as delaying them
+    fn is_immediate(term: &Term) -> bool {
+        match term {
+            Term::Local(_) | Term::Primitive(_) | Term::Int(_) | Term::Text(_) | Term::Function(_, _) | Term::Erased => true,
+            Term::Tuple(items) => items.iter().all(Self::is_immediate),
+            Term::Record(fields) => fields.iter().all(|(_, x)| Self::is_immediate(x)),
+            Term::Either(_, payload) => Self::is_immediate(payload),
+            _ => false,
+        }
+    }
+
+    // generates a function of the table and gives its index with how to read what it captures
+    fn function(
+        &mut self,
+        name: &str,
+        parameters: &[String],
+        body: impl FnOnce(&mut Self) -> Result<(), String>,
+    ) -> Result<(usize, Vec<String>), String> {
+        let function = self.fresh(&format!("function_{}", identifier(name)));
+        self.table.push(function.clone());
+        let index = self.table.len() - 1;
+        self.define(&function, parameters, body).map(|captures| (index, captures))
+    }
+
+    fn define(
+        &mut self,
+        function: &str,
+        parameters: &[String],
+        body: impl FnOnce(&mut Self) -> Result<(), String>,
+    ) -> Result<Vec<String>, String> {
+        self.scopes.push(Scope {
+            code: String::new(),
+            indent: 2,
+            locals: vec![],
+            variables: vec![],
+            captures: vec![],
+        });
+        for (i, parameter) in parameters.iter().enumerate() {
+            self.emit(&format!("local.get $arguments\ni32.load offset={}", 4 * i));
+            self.bind(parameter);
+        }
+        body(self)?;
+        // every path has returned, but the end of a block does not show it
+        if self.scope().code.ends_with("end\n") {
+            self.emit("unreachable");
+        }
+        let scope = self.scopes.pop().unwrap();
+        let mut text = format!("  (func {} (param $environment i32) (param $arguments i32) (result i32)\n", function);
+        for local in &scope.locals {
+            text.push_str(&format!("    (local {} i32)\n", local));
+        }
+        text.push_str(&scope.code);
+        text.pop();
+        text.push_str(")\n");
+        self.functions.push(text);
+        Ok(scope.captures.into_iter().map(|(_, access)| access).collect())
+    }
+
+    // makes a closure or a thunk and stores what it captures in it
+    fn allocate(&mut self, make: &str, captures: &[String]) {
+        self.emit(make);
+        if captures.is_empty() {
+            return;
+        }
+        let object = self.local("object");
+        self.emit(&format!("local.set {}", object));
+        for (i, capture) in captures.iter().enumerate() {
+            self.emit(&format!("local.get {}", object));
+            self.emit(capture);
+            self.emit(&format!("i32.store offset={}", 20 + 4 * i));
+        }
+        self.emit(&format!("local.get {}", object));
+    }
+
+    fn delayed(&mut self, term: &Term) -> Result<(), String> {
+        if Self::is_immediate(term) {
+            return self.value(term);
+        }
+        let (index, captures) = self.function("thunk", &[], |generator| generator.tail(term))?;
+        self.allocate(&format!("i32.const {}\ni32.const {}\ncall $thunk", index, captures.len()), &captures);
+        Ok(())
+    }
+
+    fn lets(&mut self, lets: &[(String, Term)]) -> Result<usize, String> {
+        let depth = self.scope().variables.len();
+        for (name, value) in lets {
+            self.value(value)?;
+            self.bind(name);
+        }
+        Ok(depth)
+    }
+
+    // the function and an array of the arguments on the stack, ready for $apply or $tail_call
+    fn call(&mut self, function: &Term, arguments: &[Term]) -> Result<(), String> {
+        self.value(function)?;
+        let array = self.local("arguments");
+        self.emit(&format!("i32.const {}\ncall $arguments\nlocal.set {}", arguments.len(), array));
+        for (i, argument) in arguments.iter().enumerate() {
+            self.emit(&format!("local.get {}", array));
+            self.value(argument)?;
+            self.emit(&format!("i32.store offset={}", 4 * i));
+        }
+        self.emit(&format!("local.get {}\ni32.const {}", array, arguments.len()));
+        Ok(())
+    }
+
+    // leaves the value on the stack
+    fn value(&mut self, term: &Term) -> Result<(), String> {
+        match term {
+            Term::Local(name) => {
+                let depth = self.scopes.len() - 1;
+                let access = self.resolve(name, depth).ok_or_else(|| format!("the local {} is not bound", name))?;
+                self.emit(&access);
+            },
+            Term::Global(name) => {
+                let global = self.globals[name];
+                self.emit(&format!("i32.const {}\ncall $global", global));
+            },
+            Term::Primitive(primitive) => self.emit(&format!(
+                "i32.const {}\ni32.const {}\ni32.const 0\ncall $closure",
+                table_index(*primitive),
+                primitive.arity()
+            )),
+            Term::Call(primitive, arguments) => {
+                for argument in arguments {
+                    self.value(argument)?;
+                }
+                self.emit(&format!("call ${}", runtime_function(*primitive)));
+            },
+            Term::Tuple(items) if items.is_empty() => self.emit(&format!("i32.const {}", UNIT)),
+            Term::Tuple(items) => {
+                let tuple = self.local("tuple");
+                self.emit(&format!("i32.const {}\ncall $tuple\nlocal.set {}", items.len(), tuple));
+                for (i, item) in items.iter().enumerate() {
+                    self.emit(&format!("local.get {}", tuple));
+                    self.value(item)?;
+                    self.emit(&format!("i32.store offset={}", 8 + 4 * i));
+                }
+                self.emit(&format!("local.get {}", tuple));
+            },
+            Term::Record(fields) => {
+                let record = self.local("record");
+                self.emit(&format!("i32.const {}\ncall $record\nlocal.set {}", fields.len(), record));
+                for (i, (name, field)) in fields.iter().enumerate() {
+                    let symbol = self.symbol(name);
+                    self.emit(&format!("local.get {}\ni32.const {}\ni32.store offset={}", record, symbol, 8 + 8 * i));
+                    self.emit(&format!("local.get {}", record));
+                    self.value(field)?;
+                    self.emit(&format!("i32.store offset={}", 12 + 8 * i));
+                }
+                self.emit(&format!("local.get {}", record));
+            },
+            Term::Either(name, payload) => {
+                let constructor = self.symbol(name);
+                self.emit(&format!("i32.const {}", constructor));
+                self.value(payload)?;
+                self.emit("call $either");
+            },
+            Term::Lazy(term) => self.delayed(term)?,
+            Term::Match(scrutinee, arms) => {
+                let result = self.local("result");
+                let end = self.fresh("end");
+                self.open(&format!("block {}", end));
+                self.matching(scrutinee, arms, Some((&result, &end)))?;
+                self.close();
+                self.emit(&format!("local.get {}", result));
+            },
+            Term::Function(parameters, body) => {
+                // only a definition is a function, what it captures are the thunks it delays
+                if self.scopes.len() > 1 {
+                    return Err("functions are lifted to definitions before lowering".to_string());
+                }
+                let name = format!("lambda_{}", parameters.join("_"));
+                let (index, captures) = self.function(&name, parameters, |generator| generator.tail(body))?;
+                let (arity, captured) = (parameters.len(), captures.len());
+                let make = format!("i32.const {}\ni32.const {}\ni32.const {}\ncall $closure", index, arity, captured);
+                self.allocate(&make, &captures);
+            },
+            Term::Apply(function, arguments) => {
+                self.call(function, arguments)?;
+                self.emit("call $apply");
+            },
+            Term::Let(lets, body) => {
+                let depth = self.lets(lets)?;
+                self.value(body)?;
+                self.scope().variables.truncate(depth);
+            },
+            Term::Int(n) => {
+                let address = self.data.int(*n);
+                self.emit(&format!("i32.const {}", address));
+            },
+            Term::Text(text) => {
+                let address = self.data.text(text);
+                self.emit(&format!("i32.const {}", address));
+            },
+            Term::Erased => self.emit(&format!("i32.const {}", TYPE_VALUE)),
+            Term::Fail(message) => self.fail(message),
+        }
+        Ok(())
+    }
+
+    // returns the value, a call at the end is left to the caller so loops take no stack
+    fn tail(&mut self, term: &Term) -> Result<(), String> {
+        match term {
+            Term::Match(scrutinee, arms) => self.matching(scrutinee, arms, None),
+            Term::Apply(function, arguments) => {
+                self.call(function, arguments)?;
+                self.emit("call $tail_call\nreturn");
+                Ok(())
+            },
+            Term::Let(lets, body) => {
+                let depth = self.lets(lets)?;
+                self.tail(body)?;
+                self.scope().variables.truncate(depth);
+                Ok(())
+            },
+            term => {
+                self.value(term)?;
+                self.emit("return");
+                Ok(())
+            },
+        }
+    }
+
+    // `target` is where a match that is not in tail position puts its value and the block it leaves
+    fn matching(&mut self, scrutinee: &Term, arms: &[(Pattern, Term)], target: Option<(&str, &str)>) -> Result<(), String> {
+        self.value(scrutinee)?;
+        let local = self.local("scrutinee");
+        self.emit(&format!("local.set {}", local));
+        let patterns: Vec<&Pattern> = arms.iter().map(|(pattern, _)| pattern).collect();
+        let tree = compile_match(&patterns)?;
+        self.tree(&tree, arms, &local, target)
+    }
+
+    fn select(&mut self, scrutinee: &str, occurrence: &[Step]) {
+        self.emit(&format!("local.get {}", scrutinee));
+        for step in occurrence {
+            match step {
+                Step::Payload => self.emit("call $payload"),
+                Step::Index(i) => self.emit(&format!("i32.const {}\ncall $index", i)),
+                Step::Field(name) => {
+                    let symbol = self.symbol(name);
+                    self.emit(&format!("i32.const {}\ncall $field", symbol));
+                },
+            }
+        }
+    }
+
+    fn tree(
+        &mut self,
+        tree: &DecisionTree,
+        arms: &[(Pattern, Term)],
+        scrutinee: &str,
+        target: Option<(&str, &str)>,
+    ) -> Result<(), String> {
+        match tree {
+            DecisionTree::Fail => self.emit(&format!("local.get {}\ncall $no_match\nreturn", scrutinee)),
+            DecisionTree::Leaf(arm, bindings) => {
+                let depth = self.scope().variables.len();
+                for (name, occurrence) in bindings {
+                    self.select(scrutinee, occurrence);
+                    self.bind(name.get_name());
+                }
+                match target {
+                    Some((result, end)) => {
+                        self.value(&arms[*arm].1)?;
+                        self.emit(&format!("local.set {}\nbr {}", result, end));
+                    },
+                    None => self.tail(&arms[*arm].1)?,
+                }
+                self.scope().variables.truncate(depth);
+            },
+            // constructors go through a branch table indexed by their symbol
+            DecisionTree::Switch(occurrence, cases, default) if matches!(cases.first(), Some((Case::Constructor(_), _))) => {
+                let otherwise = self.fresh("otherwise");
+                let labels: Vec<String> = cases.iter().map(|_| self.fresh("case")).collect();
+                let symbols: Vec<usize> = cases
+                    .iter()
+                    .map(|(case, _)| match case {
+                        Case::Constructor(name) => self.symbol(name),
+                        Case::Literal(_) => unreachable!(),
+                    })
+                    .collect();
+                self.open(&format!("block {}", otherwise));
+                for label in labels.iter().rev() {
+                    self.open(&format!("block {}", label));
+                }
+                self.select(scrutinee, occurrence);
+                let size = symbols.iter().max().unwrap() + 2;
+                let table: Vec<&str> = (0..size)
+                    .map(|i| match symbols.iter().position(|x| i == x + 1) {
+                        Some(case) => labels[case].as_str(),
+                        None => otherwise.as_str(),
+                    })
+                    .collect();
+                self.emit(&format!("call $constructor\ni32.const 1\ni32.add\nbr_table {} {}", table.join(" "), otherwise));
+                for (_, tree) in cases {
+                    self.close();
+                    self.tree(tree, arms, scrutinee, target)?;
+                }
+                self.close();
+                self.default(default, arms, scrutinee, target)?;
+            },
+            DecisionTree::Switch(occurrence, cases, default) => {
+                for (case, tree) in cases {
+                    self.select(scrutinee, occurrence);
+                    match case {
+                        Case::Literal(AtomicValue::Int(n)) => self.emit(&format!("i32.const {}\ncall $int_is", n)),
+                        Case::Literal(AtomicValue::StringLiteral(text)) => {
+                            let address = self.data.text(text);
+                            self.emit(&format!("i32.const {}\ni32.load offset=8", address));
+                            self.emit(&format!("i32.const {}\ncall $text_is", text.len()));
+                        },
+                        Case::Constructor(_) => unreachable!(),
+                    }
+                    self.open("if");
+                    self.tree(tree, arms, scrutinee, target)?;
+                    self.close();
+                }
+                self.default(default, arms, scrutinee, target)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn default(
+        &mut self,
+        default: &Option<Box<DecisionTree>>,
+        arms: &[(Pattern, Term)],
+        scrutinee: &str,
+        target: Option<(&str, &str)>,
+    ) -> Result<(), String> {
+        match default {
+            Some(tree) => self.tree(tree, arms, scrutinee, target),
+            None => self.tree(&DecisionTree::Fail, arms, scrutinee, target),
+        }
+    }
+}
+
+// the erased program as a module with the runtime, exporting `run` that gives `main` as a text
+// a failure traps, and `error` then gives its message, texts are [1, length, bytes] in the exported memory
+pub fn emit_wat(program: &[Let]) -> Result<String, String> {
+    let program = lift(program)?;
+    let program = &program.definitions;
+    let main = program.iter().position(|(name, _)| name == "main").ok_or("unbound name main")?;
+    let mut generator = Generator {
+        functions: vec![],
+        table: PRIMITIVES.iter().map(|(_, x)| format!("$code_{}", &x.name()[1..])).collect(),
+        scopes: vec![],
+        globals: program.iter().enumerate().map(|(i, (name, _))| (name.clone(), i)).collect(),
+        symbols: vec![],
+        data: Data {
+            bytes: vec![],
+            texts: HashMap::new(),
+            ints: HashMap::new(),
+        },
+        fresh: 0,
+    };
+    // definitions come right after the primitives in the table, where the runtime looks for them
+    let definitions: Vec<String> =
+        program.iter().map(|(name, _)| generator.fresh(&format!("definition_{}", identifier(name)))).collect();
+    generator.table.extend(definitions.iter().cloned());
+    for ((name, term), function) in program.iter().zip(&definitions) {
+        generator
+            .define(function, &[], |generator| generator.tail(term))
+            .map_err(|error| format!("in {}: {}", name, error))?;
+    }
+    let names: Vec<u32> = program.iter().map(|(name, _)| generator.data.text(name) as u32).collect();
+    let names = generator.data.words(&names);
+    let symbols: Vec<String> = generator.symbols.clone();
+    let symbols: Vec<u32> = symbols.iter().map(|x| generator.data.text(x) as u32).collect();
+    let symbols = generator.data.words(&symbols);
+    let states = generator.data.words(&vec![0; program.len().div_ceil(4)]);
+    let values = generator.data.words(&vec![0; program.len()]);
+    let heap = generator.data.address();
+
+    let mut result = format!("(module\n{}", RUNTIME);
+    result.push_str(&format!("  (table {} funcref)\n", generator.table.len()));
+    result.push_str(&format!("  (elem (i32.const 0) {})\n", generator.table.join(" ")));
+    result.push_str(&format!("  (data (i32.const {}) {})\n", DATA_START, generator.data.literal()));
+    result.push_str(&format!("  (global $heap (mut i32) (i32.const {}))\n", heap));
+    result.push_str(&format!("  (global $symbols i32 (i32.const {}))\n", symbols));
+    result.push_str(&format!("  (global $global_names i32 (i32.const {}))\n", names));
+    result.push_str(&format!("  (global $global_states i32 (i32.const {}))\n", states));
+    result.push_str(&format!("  (global $global_values i32 (i32.const {}))\n\n", values));
+    result.push_str(&generator.functions.join("\n"));
+    result.push_str("\n  (func $run (export \"run\") (result i32)\n");
+    result.push_str(&format!("    i32.const {}\n    call $global\n    call $force_all\n    call $show))\n", main));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod wat_tests {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::process::Command;
+
+    use crate::compiling_process::translating::testing::{self, directory, interpret, program, succeeds};
+    use crate::inner_representation::abstract_syntax_tree::Let;
+    use super::emit_wat;
+
+    // the module without comments and strings, which may hold anything
+    fn words(module: &str) -> Vec<String> {
+        let mut text = String::new();
+        let mut characters = module.chars().peekable();
+        while let Some(x) = characters.next() {
+            match x {
+                ';' if characters.peek() == Some(&';') => {
+                    characters.by_ref().take_while(|x| *x != '\n').for_each(drop);
+                    text.push('\n');
+                },
+                '"' => {
+                    while let Some(x) = characters.next() {
+                        match x {
+                            '\\' => drop(characters.next()),
+                            '"' => break,
+                            _ => {},
+                        }
+                    }
+                    text.push_str(" \"\" ");
+                },
+                '(' | ')' => text.push_str(&format!(" {} ", x)),
+                x => text.push(x),
+            }
+        }
+        text.split_whitespace().map(|x| x.to_string()).collect()
+    }
+
+    type Reading<'a> = (&'a str, HashSet<&'a str>, Vec<Option<&'a str>>);
+
+    // what a text-format validator would find wrong with the structure of the module
+    fn validate(module: &str) -> Result<(), String> {
+        let words = words(module);
+        let defined = |kind: &str| -> HashSet<&str> {
+            words.windows(3).filter(|x| x[0] == "(" && x[1] == kind).map(|x| x[2].as_str()).collect()
+        };
+        let (functions, globals) = (defined("func"), defined("global"));
+        let mut depth = 0;
+        // the function being read, its locals and the labels open at this point
+        let mut function: Option<Reading> = None;
+        for (i, word) in words.iter().enumerate() {
+            let next = words.get(i + 1).map(String::as_str).unwrap_or("");
+            match (word.as_str(), &mut function) {
+                ("(", _) => {
+                    depth += 1;
+                    if depth == 2 && next == "func" {
+                        function = Some((words[i + 2].as_str(), HashSet::new(), vec![]));
+                    }
+                },
+                (")", _) if depth == 0 => return Err("unbalanced parentheses".to_string()),
+                (")", Some((name, _, labels))) if depth == 2 => {
+                    if !labels.is_empty() {
+                        return Err(format!("{} leaves a block open", name));
+                    }
+                    depth -= 1;
+                    function = None;
+                },
+                (")", _) => depth -= 1,
+                (_, None) => {},
+                ("param", Some((_, locals, _))) | ("local", Some((_, locals, _))) => drop(locals.insert(next)),
+                ("call", Some((name, _, _))) if !functions.contains(next) => {
+                    return Err(format!("{} calls {} which is not defined", name, next))
+                },
+                ("global.get", Some((name, _, _))) | ("global.set", Some((name, _, _))) if !globals.contains(next) => {
+                    return Err(format!("{} uses the global {} which is not defined", name, next))
+                },
+                ("local.get", Some((name, locals, _))) | ("local.set", Some((name, locals, _))) if !locals.contains(next) => {
+                    return Err(format!("{} uses the local {} which is not declared", name, next))
+                },
+                ("block", Some((_, _, labels))) | ("loop", Some((_, _, labels))) | ("if", Some((_, _, labels))) => {
+                    labels.push(Some(next).filter(|x| x.starts_with('$')))
+                },
+                ("end", Some((name, _, labels))) => {
+                    labels.pop().ok_or_else(|| format!("{} ends a block it did not open", name))?;
+                },
+                ("br", Some((name, _, labels))) | ("br_if", Some((name, _, labels))) | ("br_table", Some((name, _, labels))) => {
+                    for label in words[i + 1..].iter().take_while(|x| x.starts_with('$')) {
+                        if !labels.contains(&Some(label.as_str())) {
+                            return Err(format!("{} branches to {} outside of it", name, label));
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+        if depth != 0 {
+            return Err("unbalanced parentheses".to_string());
+        }
+        let elements = words.iter().skip_while(|x| *x != "elem").skip(5).take_while(|x| *x != ")");
+        for element in elements {
+            if !functions.contains(element.as_str()) {
+                return Err(format!("the table holds {} which is not defined", element));
+            }
+        }
+        for export in ["memory", "run", "error"].iter() {
+            if !module.contains(&format!("(export \"{}\")", export)) {
+                return Err(format!("nothing is exported as {}", export));
+            }
+        }
+        Ok(())
+    }
+
+    // what the module gives when run under node once wat2wasm has assembled it
+    fn run(name: &str, module: &str) -> Result<String, String> {
+        let directory = directory("wat");
+        let (source, binary) = (directory.join(format!("{}.wat", name)), directory.join(format!("{}.wasm", name)));
+        fs::write(&source, module).unwrap();
+        succeeds(Command::new("wat2wasm").arg(&source).arg("-o").arg(&binary));
+        let host = "
+            const { memory, run, error } = new WebAssembly.Instance(
+                new WebAssembly.Module(require('fs').readFileSync(process.argv[1]))
+            ).exports;
+            const text = (pointer) => {
+                const [, length, bytes] = new Uint32Array(memory.buffer, pointer, 3);
+                return new TextDecoder().decode(new Uint8Array(memory.buffer, bytes, length));
+            };
+            try { console.log(text(run())); } catch (e) { console.error(text(error())); process.exit(1); }
+        ";
+        testing::run(Command::new("node").arg("-e").arg(host).arg(&binary))
+    }
+
+    fn values() -> Vec<Let> {
+        program("
+            $numbers = Cons (1, Cons (2, Cons (3, Nil)));
+            $sum: List Int -> Int = xs ~> foldr (x n ~> #add x n) 0 xs;
+            $point = {x = 1, y = /two/};
+            $swap = p ~> p | {x, y} -> {x = y, y = x};
+            $classify = n ~> n | (0 | 1) -> /small/ | 2 -> /two/ | _ -> /big/;
+            $words = s ~> s | /one/ -> 1 | /two/ -> 2 | _ -> 0;
+            $over = (x ~> y ~> #sub x y) 10 3;
+            $main = (
+                sum (map (x ~> #mul x 10) numbers),
+                #concat (#show (#div -7 2)) (#slice /\"quoted\"/ 1 6),
+                swap point,
+                map classify (Cons (0, Cons (2, Cons (7, Nil)))),
+                (words /two/, words /three/, #div -2147483648 -1, #mod 7 -3),
+                (over, #add 2147483647 1, Just (Just refl), #add 1, #length /é/, #lt 1 2)
+            );
+        ")
+    }
+
+    fn codata() -> Vec<Let> {
+        program("
+            $Stream = A ~> codata head A * tail (Stream A);
+            $from: Int -> Stream Int = n ~> {head = n, tail = from (#add n 1)};
+            $take: {A : @} -> Int -> Stream A -> List A = n s ~> #le n 0
+                | 1 -> Nil
+                | _ -> (s | {head, tail} -> Cons (head, take (#sub n 1) tail));
+            $count = n acc ~> n | 0 -> acc | _ -> count (#sub n 1) (#add acc 2);
+            $main = (take 3 (from 10), count 1000000 0);
+        ")
+    }
+
+    const FAILING: [(&str, &str, &str); 4] = [
+        ("division", "$main = #mod 1 0;", "division by zero"),
+        ("hole", "$main = #add 1 ?later;", "reached the unfinished hole ?later"),
+        ("no_match", "$main = Nothing | Just x -> x;", "no pattern matches Nothing ()"),
+        ("loop", "$main = main;", "main is defined in terms of itself"),
+    ];
+
+    #[test]
+    fn unit_tests() {
+        assert_eq!(emit_wat(&program("$x = 1;")).unwrap_err(), "unbound name main");
+        assert_eq!(validate("(module (func $f (result i32) call $g))"), Err("$f calls $g which is not defined".to_string()));
+        assert!(validate("(module (func $f block $a end br $a))").unwrap_err().contains("$f branches to $a outside of it"));
+
+        let module = emit_wat(&values()).unwrap();
+        assert_eq!(validate(&module), Ok(()));
+        assert!(module.contains("(export \"run\")"));
+        // every function goes in the table, whose first entries are the primitives
+        assert!(module.contains("(elem (i32.const 0) $code_add $code_sub"));
+    }
+
+    #[test]
+    fn codata_and_loops() {
+        let module = emit_wat(&codata()).unwrap();
+        assert_eq!(validate(&module), Ok(()));
+        assert!(module.contains("call $thunk"));
+        assert!(module.contains("call $tail_call"));
+        for (_, text, _) in FAILING.iter() {
+            assert_eq!(validate(&emit_wat(&program(text)).unwrap()), Ok(()));
+        }
+    }
+
+    #[test]
+    #[ignore = "runs wat2wasm and node"]
+    fn run_by_node() {
+        let program = values();
+        assert_eq!(run("unit_tests", &emit_wat(&program).unwrap()), interpret(&program, "main"));
+        let expected = Ok("(Cons (10, Cons (11, Cons (12, Nil ()))), 2000000)".to_string());
+        assert_eq!(run("codata_and_loops", &emit_wat(&codata()).unwrap()), expected);
+        for (name, text, expected) in FAILING.iter() {
+            assert_eq!(run(name, &emit_wat(&self::program(text)).unwrap()), Err(expected.to_string()));
+        }
+    }
+}
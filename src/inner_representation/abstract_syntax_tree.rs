@@ -1,58 +1,471 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct AST {
     for_compiler: Vec<CompilerCommand>,
     program: Vec<Let>,
 }
 
+impl AST {
+    pub fn new(for_compiler: Vec<CompilerCommand>, program: Vec<Let>) -> AST {
+        AST {
+            for_compiler,
+            program,
+        }
+    }
+
+    pub fn get_commands(&self) -> &Vec<CompilerCommand> {
+        &self.for_compiler
+    }
+
+    pub fn get_program(&self) -> &Vec<Let> {
+        &self.program
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum CompilerCommand {
     Include(String),
     Load(String),
+    // the named definition is total, its recursive calls are not checked
+    Terminating(String),
 }
-pub struct Expr(Vec<Let>, Value);
 
-pub struct Let(Name, Value, Type);
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr(pub Vec<Let>, pub Value);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Let(pub Name, pub Value, pub Option<Type>);
 
 //$val: Ban Int * Nap String = 32 * '32'
 //$val2: Int = Ban val
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
+    Var(Name),
     Tuple(Vec<Value>),
+    Record(Vec<(Name, Value)>),
     Either(Name, Box<Value>),
-    Match(Box<Value>, Vec<(Name, Name, Value)>),
-    Function(Vec<Name>, Box<Expr>),
+    Match(Box<Value>, Vec<(Pattern, Value)>),
+    Function(Vec<(Name, Option<Type>)>, Box<Expr>),
     Application(Box<Value>, Vec<Value>),
-    Constant(AtomicValue),
-    Type(Box<Type>)
+    Constant(AtomicValue, Option<Span>),
+    Type(Box<Type>),
+    // ?name, a part of the program still to be written
+    Hole(Name),
+    // f @{Int} 3, an argument given for an implicit parameter
+    Implicit(Box<Value>),
+    // refl, the proof of x == x
+    Refl,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum AtomicValue {
     Int(i32),
     StringLiteral(String),
 }
 
+// Cons (x, _) | Nil _
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Binder(Name),
+    Constructor(Name, Box<Pattern>),
+    Tuple(Vec<Pattern>),
+    Record(Vec<(Name, Pattern)>),
+    Literal(AtomicValue),
+    Or(Vec<Pattern>),
+    // matches every proof of a == b, the two sides are the same in the arm
+    Refl,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Type {
     Product(Vec<(Name, Type)>),
     CoProduct(Vec<(Name, Box<Type>)>),
+    // x Int * ..r, any record with at least the field x, the row r stands for the other fields
+    OpenProduct(Vec<(Name, Type)>, Name),
+    // Circle Int + ..r, a variant that can be extended with the constructors of r
+    OpenCoProduct(Vec<(Name, Box<Type>)>, Name),
     Function(Box<Type>, Box<Type>),
+    // (A : @) -> A -> A, the bound name may appear in the result type
+    Pi(Visibility, Name, Box<Type>, Box<Type>),
+    Application(Box<Type>, Vec<Value>),
     TypeVar(Name),
+    Universe(Level),
     Atomic(AtomicType),
+    Hole(Name),
+    // class eq (A -> A -> Int), a record of methods whose values are found by instance search
+    Class(Vec<(Name, Type)>),
+    // plus n 0 == n, the proofs that two values are the same
+    Equal(Box<Value>, Box<Value>),
+    // codata head Int * tail Stream, a record or variant whose values are unfolded lazily and may go on forever
+    Codata(Box<Type>),
+}
+
+// implicit parameters, {A : @} -> A -> A, are filled in at call sites
+// instance parameters, [d : Eq A] -> A -> A -> Int, by instance search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Explicit,
+    Implicit,
+    Instance,
 }
 
+// @ is @0, @1 is the universe @0 lives in, @l is level polymorphic
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Level {
+    Constant(usize),
+    Variable(Name),
+    Successor(Box<Level>),
+    Maximum(Vec<Level>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AtomicType {
-    Universe,
     Top,
     Bottom,
     Int,
     String,
 }
 
+// byte offsets into the program text, with the line and column the span begins at, both counted from 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub begin: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn merge(self, another: Span) -> Span {
+        let first = if another.begin < self.begin { another } else { self };
+        Span {
+            end: self.end.max(another.end),
+            ..first
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Name {
     name: String,
     id: usize,
     context: Context,
+    span: Option<Span>,
+}
+
+impl Name {
+    pub fn new(name: String, context: Context) -> Name {
+        Name {
+            name,
+            id: 0,
+            context,
+            span: None,
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Name {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn get_span(&self) -> Option<Span> {
+        self.span
+    }
+
+    // the binding the name is, or refers to, once resolved, names that are not local are left at 0
+    pub fn with_id(mut self, id: usize) -> Name {
+        self.id = id;
+        self
+    }
+
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_context(&self) -> Context {
+        self.context
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Context {
     TypeContext,
     ValueContext,
     Constructor,
     None,
-}
\ No newline at end of file
+}
+
+fn merge_spans(spans: impl Iterator<Item = Option<Span>>) -> Option<Span> {
+    spans.flatten().fold(None, |result, span| match result {
+        Some(result) => Some(span.merge(result)),
+        None => Some(span),
+    })
+}
+
+impl Value {
+    // covers every name mentioned in the value
+    pub fn get_span(&self) -> Option<Span> {
+        match self {
+            Value::Var(name) => name.get_span(),
+            Value::Tuple(items) => merge_spans(items.iter().map(|x| x.get_span())),
+            Value::Record(fields) => merge_spans(
+                fields.iter().flat_map(|(name, x)| vec![name.get_span(), x.get_span()])
+            ),
+            Value::Either(name, payload) => merge_spans(vec![name.get_span(), payload.get_span()].into_iter()),
+            Value::Match(scrutinee, arms) => merge_spans(
+                arms.iter().map(|(_, x)| x.get_span()).chain(vec![scrutinee.get_span()])
+            ),
+            Value::Function(parameters, body) => merge_spans(
+                parameters.iter().map(|(name, _)| name.get_span()).chain(vec![body.1.get_span()])
+            ),
+            Value::Application(function, arguments) => merge_spans(
+                arguments.iter().map(|x| x.get_span()).chain(vec![function.get_span()])
+            ),
+            Value::Constant(_, span) => *span,
+            Value::Type(typ) => typ.get_span(),
+            Value::Hole(name) => name.get_span(),
+            Value::Implicit(value) => value.get_span(),
+            Value::Refl => None,
+        }
+    }
+}
+
+impl Let {
+    // a local function sees itself like a definition does, other local lets only see the ones before them
+    pub fn is_recursive(&self) -> bool {
+        matches!(&self.1, Value::Function(parameters, _) if !parameters.is_empty())
+    }
+}
+
+impl Type {
+    pub fn get_span(&self) -> Option<Span> {
+        match self {
+            Type::Product(fields) | Type::Class(fields) => merge_spans(fields.iter().map(|(_, x)| x.get_span())),
+            Type::OpenProduct(fields, tail) => merge_spans(
+                fields.iter().map(|(_, x)| x.get_span()).chain(vec![tail.get_span()])
+            ),
+            Type::OpenCoProduct(constructors, tail) => merge_spans(
+                constructors.iter().flat_map(|(name, x)| vec![name.get_span(), x.get_span()]).chain(vec![tail.get_span()])
+            ),
+            Type::CoProduct(constructors) => merge_spans(
+                constructors.iter().flat_map(|(name, x)| vec![name.get_span(), x.get_span()])
+            ),
+            Type::Function(from, to) => merge_spans(vec![from.get_span(), to.get_span()].into_iter()),
+            Type::Pi(_, name, from, to) => merge_spans(vec![name.get_span(), from.get_span(), to.get_span()].into_iter()),
+            Type::Application(function, arguments) => merge_spans(
+                arguments.iter().map(|x| x.get_span()).chain(vec![function.get_span()])
+            ),
+            Type::TypeVar(name) | Type::Hole(name) => name.get_span(),
+            Type::Universe(_) | Type::Atomic(_) => None,
+            Type::Equal(left, right) => merge_spans(vec![left.get_span(), right.get_span()].into_iter()),
+            Type::Codata(body) => body.get_span(),
+        }
+    }
+}
+
+impl Pattern {
+    pub fn binders(&self) -> Vec<&Name> {
+        match self {
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Refl => vec![],
+            Pattern::Binder(name) => vec![name],
+            Pattern::Constructor(_, inner) => inner.binders(),
+            Pattern::Tuple(items) => items.iter().flat_map(|x| x.binders()).collect(),
+            Pattern::Record(fields) => fields.iter().flat_map(|(_, x)| x.binders()).collect(),
+            Pattern::Or(alternatives) => match alternatives.first() {
+                Some(first) => first.binders(),
+                None => vec![],
+            },
+        }
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> Pattern {
+        match self {
+            Pattern::Binder(name) if name.get_name() == from => {
+                let renamed = Name::new(to.to_string(), name.get_context());
+                Pattern::Binder(match name.get_span() {
+                    Some(span) => renamed.with_span(span),
+                    None => renamed,
+                })
+            },
+            Pattern::Constructor(name, inner) => Pattern::Constructor(name.clone(), Box::new(inner.rename(from, to))),
+            Pattern::Tuple(items) => Pattern::Tuple(items.iter().map(|x| x.rename(from, to)).collect()),
+            Pattern::Record(fields) => Pattern::Record(
+                fields.iter().map(|(name, x)| (name.clone(), x.rename(from, to))).collect()
+            ),
+            Pattern::Or(alternatives) => Pattern::Or(alternatives.iter().map(|x| x.rename(from, to)).collect()),
+            other => other.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Constant(n) => write!(f, "{}", n),
+            Level::Variable(name) => write!(f, "{}", name),
+            Level::Successor(level) => write!(f, "{}+1", level),
+            Level::Maximum(levels) => {
+                let levels: Vec<String> = levels.iter().map(|x| x.to_string()).collect();
+                write!(f, "max {}", levels.join(" "))
+            },
+        }
+    }
+}
+
+impl fmt::Display for AtomicType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtomicType::Top => write!(f, "."),
+            AtomicType::Bottom => write!(f, "!"),
+            AtomicType::Int => write!(f, "Int"),
+            AtomicType::String => write!(f, "String"),
+        }
+    }
+}
+
+// positional components of a product are named by their index
+pub fn is_positional(name: &str) -> bool {
+    name.parse::<usize>().is_ok()
+}
+
+impl Type {
+    fn print_argument(&self) -> String {
+        match self {
+            Type::TypeVar(_) | Type::Universe(_) | Type::Atomic(_) | Type::Hole(_) => self.to_string(),
+            _ => format!("({})", self),
+        }
+    }
+}
+
+impl Value {
+    // values inside types, anything bigger than a name or a constant is elided
+    fn print_argument(&self) -> String {
+        match self {
+            Value::Type(typ) => typ.print_argument(),
+            Value::Var(name) => name.to_string(),
+            Value::Hole(name) => format!("?{}", name),
+            Value::Constant(constant, _) => constant.to_string(),
+            Value::Refl => "refl".to_string(),
+            Value::Implicit(value) => match &**value {
+                Value::Type(typ) => format!("@{{{}}}", typ),
+                _ => "@{...}".to_string(),
+            },
+            _ => "(...)".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Product(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, x)| match (is_positional(name.get_name()), x) {
+                        (true, Type::Product(_))
+                        | (true, Type::CoProduct(_))
+                        | (true, Type::Function(_, _))
+                        | (true, Type::Pi(_, _, _, _)) => {
+                            format!("({})", x)
+                        },
+                        (true, _) => x.to_string(),
+                        (false, _) => format!("{} {}", name, x.print_argument()),
+                    })
+                    .collect();
+                write!(f, "{}", fields.join(" * "))
+            },
+            Type::CoProduct(constructors) => {
+                let constructors: Vec<String> = constructors
+                    .iter()
+                    .map(|(name, x)| match **x {
+                        Type::CoProduct(_) | Type::Function(_, _) | Type::Pi(_, _, _, _) => format!("{} ({})", name, x),
+                        _ => format!("{} {}", name, x),
+                    })
+                    .collect();
+                write!(f, "{}", constructors.join(" + "))
+            },
+            Type::OpenProduct(fields, tail) if fields.is_empty() => write!(f, "..{}", tail),
+            Type::OpenProduct(fields, tail) => write!(f, "{} * ..{}", Type::Product(fields.clone()), tail),
+            Type::OpenCoProduct(constructors, tail) if constructors.is_empty() => write!(f, "..{}", tail),
+            Type::OpenCoProduct(constructors, tail) => write!(f, "{} + ..{}", Type::CoProduct(constructors.clone()), tail),
+            Type::Function(from, to) => match **from {
+                Type::Function(_, _) | Type::Pi(_, _, _, _) => write!(f, "({}) -> {}", from, to),
+                _ => write!(f, "{} -> {}", from, to),
+            },
+            Type::Pi(Visibility::Explicit, name, from, to) => write!(f, "({} : {}) -> {}", name, from, to),
+            Type::Pi(Visibility::Implicit, name, from, to) => write!(f, "{{{} : {}}} -> {}", name, from, to),
+            Type::Pi(Visibility::Instance, name, from, to) => write!(f, "[{} : {}] -> {}", name, from, to),
+            Type::Application(function, arguments) => {
+                write!(f, "{}", function.print_argument())?;
+                arguments.iter().try_for_each(|x| write!(f, " {}", x.print_argument()))
+            },
+            Type::TypeVar(name) => write!(f, "{}", name),
+            Type::Universe(level) => match level {
+                Level::Constant(_) | Level::Variable(_) => write!(f, "@{}", level),
+                _ => write!(f, "@({})", level),
+            },
+            Type::Atomic(atomic) => write!(f, "{}", atomic),
+            Type::Hole(name) => write!(f, "?{}", name),
+            Type::Class(fields) => write!(f, "class {}", Type::Product(fields.clone())),
+            Type::Equal(left, right) => write!(f, "{} == {}", left.print_argument(), right.print_argument()),
+            Type::Codata(body) => write!(f, "codata {}", body),
+        }
+    }
+}
+
+impl fmt::Display for AtomicValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtomicValue::Int(n) => write!(f, "{}", n),
+            AtomicValue::StringLiteral(text) => write!(f, "/{}/", text),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Binder(name) => write!(f, "{}", name),
+            Pattern::Constructor(name, inner) => match **inner {
+                Pattern::Constructor(_, _) | Pattern::Or(_) => write!(f, "{} ({})", name, inner),
+                _ => write!(f, "{} {}", name, inner),
+            },
+            Pattern::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|x| x.to_string()).collect();
+                write!(f, "({})", items.join(", "))
+            },
+            Pattern::Record(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, x)| format!("{} = {}", name, x))
+                    .collect();
+                write!(f, "{{{}}}", fields.join(", "))
+            },
+            Pattern::Literal(value) => write!(f, "{}", value),
+            Pattern::Refl => write!(f, "refl"),
+            Pattern::Or(alternatives) => {
+                let alternatives: Vec<String> = alternatives.iter().map(|x| x.to_string()).collect();
+                write!(f, "{}", alternatives.join(" | "))
+            },
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::inner_representation::abstract_syntax_tree::{AtomicValue, Name};
+
+// position of a sub-value inside the scrutinee, read from the root
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    Payload,
+    Index(usize),
+    Field(String),
+}
+
+pub type Occurrence = Vec<Step>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Case {
+    Constructor(String),
+    Literal(AtomicValue),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecisionTree {
+    Fail,
+    Leaf(usize, Vec<(Name, Occurrence)>),
+    Switch(Occurrence, Vec<(Case, DecisionTree)>, Option<Box<DecisionTree>>),
+}
+
+fn print_occurrence(occurrence: &[Step]) -> String {
+    occurrence
+        .iter()
+        .fold("$".to_string(), |text, step| match step {
+            Step::Payload => text + ".payload",
+            Step::Index(i) => format!("{}.{}", text, i),
+            Step::Field(name) => format!("{}.{}", text, name),
+        })
+}
+
+impl DecisionTree {
+    fn print(&self, fmt: &mut std::fmt::Formatter, prefix: String) -> std::fmt::Result {
+        match self {
+            DecisionTree::Fail => writeln!(fmt, "{}fail", prefix),
+            DecisionTree::Leaf(arm, bindings) => {
+                let bindings: Vec<String> = bindings
+                    .iter()
+                    .map(|(name, occurrence)| format!("{} = {}", name, print_occurrence(occurrence)))
+                    .collect();
+                writeln!(fmt, "{}arm {} [{}]", prefix, arm, bindings.join(", "))
+            },
+            DecisionTree::Switch(occurrence, cases, default) => {
+                writeln!(fmt, "{}switch {}", prefix, print_occurrence(occurrence))?;
+                for (case, tree) in cases {
+                    match case {
+                        Case::Constructor(name) => writeln!(fmt, "{}|- {}", prefix, name)?,
+                        Case::Literal(value) => writeln!(fmt, "{}|- {}", prefix, value)?,
+                    }
+                    tree.print(fmt, prefix.clone() + "|  ")?;
+                }
+                if let Some(tree) = default {
+                    writeln!(fmt, "{}`- _", prefix)?;
+                    tree.print(fmt, prefix + "   ")?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for DecisionTree {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.print(fmt, "".to_string())
+    }
+}
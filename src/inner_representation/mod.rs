@@ -1,3 +1,4 @@
 pub mod abstract_syntax_tree;
+pub mod decision_tree;
 pub mod token;
 pub mod token_tree;
\ No newline at end of file
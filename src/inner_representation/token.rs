@@ -1,7 +1,10 @@
+use crate::inner_representation::abstract_syntax_tree::Span;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Token {
     text: String,
     kind: TokenKind,
+    span: Option<Span>,
 }
 
 impl std::fmt::Display for Token {
@@ -15,9 +18,19 @@ impl Token {
         Token {
             text,
             kind,
+            span: None,
         }
     }
-    
+
+    pub fn with_span(mut self, span: Span) -> Token {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn get_span(&self) -> Option<Span> {
+        self.span
+    }
+
     pub fn get_kind(&self) -> TokenKind {
         self.kind
     }
@@ -25,6 +38,10 @@ impl Token {
     pub fn get_text(self) -> String {
         self.text
     }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -200,13 +217,16 @@ impl TokenKind {
 pub struct RawToken {
     pub text: String,
     pub kind: RawTokenKind,
+    // where the text starts in the program
+    pub offset: usize,
 }
 
 impl RawToken {
-    pub fn new(text: String, kind: RawTokenKind) -> RawToken {
+    pub fn new(text: String, kind: RawTokenKind, offset: usize) -> RawToken {
         RawToken {
             text,
             kind,
+            offset,
         }
     }
 }
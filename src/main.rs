@@ -1,6 +1,172 @@
 mod inner_representation;
 mod compiling_process;
 mod utils;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use compiling_process::executing_compiler_extructions::load;
+use compiling_process::interpreting::Interpreter;
+use compiling_process::static_analysis::classes::{elaborate_typed, elaborate_with};
+use compiling_process::static_analysis::holes::report_goals;
+use compiling_process::static_analysis::normalising::DEFAULT_FUEL;
+use compiling_process::static_analysis::termination::terminating;
+use compiling_process::static_analysis::type_inference::{as_type_definition, Inference};
+use compiling_process::translating::bytecode::{translate, VirtualMachine};
+use compiling_process::translating::c::emit_c;
+use compiling_process::translating::js::emit_js;
+use compiling_process::translating::llvm::{self, emit_llvm};
+use compiling_process::translating::rust::{emit_rust, typed};
+use compiling_process::translating::scheme::emit_scheme;
+use compiling_process::translating::wat::emit_wat;
+
+const USAGE: &str = "usage: test-language <check|run|goals|build> <file> [--no-prelude] [--interpret] \
+                     [--target c|js|wat|llvm|rust|scheme] [--output <file>] [--cc] [--erase-types] \
+                     [--cumulative] [--fuel <reductions>]";
+
+// options followed by a value, the other arguments starting with `--` are flags
+const OPTIONS: [&str; 3] = ["--target", "--output", "--fuel"];
+
+// writes the program in the target language next to the source
+fn build(path: &Path, extension: &str, source: String, output: Option<&str>) -> Result<PathBuf, String> {
+    let output = output.map(Path::new).map(Path::to_path_buf).unwrap_or_else(|| path.with_extension(extension));
+    write(&output, source)?;
+    Ok(output)
+}
+
+fn write(path: &Path, text: String) -> Result<(), String> {
+    fs::write(path, text).map_err(|error| format!("cannot write {}: {}", path.display(), error))?;
+    println!("wrote {}", path.display());
+    Ok(())
+}
+
+// turns what was written into an executable next to the first source
+fn compile(compiler: &str, flags: &[&str], sources: &[&Path]) -> Result<(), String> {
+    let executable = sources[0].with_extension("");
+    let status = Command::new(compiler)
+        .args(flags)
+        .arg("-o")
+        .arg(&executable)
+        .args(sources)
+        .status()
+        .map_err(|error| format!("cannot run {}: {}", compiler, error))?;
+    if !status.success() {
+        return Err(format!("{} failed on {}", compiler, sources[0].display()));
+    }
+    println!("wrote {}", executable.display());
+    Ok(())
+}
+
+fn run(arguments: &[String]) -> Result<(), String> {
+    let mut flags = vec![];
+    let mut options = vec![];
+    let mut positional = vec![];
+    let mut rest = arguments.iter();
+    while let Some(argument) = rest.next() {
+        if OPTIONS.contains(&argument.as_str()) {
+            let value = rest.next().ok_or_else(|| format!("{} needs a value", argument))?;
+            options.push((argument.as_str(), value.as_str()));
+        } else if argument.starts_with("--") {
+            flags.push(argument.as_str());
+        } else {
+            positional.push(argument);
+        }
+    }
+    let flag = |name: &str| flags.contains(&name);
+    let option = |name: &str| options.iter().rev().find(|(x, _)| *x == name).map(|(_, value)| *value);
+    let (prelude, interpret) = (!flag("--no-prelude"), flag("--interpret"));
+    let (command, path) = match &positional[..] {
+        [command, path] => (command.as_str(), Path::new(path.as_str())),
+        _ => return Err(USAGE.to_string()),
+    };
+    let (ast, skip) = load(path, prelude)?;
+    let fuel = match option("--fuel") {
+        Some(fuel) => fuel.parse::<usize>().map_err(|_| format!("--fuel takes a number of reductions, not {}", fuel))?,
+        None => DEFAULT_FUEL,
+    };
+    // with --cumulative a type of a universe also lives in the universes above it
+    let checker = || {
+        let inference = Inference::new().terminating(terminating(ast.get_commands())).fuel(fuel);
+        if flag("--cumulative") { inference.cumulative() } else { inference }
+    };
+    let inference = checker();
+    match command {
+        "check" => {
+            let mut inference = inference;
+            let types = inference.infer_program(ast.get_program())?;
+            for definition in &ast.get_program()[skip..] {
+                if as_type_definition(&definition.1).is_none() {
+                    println!("{} : {}", definition.0, types[definition.0.get_name()]);
+                }
+            }
+        },
+        "goals" => {
+            for goal in report_goals(inference, ast.get_program())? {
+                println!("{}", goal);
+            }
+        },
+        "run" => {
+            let program = elaborate_with(inference, ast.get_program())?;
+            // the tree-walking interpreter is kept to compare the virtual machine against
+            if interpret {
+                let interpreter = Interpreter::new(&program);
+                let main = interpreter.get("main")?;
+                println!("{}", interpreter.force_all(main)?);
+            } else {
+                let bytecode = translate(&program)?;
+                let machine = VirtualMachine::new(&bytecode);
+                let main = machine.get("main")?;
+                println!("{}", machine.show(&machine.force_all(main)?));
+            }
+        },
+        "build" => {
+            // the Rust backend writes the types the checker found, for its local functions as well
+            let target = option("--target").unwrap_or("c");
+            let (program, types) = match target {
+                "rust" => typed(inference, checker(), ast.get_program())?,
+                _ => elaborate_typed(inference, ast.get_program())?,
+            };
+            match target {
+                "c" => {
+                    let output = build(path, "c", emit_c(&program)?, option("--output"))?;
+                    if flag("--cc") {
+                        compile("cc", &["-std=c99", "-O2"], &[&output])?;
+                    }
+                },
+                "js" => {
+                    build(path, "js", emit_js(&program, flag("--erase-types"))?, option("--output"))?;
+                },
+                "wat" => {
+                    build(path, "wat", emit_wat(&program)?, option("--output"))?;
+                },
+                // the module only runs linked with the C runtime, which is written next to it
+                "llvm" => {
+                    let output = build(path, "ll", emit_llvm(&program)?, option("--output"))?;
+                    let runtime = output.with_extension("runtime.c");
+                    write(&runtime, llvm::RUNTIME.to_string())?;
+                    if flag("--cc") {
+                        compile("clang", &["-O2"], &[&output, &runtime])?;
+                    }
+                },
+                "rust" => {
+                    build(path, "rs", emit_rust(&program, &types)?, option("--output"))?;
+                },
+                "scheme" => {
+                    build(path, "scm", emit_scheme(&program)?, option("--output"))?;
+                },
+                target => return Err(format!("unknown target {}", target)),
+            }
+        },
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
 fn main() {
-    println!("Hello, world!");
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(error) = run(&arguments) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
 }